@@ -0,0 +1,118 @@
+
+use crate::seed::derive_seed;
+use crate::voronoi::VoronoiCenter;
+use glam::{vec2, UVec2, Vec2};
+use ndarray::Array2;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+/// Result of `Annealing::optimize`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Annealed {
+    /// Fitness of the map before optimizing
+    pub initial_fitness: f64,
+    /// Fitness of the returned (best) map
+    pub fitness: f64,
+    /// Number of accepted mutations
+    pub accepted: usize,
+}
+
+/// Simulated annealing optimizer, a finishing pass that mutates a map (of any type, eg. an
+/// `Array2` or voronoi centers) to maximize a fitness function, eg. to hit a desired water
+/// percentage or path length between spawns.
+///
+/// Every iteration applies one random mutation to a copy of the current map. Improvements are
+/// always accepted, a mutation that lowers the fitness by `d` is accepted with probability
+/// `exp(-d / temperature)`. The temperature falls exponentially from `initial_temperature` to
+/// `final_temperature`, with an initial temperature of 0 this is plain hill climbing.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Annealing {
+    pub seed: u64,
+    pub iterations: usize,
+    /// Should be in the order of the fitness differences of single mutations
+    pub initial_temperature: f64,
+    pub final_temperature: f64,
+}
+
+impl Default for Annealing {
+    fn default() -> Self {
+        Self { seed: 0, iterations: 1000, initial_temperature: 1.0, final_temperature: 0.001 }
+    }
+}
+
+impl Annealing {
+    /// Hill climbing: only mutations that do not lower the fitness are accepted.
+    pub fn hill_climbing(seed: u64, iterations: usize) -> Self {
+        Self { seed, iterations, initial_temperature: 0.0, final_temperature: 0.0 }
+    }
+
+    /// Temperature in iteration `i`.
+    pub fn temperature(&self, i: usize) -> f64 {
+        if self.initial_temperature <= 0.0 {
+            return 0.0;
+        }
+        let t = i as f64 / self.iterations.saturating_sub(1).max(1) as f64;
+        let end = self.final_temperature.clamp(f64::MIN_POSITIVE, self.initial_temperature);
+        self.initial_temperature * (end / self.initial_temperature).powf(t)
+    }
+
+    /// Optimize `map` in place, it is replaced by the best map found.
+    /// `mutate` applies one random change to a map using the given rng (see `swap_tiles` and
+    /// `move_center`), results are deterministic for the same seed.
+    pub fn optimize<M, Mu, F>(&self, map: &mut M, mut mutate: Mu, fitness: F) -> Annealed
+    where
+        M: Clone,
+        Mu: FnMut(&mut M, &mut StdRng),
+        F: Fn(&M) -> f64,
+    {
+        let mut rng = StdRng::seed_from_u64(derive_seed(self.seed, 0));
+        let mut current = map.clone();
+        let mut current_fitness = fitness(&current);
+        let mut result = Annealed { initial_fitness: current_fitness, fitness: current_fitness, accepted: 0 };
+
+        for i in 0..self.iterations {
+            let temperature = self.temperature(i);
+            let mut candidate = current.clone();
+            mutate(&mut candidate, &mut rng);
+            let f = fitness(&candidate);
+
+            let accept = match f >= current_fitness {
+                true => true,
+                false if temperature > 0.0 => rng.gen::<f64>() < ((f - current_fitness) / temperature).exp(),
+                false => false,
+            };
+            if !accept {
+                continue;
+            }
+            current = candidate;
+            current_fitness = f;
+            result.accepted += 1;
+            if f > result.fitness {
+                result.fitness = f;
+                *map = current.clone();
+            }
+        }
+        result
+    }
+}
+
+/// Mutation that swaps two random tiles of `a`.
+pub fn swap_tiles<T, R: Rng>(a: &mut Array2<T>, rng: &mut R) {
+    let (sx, sy) = a.dim();
+    if sx == 0 || sy == 0 {
+        return;
+    }
+    let p = (rng.gen_range(0..sx), rng.gen_range(0..sy));
+    let q = (rng.gen_range(0..sx), rng.gen_range(0..sy));
+    a.swap(p, q);
+}
+
+/// Mutation that moves a random center by up to `max_distance` along each axis, keeping it
+/// within a map of the given size.
+pub fn move_center<R: Rng>(centers: &mut [VoronoiCenter], size: UVec2, max_distance: f32, rng: &mut R) {
+    if centers.is_empty() || max_distance <= 0.0 {
+        return;
+    }
+    let i = rng.gen_range(0..centers.len());
+    let d = vec2(rng.gen_range(-max_distance..=max_distance), rng.gen_range(-max_distance..=max_distance));
+    centers[i].position = (centers[i].position + d).clamp(Vec2::ZERO, size.as_vec2() - 1e-3);
+}
@@ -0,0 +1,92 @@
+
+use crate::neighborhood::{manhattan, Metric, Neighborhood};
+use crate::tile::Tile;
+use glam::{ivec2, BVec2};
+use ndarray::{Array2, Zip};
+
+/// Update rule of a `CellularAutomaton`: the next value of a cell from its current value and
+/// its neighborhood (in the current generation).
+/// Implemented for closures `Fn(T, &Neighborhood<T>) -> T`.
+pub trait Rule<T: Tile> {
+    fn next(&self, cell: T, neighborhood: &Neighborhood<T>) -> T;
+}
+
+impl<T: Tile, F: Fn(T, &Neighborhood<T>) -> T> Rule<T> for F {
+    fn next(&self, cell: T, neighborhood: &Neighborhood<T>) -> T {
+        self(cell, neighborhood)
+    }
+}
+
+/// Generic cellular automaton, eg. for smoothing, cave generation, fire spread or erosion like
+/// passes.
+/// All cells are updated simultaneously from the previous generation (double buffered), with
+/// the `rayon` feature in parallel.
+pub struct CellularAutomaton<R> {
+    pub rule: R,
+    pub radius: u32,
+    pub metric: Metric,
+    /// Wrap the neighborhood around the map along x and/or y, see `Neighborhood::wrapping`
+    pub wrap: BVec2,
+}
+
+impl<R> CellularAutomaton<R> {
+    /// Automaton with the 4 direct neighbors as neighborhood.
+    pub fn new(rule: R) -> Self {
+        Self { rule, radius: 1, metric: manhattan, wrap: BVec2::FALSE }
+    }
+
+    /// Use the neighborhood of `radius` in `metric`, see `Neighborhood::with_shape`.
+    pub fn with_shape(mut self, radius: u32, metric: Metric) -> Self {
+        self.radius = radius;
+        self.metric = metric;
+        self
+    }
+
+    pub fn wrapping(mut self, wrap: BVec2) -> Self {
+        self.wrap = wrap;
+        self
+    }
+
+    /// Compute the next generation of `a` into `next` (which must have the size of `a`).
+    /// Returns the number of cells that changed.
+    pub fn step<T>(&self, a: &Array2<T::Numeric>, next: &mut Array2<T::Numeric>) -> usize
+    where
+        T: Tile,
+        T::Numeric: Send + Sync,
+        R: Rule<T> + Sync,
+    {
+        assert_eq!(a.dim(), next.dim());
+        let f = |(x, y): (usize, usize), v: &mut T::Numeric| {
+            let neighborhood = Neighborhood::with_shape(a, ivec2(x as i32, y as i32), self.radius, self.metric)
+                .wrapping(self.wrap);
+            *v = self.rule.next(a[[x, y]].into(), &neighborhood).as_numeric();
+        };
+
+        #[cfg(feature = "rayon")]
+        Zip::indexed(&mut *next).par_for_each(f);
+        #[cfg(not(feature = "rayon"))]
+        Zip::indexed(&mut *next).for_each(f);
+
+        a.iter().zip(next.iter()).filter(|(u, v)| u != v).count()
+    }
+
+    /// Step `a` until no cell changes anymore (convergence) or `max_steps` steps were made.
+    /// Returns the number of steps that changed `a`, less than `max_steps` if the automaton
+    /// converged.
+    pub fn run<T>(&self, a: &mut Array2<T::Numeric>, max_steps: usize) -> usize
+    where
+        T: Tile,
+        T::Numeric: Send + Sync,
+        R: Rule<T> + Sync,
+    {
+        let mut next = a.clone();
+        for i in 0..max_steps {
+            let changed = self.step::<T>(a, &mut next);
+            std::mem::swap(a, &mut next);
+            if changed == 0 {
+                return i;
+            }
+        }
+        max_steps
+    }
+}
@@ -0,0 +1,179 @@
+//! Generate maps from pipeline config files.
+//!
+//! ```text
+//! mapgen2d-cli <pipeline file> [key=value ...]
+//! ```
+//!
+//! The pipeline file uses the format of `mapgen_2d::config`. Besides the parameters of the
+//! selected generator it has the keys
+//!
+//! * `generator`: `"map"` (`MapConfig`), `"noise"` (`NoiseConfig`) or `"voronoi"`
+//!   (`VoronoiConfig`)
+//! * `preset`: optional preset name of the generator config, applied before all other keys
+//! * `output`: output path, the format is chosen by extension (`.png`, `.csv`, `.tmx`).
+//!   May be given multiple times.
+//! * `tile_size` and `tileset`: tile size and tileset path written to `.tmx` files
+//!
+//! Additional `key=value` arguments override the values of the file.
+//! Wave function collapse is not supported as it requires a probability callback.
+
+use glam::{uvec2, UVec2};
+use mapgen_2d::config::{parse, Config, ConfigError, MapConfig, NoiseConfig, Value, VoronoiConfig};
+use mapgen_2d::io::{csv, png, tmx::TmxExport};
+use mapgen_2d::palette::{distinct_colors, DiscretePalette};
+use mapgen_2d::voronoi::VoronoiTile;
+use ndarray::Array2;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+use std::process::ExitCode;
+
+/// A generated map in the form needed by the writers
+enum Output {
+    /// Values in [0, 1)
+    Field(Array2<f64>),
+    /// Tile ids, `None` for empty tiles, with colors for previews
+    Tiles(Array2<Option<u32>>, DiscretePalette<Option<u32>>),
+}
+
+struct Pipeline {
+    generator: String,
+    preset: Option<String>,
+    outputs: Vec<String>,
+    tile_size: UVec2,
+    tileset: Option<String>,
+    /// Parameters for the generator config
+    parameters: Vec<(String, Value)>,
+}
+
+fn read_pipeline(text: &str) -> Result<Pipeline, ConfigError> {
+    let mut r = Pipeline {
+        generator: String::new(),
+        preset: None,
+        outputs: Vec::new(),
+        tile_size: uvec2(16, 16),
+        tileset: None,
+        parameters: Vec::new(),
+    };
+    for (key, value) in parse(text)? {
+        match key.as_str() {
+            "generator" => r.generator = value.as_str(&key)?.to_string(),
+            "preset" => r.preset = Some(value.as_str(&key)?.to_string()),
+            "output" => r.outputs.push(value.as_str(&key)?.to_string()),
+            "tile_size" => r.tile_size = value.as_uvec2(&key)?,
+            "tileset" => r.tileset = Some(value.as_str(&key)?.to_string()),
+            _ => r.parameters.push((key, value)),
+        }
+    }
+    Ok(r)
+}
+
+fn configure<C: Config>(preset: Option<C>, pipeline: &Pipeline) -> Result<C, ConfigError> {
+    let mut config = match (&pipeline.preset, preset) {
+        (None, _) => C::default(),
+        (Some(_), Some(p)) => p,
+        (Some(name), None) => {
+            return Err(ConfigError::Invalid { key: "preset".to_string(), message: format!("unknown preset '{}'", name) })
+        }
+    };
+    for (key, value) in pipeline.parameters.iter() {
+        config.set(key, value)?;
+    }
+    config.validate()?;
+    Ok(config)
+}
+
+fn generate(pipeline: &Pipeline) -> Result<Output, ConfigError> {
+    let preset = pipeline.preset.as_deref().unwrap_or_default();
+    match pipeline.generator.as_str() {
+        "noise" => {
+            let config: NoiseConfig = configure(NoiseConfig::preset(preset), pipeline)?;
+            Ok(Output::Field(config.noise().generate()))
+        }
+        "map" => {
+            let config: MapConfig = configure(MapConfig::preset(preset), pipeline)?;
+            let map = config.builder().build();
+            let rivers = map.rivers.unwrap_or_else(|| Array2::from_elem(map.land.dim(), false));
+            let tiles = ndarray::Zip::from(&map.land)
+                .and(&rivers)
+                .map_collect(|&land, &river| Some(if river { 2 } else { land as u32 }));
+            let palette = DiscretePalette::new([0, 0, 0])
+                .with(Some(0), [30, 60, 160])
+                .with(Some(1), [70, 140, 50])
+                .with(Some(2), [90, 150, 230]);
+            Ok(Output::Tiles(tiles, palette))
+        }
+        "voronoi" => {
+            let config: VoronoiConfig = configure(None, pipeline)?;
+            let result = config.voronoi().generate();
+            let tiles = result.into_tiles(|t| match t {
+                VoronoiTile::Cell(i) => Some(i as u32),
+                _ => None,
+            });
+            let palette = distinct_colors(config.centers)
+                .into_iter()
+                .enumerate()
+                .fold(DiscretePalette::new([0, 0, 0]), |p, (i, c)| p.with(Some(i as u32), c));
+            Ok(Output::Tiles(tiles, palette))
+        }
+        g => Err(ConfigError::Invalid {
+            key: "generator".to_string(),
+            message: format!("unknown generator '{}', expected \"map\", \"noise\" or \"voronoi\"", g),
+        }),
+    }
+}
+
+fn write(output: &Output, path: &str, pipeline: &Pipeline) -> Result<(), String> {
+    let extension = Path::new(path).extension().and_then(|e| e.to_str()).unwrap_or_default();
+    let file = BufWriter::new(File::create(path).map_err(|e| e.to_string())?);
+    let r = match (extension, output) {
+        ("png", Output::Field(a)) => png::write_gray8(file, &a.mapv(|v| (v * 256.0) as u8)),
+        ("png", Output::Tiles(a, palette)) => png::write_rgb8(file, &palette.apply(a)),
+        ("csv", Output::Field(a)) => csv::write(file, a, ','),
+        ("csv", Output::Tiles(a, _)) => {
+            csv::write(file, &a.mapv(|t| t.map_or(-1, |id| id as i64)), ',')
+        }
+        ("tmx", Output::Tiles(a, _)) => {
+            let (width, height) = a.dim();
+            let mut tmx = TmxExport::new(width, height, pipeline.tile_size.x, pipeline.tile_size.y)
+                .layer(&pipeline.generator, a, |t| *t);
+            if let Some(tileset) = &pipeline.tileset {
+                tmx = tmx.tileset(tileset);
+            }
+            tmx.write(file)
+        }
+        ("tmx", Output::Field(_)) => return Err("tmx output requires a tile generator".to_string()),
+        _ => return Err(format!("unsupported output format '{}'", extension)),
+    };
+    r.map_err(|e| e.to_string())
+}
+
+fn run(args: &[String]) -> Result<(), String> {
+    let path = args.first().ok_or("usage: mapgen2d-cli <pipeline file> [key=value ...]")?;
+    let mut text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    for arg in args[1..].iter() {
+        text.push('\n');
+        text.push_str(arg);
+    }
+
+    let pipeline = read_pipeline(&text).map_err(|e| format!("{}: {}", path, e))?;
+    if pipeline.outputs.is_empty() {
+        return Err(format!("{}: no output given", path));
+    }
+    let output = generate(&pipeline).map_err(|e| format!("{}: {}", path, e))?;
+    for o in pipeline.outputs.iter() {
+        write(&output, o, &pipeline).map_err(|e| format!("{}: {}", o, e))?;
+    }
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match run(&args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
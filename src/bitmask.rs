@@ -0,0 +1,286 @@
+
+use crate::coord::map_size;
+use glam::{uvec2, IVec2, UVec2};
+use ndarray::Array2;
+use std::ops::{BitAnd, BitOr, BitXor, Not, Sub};
+
+/// Boolean map stored as a bitset, 1/8 of the memory of an `Array2<bool>` and with fast set
+/// operations. Bits are stored x-major like the arrays of this crate, so `iter` visits
+/// positions in the same order as `MapAccess::iter_with_positions`.
+///
+/// ```
+/// # use mapgen_2d::bitmask::Mask2;
+/// # use ndarray::Array2;
+/// # let land = Array2::from_shape_fn((10, 8), |(x, _)| x < 6);
+/// # let forest = Array2::from_shape_fn((10, 8), |(_, y)| y < 2);
+/// let walkable = Mask2::from(&land) & !Mask2::from(&forest);
+/// let area = walkable.count();
+/// assert_eq!(area, 6 * 6);
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Mask2 {
+    size: UVec2,
+    /// Bits beyond `size.x * size.y` in the last word are always 0
+    words: Vec<u64>,
+}
+
+impl Mask2 {
+    /// Mask of the given size with no positions set.
+    pub fn new(size: UVec2) -> Self {
+        Self::filled(size, false)
+    }
+
+    pub fn filled(size: UVec2, value: bool) -> Self {
+        let len = size.x as usize * size.y as usize;
+        let word = if value { u64::MAX } else { 0 };
+        let mut mask = Self { size, words: vec![word; len.div_ceil(64)] };
+        mask.clear_tail();
+        mask
+    }
+
+    pub fn from_fn<F: FnMut(UVec2) -> bool>(size: UVec2, mut f: F) -> Self {
+        let mut mask = Self::new(size);
+        for x in 0..size.x {
+            for y in 0..size.y {
+                if f(uvec2(x, y)) {
+                    mask.set(uvec2(x, y), true);
+                }
+            }
+        }
+        mask
+    }
+
+    pub fn size(&self) -> UVec2 {
+        self.size
+    }
+
+    fn index(&self, p: UVec2) -> usize {
+        assert!(p.x < self.size.x && p.y < self.size.y, "{} outside of mask of size {}", p, self.size);
+        p.x as usize * self.size.y as usize + p.y as usize
+    }
+
+    /// Panics if `p` is outside of the mask.
+    pub fn get(&self, p: UVec2) -> bool {
+        let i = self.index(p);
+        self.words[i / 64] & (1 << (i % 64)) != 0
+    }
+
+    /// `None` if `p` is outside of the mask.
+    pub fn get_at(&self, p: IVec2) -> Option<bool> {
+        match p.cmpge(IVec2::ZERO).all() && p.as_uvec2().cmplt(self.size).all() {
+            true => Some(self.get(p.as_uvec2())),
+            false => None,
+        }
+    }
+
+    /// Panics if `p` is outside of the mask.
+    pub fn set(&mut self, p: UVec2, value: bool) {
+        let i = self.index(p);
+        match value {
+            true => self.words[i / 64] |= 1 << (i % 64),
+            false => self.words[i / 64] &= !(1 << (i % 64)),
+        }
+    }
+
+    /// Number of set positions
+    pub fn count(&self) -> usize {
+        self.words.iter().map(|w| w.count_ones() as usize).sum()
+    }
+
+    /// True if no position is set
+    pub fn is_clear(&self) -> bool {
+        self.words.iter().all(|&w| w == 0)
+    }
+
+    /// Set positions, x-major.
+    pub fn iter(&self) -> impl Iterator<Item = UVec2> + '_ {
+        let height = self.size.y as usize;
+        self.words.iter().enumerate().flat_map(move |(i, &word)| {
+            let mut word = word;
+            std::iter::from_fn(move || {
+                if word == 0 {
+                    return None;
+                }
+                let bit = word.trailing_zeros() as usize;
+                word &= word - 1;
+                let index = i * 64 + bit;
+                Some(uvec2((index / height) as u32, (index % height) as u32))
+            })
+        })
+    }
+
+    pub fn to_array(&self) -> Array2<bool> {
+        Array2::from_shape_fn((self.size.x as usize, self.size.y as usize), |(x, y)| {
+            self.get(uvec2(x as u32, y as u32))
+        })
+    }
+
+    /// Set all positions that are set in `other`. Panics if the sizes differ.
+    pub fn union_with(&mut self, other: &Mask2) {
+        self.combine(other, |a, b| a | b);
+    }
+
+    /// Clear all positions that are not set in `other`. Panics if the sizes differ.
+    pub fn intersect_with(&mut self, other: &Mask2) {
+        self.combine(other, |a, b| a & b);
+    }
+
+    /// Clear all positions that are set in `other`. Panics if the sizes differ.
+    pub fn subtract(&mut self, other: &Mask2) {
+        self.combine(other, |a, b| a & !b);
+    }
+
+    /// Toggle all positions that are set in `other`. Panics if the sizes differ.
+    pub fn toggle(&mut self, other: &Mask2) {
+        self.combine(other, |a, b| a ^ b);
+    }
+
+    /// Toggle all positions.
+    pub fn negate(&mut self) {
+        for w in self.words.iter_mut() {
+            *w = !*w;
+        }
+        self.clear_tail();
+    }
+
+    fn combine<F: Fn(u64, u64) -> u64>(&mut self, other: &Mask2, f: F) {
+        assert_eq!(self.size, other.size, "masks of different size");
+        for (a, &b) in self.words.iter_mut().zip(other.words.iter()) {
+            *a = f(*a, b);
+        }
+    }
+
+    fn clear_tail(&mut self) {
+        let tail = (self.size.x as usize * self.size.y as usize) % 64;
+        if let (Some(last), true) = (self.words.last_mut(), tail > 0) {
+            *last &= (1 << tail) - 1;
+        }
+    }
+}
+
+impl From<&Array2<bool>> for Mask2 {
+    fn from(a: &Array2<bool>) -> Self {
+        Self::from_fn(map_size(a), |p| a[[p.x as usize, p.y as usize]])
+    }
+}
+
+impl From<&Mask2> for Array2<bool> {
+    fn from(mask: &Mask2) -> Self {
+        mask.to_array()
+    }
+}
+
+impl Not for &Mask2 {
+    type Output = Mask2;
+
+    fn not(self) -> Mask2 {
+        let mut mask = self.clone();
+        mask.negate();
+        mask
+    }
+}
+
+impl Not for Mask2 {
+    type Output = Mask2;
+
+    fn not(mut self) -> Mask2 {
+        self.negate();
+        self
+    }
+}
+
+macro_rules! set_operator {
+    ($trait:ident, $fn:ident, $method:ident) => {
+        impl $trait<&Mask2> for &Mask2 {
+            type Output = Mask2;
+
+            fn $fn(self, other: &Mask2) -> Mask2 {
+                let mut mask = self.clone();
+                mask.$method(other);
+                mask
+            }
+        }
+
+        impl $trait<Mask2> for Mask2 {
+            type Output = Mask2;
+
+            fn $fn(mut self, other: Mask2) -> Mask2 {
+                self.$method(&other);
+                self
+            }
+        }
+    };
+}
+
+set_operator!(BitOr, bitor, union_with);
+set_operator!(BitAnd, bitand, intersect_with);
+set_operator!(Sub, sub, subtract);
+set_operator!(BitXor, bitxor, toggle);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use glam::ivec2;
+
+    /// 9x15 map (135 bits, so the last word is partial) with a diagonal pattern
+    fn pattern() -> Array2<bool> {
+        Array2::from_shape_fn((9, 15), |(x, y)| (x + 2 * y) % 3 == 0)
+    }
+
+    #[test]
+    fn array_round_trip_and_access() {
+        let a = pattern();
+        let mask = Mask2::from(&a);
+        assert_eq!(mask.size(), uvec2(9, 15));
+        assert_eq!(Array2::from(&mask), a);
+        assert_eq!(mask.count(), a.iter().filter(|&&b| b).count());
+        assert_eq!(mask.get_at(ivec2(3, 0)), Some(true));
+        assert_eq!(mask.get_at(ivec2(9, 0)), None);
+        assert_eq!(mask.get_at(ivec2(0, -1)), None);
+
+        let mut mask = Mask2::new(uvec2(9, 15));
+        assert!(mask.is_clear());
+        mask.set(uvec2(8, 14), true);
+        assert!(mask.get(uvec2(8, 14)) && mask.count() == 1);
+        mask.set(uvec2(8, 14), false);
+        assert!(mask.is_clear());
+    }
+
+    #[test]
+    fn iteration_order() {
+        let a = pattern();
+        let positions: Vec<UVec2> = Mask2::from(&a).iter().collect();
+        let expected: Vec<UVec2> = a
+            .indexed_iter()
+            .filter(|(_, &b)| b)
+            .map(|((x, y), _)| uvec2(x as u32, y as u32))
+            .collect();
+        assert_eq!(positions, expected);
+    }
+
+    #[test]
+    fn set_operations() {
+        let a = Mask2::from(&pattern());
+        let b = Mask2::from_fn(uvec2(9, 15), |p| p.x < 4);
+        for p in (0..9).flat_map(|x| (0..15).map(move |y| uvec2(x, y))) {
+            assert_eq!((&a | &b).get(p), a.get(p) || b.get(p));
+            assert_eq!((&a & &b).get(p), a.get(p) && b.get(p));
+            assert_eq!((&a - &b).get(p), a.get(p) && !b.get(p));
+            assert_eq!((&a ^ &b).get(p), a.get(p) != b.get(p));
+            assert_eq!((!&a).get(p), !a.get(p));
+        }
+
+        // Negation does not set the unused bits of the last word
+        let full = Mask2::filled(uvec2(9, 15), true);
+        assert_eq!(full.count(), 135);
+        assert_eq!(!Mask2::new(uvec2(9, 15)), full);
+        assert_eq!((!full.clone()).count(), 0);
+        assert_eq!((a.clone() | !a.clone()).count(), 135);
+    }
+
+    #[test]
+    #[should_panic(expected = "masks of different size")]
+    fn different_sizes() {
+        let _ = Mask2::new(uvec2(4, 4)) | Mask2::new(uvec2(4, 5));
+    }
+}
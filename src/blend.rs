@@ -0,0 +1,165 @@
+
+//! Element-wise combinators for `Array2<f64>` fields (heightmaps, noise, gradients).
+//!
+//! All binary operations panic if the shapes of their operands differ.
+//!
+//! Formulas can be written with `Blend` which accumulates into a single array, eg.
+//! `0.6 * noise1 + 0.4 * ridged - falloff` becomes
+//!
+//! ```
+//! # use mapgen_2d::blend::Blend;
+//! # use ndarray::Array2;
+//! # let [noise1, ridged, falloff] = [0.5, 1.0, 0.25].map(|v| Array2::from_elem((8, 8), v));
+//! let height = (Blend::from(&noise1).scale(0.6).add_scaled(&ridged, 0.4) - &falloff).build();
+//! # assert!(height.iter().all(|v| (v - 0.45).abs() < 1e-12));
+//! ```
+
+use ndarray::{Array2, Zip};
+use std::ops::{Add, Sub};
+
+pub fn add(a: &Array2<f64>, b: &Array2<f64>) -> Array2<f64> {
+    Zip::from(a).and(b).map_collect(|x, y| x + y)
+}
+
+pub fn multiply(a: &Array2<f64>, b: &Array2<f64>) -> Array2<f64> {
+    Zip::from(a).and(b).map_collect(|x, y| x * y)
+}
+
+pub fn min(a: &Array2<f64>, b: &Array2<f64>) -> Array2<f64> {
+    Zip::from(a).and(b).map_collect(|x, y| x.min(*y))
+}
+
+pub fn max(a: &Array2<f64>, b: &Array2<f64>) -> Array2<f64> {
+    Zip::from(a).and(b).map_collect(|x, y| x.max(*y))
+}
+
+/// `a * (1 - weight) + b * weight`, per tile.
+pub fn lerp(a: &Array2<f64>, b: &Array2<f64>, weight: &Array2<f64>) -> Array2<f64> {
+    Zip::from(a).and(b).and(weight).map_collect(|x, y, w| x + (y - x) * w)
+}
+
+pub fn add_inplace(a: &mut Array2<f64>, b: &Array2<f64>) {
+    Zip::from(a).and(b).for_each(|x, y| *x += y);
+}
+
+/// `a += b * factor`
+pub fn add_scaled_inplace(a: &mut Array2<f64>, b: &Array2<f64>, factor: f64) {
+    Zip::from(a).and(b).for_each(|x, y| *x += y * factor);
+}
+
+pub fn multiply_inplace(a: &mut Array2<f64>, b: &Array2<f64>) {
+    Zip::from(a).and(b).for_each(|x, y| *x *= y);
+}
+
+pub fn min_inplace(a: &mut Array2<f64>, b: &Array2<f64>) {
+    Zip::from(a).and(b).for_each(|x, y| *x = x.min(*y));
+}
+
+pub fn max_inplace(a: &mut Array2<f64>, b: &Array2<f64>) {
+    Zip::from(a).and(b).for_each(|x, y| *x = x.max(*y));
+}
+
+pub fn lerp_inplace(a: &mut Array2<f64>, b: &Array2<f64>, weight: &Array2<f64>) {
+    Zip::from(a).and(b).and(weight).for_each(|x, y, w| *x += (y - *x) * w);
+}
+
+/// Overwrite `target` with `source` wherever `mask` is set.
+pub fn masked_overwrite<T: Clone>(target: &mut Array2<T>, source: &Array2<T>, mask: &Array2<bool>) {
+    Zip::from(target).and(source).and(mask).for_each(|t, s, &m| {
+        if m {
+            *t = s.clone();
+        }
+    });
+}
+
+/// Builder for blending formulas. Owns a single accumulator array that all operations
+/// are applied to in place.
+pub struct Blend {
+    acc: Array2<f64>,
+}
+
+impl Blend {
+    /// Start with a copy of `a`.
+    pub fn from(a: &Array2<f64>) -> Self {
+        Self { acc: a.clone() }
+    }
+
+    /// Start with `a`, without copying.
+    pub fn new(a: Array2<f64>) -> Self {
+        Self { acc: a }
+    }
+
+    pub fn scale(mut self, factor: f64) -> Self {
+        self.acc.mapv_inplace(|x| x * factor);
+        self
+    }
+
+    pub fn offset(mut self, value: f64) -> Self {
+        self.acc.mapv_inplace(|x| x + value);
+        self
+    }
+
+    pub fn add_scaled(mut self, b: &Array2<f64>, factor: f64) -> Self {
+        add_scaled_inplace(&mut self.acc, b, factor);
+        self
+    }
+
+    pub fn multiply(mut self, b: &Array2<f64>) -> Self {
+        multiply_inplace(&mut self.acc, b);
+        self
+    }
+
+    pub fn min(mut self, b: &Array2<f64>) -> Self {
+        min_inplace(&mut self.acc, b);
+        self
+    }
+
+    pub fn max(mut self, b: &Array2<f64>) -> Self {
+        max_inplace(&mut self.acc, b);
+        self
+    }
+
+    /// Blend towards `b`, by `weight` per tile.
+    pub fn lerp(mut self, b: &Array2<f64>, weight: &Array2<f64>) -> Self {
+        lerp_inplace(&mut self.acc, b, weight);
+        self
+    }
+
+    /// Replace tiles where `mask` is set by the values from `b`.
+    pub fn overwrite(mut self, b: &Array2<f64>, mask: &Array2<bool>) -> Self {
+        masked_overwrite(&mut self.acc, b, mask);
+        self
+    }
+
+    pub fn clamp(mut self, lo: f64, hi: f64) -> Self {
+        self.acc.mapv_inplace(|x| x.clamp(lo, hi));
+        self
+    }
+
+    /// Apply an arbitrary function to every tile.
+    pub fn map<F: Fn(f64) -> f64>(mut self, f: F) -> Self {
+        self.acc.mapv_inplace(f);
+        self
+    }
+
+    pub fn build(self) -> Array2<f64> {
+        self.acc
+    }
+}
+
+impl Add<&Array2<f64>> for Blend {
+    type Output = Blend;
+
+    fn add(mut self, b: &Array2<f64>) -> Blend {
+        add_inplace(&mut self.acc, b);
+        self
+    }
+}
+
+impl Sub<&Array2<f64>> for Blend {
+    type Output = Blend;
+
+    fn sub(self, b: &Array2<f64>) -> Blend {
+        self.add_scaled(b, -1.0)
+    }
+}
@@ -0,0 +1,170 @@
+//! Turning a map of block ids (eg. a wave function collapse over blocks, where every tile of
+//! the result stands for a small pattern of tiles) into the actual tiles.
+//!
+//! Block `ids[[x, y]]` is placed with its top left tile at `(x, y) * stride`. With a stride
+//! smaller than the block size, neighboring blocks overlap and the overlapping tiles are
+//! resolved according to `Overlap`.
+
+use crate::coord::{map_size, MapAccess};
+use glam::UVec2;
+use ndarray::Array2;
+
+/// How `apply_blocks` resolves tiles covered by several blocks.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Overlap {
+    /// The most frequent value, ties go to the block placed first (in the iteration order of
+    /// the id map)
+    Majority,
+    /// The value of the block with the highest priority (`priorities[id]`), ties go to the
+    /// block placed first
+    Priority(Vec<i32>),
+}
+
+/// A tile for which the overlapping blocks disagree.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BlockConflict<T> {
+    pub position: UVec2,
+    /// The distinct values of the blocks, in placement order
+    pub values: Vec<T>,
+    /// The value that was written
+    pub chosen: T,
+}
+
+/// Result of `apply_blocks`.
+#[derive(Clone, Debug)]
+pub struct BlockOutput<T> {
+    pub tiles: Array2<T>,
+    pub conflicts: Vec<BlockConflict<T>>,
+}
+
+/// Write the tiles of `blocks` (all of the same size) into a map according to the block ids
+/// in `ids`, see the module documentation. Ids that are not an index of `blocks` (eg.
+/// undetermined cells) place nothing, tiles not covered by any block get `fill`.
+/// The output has size `(ids size - 1) * stride + block size`.
+/// Panics if the blocks differ in size, a stride component is 0, or the priorities of
+/// `Overlap::Priority` do not cover all blocks.
+pub fn apply_blocks<T: Clone + PartialEq>(
+    ids: &Array2<usize>,
+    blocks: &[Array2<T>],
+    stride: UVec2,
+    overlap: &Overlap,
+    fill: T,
+) -> BlockOutput<T> {
+    assert!(stride.x > 0 && stride.y > 0);
+    let block_size = blocks.first().map_or(UVec2::ZERO, map_size);
+    assert!(blocks.iter().all(|b| map_size(b) == block_size), "blocks differ in size");
+    if let Overlap::Priority(priorities) = overlap {
+        assert!(priorities.len() >= blocks.len(), "missing block priorities");
+    }
+
+    let size = match map_size(ids).cmpgt(UVec2::ZERO).all() && !blocks.is_empty() {
+        true => (map_size(ids) - 1) * stride + block_size,
+        false => UVec2::ZERO,
+    };
+    // Candidates per tile as (block id, value), in placement order
+    let mut candidates: Array2<Vec<(usize, T)>> = Array2::from_elem((size.x as usize, size.y as usize), Vec::new());
+    for (p, &id) in ids.iter_with_positions() {
+        let block = match blocks.get(id) {
+            Some(b) => b,
+            None => continue,
+        };
+        for (q, t) in block.iter_with_positions() {
+            candidates.at_mut(p * stride + q).push((id, t.clone()));
+        }
+    }
+
+    let mut conflicts = Vec::new();
+    let tiles = Array2::from_shape_fn(candidates.dim(), |(x, y)| {
+        let c = &candidates[[x, y]];
+        let mut values: Vec<T> = Vec::new();
+        for (_, v) in c.iter() {
+            if !values.contains(v) {
+                values.push(v.clone());
+            }
+        }
+        let chosen = match overlap {
+            Overlap::Majority => values
+                .iter()
+                .enumerate()
+                .max_by_key(|&(i, v)| (c.iter().filter(|(_, w)| w == v).count(), usize::MAX - i))
+                .map(|(_, v)| v.clone()),
+            Overlap::Priority(priorities) => c
+                .iter()
+                .enumerate()
+                .max_by_key(|&(i, (id, _))| (priorities[*id], usize::MAX - i))
+                .map(|(_, (_, v))| v.clone()),
+        };
+        let chosen = chosen.unwrap_or_else(|| fill.clone());
+        if values.len() > 1 {
+            let position = UVec2::new(x as u32, y as u32);
+            conflicts.push(BlockConflict { position, values, chosen: chosen.clone() });
+        }
+        chosen
+    });
+
+    BlockOutput { tiles, conflicts }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use glam::uvec2;
+    use ndarray::arr2;
+
+    /// Row of block ids 0, 1, 0 with 3x1 blocks `[1, 2, 3]` and `[2, 3, 1]`
+    fn row() -> (Array2<usize>, Vec<Array2<u8>>) {
+        (arr2(&[[0], [1], [0]]), vec![arr2(&[[1], [2], [3]]), arr2(&[[2], [3], [1]])])
+    }
+
+    #[test]
+    fn blocks_without_overlap() {
+        let blocks = vec![arr2(&[[1, 2], [3, 4]]), arr2(&[[5, 6], [7, 8]])];
+        let ids = arr2(&[[0, 1], [9, 0]]);
+        let r = apply_blocks(&ids, &blocks, uvec2(2, 2), &Overlap::Majority, 0);
+        let expected = arr2(&[[1, 2, 5, 6], [3, 4, 7, 8], [0, 0, 1, 2], [0, 0, 3, 4]]);
+        assert_eq!(r.tiles, expected);
+        assert!(r.conflicts.is_empty());
+
+        let r = apply_blocks(&ids, &blocks, uvec2(3, 1), &Overlap::Majority, 0);
+        assert_eq!(r.tiles.dim(), (5, 3));
+        assert_eq!(r.tiles.row(2).to_vec(), vec![0, 0, 0]);
+        assert!(apply_blocks(&Array2::zeros((0, 2)), &blocks, uvec2(1, 1), &Overlap::Majority, 0).tiles.is_empty());
+    }
+
+    #[test]
+    fn majority() {
+        let (ids, blocks) = row();
+        let r = apply_blocks(&ids, &blocks, uvec2(1, 1), &Overlap::Majority, 0);
+        assert_eq!(r.tiles.column(0).to_vec(), vec![1, 2, 3, 1, 3]);
+        let expected = vec![
+            BlockConflict { position: uvec2(2, 0), values: vec![3, 1], chosen: 3 },
+            BlockConflict { position: uvec2(3, 0), values: vec![1, 2], chosen: 1 },
+        ];
+        assert_eq!(r.conflicts, expected);
+    }
+
+    #[test]
+    fn priority() {
+        let (ids, blocks) = row();
+        let r = apply_blocks(&ids, &blocks, uvec2(1, 1), &Overlap::Priority(vec![5, 0]), 0);
+        assert_eq!(r.tiles.column(0).to_vec(), vec![1, 2, 3, 2, 3]);
+        assert_eq!(r.conflicts[1].chosen, 2);
+        let r = apply_blocks(&ids, &blocks, uvec2(1, 1), &Overlap::Priority(vec![0, 5]), 0);
+        assert_eq!(r.tiles.column(0).to_vec(), vec![1, 2, 3, 1, 3]);
+    }
+
+    #[test]
+    #[should_panic(expected = "missing block priorities")]
+    fn missing_priorities() {
+        let (ids, blocks) = row();
+        apply_blocks(&ids, &blocks, uvec2(1, 1), &Overlap::Priority(vec![1]), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "blocks differ in size")]
+    fn blocks_differ_in_size() {
+        let (ids, mut blocks) = row();
+        blocks.push(arr2(&[[1, 2]]));
+        apply_blocks(&ids, &blocks, uvec2(1, 1), &Overlap::Majority, 0);
+    }
+}
@@ -0,0 +1,86 @@
+
+use std::collections::{BTreeMap, BinaryHeap, HashMap};
+use std::hash::Hash;
+
+/// Approximate max-priority queue that groups scores into buckets of width `resolution`.
+/// Within a bucket, items are ordered by their (integer) tie breaker only, so items whose
+/// scores differ by less than `resolution` may come out in any order.
+/// Changing the priority of an item is O(log n) (with a small constant) and does not need to
+/// find the old entry: outdated entries are skipped lazily when popping.
+#[derive(Clone)]
+pub struct BucketQueue<T> {
+    resolution: f32,
+    buckets: BTreeMap<i64, BinaryHeap<(u64, T)>>,
+    /// Current bucket of every item in the queue
+    current: HashMap<T, (i64, u64)>,
+}
+
+impl<T> BucketQueue<T>
+where
+    T: Copy + Eq + Hash + Ord,
+{
+    /// Panics if `resolution` is not positive.
+    pub fn new(resolution: f32) -> Self {
+        assert!(resolution > 0.0);
+        Self {
+            resolution,
+            buckets: BTreeMap::new(),
+            current: HashMap::new(),
+        }
+    }
+
+    fn bucket(&self, score: f32) -> i64 {
+        (score / self.resolution).floor() as i64
+    }
+
+    /// Insert `item`, or move it if it is already queued.
+    pub fn push(&mut self, item: T, score: f32, tie_breaker: u64) {
+        let key = (self.bucket(score), tie_breaker);
+        if self.current.insert(item, key) == Some(key) {
+            // Entry is already there
+            return;
+        }
+        self.buckets.entry(key.0).or_default().push((tie_breaker, item));
+    }
+
+    /// Remove and return an item with the highest score.
+    pub fn pop(&mut self) -> Option<T> {
+        loop {
+            let mut last = self.buckets.last_entry()?;
+            let bucket = *last.key();
+            match last.get_mut().pop() {
+                Some((tie_breaker, item)) => {
+                    if self.current.get(&item) == Some(&(bucket, tie_breaker)) {
+                        self.current.remove(&item);
+                        return Some(item);
+                    }
+                    // Outdated entry
+                }
+                None => {
+                    last.remove();
+                }
+            }
+        }
+    }
+
+    pub fn remove(&mut self, item: &T) {
+        self.current.remove(item);
+    }
+
+    pub fn contains(&self, item: &T) -> bool {
+        self.current.contains_key(item)
+    }
+
+    pub fn len(&self) -> usize {
+        self.current.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.current.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.buckets.clear();
+        self.current.clear();
+    }
+}
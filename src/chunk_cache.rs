@@ -0,0 +1,348 @@
+//! Memoization of generated world chunks for streaming open worlds.
+//!
+//! Generators in this crate are deterministic per seed, so a chunk can be regenerated at any
+//! time; `ChunkCache` keeps the most recently used chunks in memory and optionally persists
+//! them (eg. to skip expensive generation on the next start, or to keep player edits).
+//!
+//! ```
+//! # use mapgen_2d::chunk_cache::{ChunkCache, DirectoryStore};
+//! # use glam::{ivec2, IVec2};
+//! # use ndarray::Array2;
+//! # let seed = 7;
+//! # let dir = std::env::temp_dir().join("mapgen-2d-chunk-cache-doctest");
+//! let generator = |seed: u64, chunk: IVec2| Array2::from_elem((16, 16), (seed as i32 + chunk.x + chunk.y) as u8);
+//! let mut cache = ChunkCache::new(generator, 64).store(DirectoryStore::new(&dir)?);
+//! let chunk = cache.get(seed, ivec2(-3, 7))?;
+//! # assert_eq!(chunk[[0, 0]], 11);
+//! # std::fs::remove_dir_all(&dir)?;
+//! # Ok::<(), std::io::Error>(())
+//! ```
+
+use crate::compress::{compress, decompress, PaletteValue};
+use glam::IVec2;
+use ndarray::Array2;
+use std::collections::HashMap;
+use std::fs;
+use std::hash::Hash;
+use std::io;
+use std::path::PathBuf;
+
+/// Generator of chunks of an infinite map.
+/// Implemented for closures `FnMut(u64, IVec2) -> Array2<T>`, whose configuration key is 0.
+pub trait ChunkGenerator<T> {
+    /// Generate the chunk at chunk coordinates `chunk`. Must only depend on `seed`, `chunk`
+    /// and the configuration.
+    fn generate_chunk(&mut self, seed: u64, chunk: IVec2) -> Array2<T>;
+
+    /// Hash of the configuration that the chunks depend on. Cached chunks generated with a
+    /// different key are discarded.
+    fn config_key(&self) -> u64 {
+        0
+    }
+}
+
+impl<T, F: FnMut(u64, IVec2) -> Array2<T>> ChunkGenerator<T> for F {
+    fn generate_chunk(&mut self, seed: u64, chunk: IVec2) -> Array2<T> {
+        self(seed, chunk)
+    }
+}
+
+/// Identifies a generated chunk.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ChunkKey {
+    pub seed: u64,
+    pub chunk: IVec2,
+    /// `ChunkGenerator::config_key` of the generator
+    pub config: u64,
+}
+
+/// Persistence of chunks behind the memory cache.
+pub trait ChunkStore<T> {
+    /// The stored chunk, `None` if there is none.
+    fn load(&mut self, key: ChunkKey) -> io::Result<Option<Array2<T>>>;
+
+    fn save(&mut self, key: ChunkKey, chunk: &Array2<T>) -> io::Result<()>;
+}
+
+/// Stores chunks as files in a directory, compressed with `compress`.
+/// The configuration key is part of the file name, so chunks of other configurations are
+/// ignored (and can be removed with `remove_other_configs`).
+#[derive(Clone, Debug)]
+pub struct DirectoryStore {
+    pub dir: PathBuf,
+}
+
+impl DirectoryStore {
+    /// Creates `dir` if it does not exist.
+    pub fn new<P: Into<PathBuf>>(dir: P) -> io::Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        Ok(Self { dir })
+    }
+
+    fn path(&self, key: ChunkKey) -> PathBuf {
+        let name = format!("{:016x}_{:016x}_{}_{}.mg2c", key.config, key.seed, key.chunk.x, key.chunk.y);
+        self.dir.join(name)
+    }
+
+    /// Delete the stored chunks of all configurations except `config`.
+    pub fn remove_other_configs(&self, config: u64) -> io::Result<()> {
+        let prefix = format!("{:016x}_", config);
+        for entry in fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if name.ends_with(".mg2c") && !name.starts_with(&prefix) {
+                fs::remove_file(entry.path())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<T: Eq + Hash + Clone + PaletteValue> ChunkStore<T> for DirectoryStore {
+    fn load(&mut self, key: ChunkKey) -> io::Result<Option<Array2<T>>> {
+        let bytes = match fs::read(self.path(key)) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        decompress(&bytes)
+            .map(Some)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    fn save(&mut self, key: ChunkKey, chunk: &Array2<T>) -> io::Result<()> {
+        fs::write(self.path(key), compress(chunk))
+    }
+}
+
+/// Least recently used cache of chunks generated by `generator`, with an optional store.
+pub struct ChunkCache<T, G> {
+    pub generator: G,
+    /// Maximum number of chunks kept in memory
+    pub capacity: usize,
+    store: Option<Box<dyn ChunkStore<T>>>,
+    config: u64,
+    chunks: HashMap<(u64, IVec2), (Array2<T>, u64)>,
+    tick: u64,
+}
+
+impl<T, G: ChunkGenerator<T>> ChunkCache<T, G> {
+    pub fn new(generator: G, capacity: usize) -> Self {
+        let config = generator.config_key();
+        Self { generator, capacity: capacity.max(1), store: None, config, chunks: HashMap::new(), tick: 0 }
+    }
+
+    /// Persist generated chunks in `store` and load them from there before generating.
+    pub fn store<S: ChunkStore<T> + 'static>(mut self, store: S) -> Self {
+        self.store = Some(Box::new(store));
+        self
+    }
+
+    /// The chunk at `chunk` for `seed`: from memory, the store or newly generated (and then
+    /// saved to the store). Discards all chunks in memory first if the configuration key of
+    /// the generator changed.
+    pub fn get(&mut self, seed: u64, chunk: IVec2) -> io::Result<&Array2<T>> {
+        let config = self.update_config();
+        self.tick += 1;
+        let tick = self.tick;
+        if !self.chunks.contains_key(&(seed, chunk)) {
+            let key = ChunkKey { seed, chunk, config };
+            let stored = match self.store.as_mut() {
+                Some(store) => store.load(key)?,
+                None => None,
+            };
+            let a = match stored {
+                Some(a) => a,
+                None => {
+                    let a = self.generator.generate_chunk(seed, chunk);
+                    if let Some(store) = self.store.as_mut() {
+                        store.save(key, &a)?;
+                    }
+                    a
+                }
+            };
+            if self.chunks.len() >= self.capacity {
+                self.evict();
+            }
+            self.chunks.insert((seed, chunk), (a, tick));
+        }
+
+        let (a, used) = self.chunks.get_mut(&(seed, chunk)).unwrap();
+        *used = tick;
+        Ok(a)
+    }
+
+    /// Replace a chunk (eg. after the player changed it), in memory and in the store, for the
+    /// current configuration key of the generator (see `get`).
+    pub fn put(&mut self, seed: u64, chunk: IVec2, a: Array2<T>) -> io::Result<()> {
+        let config = self.update_config();
+        if let Some(store) = self.store.as_mut() {
+            store.save(ChunkKey { seed, chunk, config }, &a)?;
+        }
+        if !self.chunks.contains_key(&(seed, chunk)) && self.chunks.len() >= self.capacity {
+            self.evict();
+        }
+        self.tick += 1;
+        self.chunks.insert((seed, chunk), (a, self.tick));
+        Ok(())
+    }
+
+    pub fn contains(&self, seed: u64, chunk: IVec2) -> bool {
+        self.chunks.contains_key(&(seed, chunk))
+    }
+
+    /// Number of chunks in memory
+    pub fn len(&self) -> usize {
+        self.chunks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chunks.is_empty()
+    }
+
+    /// Discard all chunks in memory (the store is left as it is).
+    pub fn clear(&mut self) {
+        self.chunks.clear();
+    }
+
+    /// The current configuration key of the generator. Discards all chunks in memory if it
+    /// changed since the last call.
+    fn update_config(&mut self) -> u64 {
+        let config = self.generator.config_key();
+        if config != self.config {
+            self.clear();
+            self.config = config;
+        }
+        config
+    }
+
+    /// Remove the least recently used chunk from memory.
+    fn evict(&mut self) {
+        let oldest = self.chunks.iter().min_by_key(|(_, (_, used))| *used).map(|(k, _)| *k);
+        if let Some(k) = oldest {
+            self.chunks.remove(&k);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use glam::ivec2;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    /// Counts the generated chunks, the configuration key can be changed
+    struct Counting {
+        calls: usize,
+        config: u64,
+    }
+
+    impl ChunkGenerator<u8> for Counting {
+        fn generate_chunk(&mut self, seed: u64, chunk: IVec2) -> Array2<u8> {
+            self.calls += 1;
+            Array2::from_elem((4, 4), (seed as i32 + chunk.x + 10 * chunk.y + self.config as i32) as u8)
+        }
+
+        fn config_key(&self) -> u64 {
+            self.config
+        }
+    }
+
+    /// Store in a map that the test keeps a handle to
+    #[derive(Clone, Default)]
+    struct MemoryStore(Rc<RefCell<HashMap<ChunkKey, Array2<u8>>>>);
+
+    impl ChunkStore<u8> for MemoryStore {
+        fn load(&mut self, key: ChunkKey) -> io::Result<Option<Array2<u8>>> {
+            Ok(self.0.borrow().get(&key).cloned())
+        }
+
+        fn save(&mut self, key: ChunkKey, chunk: &Array2<u8>) -> io::Result<()> {
+            self.0.borrow_mut().insert(key, chunk.clone());
+            Ok(())
+        }
+    }
+
+    fn cache(capacity: usize) -> ChunkCache<u8, Counting> {
+        ChunkCache::new(Counting { calls: 0, config: 0 }, capacity)
+    }
+
+    #[test]
+    fn hits_and_misses() {
+        let mut c = cache(2);
+        assert_eq!(c.get(1, ivec2(2, 3)).unwrap()[[0, 0]], 33);
+        assert_eq!(c.get(1, ivec2(2, 3)).unwrap()[[0, 0]], 33);
+        assert_eq!(c.generator.calls, 1);
+
+        // Other seeds are other chunks
+        c.get(2, ivec2(2, 3)).unwrap();
+        assert_eq!(c.generator.calls, 2);
+
+        // The least recently used chunk is evicted
+        c.get(1, ivec2(2, 3)).unwrap();
+        c.get(1, ivec2(0, 0)).unwrap();
+        assert_eq!(c.len(), 2);
+        assert!(c.contains(1, ivec2(2, 3)) && c.contains(1, ivec2(0, 0)));
+        assert!(!c.contains(2, ivec2(2, 3)));
+        c.get(2, ivec2(2, 3)).unwrap();
+        assert_eq!(c.generator.calls, 4);
+    }
+
+    #[test]
+    fn config_change_invalidates() {
+        let store = MemoryStore::default();
+        let mut c = cache(8).store(store.clone());
+        c.get(1, ivec2(0, 0)).unwrap();
+        c.get(1, ivec2(1, 0)).unwrap();
+        assert_eq!(c.generator.calls, 2);
+
+        c.generator.config = 5;
+        assert_eq!(c.get(1, ivec2(0, 0)).unwrap()[[0, 0]], 6);
+        assert_eq!(c.len(), 1);
+        assert_eq!(c.generator.calls, 3);
+        assert_eq!(store.0.borrow().len(), 3);
+
+        // Chunks of the old configuration are still in the store
+        c.generator.config = 0;
+        assert_eq!(c.get(1, ivec2(1, 0)).unwrap()[[0, 0]], 2);
+        assert_eq!(c.generator.calls, 3);
+    }
+
+    #[test]
+    fn put_uses_current_config() {
+        let store = MemoryStore::default();
+        let mut c = cache(8).store(store.clone());
+        c.get(1, ivec2(0, 0)).unwrap();
+
+        c.generator.config = 5;
+        c.put(1, ivec2(0, 0), Array2::from_elem((4, 4), 99)).unwrap();
+        let key = ChunkKey { seed: 1, chunk: ivec2(0, 0), config: 5 };
+        assert_eq!(store.0.borrow()[&key][[0, 0]], 99);
+
+        // The edited chunk is loaded for the current configuration instead of being generated
+        c.clear();
+        assert_eq!(c.get(1, ivec2(0, 0)).unwrap()[[0, 0]], 99);
+        assert_eq!(c.generator.calls, 1);
+    }
+
+    #[test]
+    fn directory_store() {
+        let dir = std::env::temp_dir().join(format!("mapgen-2d-chunk-cache-{}", std::process::id()));
+        let mut store = DirectoryStore::new(&dir).unwrap();
+        let key = ChunkKey { seed: 3, chunk: ivec2(-1, 2), config: 7 };
+        let a = Array2::from_shape_fn((5, 3), |(x, y)| (x * y) as u8);
+        store.save(key, &a).unwrap();
+        store.save(ChunkKey { config: 8, ..key }, &a).unwrap();
+        assert_eq!(store.load(key).unwrap(), Some(a.clone()));
+        assert_eq!(ChunkStore::<u8>::load(&mut store, ChunkKey { seed: 4, ..key }).unwrap(), None);
+
+        store.remove_other_configs(8).unwrap();
+        let r = ChunkStore::<u8>::load(&mut store, key);
+        let other = store.load(ChunkKey { config: 8, ..key });
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(r.unwrap(), None);
+        assert_eq!(other.unwrap(), Some(a));
+    }
+}
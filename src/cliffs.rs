@@ -0,0 +1,81 @@
+
+use crate::coord::MapAccess;
+use crate::neighborhood::AUTOTILE8;
+use glam::{IVec2, UVec2};
+use ndarray::Array2;
+
+/// Quantize a height map with values in [0, 1] into `levels` terraces `0..levels`.
+pub fn terrace(height: &Array2<f64>, levels: u32) -> Array2<u32> {
+    let max = levels.saturating_sub(1);
+    height.mapv(|h| ((h * levels as f64).floor().max(0.0) as u32).min(max))
+}
+
+/// Transition that can not be drawn with single level cliff tiles.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ViolationKind {
+    /// The neighbor at `offset` is more than one level lower
+    Steep { offset: IVec2, difference: u32 },
+    /// The neighbor at `offset` is lower and the opposite one higher, so the tile would be
+    /// the top of one cliff and the foot of another (two levels across one tile)
+    Sandwiched { offset: IVec2 },
+    /// The neighbors at `offset` and `-offset` are both lower, a ridge one tile wide that
+    /// would need cliffs on both sides
+    Ridge { offset: IVec2 },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Violation {
+    pub position: UVec2,
+    pub kind: ViolationKind,
+}
+
+/// Result of `cliffs`.
+#[derive(Clone, Debug)]
+pub struct CliffLayer {
+    /// Per tile an `AUTOTILE8` mask of the neighbors that are lower than the tile (before
+    /// `reduce_blob_mask`), 0 for tiles that are not at the top of a cliff. Neighbors
+    /// outside of the map count as the same level.
+    pub transitions: Array2<u8>,
+    /// Each problem is reported once, at the tile that would hold the cliff
+    pub violations: Vec<Violation>,
+}
+
+/// Where cliff tiles must be placed in a terraced height layer (eg. from `terrace`), with cliffs
+/// belonging to the upper tile, and which transitions are impossible for tilesets with cliffs
+/// of one level (RTS style terraced maps).
+/// Only the 4 direct neighbors are checked for violations, diagonal steps are covered by the
+/// corner bits of the transition masks.
+pub fn cliffs(levels: &Array2<u32>) -> CliffLayer {
+    let mut transitions = Array2::from_elem(levels.dim(), 0_u8);
+    let mut violations = Vec::new();
+    let direct = [IVec2::new(0, -1), IVec2::new(1, 0), IVec2::new(0, 1), IVec2::new(-1, 0)];
+
+    for (p, &h) in levels.iter_with_positions() {
+        let level = |o: IVec2| levels.get_at(p.as_ivec2() + o).copied();
+        let mut mask = 0;
+        for &(o, bit) in AUTOTILE8.iter() {
+            if level(o).is_some_and(|n| n < h) {
+                mask |= bit;
+            }
+        }
+        *transitions.at_mut(p) = mask;
+
+        for (i, &o) in direct.iter().enumerate() {
+            let (n, opposite) = match (level(o), level(-o)) {
+                (Some(n), opposite) if n < h => (n, opposite),
+                _ => continue,
+            };
+            let mut report = |kind| violations.push(Violation { position: p, kind });
+            if h - n > 1 {
+                report(ViolationKind::Steep { offset: o, difference: h - n });
+            }
+            match opposite {
+                Some(m) if m > h => report(ViolationKind::Sandwiched { offset: o }),
+                // Report ridges once, for north or east
+                Some(m) if m < h && i < 2 => report(ViolationKind::Ridge { offset: o }),
+                _ => {}
+            }
+        }
+    }
+    CliffLayer { transitions, violations }
+}
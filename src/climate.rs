@@ -0,0 +1,303 @@
+
+use crate::colored_noise::ColoredNoise;
+use crate::map_stack::MapStack;
+use crate::seed::derive_seed;
+use glam::{dvec2, uvec2, UVec2};
+use ndarray::{Array2, Zip};
+
+/// Latitude based temperature field:
+/// `temperature = base(latitude) - lapse_rate * max(elevation - sea_level, 0) + noise`.
+///
+/// `base` interpolates between `pole_temperature` and `equator_temperature` with the cosine
+/// of the latitude. With the defaults, temperatures are roughly in [0, 1] (before elevation
+/// and noise), which makes them easy to combine with other normalized layers for biome
+/// lookup.
+/// Uses the same conventions as `ColoredNoise` (`size` field, `generate()`).
+#[derive(Clone)]
+pub struct Temperature {
+    pub size: UVec2,
+    /// Latitude of the first (y = 0) and last row in degrees, eg. `(90.0, -90.0)` for a whole
+    /// world map with north at the top
+    pub latitudes: (f64, f64),
+    pub equator_temperature: f64,
+    pub pole_temperature: f64,
+    /// Temperature decrease per unit of elevation above `sea_level`
+    pub lapse_rate: f64,
+    /// Default 0.4, like `MapBuilder`
+    pub sea_level: f64,
+    /// Amplitude of the noise added to the temperature (0.0 = no noise)
+    pub noise_amplitude: f64,
+    pub noise_color: f64,
+    pub seed: u64,
+}
+
+impl Default for Temperature {
+    fn default() -> Self {
+        Self {
+            size: uvec2(100, 100),
+            latitudes: (60.0, -60.0),
+            equator_temperature: 1.0,
+            pole_temperature: 0.0,
+            lapse_rate: 1.0,
+            sea_level: 0.4,
+            noise_amplitude: 0.1,
+            noise_color: -2.0,
+            seed: 0,
+        }
+    }
+}
+
+impl Temperature {
+    /// Latitude of row `y` in degrees.
+    pub fn latitude(&self, y: usize) -> f64 {
+        let (first, last) = self.latitudes;
+        let t = if self.size.y > 1 { y as f64 / (self.size.y - 1) as f64 } else { 0.5 };
+        first + (last - first) * t
+    }
+
+    /// Temperature at sea level without noise for row `y`.
+    pub fn base(&self, y: usize) -> f64 {
+        let c = self.latitude(y).to_radians().cos().max(0.0);
+        self.pole_temperature + (self.equator_temperature - self.pole_temperature) * c
+    }
+
+    /// Temperature field, `elevation` (if given) must have the size of the map.
+    pub fn generate(&self, elevation: Option<&Array2<f64>>) -> Array2<f64> {
+        let mut r = Array2::from_shape_fn((self.size.x as usize, self.size.y as usize), |(_, y)| self.base(y));
+
+        if let Some(e) = elevation {
+            assert_eq!(e.dim(), r.dim());
+            Zip::from(&mut r).and(e).for_each(|t, &h| {
+                *t -= self.lapse_rate * (h - self.sea_level).max(0.0);
+            });
+        }
+
+        if self.noise_amplitude != 0.0 {
+            let noise = ColoredNoise {
+                size: self.size,
+                color: self.noise_color,
+                seed: derive_seed(self.seed, 0),
+                ..Default::default()
+            }
+            .generate();
+            Zip::from(&mut r).and(&noise).for_each(|t, &n| {
+                *t += self.noise_amplitude * (n - 0.5);
+            });
+        }
+        r
+    }
+
+    /// Generate a temperature field of the size of `layers` and store it as layer `name`.
+    /// If `elevation` is given, it names the `f64` elevation layer to apply the lapse rate
+    /// to. Returns `false` (and does nothing) if that layer does not exist.
+    pub fn generate_into_layer(&self, layers: &mut MapStack, elevation: Option<&str>, name: &str) -> bool {
+        let t = Self { size: layers.size(), ..self.clone() };
+        let r = match elevation.map(|e| layers.get::<f64>(e)) {
+            Some(None) => return false,
+            Some(e) => t.generate(e),
+            None => t.generate(None),
+        };
+        layers.insert(name, r);
+        true
+    }
+}
+
+/// Moisture transport for rain shadows.
+///
+/// Air moves across the map with the prevailing wind, picking up moisture over water and
+/// raining it out over land, most of it on windward slopes. Leeward sides of mountain ranges
+/// thus end up dry. Tiles below `sea_level` are water.
+#[derive(Clone)]
+pub struct Moisture {
+    /// Direction the wind comes from in degrees clockwise from north (negative y), eg. 270
+    /// for westerlies
+    pub wind_from: f64,
+    pub sea_level: f64,
+    /// Moisture of the air entering the map, in [0, 1]
+    pub initial_moisture: f64,
+    /// Fraction of the missing moisture the air picks up per water tile
+    pub evaporation: f64,
+    /// Fraction of its moisture the air loses per tile anywhere
+    pub precipitation: f64,
+    /// Additional fraction lost per unit of elevation gain (orographic lift)
+    pub orographic: f64,
+}
+
+impl Default for Moisture {
+    fn default() -> Self {
+        Self {
+            wind_from: 270.0,
+            sea_level: 0.4,
+            initial_moisture: 1.0,
+            evaporation: 0.2,
+            precipitation: 0.02,
+            orographic: 5.0,
+        }
+    }
+}
+
+impl Moisture {
+    /// Precipitation per tile of heightmap `elevation`, normalized to [0, 1].
+    pub fn generate(&self, elevation: &Array2<f64>) -> Array2<f64> {
+        let (sx, sy) = elevation.dim();
+        let mut rain = Array2::zeros((sx, sy));
+        let mut air = Array2::zeros((sx, sy));
+        if sx == 0 || sy == 0 {
+            return rain;
+        }
+        let height = elevation.mapv(|h| h.max(self.sea_level));
+
+        // Scale the wind so that one step crosses exactly one row or column; the upwind point
+        // then lies between two tiles that were already visited
+        let a = self.wind_from.to_radians();
+        let wind = dvec2(-a.sin(), a.cos());
+        let wind = wind / wind.x.abs().max(wind.y.abs());
+        let transposed = wind.y.abs() > wind.x.abs();
+        let (major, minor) = if transposed { (wind.y, wind.x) } else { (wind.x, wind.y) };
+        let (n_major, n_minor) = if transposed { (sy, sx) } else { (sx, sy) };
+        let index = |i: usize, j: usize| if transposed { [j, i] } else { [i, j] };
+
+        for step in 0..n_major {
+            let i = if major > 0.0 { step } else { n_major - 1 - step };
+            for j in 0..n_minor {
+                let h = height[index(i, j)];
+                let (m, h_up) = if step == 0 {
+                    (self.initial_moisture, h)
+                } else {
+                    let ui = if major > 0.0 { i - 1 } else { i + 1 };
+                    let uj = (j as f64 - minor).clamp(0.0, (n_minor - 1) as f64);
+                    let (j0, j1) = (uj.floor() as usize, uj.ceil() as usize);
+                    let t = uj - j0 as f64;
+                    let lerp = |a: &Array2<f64>| a[index(ui, j0)] * (1.0 - t) + a[index(ui, j1)] * t;
+                    (lerp(&air), lerp(&height))
+                };
+
+                let r = m * (self.precipitation + self.orographic * (h - h_up).max(0.0)).min(1.0);
+                let mut m = m - r;
+                if elevation[index(i, j)] < self.sea_level {
+                    m += self.evaporation * (1.0 - m).max(0.0);
+                }
+                rain[index(i, j)] = r;
+                air[index(i, j)] = m;
+            }
+        }
+
+        let max = rain.fold(0.0, |a: f64, &b| a.max(b));
+        if max > 0.0 {
+            rain /= max;
+        }
+        rain
+    }
+
+    /// Generate moisture from the `f64` layer `elevation` and store it as layer `name`.
+    /// Returns `false` (and does nothing) if the elevation layer does not exist.
+    pub fn generate_into_layer(&self, layers: &mut MapStack, elevation: &str, name: &str) -> bool {
+        let r = match layers.get::<f64>(elevation) {
+            Some(e) => self.generate(e),
+            None => return false,
+        };
+        layers.insert(name, r);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ndarray::Axis;
+
+    /// 4x5 whole world temperature without noise, row 2 is the equator
+    fn world() -> Temperature {
+        Temperature { size: uvec2(4, 5), latitudes: (90.0, -90.0), noise_amplitude: 0.0, ..Default::default() }
+    }
+
+    #[test]
+    fn temperature_by_latitude_and_elevation() {
+        let t = world();
+        assert_eq!(t.latitude(0), 90.0);
+        assert_eq!(t.latitude(2), 0.0);
+        assert_eq!(t.latitude(4), -90.0);
+        assert_eq!(t.base(2), 1.0);
+        assert!(t.base(0).abs() < 1e-12 && t.base(4).abs() < 1e-12);
+        assert_eq!(t.base(1), t.base(3));
+
+        let mut elevation = Array2::zeros((4, 5));
+        elevation[[1, 2]] = 0.9;
+        let r = t.generate(Some(&elevation));
+        for ((x, y), &v) in r.indexed_iter() {
+            let expected = if (x, y) == (1, 2) { 0.5 } else { t.base(y) };
+            assert!((v - expected).abs() < 1e-12, "{v} at ({x}, {y})");
+        }
+    }
+
+    #[test]
+    fn temperature_noise() {
+        let t = Temperature { noise_amplitude: 0.2, seed: 3, ..world() };
+        let r = t.generate(None);
+        assert_eq!(r, t.generate(None));
+        assert_ne!(r, Temperature { seed: 4, ..t.clone() }.generate(None));
+        for ((_, y), &v) in r.indexed_iter() {
+            assert!((v - t.base(y)).abs() <= 0.1 + 1e-12);
+        }
+    }
+
+    #[test]
+    fn temperature_into_layer() {
+        let mut layers = MapStack::new(uvec2(3, 2));
+        assert!(!world().generate_into_layer(&mut layers, Some("elevation"), "temperature"));
+        assert!(!layers.contains("temperature"));
+
+        layers.insert_filled("elevation", 1.0);
+        assert!(world().generate_into_layer(&mut layers, Some("elevation"), "temperature"));
+        assert!(world().generate_into_layer(&mut layers, None, "sea level"));
+        let t = layers.get::<f64>("temperature").unwrap();
+        assert_eq!(t.dim(), (3, 2));
+        assert!(Zip::from(t).and(layers.get::<f64>("sea level").unwrap()).all(|a, b| (b - a - 0.6).abs() < 1e-12));
+    }
+
+    /// 12x3 west to east profile: ocean, lowland, a ridge at x = 7 and lowland behind it
+    fn ridge() -> Array2<f64> {
+        Array2::from_shape_fn((12, 3), |(x, _)| match x {
+            0..=3 => 0.0,
+            7 => 1.0,
+            _ => 0.5,
+        })
+    }
+
+    fn close(a: &Array2<f64>, b: &Array2<f64>) -> bool {
+        a.dim() == b.dim() && Zip::from(a).and(b).all(|a, b| (a - b).abs() < 1e-9)
+    }
+
+    #[test]
+    fn rain_shadow() {
+        let rain = Moisture::default().generate(&ridge());
+        assert!(rain.iter().all(|&r| (0.0..=1.0).contains(&r)));
+        assert_eq!(rain.fold(0.0, |a: f64, &b| a.max(b)), 1.0);
+        for y in 0..3 {
+            assert!(rain[[7, y]] > rain[[6, y]]);
+            assert!(rain[[9, y]] < rain[[5, y]]);
+            assert!(rain[[4, y]] > rain[[3, y]]);
+        }
+    }
+
+    #[test]
+    fn wind_directions() {
+        let westerlies = Moisture::default().generate(&ridge());
+        let mut mirrored = ridge();
+        mirrored.invert_axis(Axis(0));
+        let mut easterlies = Moisture { wind_from: 90.0, ..Default::default() }.generate(&mirrored);
+        easterlies.invert_axis(Axis(0));
+        assert!(close(&westerlies, &easterlies));
+
+        let transposed = ridge().reversed_axes();
+        let northerlies = Moisture { wind_from: 0.0, ..Default::default() }.generate(&transposed);
+        assert!(close(&westerlies, &northerlies.reversed_axes()));
+
+        assert!(Moisture::default().generate(&Array2::zeros((0, 4))).is_empty());
+        let mut layers = MapStack::new(uvec2(12, 3));
+        assert!(!Moisture::default().generate_into_layer(&mut layers, "elevation", "moisture"));
+        layers.insert("elevation", ridge());
+        assert!(Moisture::default().generate_into_layer(&mut layers, "elevation", "moisture"));
+        assert_eq!(layers.get::<f64>("moisture"), Some(&westerlies));
+    }
+}
@@ -1,47 +1,580 @@
-use ndarray::{Array2, Axis};
+use glam::{uvec2, uvec3, UVec2, UVec3};
+use ndarray::{s, Array2, Array3, ArrayBase, ArrayViewMut2, Axis, DataMut, Dimension, Zip};
+use crate::seed::derive_seed;
+use crate::error::MapgenError;
 use ndrustfft::{ndifft, ndifft_r2c, Complex, FftHandler, R2cFftHandler};
+use crate::map_stack::MapStack;
+use crate::report::{GenerationReport, ValueStats};
+#[cfg(feature = "wgpu")]
+use crate::gpu::Backend;
+use std::collections::HashMap;
 use rand::{
     SeedableRng,
     distributions::{Distribution, Uniform}
 };
 
-// TODO: Configure with a struct/builder like voronoi
+/// How the output of the spectral synthesis is mapped to the final values.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(rename_all = "snake_case"))]
+pub enum Normalization {
+    /// Absolute values mapped to [0, 1) per map. Every map uses the full range, so
+    /// neighboring chunks do not line up.
+    #[default]
+    UnitRange,
+    /// The raw output of the inverse transform, centered around 0
+    Raw,
+    /// Shifted and scaled to mean 0 and standard deviation 1 per map, keeping the shape of
+    /// the distribution
+    ZScore,
+    /// Raw values in `[min, max]` mapped linearly to [0, 1], values outside are clamped.
+    /// Independent of the other values of the map, so consistent across chunks.
+    Clamp { min: f64, max: f64 },
+    /// Histogram equalization: values are replaced by their rank divided by the number of
+    /// tiles, giving a uniform distribution in [0, 1)
+    Equalize,
+}
+
+impl Normalization {
+    /// The range of `Clamp` must be finite and not empty.
+    fn validate(&self) -> Result<(), MapgenError> {
+        if let Normalization::Clamp { min, max } = *self {
+            if !(min.is_finite() && max.is_finite() && min < max) {
+                return Err(MapgenError::invalid("normalization", format!("invalid range [{}, {}]", min, max)));
+            }
+        }
+        Ok(())
+    }
+
+    /// Normalize `r` in place. Uses only basic IEEE operations, so the result is the same on
+    /// all platforms for the same input.
+    pub fn apply<S, D>(&self, r: &mut ArrayBase<S, D>)
+    where
+        S: DataMut<Elem = f64>,
+        D: Dimension,
+    {
+        if r.is_empty() {
+            return;
+        }
+        match *self {
+            Normalization::UnitRange => normalize(r),
+            Normalization::Raw => {}
+            Normalization::ZScore => {
+                let n = r.len() as f64;
+                let mean = r.sum() / n;
+                let sd = (r.iter().map(|x| (x - mean) * (x - mean)).sum::<f64>() / n).sqrt();
+                let sd = if sd > 0.0 { sd } else { 1.0 };
+                r.mapv_inplace(|x| (x - mean) / sd);
+            }
+            Normalization::Clamp { min, max } => {
+                let d = max - min;
+                r.mapv_inplace(|x| if d > 0.0 { ((x - min) / d).clamp(0.0, 1.0) } else { 0.0 });
+            }
+            Normalization::Equalize => {
+                let mut order: Vec<usize> = (0..r.len()).collect();
+                let values: Vec<f64> = r.iter().copied().collect();
+                order.sort_by(|&a, &b| values[a].total_cmp(&values[b]));
+                let mut ranks = vec![0.0; values.len()];
+                for (rank, &i) in order.iter().enumerate() {
+                    ranks[i] = rank as f64 / values.len() as f64;
+                }
+                for (v, rank) in r.iter_mut().zip(ranks) {
+                    *v = rank;
+                }
+            }
+        }
+    }
+}
+
+/// Spectral ("colored") noise, by default normalized to [0, 1) (see `Normalization`).
+/// `color` is the exponent of the frequency weighting, eg. -2.0 for brown noise,
+/// 0.0 for white noise.
+#[derive(Clone)]
+pub struct ColoredNoise {
+    pub size: UVec2,
+    pub color: f64,
+    pub seed: u64,
+    /// Make the left/right and top/bottom edges match seamlessly, so that the map can be
+    /// repeated (eg. for backgrounds) or wrapped around (default: false).
+    /// Tileable maps are the real inverse transform of a conjugate symmetric spectrum of `size`.
+    pub tileable: bool,
+    /// Synthesize a map half as large again along each axis and crop it to `size`, so that
+    /// opposite edges are unrelated (default: false). The inverse transform is periodic, so
+    /// without padding opposite edges of non-tileable maps still roughly match. Ignored for
+    /// tileable maps.
+    pub padded: bool,
+    pub normalization: Normalization,
+}
+
+impl Default for ColoredNoise {
+    fn default() -> Self {
+        Self {
+            size: uvec2(100, 100),
+            color: -2.0,
+            seed: 0,
+            tileable: false,
+            padded: false,
+            normalization: Normalization::UnitRange,
+        }
+    }
+}
+
+impl ColoredNoise {
+    pub fn tileable(mut self, tileable: bool) -> Self {
+        self.tileable = tileable;
+        self
+    }
+
+    pub fn padded(mut self, padded: bool) -> Self {
+        self.padded = padded;
+        self
+    }
+
+    pub fn normalization(mut self, normalization: Normalization) -> Self {
+        self.normalization = normalization;
+        self
+    }
+
+    /// Check the parameters: the size must not be zero, the color must be finite and the
+    /// range of `Normalization::Clamp` must be finite and not empty.
+    pub fn validate(&self) -> Result<(), MapgenError> {
+        MapgenError::check_size("size", self.size)?;
+        if !self.color.is_finite() {
+            return Err(MapgenError::invalid("color", "must be finite"));
+        }
+        self.normalization.validate()
+    }
+
+    /// Like `generate`, but checks the parameters first, see `validate`.
+    pub fn try_generate(&self) -> Result<Array2<f64>, MapgenError> {
+        self.validate()?;
+        Ok(self.generate())
+    }
+
+    pub fn generate(&self) -> Array2<f64> {
+        self.generate_with_report().0
+    }
+
+    /// Like `generate`, reusing the FFT handlers and buffers cached in `context`.
+    pub fn generate_with(&self, context: &mut NoiseContext) -> Array2<f64> {
+        colored_noise_seeded(self, context, &mut GenerationReport::default())
+    }
+
+    /// Like `generate`, also reporting the time spent per phase and value statistics.
+    pub fn generate_with_report(&self) -> (Array2<f64>, GenerationReport) {
+        let mut report = GenerationReport::default();
+        let r = colored_noise_seeded(self, &mut NoiseContext::new(), &mut report);
+        report.cells_processed = r.len();
+        report.values = ValueStats::of(&r);
+        (r, report)
+    }
+
+    /// Generate noise of the size of `layers` and store it as layer `name`.
+    pub fn generate_into_layer(&self, layers: &mut MapStack, name: &str) {
+        let size = layers.size();
+        let noise = Self { size, ..self.clone() };
+        layers.insert(name, noise.generate());
+    }
+
+    /// Like `generate`, but on `backend`. Falls back to the CPU if the GPU does not support
+    /// the size (see `Gpu::colored_noise`). The GPU computes in single precision, so the
+    /// values differ slightly from `generate`.
+    #[cfg(feature = "wgpu")]
+    pub fn generate_on(&self, backend: &Backend) -> Array2<f64> {
+        match backend {
+            Backend::Gpu(gpu) => gpu.colored_noise(self).unwrap_or_else(|| self.generate()),
+            Backend::Cpu => self.generate(),
+        }
+    }
+}
+
+/// One layer of `FractalNoise`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Octave {
+    /// Size of the features relative to the base noise, eg. 4.0 generates the octave at a
+    /// quarter of the map size and stretches it over the map
+    pub scale: f64,
+    pub amplitude: f64,
+    pub seed: u64,
+}
+
+/// Sum of several `ColoredNoise` octaves (fBm), normalized with `base.normalization`.
+/// `base` provides the color, the size, `tileable` and `padded` of all octaves. The octaves themselves
+/// are normalized to [0, 1) before they are weighted with their amplitudes.
+///
+/// ```
+/// # use mapgen_2d::colored_noise::{ColoredNoise, FractalNoise};
+/// # let (size, seed) = (glam::uvec2(64, 48), 7);
+/// let height = FractalNoise::fbm(ColoredNoise { size, seed, ..Default::default() }, 5, 2.0, 0.5).generate();
+/// assert_eq!(height.dim(), (64, 48));
+/// ```
+#[derive(Clone)]
+pub struct FractalNoise {
+    pub base: ColoredNoise,
+    pub octaves: Vec<Octave>,
+}
+
+impl FractalNoise {
+    pub fn new(base: ColoredNoise) -> Self {
+        Self { base, octaves: Vec::new() }
+    }
+
+    /// `count` octaves from coarse to fine: octave `i` (counted from the finest) has scale
+    /// `lacunarity^i` and amplitude `gain^-i`, so eg. `lacunarity` 2 and `gain` 0.5 halve
+    /// the amplitude with every halving of the feature size.
+    /// The octave seeds are derived from the seed of `base`.
+    pub fn fbm(base: ColoredNoise, count: usize, lacunarity: f64, gain: f64) -> Self {
+        let seed = base.seed;
+        (0..count).rev().fold(Self::new(base), |noise, i| {
+            noise.octave(lacunarity.powi(i as i32), gain.powi(-(i as i32)), derive_seed(seed, i as u64))
+        })
+    }
+
+    pub fn octave(mut self, scale: f64, amplitude: f64, seed: u64) -> Self {
+        self.octaves.push(Octave { scale, amplitude, seed });
+        self
+    }
+
+    pub fn generate(&self) -> Array2<f64> {
+        self.generate_with(&mut NoiseContext::new())
+    }
+
+    /// Like `generate`, reusing the FFT handlers and buffers cached in `context`.
+    pub fn generate_with(&self, context: &mut NoiseContext) -> Array2<f64> {
+        let size = self.base.size;
+        let mut r: Array2<f64> = Array2::zeros((size.x as usize, size.y as usize));
+        let mut report = GenerationReport::default();
+        for octave in self.octaves.iter() {
+            self.accumulate(octave, &mut r, context, &mut report);
+        }
+        if !self.octaves.is_empty() {
+            self.base.normalization.apply(&mut r);
+        }
+        r
+    }
+
+    /// Generate noise of the size of `layers` and store it as layer `name`.
+    pub fn generate_into_layer(&self, layers: &mut MapStack, name: &str) {
+        let size = layers.size();
+        let noise = Self { base: ColoredNoise { size, ..self.base.clone() }, ..self.clone() };
+        layers.insert(name, noise.generate());
+    }
+
+    /// Add `octave` (stretched over the map with bilinear interpolation) to `r`. The octave is
+    /// synthesized in the buffers of `context`, so no map is allocated per octave.
+    fn accumulate(
+        &self,
+        octave: &Octave,
+        r: &mut Array2<f64>,
+        context: &mut NoiseContext,
+        report: &mut GenerationReport,
+    ) {
+        let (sx, sy) = r.dim();
+        let scale = octave.scale.max(1.0);
+        // At least 2x2, a single tile would be constant
+        let octave_size = uvec2(
+            ((sx as f64 / scale).ceil() as u32).max(2),
+            ((sy as f64 / scale).ceil() as u32).max(2),
+        );
+        let octave_noise = ColoredNoise {
+            size: octave_size,
+            seed: octave.seed,
+            normalization: Normalization::UnitRange,
+            ..self.base.clone()
+        };
+        let noise = synthesize(&octave_noise, context, report);
+        let (nx, ny) = noise.dim();
+        let (fx, fy) = (nx as f64 / sx as f64, ny as f64 / sy as f64);
+        let tileable = self.base.tileable;
+
+        // Neighbor indices and weight along one axis, wrapping around for tileable noise
+        let axis = |p: f64, n: usize| -> (usize, usize, f64) {
+            match tileable {
+                true => {
+                    let i = p.floor();
+                    let i0 = (i as isize).rem_euclid(n as isize) as usize;
+                    (i0, (i0 + 1) % n, p - i)
+                }
+                false => {
+                    let i0 = (p.floor().max(0.0) as usize).min(n - 1);
+                    let i1 = (i0 + 1).min(n - 1);
+                    (i0, i1, (p - i0 as f64).clamp(0.0, 1.0))
+                }
+            }
+        };
+
+        Zip::indexed(r).for_each(|(x, y), v| {
+            let (x0, x1, tx) = axis((x as f64 + 0.5) * fx - 0.5, nx);
+            let (y0, y1, ty) = axis((y as f64 + 0.5) * fy - 0.5, ny);
+            let top = noise[[x0, y0]] * (1.0 - tx) + noise[[x1, y0]] * tx;
+            let bottom = noise[[x0, y1]] * (1.0 - tx) + noise[[x1, y1]] * tx;
+            *v += octave.amplitude * (top * (1.0 - ty) + bottom * ty);
+        });
+    }
+}
+
 // TODO: Consider making this generic by using num traits and substituting `as` keyword with
 // from/into calls
 pub fn colored_noise(size_x: usize, size_y: usize, color: f64) -> Array2<f64> {
-    let f_domain = generate_freq_domain_noise(size_x, size_y, color);
+    let noise = ColoredNoise { size: uvec2(size_x as u32, size_y as u32), color, seed: 1234, ..Default::default() };
+    noise.generate_with(&mut NoiseContext::new())
+}
 
-    let mut handler_ax0 = FftHandler::<f64>::new(size_x);
-    let mut handler_ax1 = R2cFftHandler::<f64>::new(size_y);
+/// Cached FFT handlers and scratch buffers per map size, for generating many noise maps of
+/// the same sizes (eg. one per chunk or layer), see `ColoredNoise::generate_with`.
+#[derive(Default)]
+pub struct NoiseContext {
+    plans: HashMap<(usize, usize), Plan>,
+}
 
-    // TODO: Allow providing this from outside
-    let mut r: Array2<f64> = Array2::zeros((size_x, size_y));
-    {
-        let mut work: Array2<Complex<f64>> = Array2::zeros((size_x, size_y / 2 + 1));
-        ndifft(&f_domain, &mut work, &mut handler_ax0, 0);
-        ndifft_r2c(&work, &mut r, &mut handler_ax1, 1);
+struct Plan {
+    handler_ax0: FftHandler<f64>,
+    handler_ax1: R2cFftHandler<f64>,
+    f_domain: Array2<Complex<f64>>,
+    work: Array2<Complex<f64>>,
+    output: Array2<f64>,
+}
+
+impl NoiseContext {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of map sizes with cached handlers and buffers
+    pub fn len(&self) -> usize {
+        self.plans.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.plans.is_empty()
     }
 
+    /// Drop all cached handlers and buffers.
+    pub fn clear(&mut self) {
+        self.plans.clear();
+    }
+
+    fn plan(&mut self, size_x: usize, size_y: usize) -> &mut Plan {
+        self.plans.entry((size_x, size_y)).or_insert_with(|| Plan {
+            handler_ax0: FftHandler::new(size_x),
+            handler_ax1: R2cFftHandler::new(size_y),
+            f_domain: Array2::zeros((size_x, size_y / 2 + 1)),
+            work: Array2::zeros((size_x, size_y / 2 + 1)),
+            output: Array2::zeros((size_x, size_y)),
+        })
+    }
+}
+
+fn colored_noise_seeded(
+    noise: &ColoredNoise,
+    context: &mut NoiseContext,
+    report: &mut GenerationReport,
+) -> Array2<f64> {
+    synthesize(noise, context, report).to_owned()
+}
+
+/// Synthesize and normalize `noise` in the output buffer cached in `context`, cropped to
+/// `noise.size`. Allocates nothing once `context` has a plan for the synthesis size.
+fn synthesize<'a>(
+    noise: &ColoredNoise,
+    context: &'a mut NoiseContext,
+    report: &mut GenerationReport,
+) -> ArrayViewMut2<'a, f64> {
+    let size = synthesis_size(noise);
+    let plan = context.plan(size.x as usize, size.y as usize);
+    report.time("frequency domain", || fill_spectrum(noise, &mut plan.f_domain));
+
+    report.time("inverse fft", || {
+        ndifft(&plan.f_domain, &mut plan.work, &mut plan.handler_ax0, 0);
+        ndifft_r2c(&plan.work, &mut plan.output, &mut plan.handler_ax1, 1);
+    });
+    let mut r = plan.output.slice_mut(s![..noise.size.x as usize, ..noise.size.y as usize]);
+
+    report.time("normalize", || noise.normalization.apply(&mut r));
+    r
+}
+
+/// Size of the periodic map synthesized for `noise`, which is cropped to `noise.size` if the
+/// noise is padded (see `ColoredNoise::padded`) or a single row.
+pub(crate) fn synthesis_size(noise: &ColoredNoise) -> UVec2 {
+    let size = match noise.padded && !noise.tileable {
+        true => noise.size + (noise.size + UVec2::ONE) / 2,
+        false => noise.size,
+    };
+    // The inverse real transform of a single row gives infinite values
+    size.max(uvec2(1, 2))
+}
+
+/// Overwrite the half spectrum `f_domain` (of size `synthesis_size(noise).y / 2 + 1` along
+/// axis 1) with the spectrum of the map synthesized for `noise`.
+pub(crate) fn fill_spectrum(noise: &ColoredNoise, f_domain: &mut Array2<Complex<f64>>) {
+    let size = synthesis_size(noise);
+    fill_freq_domain_noise(f_domain, size.x as usize, size.y as usize, noise.color, noise.seed);
+    if noise.tileable {
+        make_hermitian(f_domain, size.y as usize);
+    }
+}
+
+/// Crop the synthesized map `r` to `noise.size`.
+#[cfg(feature = "wgpu")]
+pub(crate) fn crop_to_size(noise: &ColoredNoise, r: Array2<f64>) -> Array2<f64> {
+    match synthesis_size(noise) == noise.size {
+        true => r,
+        false => r.slice(s![..noise.size.x as usize, ..noise.size.y as usize]).to_owned(),
+    }
+}
+
+/// Enforce the conjugate symmetry of the spectrum of a real signal in the columns of the half
+/// spectrum `f` (of a signal with `size_y` rows) that are their own mirror image (the zero and
+/// the Nyquist frequency), so that the inverse real transform uses the spectrum as it is
+/// instead of dropping the imaginary parts there.
+fn make_hermitian(f: &mut Array2<Complex<f64>>, size_y: usize) {
+    let size_x = f.len_of(Axis(0));
+    let mut columns = vec![0];
+    if size_y.is_multiple_of(2) && size_y > 0 {
+        columns.push(size_y / 2);
+    }
+    for y in columns {
+        for x in 0..size_x {
+            let mirror = (size_x - x) % size_x;
+            if mirror == x {
+                f[[x, y]].im = 0.0;
+            } else if x < mirror {
+                f[[mirror, y]] = f[[x, y]].conj();
+            }
+        }
+    }
+}
+
+/// Map absolute values to [0, 1)
+/// Uses only basic IEEE operations, so the result is the same on all platforms for the same
+/// input.
+fn normalize<S, D>(r: &mut ArrayBase<S, D>)
+where
+    S: DataMut<Elem = f64>,
+    D: Dimension,
+{
     r.mapv_inplace(|x| x.abs());
 
     let max = *r.iter().max_by(|x, y| x.partial_cmp(y).unwrap()).unwrap();
     let min = *r.iter().min_by(|x, y| x.partial_cmp(y).unwrap()).unwrap();
     let d = max - min;
+    if d <= 0.0 {
+        // Constant map (eg. a 1x1 octave of `FractalNoise`), avoid dividing by zero
+        r.fill(0.0);
+        return;
+    }
 
     // Normalize to [0, 1]
     // This will leave exactly one element be 1.0 which is usually undesirable
     r.mapv_inplace(|x| (x - min) / d);
     // Replace the 1.0 element with 1.0-eps so that we have values in [0, 1) now.
     r.mapv_inplace(|x| if x >= 1.0 { 1.0 - f64::EPSILON } else { x });
+}
 
-    r
+/// 3D version of `ColoredNoise`, eg. for voxel maps or layered cave systems.
+/// Values are normalized with `normalization`, by default to [0, 1).
+/// The output is the inverse transform of a half spectrum along z, so like a tileable
+/// `ColoredNoise` it is periodic along all axes.
+#[derive(Clone)]
+pub struct ColoredNoise3 {
+    pub size: UVec3,
+    pub color: f64,
+    pub seed: u64,
+    pub normalization: Normalization,
+}
+
+impl Default for ColoredNoise3 {
+    fn default() -> Self {
+        Self {
+            size: uvec3(32, 32, 32),
+            color: -2.0,
+            seed: 0,
+            normalization: Normalization::UnitRange,
+        }
+    }
+}
+
+impl ColoredNoise3 {
+    pub fn normalization(mut self, normalization: Normalization) -> Self {
+        self.normalization = normalization;
+        self
+    }
+
+    /// Check the parameters, see `ColoredNoise::validate`.
+    pub fn validate(&self) -> Result<(), MapgenError> {
+        if self.size.cmpeq(UVec3::ZERO).any() {
+            return Err(MapgenError::invalid("size", format!("must not be zero, got {}", self.size)));
+        }
+        if !self.color.is_finite() {
+            return Err(MapgenError::invalid("color", "must be finite"));
+        }
+        self.normalization.validate()
+    }
+
+    /// Like `generate`, but checks the parameters first, see `validate`.
+    pub fn try_generate(&self) -> Result<Array3<f64>, MapgenError> {
+        self.validate()?;
+        Ok(self.generate())
+    }
+
+    pub fn generate(&self) -> Array3<f64> {
+        let (sx, sy, sz) = (self.size.x as usize, self.size.y as usize, self.size.z as usize);
+        let f_domain = generate_freq_domain_noise3(sx, sy, sz, self.color, self.seed);
+
+        let mut handler_ax0 = FftHandler::<f64>::new(sx);
+        let mut handler_ax1 = FftHandler::<f64>::new(sy);
+        let mut handler_ax2 = R2cFftHandler::<f64>::new(sz);
+
+        let mut r: Array3<f64> = Array3::zeros((sx, sy, sz));
+        {
+            let mut work: Array3<Complex<f64>> = Array3::zeros((sx, sy, sz / 2 + 1));
+            let mut work2: Array3<Complex<f64>> = Array3::zeros((sx, sy, sz / 2 + 1));
+            ndifft(&f_domain, &mut work, &mut handler_ax0, 0);
+            ndifft(&work, &mut work2, &mut handler_ax1, 1);
+            ndifft_r2c(&work2, &mut r, &mut handler_ax2, 2);
+        }
+
+        self.normalization.apply(&mut r);
+        r
+    }
+}
+
+fn generate_freq_domain_noise3(size_x: usize, size_y: usize, size_z: usize, color: f64, seed: u64) -> Array3<Complex<f64>> {
+    let mut f_domain: Array3<Complex<f64>> = Array3::zeros((size_x, size_y, size_z / 2 + 1));
+
+    let mut rng = rand::rngs::StdRng::seed_from_u64(seed);
+    let uniform = Uniform::<f64>::from(-1. ..1.);
+    let c = [size_x as f64 / 2., size_y as f64 / 2., size_z as f64 / 2.];
+
+    for ((x, y, z), v) in f_domain.indexed_iter_mut() {
+        let distance = ((x as f64 - c[0]).powf(2.) + (y as f64 - c[1]).powf(2.) + (z as f64 - c[2]).powf(2.)).sqrt();
+        let weight = if distance != 0.0 { distance.powf(color) } else { 0.0 };
+        *v = Complex::new(uniform.sample(&mut rng), uniform.sample(&mut rng)) * weight;
+    }
+
+    f_domain
+}
+
+/// Generate colored noise of the size of `layers` and store it as layer `name`.
+pub fn colored_noise_into_layer(layers: &mut MapStack, name: &str, color: f64) {
+    let size = layers.size();
+    layers.insert(name, colored_noise(size.x as usize, size.y as usize, color));
 }
 
 pub fn generate_freq_domain_noise(size_x: usize, size_y: usize, color: f64) -> Array2<Complex<f64>> {
+    generate_freq_domain_noise_seeded(size_x, size_y, color, 1234)
+}
+
+pub fn generate_freq_domain_noise_seeded(size_x: usize, size_y: usize, color: f64, seed: u64) -> Array2<Complex<f64>> {
     let mut f_domain: Array2<Complex<f64>> = Array2::zeros((size_x, size_y / 2 + 1));
+    fill_freq_domain_noise(&mut f_domain, size_x, size_y, color, seed);
+    f_domain
+}
 
-    // TODO: Allow providing seed from outside
-    let mut rng = rand::rngs::StdRng::seed_from_u64(1234);
+/// Overwrite the half spectrum `f_domain` of a `size_x` x `size_y` map with weighted random
+/// values, see `generate_freq_domain_noise_seeded`.
+fn fill_freq_domain_noise(f_domain: &mut Array2<Complex<f64>>, size_x: usize, size_y: usize, color: f64, seed: u64) {
+    let mut rng = rand::rngs::StdRng::seed_from_u64(seed);
     let uniform = Uniform::<f64>::from(-1. ..1.);
     let cx = (size_x as f64) / 2.;
     let cy = (size_y as f64) / 2.;
@@ -54,7 +587,177 @@ pub fn generate_freq_domain_noise(size_x: usize, size_y: usize, color: f64) -> A
                 Complex::new(uniform.sample(&mut rng), uniform.sample(&mut rng)) * weight;
         }
     }
-
-    f_domain
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ndarray::ArrayView2;
+
+    /// Mean difference across the wrap-around seams relative to the mean difference between
+    /// neighbors in the middle of the map, over several seeds
+    fn seam_ratio(noise: ColoredNoise) -> f64 {
+        let (sx, sy) = (64, 48);
+        let (mut seam, mut inner) = (0.0, 0.0);
+        for seed in 0..10 {
+            let a = ColoredNoise { size: uvec2(sx as u32, sy as u32), seed, ..noise.clone() }.generate();
+            for y in 0..sy {
+                seam += (a[[0, y]] - a[[sx - 1, y]]).abs();
+                inner += (a[[sx / 2 - 1, y]] - a[[sx / 2, y]]).abs();
+            }
+            for x in 0..sx {
+                seam += (a[[x, 0]] - a[[x, sy - 1]]).abs();
+                inner += (a[[x, sy / 2 - 1]] - a[[x, sy / 2]]).abs();
+            }
+        }
+        seam / inner
+    }
+
+    #[test]
+    fn tileable_edges_match() {
+        let ratio = seam_ratio(ColoredNoise::default().tileable(true).padded(true));
+        assert!(ratio < 1.5, "{}", ratio);
+    }
+
+    #[test]
+    fn padded_edges_differ() {
+        let ratio = seam_ratio(ColoredNoise::default().padded(true));
+        assert!(ratio > 3.0, "{}", ratio);
+    }
+
+    #[test]
+    fn non_tileable_size() {
+        for size in [uvec2(1, 1), uvec2(7, 3), uvec2(3, 7), uvec2(16, 1)] {
+            for (tileable, padded) in [(false, false), (false, true), (true, false)] {
+                let a = ColoredNoise { size, tileable, padded, ..Default::default() }.generate();
+                assert_eq!(a.dim(), (size.x as usize, size.y as usize));
+            }
+        }
+    }
+
+    /// The default (unpadded) output must not change. If this fails after an intentional
+    /// change, maps generated from shared seeds differ from earlier versions.
+    #[test]
+    fn default_golden() {
+        let a = colored_noise(12, 9, -2.0);
+        let expected = [0.5043889877002042, 0.2689817629079773, 0.4428820107197593, 0.39367682351272915];
+        for (p, v) in [[0, 0], [5, 3], [11, 8], [7, 1]].into_iter().zip(expected) {
+            assert!((a[p] - v).abs() < 1e-12, "{} at {:?}", a[p], p);
+        }
+
+        let a = ColoredNoise { size: uvec2(10, 7), seed: 42, ..Default::default() }.generate();
+        let expected = [0.17050884862799912, 0.9887295210832397, 0.4420989277326648, 0.3164672625999094];
+        for (p, v) in [[0, 0], [5, 3], [9, 6], [7, 1]].into_iter().zip(expected) {
+            assert!((a[p] - v).abs() < 1e-12, "{} at {:?}", a[p], p);
+        }
+    }
+
+    /// Mean difference between neighbors along x and along y
+    fn roughness(a: &Array2<f64>) -> (f64, f64) {
+        let (sx, sy) = a.dim();
+        let diff = |a: ArrayView2<f64>, b: ArrayView2<f64>| Zip::from(a).and(b).fold(0.0, |d, a, b| d + (a - b).abs());
+        let dx = diff(a.slice(s![1.., ..]), a.slice(s![..sx - 1, ..]));
+        let dy = diff(a.slice(s![.., 1..]), a.slice(s![.., ..sy - 1]));
+        (dx / ((sx - 1) * sy) as f64, dy / (sx * (sy - 1)) as f64)
+    }
+
+    /// The spectrum of a wide map has more steps along x, so it is smoother along x than
+    /// along y. A transposed spectrum would make it smoother along y.
+    #[test]
+    fn non_square_axes() {
+        for tileable in [false, true] {
+            let noise = ColoredNoise { size: uvec2(70, 30), tileable, ..Default::default() };
+            let a = noise.generate();
+            assert_eq!(a.dim(), (70, 30));
+            let (rx, ry) = roughness(&a);
+            assert!(rx < ry, "roughness along x {} and y {}", rx, ry);
+
+            let (rx, ry) = roughness(&ColoredNoise { size: uvec2(30, 70), ..noise }.generate());
+            assert!(rx > ry, "roughness along x {} and y {}", rx, ry);
+        }
+    }
+
+    #[test]
+    fn non_square_fractal() {
+        let base = ColoredNoise { size: uvec2(7, 3), ..Default::default() };
+        let a = FractalNoise::fbm(base, 3, 2.0, 0.5).generate();
+        assert_eq!(a.dim(), (7, 3));
+        assert!(a.iter().all(|v| (0.0..1.0).contains(v)));
+    }
+
+    /// The coarsest of 8 octaves with lacunarity 2 on a 100x100 map would be smaller than 2x2,
+    /// constant maps must not turn into NaN.
+    #[test]
+    fn fractal_octaves_down_to_one_tile() {
+        for tileable in [false, true] {
+            let base = ColoredNoise { size: uvec2(100, 100), tileable, ..Default::default() };
+            let a = FractalNoise::fbm(base, 8, 2.0, 0.5).generate();
+            assert!(a.iter().all(|v| (0.0..1.0).contains(v)));
+            assert!(a.iter().any(|&v| v > 0.0));
+        }
+        let a = ColoredNoise { size: uvec2(1, 1), ..Default::default() }.generate();
+        assert_eq!(a[[0, 0]], 0.0);
+    }
+
+    /// With `Normalization::Raw` the sum of the octaves (each in [0, 1)) weighted by their
+    /// amplitudes, other normalizations apply to that sum
+    #[test]
+    fn fractal_amplitudes_and_normalization() {
+        let size = uvec2(40, 24);
+        let base = ColoredNoise { size, seed: 2, normalization: Normalization::Raw, ..Default::default() };
+        let octaves = [(4.0, 3.0, 11), (2.0, 1.5, 12), (1.0, 0.5, 13)];
+        let fractal = |base: &ColoredNoise, octaves: &[(f64, f64, u64)]| {
+            let noise = FractalNoise::new(base.clone());
+            octaves.iter().fold(noise, |noise, &(scale, amplitude, seed)| noise.octave(scale, amplitude, seed))
+        };
+
+        let raw = fractal(&base, &octaves).generate();
+        let sum = octaves
+            .iter()
+            .map(|&o| fractal(&base, &[o]).generate())
+            .fold(Array2::<f64>::zeros((40, 24)), |sum, a| sum + a);
+        assert!(raw.iter().zip(sum.iter()).all(|(a, b)| (a - b).abs() < 1e-12));
+        assert!(raw.iter().all(|v| (0.0..5.0).contains(v)));
+
+        // At scale 1 an octave is unit range noise of the map size
+        let unit = ColoredNoise { seed: 13, normalization: Normalization::UnitRange, ..base.clone() }.generate();
+        assert_eq!(fractal(&base, &[(1.0, 0.5, 13)]).generate(), unit * 0.5);
+
+        let a = fractal(&ColoredNoise { normalization: Normalization::UnitRange, ..base.clone() }, &octaves).generate();
+        assert!(a.iter().all(|v| (0.0..1.0).contains(v)));
+        assert_eq!(a.iter().copied().fold(f64::INFINITY, f64::min), 0.0);
+
+        let z = fractal(&ColoredNoise { normalization: Normalization::ZScore, ..base.clone() }, &octaves).generate();
+        let mean = z.sum() / z.len() as f64;
+        assert!(mean.abs() < 1e-9);
+
+        // Octaves synthesized in the buffers of a shared context do not affect each other
+        let mut context = NoiseContext::new();
+        let noise = fractal(&base, &octaves);
+        assert_eq!(noise.generate_with(&mut context), raw);
+        assert_eq!(noise.generate_with(&mut context), raw);
+        assert_eq!(context.len(), 3);
+    }
+
+    #[test]
+    fn noise3_normalization() {
+        let noise = ColoredNoise3 { size: uvec3(8, 6, 5), seed: 3, ..Default::default() };
+        let a = noise.try_generate().unwrap();
+        assert_eq!(a.dim(), (8, 6, 5));
+        assert!(a.iter().all(|v| (0.0..1.0).contains(v)));
+        assert!(a.iter().any(|&v| v > 0.5));
+
+        let z = noise.clone().normalization(Normalization::ZScore).generate();
+        let mean = z.sum() / z.len() as f64;
+        let sd = (z.iter().map(|v| (v - mean) * (v - mean)).sum::<f64>() / z.len() as f64).sqrt();
+        assert!(mean.abs() < 1e-9 && (sd - 1.0).abs() < 1e-9);
+
+        let raw = noise.clone().normalization(Normalization::Raw).generate();
+        assert!(raw.iter().any(|&v| v < 0.0));
+
+        let clamp = Normalization::Clamp { min: 1.0, max: 1.0 };
+        assert!(noise.clone().normalization(clamp).try_generate().is_err());
+        assert!(ColoredNoise3 { size: uvec3(4, 0, 4), ..noise }.try_generate().is_err());
+    }
+}
@@ -0,0 +1,387 @@
+
+//! Compact at-rest encoding for maps: a palette of distinct tile values plus
+//! run-length encoded palette indices.
+//!
+//! Layout of the byte stream (all integers as LEB128 varints unless noted):
+//!
+//! ```text
+//! magic "MG2C" (4 bytes) | version (1 byte) | size x | size y
+//! | palette length | palette values (see `PaletteValue`)
+//! | (palette index, run length)*
+//! ```
+//!
+//! Tiles are visited in the iteration order of the array (`a[[0, 0]]`, `a[[0, 1]]`, ...).
+
+use ndarray::Array2;
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::Hash;
+
+const MAGIC: &[u8; 4] = b"MG2C";
+pub const VERSION: u8 = 1;
+/// Largest number of tiles `decompress` accepts (64M, e.g. 8k x 8k), so that a corrupt or
+/// malicious header can't make it allocate unbounded memory. Use `decompress_with_limit` for
+/// larger maps.
+pub const MAX_TILES: usize = 1 << 26;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    BadMagic,
+    UnsupportedVersion(u8),
+    UnexpectedEnd,
+    InvalidPaletteIndex(usize),
+    /// Runs don't add up to the map size
+    LengthMismatch,
+    InvalidValue,
+    /// The map has more tiles than the decoder accepts (see `MAX_TILES`)
+    TooLarge(usize),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::BadMagic => write!(f, "not a compressed map (bad magic)"),
+            DecodeError::UnsupportedVersion(v) => write!(f, "unsupported version {}", v),
+            DecodeError::UnexpectedEnd => write!(f, "unexpected end of data"),
+            DecodeError::InvalidPaletteIndex(i) => write!(f, "invalid palette index {}", i),
+            DecodeError::LengthMismatch => write!(f, "run lengths do not match map size"),
+            DecodeError::InvalidValue => write!(f, "invalid palette value"),
+            DecodeError::TooLarge(n) => write!(f, "map of {} tiles exceeds the size limit", n),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+/// Values that can be stored in the palette of a compressed map.
+pub trait PaletteValue: Sized {
+    fn write_bytes(&self, out: &mut Vec<u8>);
+
+    /// Read a value from the start of `bytes`, return it and the number of bytes consumed.
+    fn read_bytes(bytes: &[u8]) -> Result<(Self, usize), DecodeError>;
+}
+
+macro_rules! impl_palette_value_unsigned {
+    ($($t:ty),*) => {
+        $(
+            impl PaletteValue for $t {
+                fn write_bytes(&self, out: &mut Vec<u8>) {
+                    write_varint(out, *self as u64);
+                }
+
+                fn read_bytes(bytes: &[u8]) -> Result<(Self, usize), DecodeError> {
+                    let (v, n) = read_varint(bytes)?;
+                    let v = <$t>::try_from(v).map_err(|_| DecodeError::InvalidValue)?;
+                    Ok((v, n))
+                }
+            }
+        )*
+    };
+}
+
+macro_rules! impl_palette_value_signed {
+    ($($t:ty),*) => {
+        $(
+            impl PaletteValue for $t {
+                fn write_bytes(&self, out: &mut Vec<u8>) {
+                    // zigzag encoding
+                    let v = *self as i64;
+                    write_varint(out, ((v << 1) ^ (v >> 63)) as u64);
+                }
+
+                fn read_bytes(bytes: &[u8]) -> Result<(Self, usize), DecodeError> {
+                    let (v, n) = read_varint(bytes)?;
+                    let v = ((v >> 1) as i64) ^ -((v & 1) as i64);
+                    let v = <$t>::try_from(v).map_err(|_| DecodeError::InvalidValue)?;
+                    Ok((v, n))
+                }
+            }
+        )*
+    };
+}
+
+impl_palette_value_unsigned!(u8, u16, u32, u64, usize);
+impl_palette_value_signed!(i8, i16, i32, i64, isize);
+
+impl PaletteValue for bool {
+    fn write_bytes(&self, out: &mut Vec<u8>) {
+        out.push(*self as u8);
+    }
+
+    fn read_bytes(bytes: &[u8]) -> Result<(Self, usize), DecodeError> {
+        match bytes.first() {
+            Some(0) => Ok((false, 1)),
+            Some(1) => Ok((true, 1)),
+            Some(_) => Err(DecodeError::InvalidValue),
+            None => Err(DecodeError::UnexpectedEnd),
+        }
+    }
+}
+
+impl PaletteValue for char {
+    fn write_bytes(&self, out: &mut Vec<u8>) {
+        write_varint(out, *self as u64);
+    }
+
+    fn read_bytes(bytes: &[u8]) -> Result<(Self, usize), DecodeError> {
+        let (v, n) = read_varint(bytes)?;
+        let c = u32::try_from(v).ok().and_then(char::from_u32).ok_or(DecodeError::InvalidValue)?;
+        Ok((c, n))
+    }
+}
+
+/// Encode `a` into a palette + RLE byte stream.
+pub fn compress<T>(a: &Array2<T>) -> Vec<u8>
+where
+    T: Eq + Hash + Clone + PaletteValue,
+{
+    // Palette in order of first appearance so the output is deterministic
+    let mut palette: Vec<T> = Vec::new();
+    let mut lookup: HashMap<T, usize> = HashMap::new();
+    let indices: Vec<usize> = a
+        .iter()
+        .map(|v| {
+            *lookup.entry(v.clone()).or_insert_with(|| {
+                palette.push(v.clone());
+                palette.len() - 1
+            })
+        })
+        .collect();
+
+    let mut out = Vec::new();
+    out.extend_from_slice(MAGIC);
+    out.push(VERSION);
+    write_varint(&mut out, a.dim().0 as u64);
+    write_varint(&mut out, a.dim().1 as u64);
+
+    write_varint(&mut out, palette.len() as u64);
+    for v in palette.iter() {
+        v.write_bytes(&mut out);
+    }
+
+    let mut i = 0;
+    while i < indices.len() {
+        let idx = indices[i];
+        let mut run = 1;
+        while i + run < indices.len() && indices[i + run] == idx {
+            run += 1;
+        }
+        write_varint(&mut out, idx as u64);
+        write_varint(&mut out, run as u64);
+        i += run;
+    }
+
+    out
+}
+
+/// Decode a byte stream produced by `compress`, rejecting maps of more than `MAX_TILES` tiles.
+pub fn decompress<T>(bytes: &[u8]) -> Result<Array2<T>, DecodeError>
+where
+    T: Clone + PaletteValue,
+{
+    decompress_with_limit(bytes, MAX_TILES)
+}
+
+/// Decode a byte stream produced by `compress`, rejecting maps of more than `max_tiles` tiles.
+pub fn decompress_with_limit<T>(bytes: &[u8], max_tiles: usize) -> Result<Array2<T>, DecodeError>
+where
+    T: Clone + PaletteValue,
+{
+    if bytes.len() < MAGIC.len() + 1 {
+        return Err(DecodeError::UnexpectedEnd);
+    }
+    if &bytes[..MAGIC.len()] != MAGIC {
+        return Err(DecodeError::BadMagic);
+    }
+    let version = bytes[MAGIC.len()];
+    if version != VERSION {
+        return Err(DecodeError::UnsupportedVersion(version));
+    }
+
+    let mut pos = MAGIC.len() + 1;
+    let sx = usize::try_from(next_varint(bytes, &mut pos)?).map_err(|_| DecodeError::InvalidValue)?;
+    let sy = usize::try_from(next_varint(bytes, &mut pos)?).map_err(|_| DecodeError::InvalidValue)?;
+    let total = sx.checked_mul(sy).ok_or(DecodeError::TooLarge(usize::MAX))?;
+    if total > max_tiles {
+        return Err(DecodeError::TooLarge(total));
+    }
+    let palette_len = next_varint(bytes, &mut pos)? as usize;
+
+    let mut palette = Vec::with_capacity(palette_len.min(bytes.len()));
+    for _ in 0..palette_len {
+        let (v, n) = T::read_bytes(&bytes[pos..])?;
+        pos += n;
+        palette.push(v);
+    }
+
+    let mut values = Vec::with_capacity(total.min(bytes.len() * 64));
+    while values.len() < total {
+        let idx = next_varint(bytes, &mut pos)?;
+        let run = next_varint(bytes, &mut pos)?;
+
+        let v = palette
+            .get(idx as usize)
+            .ok_or(DecodeError::InvalidPaletteIndex(idx as usize))?;
+        if run == 0 || run > (total - values.len()) as u64 {
+            return Err(DecodeError::LengthMismatch);
+        }
+        values.extend(std::iter::repeat_n(v.clone(), run as usize));
+    }
+
+    Array2::from_shape_vec((sx, sy), values).map_err(|_| DecodeError::LengthMismatch)
+}
+
+/// Read a varint at `*pos` and advance `pos` past it.
+pub(crate) fn next_varint(bytes: &[u8], pos: &mut usize) -> Result<u64, DecodeError> {
+    let (v, n) = read_varint(&bytes[*pos..])?;
+    *pos += n;
+    Ok(v)
+}
+
+pub(crate) fn write_varint(out: &mut Vec<u8>, mut v: u64) {
+    loop {
+        let b = (v & 0x7f) as u8;
+        v >>= 7;
+        if v == 0 {
+            out.push(b);
+            break;
+        }
+        out.push(b | 0x80);
+    }
+}
+
+/// Read a varint from the start of `bytes`, return it and the number of bytes consumed.
+pub(crate) fn read_varint(bytes: &[u8]) -> Result<(u64, usize), DecodeError> {
+    let mut v = 0_u64;
+    for (i, b) in bytes.iter().enumerate() {
+        if i >= 10 {
+            return Err(DecodeError::InvalidValue);
+        }
+        v |= ((b & 0x7f) as u64) << (7 * i);
+        if b & 0x80 == 0 {
+            return Ok((v, i + 1));
+        }
+    }
+    Err(DecodeError::UnexpectedEnd)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn map() -> Array2<u16> {
+        Array2::from_shape_fn((7, 5), |(x, y)| if x < 3 { 1 } else { (x * y) as u16 })
+    }
+
+    /// Header of a map of size `sx` x `sy` with a palette of `palette` `u8` values
+    fn header(sx: u64, sy: u64, palette: &[u8]) -> Vec<u8> {
+        let mut out = MAGIC.to_vec();
+        out.push(VERSION);
+        write_varint(&mut out, sx);
+        write_varint(&mut out, sy);
+        write_varint(&mut out, palette.len() as u64);
+        for v in palette {
+            v.write_bytes(&mut out);
+        }
+        out
+    }
+
+    #[test]
+    fn round_trip() {
+        let a = map();
+        assert_eq!(decompress::<u16>(&compress(&a)), Ok(a));
+
+        let b = Array2::from_shape_fn((4, 3), |(x, y)| x as i32 - y as i32 * 1000);
+        assert_eq!(decompress::<i32>(&compress(&b)), Ok(b));
+
+        let c = Array2::from_shape_fn((2, 6), |(x, y)| ['#', '.', 'ü'][(x + y) % 3]);
+        assert_eq!(decompress::<char>(&compress(&c)), Ok(c));
+
+        let empty: Array2<bool> = Array2::from_elem((0, 3), true);
+        assert_eq!(decompress::<bool>(&compress(&empty)), Ok(empty));
+    }
+
+    #[test]
+    fn uniform_map_is_small() {
+        let a = Array2::from_elem((512, 512), 3_u8);
+        let bytes = compress(&a);
+        assert!(bytes.len() < 20);
+        assert_eq!(decompress::<u8>(&bytes), Ok(a));
+    }
+
+    #[test]
+    fn truncated() {
+        let bytes = compress(&map());
+        for len in 0..bytes.len() {
+            assert_eq!(decompress::<u16>(&bytes[..len]), Err(DecodeError::UnexpectedEnd), "length {}", len);
+        }
+    }
+
+    #[test]
+    fn bad_magic_and_version() {
+        let mut bytes = compress(&map());
+        bytes[4] = VERSION + 1;
+        assert_eq!(decompress::<u16>(&bytes), Err(DecodeError::UnsupportedVersion(VERSION + 1)));
+        bytes[0] = b'X';
+        assert_eq!(decompress::<u16>(&bytes), Err(DecodeError::BadMagic));
+    }
+
+    #[test]
+    fn oversized_map() {
+        // A single run covering a huge map must be rejected before allocating
+        let mut bytes = header(1 << 20, 1 << 20, &[0]);
+        write_varint(&mut bytes, 0);
+        write_varint(&mut bytes, 1 << 40);
+        assert_eq!(decompress::<u8>(&bytes), Err(DecodeError::TooLarge(1 << 40)));
+
+        let mut bytes = header(u64::MAX, u64::MAX, &[0]);
+        write_varint(&mut bytes, 0);
+        write_varint(&mut bytes, u64::MAX);
+        assert!(matches!(decompress::<u8>(&bytes), Err(DecodeError::TooLarge(_) | DecodeError::InvalidValue)));
+
+        let bytes = compress(&map());
+        assert_eq!(decompress_with_limit::<u16>(&bytes, 34), Err(DecodeError::TooLarge(35)));
+        assert_eq!(decompress_with_limit::<u16>(&bytes, 35), Ok(map()));
+    }
+
+    #[test]
+    fn oversized_run() {
+        let mut bytes = header(2, 2, &[0]);
+        write_varint(&mut bytes, 0);
+        write_varint(&mut bytes, 5);
+        assert_eq!(decompress::<u8>(&bytes), Err(DecodeError::LengthMismatch));
+
+        let mut bytes = header(2, 2, &[0]);
+        write_varint(&mut bytes, 0);
+        write_varint(&mut bytes, u64::MAX);
+        assert_eq!(decompress::<u8>(&bytes), Err(DecodeError::LengthMismatch));
+
+        let mut bytes = header(2, 2, &[0]);
+        write_varint(&mut bytes, 0);
+        write_varint(&mut bytes, 0);
+        assert_eq!(decompress::<u8>(&bytes), Err(DecodeError::LengthMismatch));
+    }
+
+    #[test]
+    fn bad_palette_index() {
+        let mut bytes = header(2, 2, &[4, 5]);
+        write_varint(&mut bytes, 1);
+        write_varint(&mut bytes, 2);
+        write_varint(&mut bytes, 2);
+        write_varint(&mut bytes, 2);
+        assert_eq!(decompress::<u8>(&bytes), Err(DecodeError::InvalidPaletteIndex(2)));
+    }
+
+    #[test]
+    fn bad_palette_value() {
+        let mut bytes = header(1, 1, &[]);
+        // Palette of one u8 with value 300
+        bytes.pop();
+        write_varint(&mut bytes, 1);
+        write_varint(&mut bytes, 300);
+        write_varint(&mut bytes, 0);
+        write_varint(&mut bytes, 1);
+        assert_eq!(decompress::<u8>(&bytes), Err(DecodeError::InvalidValue));
+        assert!(decompress::<u16>(&bytes).is_ok());
+    }
+}
@@ -0,0 +1,870 @@
+//! Generator parameters as plain data, loadable from text files so that they can be tweaked
+//! without recompiling.
+//!
+//! The file format is a flat subset of TOML: one `key = value` per line, `#` starts a comment,
+//! values are numbers, booleans, quoted strings or lists of numbers (`[64, 32]`).
+//! Keys that are not given keep their default (or preset) value.
+//!
+//! ```
+//! # use mapgen_2d::config::{Config, MapConfig};
+//! # let path = std::env::temp_dir().join("mapgen-2d-config-doctest.toml");
+//! # std::fs::write(&path, "size = [64, 48]\nrivers = 2\n").unwrap();
+//! let mut config = MapConfig::preset("archipelago").unwrap();
+//! config.merge_path(&path)?;
+//! let map = config.builder().build();
+//! # std::fs::remove_file(&path).unwrap();
+//! # assert_eq!(map.height.dim(), (64, 48));
+//! # Ok::<(), mapgen_2d::config::ConfigError>(())
+//! ```
+//!
+//! With the `serde` feature, the configs also implement `Serialize` and `Deserialize`, and
+//! `load` and `save` read and write full TOML, JSON or RON depending on the file extension.
+//! Enums are written in snake case, eg. `cell_selection = { center_distance = { weight = 0.5 } }`
+//! in TOML. Missing fields keep their default value, unknown fields are an error.
+
+use crate::colored_noise::{ColoredNoise, Normalization};
+use crate::error::MapgenError;
+use crate::map_builder::MapBuilder;
+use crate::mask::Falloff;
+use crate::voronoi::{Algorithm, Voronoi, VoronoiCenter};
+use crate::wave_function_collapse::{
+    CellSelection, ContextProbabilityCallback, Strategy, WaveFunctionCollapseConfiguration,
+};
+use crate::tile::{Tile, TileEnum};
+use glam::{uvec2, vec2, UVec2};
+use rand::{distributions::Uniform, rngs::StdRng, Rng, SeedableRng};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    /// Non-negative integer literal, kept exact (eg. for seeds)
+    Integer(u64),
+    Number(f64),
+    Bool(bool),
+    Str(String),
+    List(Vec<f64>),
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Integer(n) => write!(f, "{}", n),
+            Value::Number(n) => write!(f, "{}", n),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Str(s) => write!(f, "\"{}\"", s),
+            Value::List(l) => {
+                let items: Vec<String> = l.iter().map(|v| v.to_string()).collect();
+                write!(f, "[{}]", items.join(", "))
+            }
+        }
+    }
+}
+
+impl From<UVec2> for Value {
+    fn from(v: UVec2) -> Self {
+        Value::List(vec![v.x as f64, v.y as f64])
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(std::io::Error),
+    /// Malformed line (1-based line number)
+    Parse { line: usize, message: String },
+    UnknownKey(String),
+    /// The value of `key` has the wrong type or is out of range
+    Invalid { key: String, message: String },
+    /// The parameters were rejected by the `validate()` of the generator
+    Parameter(MapgenError),
+    /// The text is not valid for the format or the config, or the config can not be written
+    /// in the format (see `load` and `save`)
+    Format(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(e) => write!(f, "{}", e),
+            ConfigError::Parse { line, message } => write!(f, "line {}: {}", line, message),
+            ConfigError::UnknownKey(k) => write!(f, "unknown key '{}'", k),
+            ConfigError::Invalid { key, message } => write!(f, "invalid value for '{}': {}", key, message),
+            ConfigError::Parameter(e) => write!(f, "{}", e),
+            ConfigError::Format(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl From<std::io::Error> for ConfigError {
+    fn from(e: std::io::Error) -> Self {
+        ConfigError::Io(e)
+    }
+}
+
+impl From<MapgenError> for ConfigError {
+    fn from(e: MapgenError) -> Self {
+        ConfigError::Parameter(e)
+    }
+}
+
+fn invalid(key: &str, message: &str) -> ConfigError {
+    ConfigError::Invalid { key: key.to_string(), message: message.to_string() }
+}
+
+/// Parse `key = value` lines.
+pub fn parse(text: &str) -> Result<Vec<(String, Value)>, ConfigError> {
+    let mut r = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let err = |message: &str| ConfigError::Parse { line: i + 1, message: message.to_string() };
+
+        // Strip comments, but not inside strings
+        let mut in_string = false;
+        let end = line
+            .char_indices()
+            .find(|&(_, c)| {
+                if c == '"' {
+                    in_string = !in_string;
+                }
+                c == '#' && !in_string
+            })
+            .map_or(line.len(), |(j, _)| j);
+        let line = line[..end].trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let (key, value) = line.split_once('=').ok_or_else(|| err("expected 'key = value'"))?;
+        let (key, value) = (key.trim(), value.trim());
+        if key.is_empty() || !key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return Err(err("invalid key"));
+        }
+
+        let value = if let Some(s) = value.strip_prefix('"') {
+            Value::Str(s.strip_suffix('"').ok_or_else(|| err("unterminated string"))?.to_string())
+        } else if let Some(l) = value.strip_prefix('[') {
+            let l = l.strip_suffix(']').ok_or_else(|| err("unterminated list"))?;
+            let items = l
+                .split(',')
+                .map(str::trim)
+                .filter(|s| !s.is_empty())
+                .map(|s| s.parse().map_err(|_| err("expected a number in list")))
+                .collect::<Result<_, _>>()?;
+            Value::List(items)
+        } else if value == "true" || value == "false" {
+            Value::Bool(value == "true")
+        } else if let Ok(n) = value.parse() {
+            Value::Integer(n)
+        } else {
+            Value::Number(value.parse().map_err(|_| err("expected a number, boolean, string or list"))?)
+        };
+        r.push((key.to_string(), value));
+    }
+    Ok(r)
+}
+
+/// Typed access, producing `ConfigError::Invalid` on mismatch.
+impl Value {
+    pub fn as_f64(&self, key: &str) -> Result<f64, ConfigError> {
+        match self {
+            Value::Integer(n) => Ok(*n as f64),
+            Value::Number(n) => Ok(*n),
+            _ => Err(invalid(key, "expected a number")),
+        }
+    }
+
+    pub fn as_u64(&self, key: &str) -> Result<u64, ConfigError> {
+        match self {
+            Value::Integer(n) => Ok(*n),
+            _ => Err(invalid(key, "expected a non-negative integer")),
+        }
+    }
+
+    pub fn as_bool(&self, key: &str) -> Result<bool, ConfigError> {
+        match self {
+            Value::Bool(b) => Ok(*b),
+            _ => Err(invalid(key, "expected true or false")),
+        }
+    }
+
+    pub fn as_str(&self, key: &str) -> Result<&str, ConfigError> {
+        match self {
+            Value::Str(s) => Ok(s),
+            _ => Err(invalid(key, "expected a string")),
+        }
+    }
+
+    pub fn as_uvec2(&self, key: &str) -> Result<UVec2, ConfigError> {
+        match self {
+            Value::List(l) if l.len() == 2 && l.iter().all(|v| *v >= 0.0 && v.fract() == 0.0) => {
+                Ok(uvec2(l[0] as u32, l[1] as u32))
+            }
+            _ => Err(invalid(key, "expected a list of two non-negative integers")),
+        }
+    }
+}
+
+/// A set of generator parameters that can be read from and written to text.
+pub trait Config: Default {
+    /// All parameters, in the order they are written.
+    fn fields(&self) -> Vec<(&'static str, Value)>;
+
+    /// Set a single parameter.
+    fn set(&mut self, key: &str, value: &Value) -> Result<(), ConfigError>;
+
+    /// Check the parameters for consistency, called after loading.
+    fn validate(&self) -> Result<(), ConfigError> {
+        Ok(())
+    }
+
+    /// Overwrite the parameters given in `text`, keeping the others.
+    fn merge_str(&mut self, text: &str) -> Result<(), ConfigError> {
+        for (key, value) in parse(text)? {
+            self.set(&key, &value)?;
+        }
+        self.validate()
+    }
+
+    fn merge_path<P: AsRef<Path>>(&mut self, path: P) -> Result<(), ConfigError> {
+        self.merge_str(&fs::read_to_string(path)?)
+    }
+
+    /// Defaults, overwritten by the parameters given in `text`.
+    fn from_str(text: &str) -> Result<Self, ConfigError> {
+        let mut r = Self::default();
+        r.merge_str(text)?;
+        Ok(r)
+    }
+
+    fn from_path<P: AsRef<Path>>(path: P) -> Result<Self, ConfigError> {
+        Self::from_str(&fs::read_to_string(path)?)
+    }
+
+    fn to_text(&self) -> String {
+        self.fields().iter().map(|(k, v)| format!("{} = {}\n", k, v)).collect()
+    }
+
+    fn to_path<P: AsRef<Path>>(&self, path: P) -> Result<(), ConfigError> {
+        Ok(fs::write(path, self.to_text())?)
+    }
+}
+
+/// File formats of `load` and `save` (feature `serde`).
+#[cfg(feature = "serde")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Toml,
+    Json,
+    Ron,
+}
+
+#[cfg(feature = "serde")]
+impl Format {
+    /// The format for the extension of `path`: `.toml`, `.json` or `.ron`.
+    pub fn of_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "toml" => Some(Format::Toml),
+            "json" => Some(Format::Json),
+            "ron" => Some(Format::Ron),
+            _ => None,
+        }
+    }
+
+    fn of_path_or_err(path: &Path) -> Result<Self, ConfigError> {
+        Self::of_path(path).ok_or_else(|| ConfigError::Format(format!("unknown format of '{}'", path.display())))
+    }
+}
+
+/// Parse `text` in `format` and validate the result (feature `serde`).
+#[cfg(feature = "serde")]
+pub fn from_str_as<C>(text: &str, format: Format) -> Result<C, ConfigError>
+where
+    C: Config + serde::de::DeserializeOwned,
+{
+    let err = |e: &dyn fmt::Display| ConfigError::Format(e.to_string());
+    let config: C = match format {
+        Format::Toml => toml::from_str(text).map_err(|e| err(&e))?,
+        Format::Json => serde_json::from_str(text).map_err(|e| err(&e))?,
+        Format::Ron => ron::from_str(text).map_err(|e| err(&e))?,
+    };
+    config.validate()?;
+    Ok(config)
+}
+
+/// `config` as text in `format` (feature `serde`).
+#[cfg(feature = "serde")]
+pub fn to_string_as<C: serde::Serialize>(config: &C, format: Format) -> Result<String, ConfigError> {
+    let err = |e: &dyn fmt::Display| ConfigError::Format(e.to_string());
+    match format {
+        Format::Toml => toml::to_string_pretty(config).map_err(|e| err(&e)),
+        Format::Json => serde_json::to_string_pretty(config).map_err(|e| err(&e)),
+        Format::Ron => ron::ser::to_string_pretty(config, Default::default()).map_err(|e| err(&e)),
+    }
+}
+
+/// Read a config in the format given by the extension of `path` (see `Format::of_path`)
+/// and validate it (feature `serde`).
+#[cfg(feature = "serde")]
+pub fn load<C, P>(path: P) -> Result<C, ConfigError>
+where
+    C: Config + serde::de::DeserializeOwned,
+    P: AsRef<Path>,
+{
+    let format = Format::of_path_or_err(path.as_ref())?;
+    from_str_as(&fs::read_to_string(path)?, format)
+}
+
+/// Write a config in the format given by the extension of `path` (feature `serde`).
+#[cfg(feature = "serde")]
+pub fn save<C, P>(config: &C, path: P) -> Result<(), ConfigError>
+where
+    C: serde::Serialize,
+    P: AsRef<Path>,
+{
+    let format = Format::of_path_or_err(path.as_ref())?;
+    Ok(fs::write(path, to_string_as(config, format)?)?)
+}
+
+/// Parameters of `ColoredNoise`.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(default, deny_unknown_fields))]
+pub struct NoiseConfig {
+    pub size: UVec2,
+    pub color: f64,
+    pub seed: u64,
+    pub tileable: bool,
+    pub padded: bool,
+    pub normalization: Normalization,
+}
+
+impl Default for NoiseConfig {
+    fn default() -> Self {
+        let n = ColoredNoise::default();
+        Self {
+            size: n.size,
+            color: n.color,
+            seed: n.seed,
+            tileable: n.tileable,
+            padded: n.padded,
+            normalization: n.normalization,
+        }
+    }
+}
+
+impl NoiseConfig {
+    /// "white", "pink", "brown" or "blue" noise
+    pub fn preset(name: &str) -> Option<Self> {
+        let color = match name {
+            "white" => 0.0,
+            "pink" => -1.0,
+            "brown" => -2.0,
+            "blue" => 1.0,
+            _ => return None,
+        };
+        Some(Self { color, ..Default::default() })
+    }
+
+    pub fn noise(&self) -> ColoredNoise {
+        ColoredNoise {
+            size: self.size,
+            color: self.color,
+            seed: self.seed,
+            tileable: self.tileable,
+            padded: self.padded,
+            normalization: self.normalization,
+        }
+    }
+}
+
+impl Config for NoiseConfig {
+    fn fields(&self) -> Vec<(&'static str, Value)> {
+        let normalization = match self.normalization {
+            Normalization::UnitRange => Value::Str("unit_range".to_string()),
+            Normalization::Raw => Value::Str("raw".to_string()),
+            Normalization::ZScore => Value::Str("z_score".to_string()),
+            Normalization::Clamp { min, max } => Value::List(vec![min, max]),
+            Normalization::Equalize => Value::Str("equalize".to_string()),
+        };
+        vec![
+            ("size", self.size.into()),
+            ("color", Value::Number(self.color)),
+            ("seed", Value::Integer(self.seed)),
+            ("tileable", Value::Bool(self.tileable)),
+            ("padded", Value::Bool(self.padded)),
+            ("normalization", normalization),
+        ]
+    }
+
+    fn set(&mut self, key: &str, value: &Value) -> Result<(), ConfigError> {
+        match key {
+            "size" => self.size = value.as_uvec2(key)?,
+            "color" => self.color = value.as_f64(key)?,
+            "seed" => self.seed = value.as_u64(key)?,
+            "tileable" => self.tileable = value.as_bool(key)?,
+            "padded" => self.padded = value.as_bool(key)?,
+            // A `[min, max]` list selects `Normalization::Clamp`
+            "normalization" => {
+                self.normalization = match value {
+                    Value::List(l) if l.len() == 2 => Normalization::Clamp { min: l[0], max: l[1] },
+                    _ => match value.as_str(key) {
+                        Ok("unit_range") => Normalization::UnitRange,
+                        Ok("raw") => Normalization::Raw,
+                        Ok("z_score") => Normalization::ZScore,
+                        Ok("equalize") => Normalization::Equalize,
+                        _ => {
+                            return Err(invalid(
+                                key,
+                                "expected \"unit_range\", \"raw\", \"z_score\", \"equalize\" or [min, max]",
+                            ))
+                        }
+                    },
+                }
+            }
+            _ => return Err(ConfigError::UnknownKey(key.to_string())),
+        }
+        Ok(())
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        Ok(self.noise().validate()?)
+    }
+}
+
+/// Parameters of a `Voronoi` diagram with uniformly distributed random centers.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(default, deny_unknown_fields))]
+pub struct VoronoiConfig {
+    pub size: UVec2,
+    pub centers: usize,
+    pub seed: u64,
+    pub algorithm: Algorithm,
+    pub border_width: f32,
+}
+
+impl Default for VoronoiConfig {
+    fn default() -> Self {
+        Self { size: uvec2(100, 100), centers: 20, seed: 0, algorithm: Algorithm::default(), border_width: 1.0 }
+    }
+}
+
+impl VoronoiConfig {
+    pub fn voronoi(&self) -> Voronoi {
+        let mut rng = StdRng::seed_from_u64(self.seed);
+        let ux = Uniform::from(0.0..self.size.x as f32);
+        let uy = Uniform::from(0.0..self.size.y as f32);
+        let centers = (0..self.centers)
+            .map(|index| VoronoiCenter { position: vec2(rng.sample(ux), rng.sample(uy)), index })
+            .collect();
+        Voronoi::new(self.size, centers)
+            .algorithm(self.algorithm)
+            .border_width(self.border_width)
+    }
+}
+
+impl Config for VoronoiConfig {
+    fn fields(&self) -> Vec<(&'static str, Value)> {
+        let algorithm = match self.algorithm {
+            Algorithm::KdTree => "kd_tree",
+            Algorithm::JumpFlood => "jump_flood",
+        };
+        vec![
+            ("size", self.size.into()),
+            ("centers", Value::Integer(self.centers as u64)),
+            ("seed", Value::Integer(self.seed)),
+            ("algorithm", Value::Str(algorithm.to_string())),
+            ("border_width", Value::Number(self.border_width as f64)),
+        ]
+    }
+
+    fn set(&mut self, key: &str, value: &Value) -> Result<(), ConfigError> {
+        match key {
+            "size" => self.size = value.as_uvec2(key)?,
+            "centers" => self.centers = value.as_u64(key)? as usize,
+            "seed" => self.seed = value.as_u64(key)?,
+            "algorithm" => {
+                self.algorithm = match value.as_str(key)? {
+                    "kd_tree" => Algorithm::KdTree,
+                    "jump_flood" => Algorithm::JumpFlood,
+                    _ => return Err(invalid(key, "expected \"kd_tree\" or \"jump_flood\"")),
+                }
+            }
+            "border_width" => self.border_width = value.as_f64(key)? as f32,
+            _ => return Err(ConfigError::UnknownKey(key.to_string())),
+        }
+        Ok(())
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        Ok(self.voronoi().validate()?)
+    }
+}
+
+/// The data part of a `WaveFunctionCollapseConfiguration` (everything except the tiles and
+/// the probability callback).
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(default, deny_unknown_fields))]
+pub struct WfcConfig {
+    pub seed: u64,
+    pub size: UVec2,
+    pub neighborhood_size: u32,
+    pub cell_selection: CellSelection,
+    pub retries: u32,
+    pub strategy: Strategy,
+    pub max_bombings: u32,
+}
+
+impl Default for WfcConfig {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            size: uvec2(100, 100),
+            neighborhood_size: 1,
+            cell_selection: CellSelection::MinEntropy,
+            retries: 0,
+            strategy: Strategy::Bomb,
+            max_bombings: 100,
+        }
+    }
+}
+
+impl WfcConfig {
+    /// Apply these parameters to `configuration`.
+    pub fn apply<T, F, const N: usize>(
+        &self,
+        mut configuration: WaveFunctionCollapseConfiguration<T, F, N>,
+    ) -> WaveFunctionCollapseConfiguration<T, F, N>
+    where
+        F: ContextProbabilityCallback<T, N>,
+        T: Tile,
+    {
+        configuration.seed = self.seed;
+        configuration.size = self.size;
+        configuration
+            .neighborhood_size(self.neighborhood_size)
+            .cell_selection(self.cell_selection)
+            .retries(self.retries)
+            .strategy(self.strategy)
+            .max_bombings(self.max_bombings)
+    }
+}
+
+impl Config for WfcConfig {
+    fn fields(&self) -> Vec<(&'static str, Value)> {
+        let cell_selection = match self.cell_selection {
+            CellSelection::MaxEntropy => "max_entropy",
+            CellSelection::MinEntropy => "min_entropy",
+            CellSelection::Scanline => "scanline",
+            CellSelection::RandomTies => "random_ties",
+            CellSelection::CenterDistance { .. } => "center_distance",
+        };
+        let mut r = vec![
+            ("seed", Value::Integer(self.seed)),
+            ("size", self.size.into()),
+            ("neighborhood_size", Value::Integer(self.neighborhood_size as u64)),
+            ("cell_selection", Value::Str(cell_selection.to_string())),
+        ];
+        if let CellSelection::CenterDistance { weight } = self.cell_selection {
+            r.push(("center_distance_weight", Value::Number(weight as f64)));
+        }
+        r.push(("retries", Value::Integer(self.retries as u64)));
+        match self.strategy {
+            Strategy::Bomb => r.push(("strategy", Value::Str("bomb".to_string()))),
+            Strategy::Backtrack { max_depth } => {
+                r.push(("strategy", Value::Str("backtrack".to_string())));
+                r.push(("backtrack_depth", Value::Integer(max_depth as u64)));
+            }
+        }
+        r.push(("max_bombings", Value::Integer(self.max_bombings as u64)));
+        r
+    }
+
+    fn set(&mut self, key: &str, value: &Value) -> Result<(), ConfigError> {
+        let as_u32 = |v: &Value| {
+            u32::try_from(v.as_u64(key)?).map_err(|_| invalid(key, "too large"))
+        };
+        match key {
+            "seed" => self.seed = value.as_u64(key)?,
+            "size" => self.size = value.as_uvec2(key)?,
+            "neighborhood_size" => self.neighborhood_size = as_u32(value)?,
+            "cell_selection" => {
+                self.cell_selection = match value.as_str(key)? {
+                    "max_entropy" => CellSelection::MaxEntropy,
+                    "min_entropy" => CellSelection::MinEntropy,
+                    "scanline" => CellSelection::Scanline,
+                    "random_ties" => CellSelection::RandomTies,
+                    "center_distance" => match self.cell_selection {
+                        c @ CellSelection::CenterDistance { .. } => c,
+                        _ => CellSelection::CenterDistance { weight: 1.0 },
+                    },
+                    _ => return Err(invalid(key, "unknown cell selection")),
+                }
+            }
+            "center_distance_weight" => {
+                self.cell_selection = CellSelection::CenterDistance { weight: value.as_f64(key)? as f32 }
+            }
+            "retries" => self.retries = as_u32(value)?,
+            "strategy" => {
+                self.strategy = match value.as_str(key)? {
+                    "bomb" => Strategy::Bomb,
+                    "backtrack" => match self.strategy {
+                        s @ Strategy::Backtrack { .. } => s,
+                        Strategy::Bomb => Strategy::Backtrack { max_depth: 100 },
+                    },
+                    _ => return Err(invalid(key, "expected \"bomb\" or \"backtrack\"")),
+                }
+            }
+            "backtrack_depth" => {
+                self.strategy = Strategy::Backtrack { max_depth: value.as_u64(key)? as usize }
+            }
+            "max_bombings" => self.max_bombings = as_u32(value)?,
+            _ => return Err(ConfigError::UnknownKey(key.to_string())),
+        }
+        Ok(())
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        // The parameters do not depend on the tile type, any will do
+        crate::tile_enum! {
+            enum AnyTile { Any }
+        }
+        Ok(self.apply(AnyTile::configuration()).validate()?)
+    }
+}
+
+/// Parameters of the `MapBuilder` pipeline (with a colored noise heightmap, without biomes).
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(default, deny_unknown_fields))]
+pub struct MapConfig {
+    pub size: UVec2,
+    pub seed: u64,
+    pub color: f64,
+    pub falloff: Falloff,
+    pub falloff_strength: f64,
+    pub sea_level: f64,
+    pub largest_landmass_only: bool,
+    pub rivers: usize,
+}
+
+impl Default for MapConfig {
+    fn default() -> Self {
+        Self {
+            size: uvec2(256, 256),
+            seed: 0,
+            color: -2.0,
+            falloff: Falloff::Radial,
+            falloff_strength: 0.5,
+            sea_level: 0.4,
+            largest_landmass_only: false,
+            rivers: 0,
+        }
+    }
+}
+
+impl MapConfig {
+    /// "continent" (one large landmass), "island" (a single island with rivers) or
+    /// "archipelago" (many small islands)
+    pub fn preset(name: &str) -> Option<Self> {
+        let d = Self::default();
+        Some(match name {
+            "continent" => Self { falloff: Falloff::Edge { width: 32.0 }, sea_level: 0.3, rivers: 8, ..d },
+            "island" => Self { falloff_strength: 0.5, sea_level: 0.05, largest_landmass_only: true, rivers: 3, ..d },
+            "archipelago" => Self { color: -1.5, falloff_strength: 0.3, sea_level: 0.45, ..d },
+            _ => return None,
+        })
+    }
+
+    pub fn builder(&self) -> MapBuilder {
+        MapBuilder::new(self.size)
+            .seed(self.seed)
+            .heightmap(crate::map_builder::Heightmap::colored(self.color))
+            .falloff(self.falloff, self.falloff_strength)
+            .sea_level(self.sea_level)
+            .largest_landmass_only(self.largest_landmass_only)
+            .rivers(self.rivers)
+    }
+}
+
+impl Config for MapConfig {
+    fn fields(&self) -> Vec<(&'static str, Value)> {
+        let falloff = match self.falloff {
+            Falloff::None => "none",
+            Falloff::Radial => "radial",
+            Falloff::Square => "square",
+            Falloff::Edge { .. } => "edge",
+        };
+        let mut r = vec![
+            ("size", self.size.into()),
+            ("seed", Value::Integer(self.seed)),
+            ("color", Value::Number(self.color)),
+            ("falloff", Value::Str(falloff.to_string())),
+        ];
+        if let Falloff::Edge { width } = self.falloff {
+            r.push(("falloff_width", Value::Number(width)));
+        }
+        r.extend([
+            ("falloff_strength", Value::Number(self.falloff_strength)),
+            ("sea_level", Value::Number(self.sea_level)),
+            ("largest_landmass_only", Value::Bool(self.largest_landmass_only)),
+            ("rivers", Value::Integer(self.rivers as u64)),
+        ]);
+        r
+    }
+
+    fn set(&mut self, key: &str, value: &Value) -> Result<(), ConfigError> {
+        match key {
+            "size" => self.size = value.as_uvec2(key)?,
+            "seed" => self.seed = value.as_u64(key)?,
+            "color" => self.color = value.as_f64(key)?,
+            "falloff" => {
+                self.falloff = match value.as_str(key)? {
+                    "none" => Falloff::None,
+                    "radial" => Falloff::Radial,
+                    "square" => Falloff::Square,
+                    "edge" => match self.falloff {
+                        f @ Falloff::Edge { .. } => f,
+                        _ => Falloff::Edge { width: 16.0 },
+                    },
+                    _ => return Err(invalid(key, "unknown falloff")),
+                }
+            }
+            "falloff_width" => self.falloff = Falloff::Edge { width: value.as_f64(key)? },
+            "falloff_strength" => self.falloff_strength = value.as_f64(key)?,
+            "sea_level" => self.sea_level = value.as_f64(key)?,
+            "largest_landmass_only" => self.largest_landmass_only = value.as_bool(key)?,
+            "rivers" => self.rivers = value.as_u64(key)? as usize,
+            _ => return Err(ConfigError::UnknownKey(key.to_string())),
+        }
+        Ok(())
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        Ok(self.builder().validate()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    /// Path in the temporary directory, unique per test process
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("mapgen-2d-{}-{}", std::process::id(), name))
+    }
+
+    fn non_default_wfc() -> WfcConfig {
+        WfcConfig {
+            seed: u64::MAX,
+            size: uvec2(30, 20),
+            cell_selection: CellSelection::CenterDistance { weight: 0.25 },
+            strategy: Strategy::Backtrack { max_depth: 7 },
+            ..Default::default()
+        }
+    }
+
+    fn non_default_map() -> MapConfig {
+        MapConfig { falloff: Falloff::Edge { width: 12.0 }, rivers: 2, ..MapConfig::preset("island").unwrap() }
+    }
+
+    #[test]
+    fn parse_values() {
+        let text = "a = 1\nb = -1.5\nc = true\nd = \"x # y\" # comment\n\n  # only a comment\ne = [64, 32.5]\nf = []";
+        let r = parse(text).unwrap();
+        assert_eq!(
+            r,
+            vec![
+                ("a".to_string(), Value::Integer(1)),
+                ("b".to_string(), Value::Number(-1.5)),
+                ("c".to_string(), Value::Bool(true)),
+                ("d".to_string(), Value::Str("x # y".to_string())),
+                ("e".to_string(), Value::List(vec![64.0, 32.5])),
+                ("f".to_string(), Value::List(vec![])),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_errors() {
+        let line = |text: &str| match parse(text) {
+            Err(ConfigError::Parse { line, .. }) => line,
+            r => panic!("{:?}", r),
+        };
+        assert_eq!(line("a = 1\n\nb"), 3);
+        assert_eq!(line("# comment\nkey with space = 1"), 2);
+        assert_eq!(line("a = \"unterminated"), 1);
+        assert_eq!(line("a = 1\na = [1, x]"), 2);
+        assert_eq!(line("a = [1, 2"), 1);
+        assert_eq!(line("a = yes"), 1);
+        // The '#' inside the string does not start a comment, so the string is unterminated
+        assert_eq!(line("a = \"#\"\nb = \"#"), 2);
+    }
+
+    #[test]
+    fn merge_and_unknown_keys() {
+        let mut c = NoiseConfig::preset("pink").unwrap();
+        c.merge_str("seed = 5\nnormalization = [-1, 1]").unwrap();
+        assert_eq!(c.color, -1.0);
+        assert_eq!(c.seed, 5);
+        assert_eq!(c.normalization, Normalization::Clamp { min: -1.0, max: 1.0 });
+
+        assert!(matches!(NoiseConfig::from_str("colour = 1"), Err(ConfigError::UnknownKey(k)) if k == "colour"));
+        assert!(matches!(NoiseConfig::from_str("seed = -1"), Err(ConfigError::Invalid { .. })));
+        assert!(matches!(NoiseConfig::from_str("size = [0, 4]"), Err(ConfigError::Parameter(_))));
+        assert!(matches!(WfcConfig::from_str("retries = 5000000000"), Err(ConfigError::Invalid { .. })));
+    }
+
+    #[test]
+    fn text_round_trip() {
+        let noise = NoiseConfig { normalization: Normalization::Clamp { min: -2.0, max: 0.5 }, ..Default::default() };
+        assert_eq!(NoiseConfig::from_str(&noise.to_text()).unwrap(), noise);
+        let voronoi = VoronoiConfig { algorithm: Algorithm::JumpFlood, border_width: 0.0, ..Default::default() };
+        assert_eq!(VoronoiConfig::from_str(&voronoi.to_text()).unwrap(), voronoi);
+        assert_eq!(WfcConfig::from_str(&non_default_wfc().to_text()).unwrap(), non_default_wfc());
+    }
+
+    #[test]
+    fn path_round_trip() {
+        let path = temp_path("map.toml");
+        non_default_map().to_path(&path).unwrap();
+        let r = MapConfig::from_path(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(r.unwrap(), non_default_map());
+
+        assert!(matches!(MapConfig::from_path(temp_path("missing.toml")), Err(ConfigError::Io(_))));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_round_trip() {
+        for format in [Format::Toml, Format::Json, Format::Ron] {
+            let text = to_string_as(&non_default_wfc(), format).unwrap();
+            assert_eq!(from_str_as::<WfcConfig>(&text, format).unwrap(), non_default_wfc(), "{}", text);
+        }
+
+        for name in ["map.toml", "map.json", "map.ron"] {
+            let path = temp_path(name);
+            save(&non_default_map(), &path).unwrap();
+            let r = load::<MapConfig, _>(&path);
+            std::fs::remove_file(&path).unwrap();
+            assert_eq!(r.unwrap(), non_default_map(), "{}", name);
+        }
+        assert!(matches!(save(&non_default_map(), temp_path("map.yaml")), Err(ConfigError::Format(_))));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_defaults_and_errors() {
+        let text = "seed = 9\ncell_selection = { center_distance = { weight = 0.5 } }\nstrategy = \"bomb\"";
+        let c: WfcConfig = from_str_as(text, Format::Toml).unwrap();
+        let cell_selection = CellSelection::CenterDistance { weight: 0.5 };
+        assert_eq!(c, WfcConfig { seed: 9, cell_selection, ..Default::default() });
+
+        let c: NoiseConfig = from_str_as(r#"{"size": [8, 4], "normalization": "z_score"}"#, Format::Json).unwrap();
+        assert_eq!(c, NoiseConfig { size: uvec2(8, 4), normalization: Normalization::ZScore, ..Default::default() });
+
+        assert!(matches!(from_str_as::<NoiseConfig>("colour = 1.0", Format::Toml), Err(ConfigError::Format(_))));
+        assert!(matches!(from_str_as::<VoronoiConfig>("(centers: 0)", Format::Ron), Err(ConfigError::Parameter(_))));
+    }
+}
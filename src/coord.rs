@@ -1,8 +1,93 @@
 
+//! Coordinate types and conversions.
+//!
+//! Maps are `Array2`s indexed `a[[x, y]]`: the first axis is x (width, growing to the right),
+//! the second axis is y (height, growing downwards). `UVec2` positions and sizes are always
+//! `(x, y)`, use `as_index2` to convert them to array indices and `map_size` to get the
+//! size of an array. Iterating an array (or a `Rect`) visits positions x-major, ie. column by
+//! column; formats that expect row-major data (images, Godot) are converted in `io`.
+//! `Width` and `Height` name the two extents where the order is easy to mix up, eg.
+//! `new_map(Width(7), Height(3), 0)` instead of `Array2::zeros((7, 3))`.
 use num::Num;
-use glam::{uvec2, UVec2, IVec2, ivec2};
+use glam::{uvec2, UVec2, IVec2, ivec2, DVec2, dvec2};
 //use std::convert::From;
-use ndarray::{s, SliceInfo, SliceInfoElem, Dim};
+use ndarray::{s, Array2, SliceInfo, SliceInfoElem, Dim};
+
+/// Size of the map `a` as (width, height).
+/// All maps in this crate are indexed `a[[x, y]]`, so the first array axis is the width.
+pub fn map_size<T>(a: &Array2<T>) -> UVec2 {
+    let (sx, sy) = a.dim();
+    uvec2(sx as u32, sy as u32)
+}
+
+/// Extent of a map along x, the first array axis. Together with `Height`, for APIs where
+/// passing a bare `(usize, usize)` or `UVec2` invites mixing up the order.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Width(pub u32);
+
+/// Extent of a map along y, the second array axis, see `Width`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Height(pub u32);
+
+/// Size as used by the generators (`(x, y)`), eg.
+/// `ColoredNoise { size: size(Width(7), Height(3)), ..Default::default() }`.
+pub fn size(width: Width, height: Height) -> UVec2 {
+    uvec2(width.0, height.0)
+}
+
+/// Width and height of the map `a`, see `map_size`.
+pub fn map_dims<T>(a: &Array2<T>) -> (Width, Height) {
+    let size = map_size(a);
+    (Width(size.x), Height(size.y))
+}
+
+/// Map of the given width and height filled with `value`, with the array shape in the order
+/// of the crate's convention.
+pub fn new_map<T: Clone>(width: Width, height: Height, value: T) -> Array2<T> {
+    Array2::from_elem((width.0 as usize, height.0 as usize), value)
+}
+
+/// Position based access to maps, so that game code does not need to convert positions to
+/// array indices.
+pub trait MapAccess<T> {
+    /// Panics if `p` is outside of the map.
+    fn at(&self, p: UVec2) -> &T;
+
+    /// Panics if `p` is outside of the map.
+    fn at_mut(&mut self, p: UVec2) -> &mut T;
+
+    /// `None` if `p` is outside of the map.
+    fn get_at(&self, p: IVec2) -> Option<&T>;
+
+    /// Iterate all tiles with their positions, x-major.
+    fn iter_with_positions<'a>(&'a self) -> impl Iterator<Item = (UVec2, &'a T)>
+    where
+        T: 'a;
+}
+
+impl<T> MapAccess<T> for Array2<T> {
+    fn at(&self, p: UVec2) -> &T {
+        &self[p.as_index2()]
+    }
+
+    fn at_mut(&mut self, p: UVec2) -> &mut T {
+        &mut self[p.as_index2()]
+    }
+
+    fn get_at(&self, p: IVec2) -> Option<&T> {
+        if p.x < 0 || p.y < 0 {
+            return None;
+        }
+        self.get((p.x as usize, p.y as usize))
+    }
+
+    fn iter_with_positions<'a>(&'a self) -> impl Iterator<Item = (UVec2, &'a T)>
+    where
+        T: 'a,
+    {
+        self.indexed_iter().map(|((x, y), v)| (uvec2(x as u32, y as u32), v))
+    }
+}
 
 pub trait UCoord2 {
     type Ordinate : Num+Clone+Copy;
@@ -121,3 +206,66 @@ impl<T> UCoord2Conversions for T
 
 }
 
+/// Isometric (diamond) projection.
+/// Grid x goes to the lower right, grid y to the lower left of the screen, the top corner of
+/// tile (0, 0) is at the screen origin. `tile_size` is the size of a tile's diamond on
+/// screen, usually twice as wide as high.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Isometric {
+    pub tile_size: DVec2,
+}
+
+impl Isometric {
+    pub fn new(tile_size: DVec2) -> Self {
+        Self { tile_size }
+    }
+
+    /// Screen position of the grid position `p` (in tiles, may be fractional).
+    pub fn to_screen(&self, p: DVec2) -> DVec2 {
+        dvec2(p.x - p.y, p.x + p.y) * self.tile_size / 2.0
+    }
+
+    /// Inverse of `to_screen`.
+    pub fn to_grid(&self, screen: DVec2) -> DVec2 {
+        let s = screen * 2.0 / self.tile_size;
+        dvec2(s.y + s.x, s.y - s.x) / 2.0
+    }
+
+    /// Screen position of the center of tile `p`.
+    pub fn tile_center(&self, p: UVec2) -> DVec2 {
+        self.to_screen(p.as_dvec2() + 0.5)
+    }
+
+    /// The tile that contains screen position `screen` (may be outside of the map).
+    pub fn tile_at(&self, screen: DVec2) -> IVec2 {
+        self.to_grid(screen).floor().as_ivec2()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn non_square_axes() {
+        let a = new_map(Width(7), Height(3), 0_u8);
+        assert_eq!(a.dim(), (7, 3));
+        assert_eq!(map_size(&a), uvec2(7, 3));
+        assert_eq!(map_dims(&a), (Width(7), Height(3)));
+        assert_eq!(size(Width(7), Height(3)), uvec2(7, 3));
+    }
+
+    #[test]
+    fn positions_are_x_y() {
+        let mut a = new_map(Width(7), Height(3), 0_u8);
+        *a.at_mut(uvec2(6, 2)) = 1;
+        assert_eq!(a[[6, 2]], 1);
+        assert_eq!(a.get_at(ivec2(6, 2)), Some(&1));
+        assert_eq!(a.get_at(ivec2(2, 6)), None);
+        let positions: Vec<UVec2> = a.iter_with_positions().map(|(p, _)| p).collect();
+        assert_eq!(positions.len(), 21);
+        assert_eq!(positions[1], uvec2(0, 1));
+        assert_eq!(positions[20], uvec2(6, 2));
+        assert_eq!(uvec2(6, 2).as_index2(), (6, 2));
+    }
+}
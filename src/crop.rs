@@ -0,0 +1,171 @@
+
+use crate::coord::{map_size, MapAccess};
+use crate::rect::Rect;
+use glam::{ivec2, IVec2, UVec2};
+use ndarray::Array2;
+
+/// How tiles outside of a map are read by `crop_with` and `pad`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Edge<T> {
+    /// Use this value
+    Fill(T),
+    /// Use the nearest tile of the map
+    Clamp,
+    /// Continue on the opposite side of the map (tileable maps)
+    Wrap,
+}
+
+impl<T: Clone> Edge<T> {
+    /// Tile at `p`, which may be outside of `a`.
+    /// Panics for `Clamp` and `Wrap` if `a` is empty.
+    pub fn get(&self, a: &Array2<T>, p: IVec2) -> T {
+        if let Some(t) = a.get_at(p) {
+            return t.clone();
+        }
+        let size = map_size(a).as_ivec2();
+        match self {
+            Edge::Fill(v) => v.clone(),
+            Edge::Clamp => a.at(p.clamp(IVec2::ZERO, size - 1).as_uvec2()).clone(),
+            Edge::Wrap => a.at(ivec2(p.x.rem_euclid(size.x), p.y.rem_euclid(size.y)).as_uvec2()).clone(),
+        }
+    }
+}
+
+/// Copy of the part of `a` covered by `rect`, `None` if `rect` is not completely within `a`.
+pub fn crop<T: Clone>(a: &Array2<T>, rect: Rect) -> Option<Array2<T>> {
+    match rect.end().cmple(map_size(a)).all() {
+        true => Some(rect.view(a).to_owned()),
+        false => None,
+    }
+}
+
+/// Map of size `size` copied from `a` starting at `anchor`, which may be partially or
+/// completely outside of `a`, tiles outside are read according to `edge`.
+pub fn crop_with<T: Clone>(a: &Array2<T>, anchor: IVec2, size: UVec2, edge: &Edge<T>) -> Array2<T> {
+    Array2::from_shape_fn((size.x as usize, size.y as usize), |(x, y)| {
+        edge.get(a, anchor + ivec2(x as i32, y as i32))
+    })
+}
+
+/// `a` with `border` tiles added on each side (`border.x` left and right, `border.y` top and
+/// bottom), filled according to `edge`.
+pub fn pad<T: Clone>(a: &Array2<T>, border: UVec2, edge: &Edge<T>) -> Array2<T> {
+    crop_with(a, -border.as_ivec2(), map_size(a) + 2 * border, edge)
+}
+
+/// Copy `source` into `target` with the (0, 0) tile of `source` at `at`.
+/// Tiles that fall outside of `target` are dropped, or with `wrap` written to the opposite
+/// side of `target` (which must not be empty then).
+/// Returns `true` if all of `source` fit into `target` without clipping or wrapping.
+pub fn embed<T: Clone>(target: &mut Array2<T>, source: &Array2<T>, at: IVec2, wrap: bool) -> bool {
+    let size = map_size(target).as_ivec2();
+    let mut fit = true;
+    for (p, t) in source.iter_with_positions() {
+        let mut q = at + p.as_ivec2();
+        if target.get_at(q).is_none() {
+            fit = false;
+            match wrap {
+                true => q = ivec2(q.x.rem_euclid(size.x), q.y.rem_euclid(size.y)),
+                false => continue,
+            }
+        }
+        *target.at_mut(q.as_uvec2()) = t.clone();
+    }
+    fit
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use glam::uvec2;
+
+    /// 3x2 map with tile value 10 * x + y
+    fn map() -> Array2<u32> {
+        Array2::from_shape_fn((3, 2), |(x, y)| 10 * x as u32 + y as u32)
+    }
+
+    #[test]
+    fn crop_inside_and_out_of_bounds() {
+        let a = map();
+        let c = crop(&a, Rect::new(uvec2(1, 0), uvec2(2, 2))).unwrap();
+        assert_eq!(c, ndarray::arr2(&[[10, 11], [20, 21]]));
+        assert_eq!(crop(&a, Rect::from_size(uvec2(3, 2))), Some(a.clone()));
+        assert_eq!(crop(&a, Rect::new(uvec2(2, 0), uvec2(2, 2))), None);
+        assert_eq!(crop(&a, Rect::new(uvec2(0, 1), uvec2(1, 2))), None);
+    }
+
+    #[test]
+    fn crop_with_fill() {
+        let c = crop_with(&map(), ivec2(-1, 1), uvec2(2, 2), &Edge::Fill(99));
+        assert_eq!(c, ndarray::arr2(&[[99, 99], [1, 99]]));
+    }
+
+    #[test]
+    fn crop_with_clamp() {
+        let c = crop_with(&map(), ivec2(2, -2), uvec2(3, 5), &Edge::Clamp);
+        assert_eq!(c.row(0).to_vec(), vec![20, 20, 20, 21, 21]);
+        assert_eq!(c.row(2).to_vec(), vec![20, 20, 20, 21, 21]);
+    }
+
+    #[test]
+    fn crop_with_wrap() {
+        let c = crop_with(&map(), ivec2(-1, -1), uvec2(5, 3), &Edge::Wrap);
+        assert_eq!(c.column(0).to_vec(), vec![21, 1, 11, 21, 1]);
+        assert_eq!(c.row(0).to_vec(), vec![21, 20, 21]);
+    }
+
+    #[test]
+    fn crop_with_completely_outside() {
+        let c = crop_with(&map(), ivec2(10, -10), uvec2(2, 2), &Edge::Fill(7));
+        assert!(c.iter().all(|&t| t == 7));
+        let c = crop_with(&map(), ivec2(10, -10), uvec2(1, 1), &Edge::Clamp);
+        assert_eq!(c[[0, 0]], 20);
+    }
+
+    #[test]
+    fn pad_border() {
+        let a = map();
+        let p = pad(&a, uvec2(1, 2), &Edge::Fill(0));
+        assert_eq!(p.dim(), (5, 6));
+        assert_eq!(crop(&p, Rect::new(uvec2(1, 2), uvec2(3, 2))), Some(a));
+        assert_eq!(p.iter().filter(|&&t| t == 0).count(), 5 * 6 - 6 + 1);
+
+        let p = pad(&map(), uvec2(1, 0), &Edge::Wrap);
+        assert_eq!(p.row(0).to_vec(), vec![20, 21]);
+        assert_eq!(p.row(4).to_vec(), vec![0, 1]);
+    }
+
+    #[test]
+    fn embed_fits() {
+        let mut target = Array2::zeros((5, 5));
+        assert!(embed(&mut target, &map(), ivec2(2, 3), false));
+        assert_eq!(target[[2, 3]], 0);
+        assert_eq!(target[[4, 4]], 21);
+        assert_eq!(target.iter().filter(|&&t| t != 0).count(), 5);
+    }
+
+    #[test]
+    fn embed_clips() {
+        let mut target = Array2::zeros((4, 4));
+        assert!(!embed(&mut target, &map(), ivec2(-1, 3), false));
+        assert_eq!(target[[0, 3]], 10);
+        assert_eq!(target[[1, 3]], 20);
+        assert_eq!(target.iter().filter(|&&t| t != 0).count(), 2);
+
+        let mut target = Array2::zeros((4, 4));
+        assert!(!embed(&mut target, &map(), ivec2(10, 10), false));
+        assert!(target.iter().all(|&t| t == 0));
+    }
+
+    #[test]
+    fn embed_wraps() {
+        let mut target = Array2::zeros((4, 4));
+        assert!(!embed(&mut target, &map(), ivec2(-1, 3), true));
+        assert_eq!(target[[3, 3]], 0);
+        assert_eq!(target[[3, 0]], 1);
+        assert_eq!(target[[0, 0]], 11);
+        assert_eq!(target[[1, 0]], 21);
+        assert_eq!(target[[0, 3]], 10);
+        assert_eq!(target.iter().filter(|&&t| t != 0).count(), 5);
+    }
+}
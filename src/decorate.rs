@@ -0,0 +1,192 @@
+//! Decoration pass that runs after the base tile map is generated: decorators query the map
+//! and emit entities (chests, trees, spawners, ...) with stable ids.
+//!
+//! Ids only depend on the seed, the decorator name, the position and the number of entities
+//! the decorator emitted before at that position, so a game can store the ids of looted or
+//! removed entities and filter them out when the same seed is generated again. Each decorator
+//! gets its own random numbers derived from its name, adding or reordering decorators does not
+//! change the output of the others (as long as they do not query `entities`).
+//!
+//! ```
+//! # use mapgen_2d::decorate::{DecorationContext, Decorations};
+//! # use ndarray::Array2;
+//! # use rand::Rng;
+//! # const FLOOR: u8 = 1;
+//! # #[derive(Clone, Debug, PartialEq)]
+//! # enum Kind { Chest }
+//! # let (seed, map) = (42, Array2::from_elem((16, 12), FLOOR));
+//! let entities = Decorations::new(seed)
+//!     .decorator("chests", |ctx: &mut DecorationContext<u8, Kind>| {
+//!         for p in ctx.map_positions_where(|t| *t == FLOOR) {
+//!             if ctx.rng_at(p).gen_bool(0.1) {
+//!                 ctx.emit(p, Kind::Chest);
+//!             }
+//!         }
+//!     })
+//!     .run(&map);
+//! # let looted = vec![entities[0].id];
+//! let entities: Vec<_> = entities.into_iter().filter(|e| !looted.contains(&e.id)).collect();
+//! ```
+
+use crate::coord::MapAccess;
+use crate::neighborhood::{find_all_within, find_nearest, Metric};
+use crate::seed::derive_seed;
+use glam::UVec2;
+use ndarray::Array2;
+use rand::{rngs::StdRng, SeedableRng};
+use std::collections::HashMap;
+
+/// Id of an emitted entity, stable across regenerations with the same seed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct EntityId(pub u64);
+
+/// An entity emitted by a decorator.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Entity<K> {
+    pub id: EntityId,
+    pub kind: K,
+    pub position: UVec2,
+}
+
+/// Id of the `n`th entity emitted by decorator `decorator` at `position`.
+pub fn entity_id(seed: u64, decorator: &str, position: UVec2, n: u32) -> EntityId {
+    let seed = decorator_seed(seed, decorator);
+    let position = ((position.y as u64) << 32) | position.x as u64;
+    EntityId(derive_seed(derive_seed(seed, position), n as u64))
+}
+
+/// Seed of a decorator, derived from its name with FNV-1a (which unlike the std hashers is
+/// stable across Rust versions).
+fn decorator_seed(seed: u64, decorator: &str) -> u64 {
+    let hash = decorator.bytes().fold(0xcbf2_9ce4_8422_2325_u64, |h, b| {
+        (h ^ b as u64).wrapping_mul(0x0100_0000_01b3)
+    });
+    derive_seed(seed, hash)
+}
+
+/// What a decorator can see and do.
+pub struct DecorationContext<'a, T, K> {
+    /// The base tile map
+    pub map: &'a Array2<T>,
+    /// Seed of this decorator
+    pub seed: u64,
+    /// Random numbers of this decorator. Draws depend on everything the decorator did
+    /// before, use `rng_at` for decisions that should stay the same when other parts of the
+    /// map change.
+    pub rng: StdRng,
+    /// Entities emitted by earlier decorators
+    pub entities: &'a [Entity<K>],
+    name: &'a str,
+    world_seed: u64,
+    emitted: Vec<Entity<K>>,
+    counts: HashMap<UVec2, u32>,
+}
+
+impl<T, K> DecorationContext<'_, T, K> {
+    /// Emit an entity at `position` and return its id.
+    pub fn emit(&mut self, position: UVec2, kind: K) -> EntityId {
+        let n = self.counts.entry(position).or_insert(0);
+        let id = entity_id(self.world_seed, self.name, position, *n);
+        *n += 1;
+        self.emitted.push(Entity { id, kind, position });
+        id
+    }
+
+    /// Entities emitted by this decorator so far
+    pub fn emitted(&self) -> &[Entity<K>] {
+        &self.emitted
+    }
+
+    /// Random numbers that only depend on the decorator and `position`.
+    pub fn rng_at(&self, position: UVec2) -> StdRng {
+        let position = ((position.y as u64) << 32) | position.x as u64;
+        StdRng::seed_from_u64(derive_seed(self.seed, position))
+    }
+
+    /// Positions of all tiles for which `pred` is true, in scanline order.
+    pub fn map_positions_where<F: Fn(&T) -> bool>(&self, pred: F) -> Vec<UVec2> {
+        let mut positions: Vec<UVec2> = self
+            .map
+            .iter_with_positions()
+            .filter(|(_, t)| pred(t))
+            .map(|(p, _)| p)
+            .collect();
+        positions.sort_by_key(|p| (p.y, p.x));
+        positions
+    }
+
+    /// Nearest tile to `from` for which `pred` is true, see `neighborhood::find_nearest`.
+    pub fn nearest<F: Fn(&T) -> bool>(&self, from: UVec2, pred: F, metric: Metric) -> Option<UVec2> {
+        find_nearest(self.map, from, pred, metric)
+    }
+
+    /// Tiles within `radius` of `from` for which `pred` is true, see
+    /// `neighborhood::find_all_within`.
+    pub fn within<F: Fn(&T) -> bool>(&self, from: UVec2, radius: u32, pred: F, metric: Metric) -> Vec<UVec2> {
+        find_all_within(self.map, from, radius, pred, metric)
+    }
+
+    /// Distance from `position` to the nearest entity (of earlier decorators or emitted by
+    /// this one) for which `pred` is true.
+    pub fn entity_distance<F: Fn(&Entity<K>) -> bool>(&self, position: UVec2, pred: F, metric: Metric) -> Option<u32> {
+        self.entities
+            .iter()
+            .chain(self.emitted.iter())
+            .filter(|e| pred(e))
+            .map(|e| metric(e.position.as_ivec2() - position.as_ivec2()))
+            .min()
+    }
+}
+
+/// A decorator run by `Decorations::run`.
+/// Implemented for closures `FnMut(&mut DecorationContext<T, K>)`.
+pub trait Decorator<T, K> {
+    fn decorate(&mut self, context: &mut DecorationContext<T, K>);
+}
+
+impl<T, K, F: FnMut(&mut DecorationContext<T, K>)> Decorator<T, K> for F {
+    fn decorate(&mut self, context: &mut DecorationContext<T, K>) {
+        self(context)
+    }
+}
+
+/// Named decorators that are run in the order they were added.
+pub struct Decorations<'a, T, K> {
+    pub seed: u64,
+    pub decorators: Vec<(String, Box<dyn Decorator<T, K> + 'a>)>,
+}
+
+impl<'a, T, K> Decorations<'a, T, K> {
+    pub fn new(seed: u64) -> Self {
+        Self { seed, decorators: Vec::new() }
+    }
+
+    /// Add a decorator. `name` is part of the ids of its entities and must be unique.
+    pub fn decorator<D: Decorator<T, K> + 'a>(mut self, name: &str, decorator: D) -> Self {
+        assert!(self.decorators.iter().all(|(n, _)| n != name), "duplicate decorator {}", name);
+        self.decorators.push((name.to_string(), Box::new(decorator)));
+        self
+    }
+
+    /// Run all decorators on `map` and return their entities in the order they were emitted.
+    pub fn run(&mut self, map: &Array2<T>) -> Vec<Entity<K>> {
+        let mut entities = Vec::new();
+        for (name, decorator) in self.decorators.iter_mut() {
+            let seed = decorator_seed(self.seed, name);
+            let mut context = DecorationContext {
+                map,
+                seed,
+                rng: StdRng::seed_from_u64(seed),
+                entities: &entities,
+                name,
+                world_seed: self.seed,
+                emitted: Vec::new(),
+                counts: HashMap::new(),
+            };
+            decorator.decorate(&mut context);
+            let emitted = context.emitted;
+            entities.extend(emitted);
+        }
+        entities
+    }
+}
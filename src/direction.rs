@@ -0,0 +1,129 @@
+//! Named grid directions. North is negative y (the top of the map), like the edges of
+//! `WaveFunctionCollapseConfiguration::borders` and the autotile masks in `neighborhood`.
+
+use glam::{ivec2, IVec2};
+
+/// One of the 4 directions to the direct neighbors of a tile.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Direction {
+    North,
+    East,
+    South,
+    West,
+}
+
+impl Direction {
+    /// All directions clockwise, starting at north
+    pub const ALL: [Direction; 4] = [Direction::North, Direction::East, Direction::South, Direction::West];
+
+    /// Offset to the neighbor in this direction
+    pub fn offset(self) -> IVec2 {
+        match self {
+            Direction::North => ivec2(0, -1),
+            Direction::East => ivec2(1, 0),
+            Direction::South => ivec2(0, 1),
+            Direction::West => ivec2(-1, 0),
+        }
+    }
+
+    /// The direction with offset `offset`, `None` if it is not the offset of a direct neighbor.
+    pub fn from_offset(offset: IVec2) -> Option<Self> {
+        Self::ALL.into_iter().find(|d| d.offset() == offset)
+    }
+
+    pub fn opposite(self) -> Self {
+        self.rotate_cw().rotate_cw()
+    }
+
+    /// The direction rotated by 90° clockwise (north becomes east)
+    pub fn rotate_cw(self) -> Self {
+        Self::ALL[(self.index() + 1) % 4]
+    }
+
+    /// The direction rotated by 90° counterclockwise (north becomes west)
+    pub fn rotate_ccw(self) -> Self {
+        Self::ALL[(self.index() + 3) % 4]
+    }
+
+    /// Position in `ALL`, eg. the index of the edge in
+    /// `WaveFunctionCollapseConfiguration::borders`
+    pub fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// One of the 8 directions to the direct and diagonal neighbors of a tile.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Direction8 {
+    North,
+    NorthEast,
+    East,
+    SouthEast,
+    South,
+    SouthWest,
+    West,
+    NorthWest,
+}
+
+impl Direction8 {
+    /// All directions clockwise, starting at north
+    pub const ALL: [Direction8; 8] = [
+        Direction8::North,
+        Direction8::NorthEast,
+        Direction8::East,
+        Direction8::SouthEast,
+        Direction8::South,
+        Direction8::SouthWest,
+        Direction8::West,
+        Direction8::NorthWest,
+    ];
+
+    /// Offset to the neighbor in this direction
+    pub fn offset(self) -> IVec2 {
+        match self {
+            Direction8::North => ivec2(0, -1),
+            Direction8::NorthEast => ivec2(1, -1),
+            Direction8::East => ivec2(1, 0),
+            Direction8::SouthEast => ivec2(1, 1),
+            Direction8::South => ivec2(0, 1),
+            Direction8::SouthWest => ivec2(-1, 1),
+            Direction8::West => ivec2(-1, 0),
+            Direction8::NorthWest => ivec2(-1, -1),
+        }
+    }
+
+    /// The direction with offset `offset`, `None` if it is not the offset of a neighbor.
+    pub fn from_offset(offset: IVec2) -> Option<Self> {
+        Self::ALL.into_iter().find(|d| d.offset() == offset)
+    }
+
+    pub fn opposite(self) -> Self {
+        Self::ALL[(self.index() + 4) % 8]
+    }
+
+    /// The direction rotated by 45° clockwise (north becomes north-east)
+    pub fn rotate_cw(self) -> Self {
+        Self::ALL[(self.index() + 1) % 8]
+    }
+
+    /// The direction rotated by 45° counterclockwise (north becomes north-west)
+    pub fn rotate_ccw(self) -> Self {
+        Self::ALL[(self.index() + 7) % 8]
+    }
+
+    /// Position in `ALL`
+    pub fn index(self) -> usize {
+        self as usize
+    }
+
+    /// True for north, east, south and west
+    pub fn is_cardinal(self) -> bool {
+        self.index().is_multiple_of(2)
+    }
+}
+
+impl From<Direction> for Direction8 {
+    fn from(d: Direction) -> Self {
+        Direction8::ALL[d.index() * 2]
+    }
+}
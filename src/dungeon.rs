@@ -0,0 +1,330 @@
+//! Topology first dungeon generation: an abstract graph of rooms (a tree plus some cycles, with
+//! locks, keys and one-way edges) is created first and then embedded into the map.
+//!
+//! Rooms are laid out on a coarse grid of slots, one room per slot, and only rooms in adjacent
+//! slots are connected. This restricts the possible graphs, but guarantees that every
+//! connection can be routed as a short corridor that does not cross other rooms.
+
+use crate::progression::Progression;
+use crate::rect::Rect;
+use crate::seed::derive_seed;
+use glam::{uvec2, IVec2, UVec2};
+use ndarray::Array2;
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+use std::collections::{HashMap, VecDeque};
+
+/// Connection between two rooms.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RoomEdge {
+    pub a: usize,
+    pub b: usize,
+    /// Lock id, the edge can only be passed with the key of the same id
+    pub lock: Option<u32>,
+    /// One-way edges can only be passed from `a` to `b`
+    pub one_way: bool,
+}
+
+impl RoomEdge {
+    pub fn new(a: usize, b: usize) -> Self {
+        Self { a, b, lock: None, one_way: false }
+    }
+
+    /// Whether the edge can be entered at `room` (ignoring locks).
+    pub fn passable_from(&self, room: usize) -> bool {
+        !self.one_way || room == self.a
+    }
+}
+
+/// Abstract dungeon topology.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RoomGraph {
+    pub rooms: usize,
+    pub edges: Vec<RoomEdge>,
+    pub entrance: usize,
+    pub exit: usize,
+    /// (lock id, room containing the key)
+    pub keys: Vec<(u32, usize)>,
+}
+
+impl RoomGraph {
+    /// All (edge index, other room) pairs of edges at `room`, including one-way edges leading
+    /// into it.
+    pub fn neighbors(&self, room: usize) -> impl Iterator<Item = (usize, usize)> + '_ {
+        self.edges.iter().enumerate().filter_map(move |(i, e)| match room {
+            r if r == e.a => Some((i, e.b)),
+            r if r == e.b => Some((i, e.a)),
+            _ => None,
+        })
+    }
+
+    /// Rooms on a shortest path from `from` to `to` using only edges for which `passable`
+    /// returns true and respecting one-way edges (both ends included), `None` if `to` can not
+    /// be reached.
+    pub fn path<F: Fn(&RoomEdge) -> bool>(&self, from: usize, to: usize, passable: F) -> Option<Vec<usize>> {
+        let mut previous = vec![None; self.rooms];
+        let mut queue = VecDeque::from([from]);
+        previous[from] = Some(from);
+        while let Some(r) = queue.pop_front() {
+            if r == to {
+                let mut path = vec![to];
+                let mut r = to;
+                while r != from {
+                    r = previous[r].unwrap();
+                    path.push(r);
+                }
+                path.reverse();
+                return Some(path);
+            }
+            for (e, other) in self.neighbors(r) {
+                let edge = &self.edges[e];
+                if previous[other].is_none() && edge.passable_from(r) && passable(edge) {
+                    previous[other] = Some(r);
+                    queue.push_back(other);
+                }
+            }
+        }
+        None
+    }
+
+    /// Rooms on a shortest path from the entrance to the exit, ignoring locks (but not one-way
+    /// edges).
+    pub fn critical_path(&self) -> Vec<usize> {
+        self.path(self.entrance, self.exit, |_| true).unwrap_or_default()
+    }
+
+    /// Rooms reachable from `from` using only edges for which `passable` returns true and
+    /// respecting one-way edges.
+    pub fn reachable<F: Fn(&RoomEdge) -> bool>(&self, from: usize, passable: F) -> Vec<bool> {
+        let mut seen = vec![false; self.rooms];
+        let mut stack = vec![from];
+        seen[from] = true;
+        while let Some(r) = stack.pop() {
+            for (e, other) in self.neighbors(r) {
+                let edge = &self.edges[e];
+                if !seen[other] && edge.passable_from(r) && passable(edge) {
+                    seen[other] = true;
+                    stack.push(other);
+                }
+            }
+        }
+        seen
+    }
+}
+
+/// Result of `DungeonGenerator::generate`.
+#[derive(Clone, Debug)]
+pub struct Dungeon {
+    pub graph: RoomGraph,
+    /// Area of each room
+    pub rooms: Vec<Rect>,
+    /// Tiles of the corridor of each edge, from room `a` to room `b` (including the tiles
+    /// inside the rooms)
+    pub corridors: Vec<Vec<UVec2>>,
+    /// `true` = floor (rooms and corridors)
+    pub floor: Array2<bool>,
+}
+
+#[derive(Clone, Debug)]
+pub struct DungeonGenerator {
+    pub size: UVec2,
+    pub seed: u64,
+    /// Size of a room slot, rooms are at most 2 tiles smaller
+    pub cell_size: UVec2,
+    pub min_room_size: UVec2,
+    /// Number of rooms, limited by the number of slots
+    pub rooms: usize,
+    /// Number of edges added to the spanning tree (if possible), each closes a cycle
+    pub cycles: usize,
+    /// Number of locked edges on the critical path, see `Progression`
+    pub locks: usize,
+    /// Number of one-way edges (if possible), see `Progression`
+    pub one_way: usize,
+}
+
+impl Default for DungeonGenerator {
+    fn default() -> Self {
+        Self {
+            size: uvec2(80, 48),
+            seed: 0,
+            cell_size: uvec2(16, 12),
+            min_room_size: uvec2(4, 3),
+            rooms: 12,
+            cycles: 2,
+            locks: 2,
+            one_way: 1,
+        }
+    }
+}
+
+impl DungeonGenerator {
+    pub fn generate(&self) -> Dungeon {
+        let mut rng = StdRng::seed_from_u64(derive_seed(self.seed, 0));
+        let (slots, graph) = self.graph(&mut rng);
+
+        let mut floor = Array2::from_elem((self.size.x as usize, self.size.y as usize), false);
+        let rooms: Vec<Rect> = slots.iter().map(|&s| self.place_room(s, &mut rng)).collect();
+        for room in &rooms {
+            room.view_mut(&mut floor).fill(true);
+        }
+
+        let corridors: Vec<Vec<UVec2>> = graph
+            .edges
+            .iter()
+            .map(|e| {
+                let corridor = route(center(&rooms[e.a]), center(&rooms[e.b]), rng.gen());
+                for p in &corridor {
+                    floor[[p.x as usize, p.y as usize]] = true;
+                }
+                corridor
+            })
+            .collect();
+
+        Dungeon { graph, rooms, corridors, floor }
+    }
+
+    /// Grow a spanning tree over the slot grid, add cycles, pick entrance/exit and place locks.
+    /// Returns the slot of each room along with the graph.
+    fn graph(&self, rng: &mut StdRng) -> (Vec<UVec2>, RoomGraph) {
+        let grid = self.size / self.cell_size.max(UVec2::ONE);
+        let n = self.rooms.min((grid.x * grid.y) as usize);
+        let mut graph = RoomGraph::default();
+        if n == 0 {
+            return (Vec::new(), graph);
+        }
+
+        let neighbors = |s: UVec2| {
+            [IVec2::X, IVec2::Y, IVec2::NEG_X, IVec2::NEG_Y]
+                .into_iter()
+                .map(move |d| s.as_ivec2() + d)
+                .filter(move |q| q.cmpge(IVec2::ZERO).all() && q.cmplt(grid.as_ivec2()).all())
+                .map(|q| q.as_uvec2())
+        };
+
+        let mut slots = vec![uvec2(rng.gen_range(0..grid.x), rng.gen_range(0..grid.y))];
+        let mut index: HashMap<UVec2, usize> = HashMap::from([(slots[0], 0)]);
+        while slots.len() < n {
+            let from = rng.gen_range(0..slots.len());
+            let free: Vec<UVec2> = neighbors(slots[from]).filter(|q| !index.contains_key(q)).collect();
+            if let Some(&q) = free.choose(rng) {
+                index.insert(q, slots.len());
+                graph.edges.push(RoomEdge::new(from, slots.len()));
+                slots.push(q);
+            }
+        }
+        graph.rooms = n;
+
+        // Extra edges between adjacent rooms that are not connected yet
+        let mut candidates: Vec<(usize, usize)> = slots
+            .iter()
+            .enumerate()
+            .flat_map(|(i, &s)| neighbors(s).filter_map(|q| index.get(&q)).map(move |&j| (i, j)))
+            .filter(|&(i, j)| i < j && !graph.edges.iter().any(|e| (e.a, e.b) == (i, j) || (e.a, e.b) == (j, i)))
+            .collect();
+        candidates.shuffle(rng);
+        for &(a, b) in candidates.iter().take(self.cycles) {
+            graph.edges.push(RoomEdge::new(a, b));
+        }
+
+        // The exit is the room farthest from the entrance
+        graph.entrance = 0;
+        graph.exit = (0..n)
+            .max_by_key(|&r| graph.path(0, r, |_| true).map_or(0, |p| p.len()))
+            .unwrap_or(0);
+        let progression = Progression { seed: rng.gen(), locks: self.locks, one_way: self.one_way };
+        progression.apply(&mut graph);
+        (slots, graph)
+    }
+
+    /// Random room inside slot `slot`, leaving at least one tile of space at each side
+    fn place_room(&self, slot: UVec2, rng: &mut StdRng) -> Rect {
+        let max = self.cell_size.max(uvec2(3, 3)) - uvec2(2, 2);
+        let min = self.min_room_size.clamp(UVec2::ONE, max);
+        let size = uvec2(rng.gen_range(min.x..=max.x), rng.gen_range(min.y..=max.y));
+        let slack = max - size;
+        let offset = uvec2(rng.gen_range(0..=slack.x), rng.gen_range(0..=slack.y));
+        Rect::new(slot * self.cell_size + UVec2::ONE + offset, size)
+    }
+}
+
+fn center(r: &Rect) -> UVec2 {
+    r.anchor + r.size / 2
+}
+
+/// L-shaped path from `a` to `b`, horizontal or vertical first
+fn route(a: UVec2, b: UVec2, horizontal_first: bool) -> Vec<UVec2> {
+    let corner = match horizontal_first {
+        true => uvec2(b.x, a.y),
+        false => uvec2(a.x, b.y),
+    };
+    let mut path = vec![a];
+    for target in [corner, b] {
+        let mut p = *path.last().unwrap();
+        while p != target {
+            p = (p.as_ivec2() + (target.as_ivec2() - p.as_ivec2()).signum()).as_uvec2();
+            path.push(p);
+        }
+    }
+    path
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mask::label_components;
+    use crate::progression::solve;
+
+    /// Dungeons of the default generator for seeds `0..10`
+    fn dungeons() -> impl Iterator<Item = (u64, Dungeon)> {
+        (0..10).map(|seed| (seed, DungeonGenerator { seed, ..Default::default() }.generate()))
+    }
+
+    #[test]
+    fn rooms_do_not_overlap() {
+        let map = Rect::from_size(uvec2(80, 48));
+        for (seed, d) in dungeons() {
+            assert_eq!(d.rooms.len(), 12);
+            for (i, a) in d.rooms.iter().enumerate() {
+                assert!(a.size.cmpge(uvec2(4, 3)).all(), "seed {}: room {} is {}", seed, i, a.size);
+                assert!(map.intersect(a) == Some(*a), "seed {}: room {} is outside", seed, i);
+                // Rooms keep a tile of space to each other
+                let grown = Rect::new(a.anchor - UVec2::ONE, a.size + 2 * UVec2::ONE);
+                for b in d.rooms[i + 1..].iter() {
+                    assert!(!grown.intersects(b), "seed {}: {:?} and {:?}", seed, a, b);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn rooms_are_connected() {
+        for (seed, d) in dungeons() {
+            let (_, sizes) = label_components(&d.floor);
+            assert_eq!(sizes.len(), 2, "seed {}: floor is not connected", seed);
+
+            let all = d.graph.reachable(d.graph.entrance, |_| true);
+            assert!(all.iter().all(|&r| r), "seed {}", seed);
+            assert!(d.graph.edges.len() >= d.rooms.len() - 1);
+
+            for (e, corridor) in d.graph.edges.iter().zip(d.corridors.iter()) {
+                assert_eq!(corridor[0], center(&d.rooms[e.a]));
+                assert_eq!(*corridor.last().unwrap(), center(&d.rooms[e.b]));
+                assert!(corridor.windows(2).all(|w| (w[0].as_ivec2() - w[1].as_ivec2()).length_squared() == 1));
+                assert!(corridor.iter().all(|p| d.floor[[p.x as usize, p.y as usize]]));
+            }
+            assert!(solve(&d.graph).completable, "seed {}", seed);
+        }
+    }
+
+    #[test]
+    fn deterministic_and_limited_by_slots() {
+        let generator = DungeonGenerator { seed: 3, rooms: 100, cycles: 0, ..Default::default() };
+        let d = generator.generate();
+        // 5x4 slots of 16x12 tiles
+        assert_eq!(d.graph.rooms, 20);
+        assert_eq!(d.graph.edges.len(), 19);
+        assert_eq!(d.floor, generator.generate().floor);
+
+        let empty = DungeonGenerator { rooms: 0, ..Default::default() }.generate();
+        assert!(empty.rooms.is_empty() && !empty.floor.iter().any(|&f| f));
+    }
+}
@@ -0,0 +1,58 @@
+//! Crate-wide error for invalid generator parameters, returned by the `validate()` method of
+//! the generators and their checked entry points: `try_generate()` of `ColoredNoise`,
+//! `Voronoi` and `WaveFunctionCollapse`, `try_build()` of `WaveFunctionCollapseConfiguration`
+//! and `MapBuilder`.
+//!
+//! Wave function collapse can also fail at runtime (eg. on a contradiction), these failures
+//! are `WfcError`s, which `WaveFunctionCollapse::try_generate` reports as `MapgenError::Wfc`.
+
+use crate::wave_function_collapse::WfcError;
+use glam::UVec2;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MapgenError {
+    /// The size given by `parameter` is zero along an axis
+    ZeroSize { parameter: &'static str, size: UVec2 },
+    /// `parameter` is out of range or inconsistent with other parameters
+    InvalidParameter { parameter: &'static str, message: String },
+    /// `N` of a wave function collapse is not the number of tiles (`Tile::MAX`)
+    TileCount { n: usize, tiles: usize },
+    /// Wave function collapse failed, or its area or symmetry is invalid
+    Wfc(WfcError),
+}
+
+impl MapgenError {
+    pub(crate) fn invalid(parameter: &'static str, message: impl Into<String>) -> Self {
+        MapgenError::InvalidParameter { parameter, message: message.into() }
+    }
+
+    /// `ZeroSize` if `size` is zero along an axis.
+    pub(crate) fn check_size(parameter: &'static str, size: UVec2) -> Result<(), Self> {
+        match size.cmpgt(UVec2::ZERO).all() {
+            true => Ok(()),
+            false => Err(MapgenError::ZeroSize { parameter, size }),
+        }
+    }
+}
+
+impl fmt::Display for MapgenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MapgenError::ZeroSize { parameter, size } => write!(f, "'{}' must not be zero, got {}", parameter, size),
+            MapgenError::InvalidParameter { parameter, message } => write!(f, "invalid '{}': {}", parameter, message),
+            MapgenError::TileCount { n, tiles } => {
+                write!(f, "N is {} but the tile type has {} tiles", n, tiles)
+            }
+            MapgenError::Wfc(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for MapgenError {}
+
+impl From<WfcError> for MapgenError {
+    fn from(e: WfcError) -> Self {
+        MapgenError::Wfc(e)
+    }
+}
@@ -0,0 +1,150 @@
+//! Convolution of `f64` maps, eg. for smoothing heightmaps and moisture fields.
+//!
+//! Kernels are centered on the tile they are applied to: the center of a kernel of size
+//! `(w, h)` is `(w / 2, h / 2)`. Kernels are not flipped (this is a correlation), which makes no
+//! difference for the usual symmetric kernels. Tiles outside of the map are read according to
+//! an `Edge`.
+//!
+//! With the `wgpu` feature, `convolve_on` and `gaussian_blur_on` run on a `gpu::Backend`.
+
+use crate::crop::Edge;
+#[cfg(feature = "wgpu")]
+use crate::gpu::Backend;
+use glam::ivec2;
+use ndarray::{Array1, Array2, Zip};
+
+/// Convolve `a` with `kernel`.
+/// With the `rayon` feature, tiles are computed in parallel.
+pub fn convolve(a: &Array2<f64>, kernel: &Array2<f64>, edge: &Edge<f64>) -> Array2<f64> {
+    if a.is_empty() {
+        return a.clone();
+    }
+    let (kx, ky) = kernel.dim();
+    let center = ivec2(kx as i32 / 2, ky as i32 / 2);
+    let mut r = Array2::zeros(a.dim());
+
+    let f = |(x, y): (usize, usize), v: &mut f64| {
+        let p = ivec2(x as i32, y as i32) - center;
+        *v = kernel
+            .indexed_iter()
+            .map(|((i, j), w)| w * edge.get(a, p + ivec2(i as i32, j as i32)))
+            .sum();
+    };
+    #[cfg(feature = "rayon")]
+    Zip::indexed(&mut r).par_for_each(f);
+    #[cfg(not(feature = "rayon"))]
+    Zip::indexed(&mut r).for_each(f);
+    r
+}
+
+/// Normalized gaussian weights with standard deviation `sigma` (in tiles), reaching out to
+/// `ceil(3 sigma)` tiles on each side. `[1.0]` for `sigma <= 0`.
+pub fn gaussian_kernel(sigma: f64) -> Array1<f64> {
+    if sigma.is_nan() || sigma <= 0.0 {
+        return Array1::ones(1);
+    }
+    let radius = (3.0 * sigma).ceil() as i64;
+    let k = Array1::from_iter((-radius..=radius).map(|d| (-(d * d) as f64 / (2.0 * sigma * sigma)).exp()));
+    let sum = k.sum();
+    k / sum
+}
+
+/// The two separable passes of a gaussian blur: `gaussian_kernel(sigma)` along x, then along y.
+pub(crate) fn gaussian_passes(sigma: f64) -> [Array2<f64>; 2] {
+    let k = gaussian_kernel(sigma);
+    let n = k.len();
+    let along_x = k.clone().into_shape((n, 1)).expect("Shape matches length");
+    let along_y = k.into_shape((1, n)).expect("Shape matches length");
+    [along_x, along_y]
+}
+
+/// Gaussian blur with standard deviation `sigma` (in tiles), as two separable passes.
+pub fn gaussian_blur(a: &Array2<f64>, sigma: f64, edge: &Edge<f64>) -> Array2<f64> {
+    let [along_x, along_y] = gaussian_passes(sigma);
+    convolve(&convolve(a, &along_x, edge), &along_y, edge)
+}
+
+/// Mean over the square of `2 radius + 1` tiles around every tile.
+pub fn box_blur(a: &Array2<f64>, radius: u32, edge: &Edge<f64>) -> Array2<f64> {
+    let n = 2 * radius as usize + 1;
+    convolve(a, &Array2::from_elem((n, n), 1.0 / (n * n) as f64), edge)
+}
+
+/// Like `convolve`, but on `backend`. Falls back to the CPU if the GPU does not support the
+/// map (see `Gpu::convolve`). The GPU computes in single precision.
+#[cfg(feature = "wgpu")]
+pub fn convolve_on(backend: &Backend, a: &Array2<f64>, kernel: &Array2<f64>, edge: &Edge<f64>) -> Array2<f64> {
+    match backend {
+        Backend::Gpu(gpu) => gpu.convolve(a, kernel, edge).unwrap_or_else(|| convolve(a, kernel, edge)),
+        Backend::Cpu => convolve(a, kernel, edge),
+    }
+}
+
+/// Like `gaussian_blur`, but on `backend`, see `convolve_on`.
+#[cfg(feature = "wgpu")]
+pub fn gaussian_blur_on(backend: &Backend, a: &Array2<f64>, sigma: f64, edge: &Edge<f64>) -> Array2<f64> {
+    match backend {
+        Backend::Gpu(gpu) => gpu.gaussian_blur(a, sigma, edge).unwrap_or_else(|| gaussian_blur(a, sigma, edge)),
+        Backend::Cpu => gaussian_blur(a, sigma, edge),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ndarray::arr2;
+
+    /// 5x4 map with a single 1.0 at (2, 1)
+    fn impulse() -> Array2<f64> {
+        let mut a = Array2::zeros((5, 4));
+        a[[2, 1]] = 1.0;
+        a
+    }
+
+    #[test]
+    fn kernel_is_centered_and_not_flipped() {
+        let kernel = arr2(&[[0.0, 1.0, 0.0], [0.0, 0.0, 2.0], [0.0, 0.0, 0.0]]);
+        let r = convolve(&impulse(), &kernel, &Edge::Fill(0.0));
+        // Tile p reads kernel[[i, j]] * a[p + (i, j) - (1, 1)]
+        assert_eq!(r[[3, 1]], 1.0);
+        assert_eq!(r[[2, 0]], 2.0);
+        assert_eq!(r.sum(), 3.0);
+    }
+
+    #[test]
+    fn edges() {
+        let a = Array2::from_shape_fn((3, 2), |(x, y)| (10 * x + y) as f64);
+        let left = arr2(&[[1.0], [0.0], [0.0]]);
+        assert_eq!(convolve(&a, &left, &Edge::Fill(-1.0)).column(0).to_vec(), vec![-1.0, 0.0, 10.0]);
+        assert_eq!(convolve(&a, &left, &Edge::Clamp).column(0).to_vec(), vec![0.0, 0.0, 10.0]);
+        assert_eq!(convolve(&a, &left, &Edge::Wrap).column(0).to_vec(), vec![20.0, 0.0, 10.0]);
+    }
+
+    #[test]
+    fn gaussian_kernel_is_normalized() {
+        let k = gaussian_kernel(1.5);
+        assert_eq!(k.len(), 11);
+        assert!((k.sum() - 1.0).abs() < 1e-12);
+        assert_eq!(k[5], *k.iter().max_by(|a, b| a.total_cmp(b)).unwrap());
+        assert_eq!(k[4], k[6]);
+        assert_eq!(gaussian_kernel(0.0), Array1::<f64>::ones(1));
+    }
+
+    #[test]
+    fn blur_keeps_constant_maps_and_mass() {
+        let a = Array2::from_elem((6, 5), 0.25);
+        for edge in [Edge::Clamp, Edge::Wrap, Edge::Fill(0.25)] {
+            let r = gaussian_blur(&a, 2.0, &edge);
+            assert!(r.iter().all(|v| (v - 0.25).abs() < 1e-12));
+        }
+
+        let r = gaussian_blur(&impulse(), 1.0, &Edge::Wrap);
+        assert!((r.sum() - 1.0).abs() < 1e-12);
+        assert_eq!(r.iter().copied().fold(0.0, f64::max), r[[2, 1]]);
+
+        let r = box_blur(&impulse(), 1, &Edge::Fill(0.0));
+        assert_eq!(r[[1, 0]], 1.0 / 9.0);
+        assert_eq!(r[[4, 1]], 0.0);
+        assert!(gaussian_blur(&Array2::zeros((0, 3)), 1.0, &Edge::Clamp).is_empty());
+    }
+}
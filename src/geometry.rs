@@ -0,0 +1,433 @@
+//! Lines, shapes, rays and visibility on the tile grid.
+//!
+//! Lines and shapes are lists of tile positions (`IVec2`, so they may extend beyond the map),
+//! functions that take a map ignore or stop at positions outside of it.
+
+use crate::coord::{map_size, MapAccess};
+use glam::{ivec2, IVec2, UVec2};
+use ndarray::Array2;
+use std::collections::VecDeque;
+
+/// Tiles of the line from `start` to `end` (both included) by Bresenham's algorithm: one tile
+/// per step along the major axis, 8-connected. Good for straight corridors and line of sight.
+#[derive(Clone, Debug)]
+pub struct Bresenham {
+    p: IVec2,
+    end: IVec2,
+    /// (|dx|, -|dy|)
+    delta: IVec2,
+    step: IVec2,
+    error: i32,
+    done: bool,
+}
+
+impl Bresenham {
+    pub fn new(start: IVec2, end: IVec2) -> Self {
+        let delta = ivec2((end.x - start.x).abs(), -(end.y - start.y).abs());
+        Self { p: start, end, delta, step: (end - start).signum(), error: delta.x + delta.y, done: false }
+    }
+}
+
+impl Iterator for Bresenham {
+    type Item = IVec2;
+
+    fn next(&mut self) -> Option<IVec2> {
+        if self.done {
+            return None;
+        }
+        let p = self.p;
+        if p == self.end {
+            self.done = true;
+            return Some(p);
+        }
+        let e2 = 2 * self.error;
+        if e2 >= self.delta.y {
+            self.error += self.delta.y;
+            self.p.x += self.step.x;
+        }
+        if e2 <= self.delta.x {
+            self.error += self.delta.x;
+            self.p.y += self.step.y;
+        }
+        Some(p)
+    }
+}
+
+/// All tiles touched by the segment between the centers of `start` and `end` (both included),
+/// 4-connected. Where the segment passes exactly through a tile corner, both tiles at the
+/// side of the corner are included as well as the tile beyond. Good for walls that must block
+/// a line completely.
+#[derive(Clone, Debug)]
+pub struct Supercover {
+    p: IVec2,
+    /// (|dx|, |dy|)
+    n: IVec2,
+    step: IVec2,
+    /// Steps taken along each axis
+    i: IVec2,
+    pending: VecDeque<IVec2>,
+}
+
+impl Supercover {
+    pub fn new(start: IVec2, end: IVec2) -> Self {
+        Self {
+            p: start,
+            n: (end - start).abs(),
+            step: (end - start).signum(),
+            i: IVec2::ZERO,
+            pending: VecDeque::from([start]),
+        }
+    }
+}
+
+impl Iterator for Supercover {
+    type Item = IVec2;
+
+    fn next(&mut self) -> Option<IVec2> {
+        if let Some(p) = self.pending.pop_front() {
+            return Some(p);
+        }
+        if self.i.x >= self.n.x && self.i.y >= self.n.y {
+            return None;
+        }
+        // Compare where the segment crosses the next vertical and horizontal tile edge
+        let decision = (1 + 2 * self.i.x) * self.n.y - (1 + 2 * self.i.y) * self.n.x;
+        match decision {
+            0 => {
+                self.pending.push_back(self.p + ivec2(0, self.step.y));
+                self.pending.push_back(self.p + self.step);
+                let side = self.p + ivec2(self.step.x, 0);
+                self.p += self.step;
+                self.i += IVec2::ONE;
+                Some(side)
+            }
+            d if d < 0 => {
+                self.p.x += self.step.x;
+                self.i.x += 1;
+                Some(self.p)
+            }
+            _ => {
+                self.p.y += self.step.y;
+                self.i.y += 1;
+                Some(self.p)
+            }
+        }
+    }
+}
+
+/// Follow the Bresenham line from `from` towards `to` and return the first tile (after `from`)
+/// for which `until` is true. `None` if the ray reaches `to` or leaves the map without a hit.
+pub fn cast_ray<T, F>(a: &Array2<T>, from: UVec2, to: IVec2, until: F) -> Option<UVec2>
+where
+    F: Fn(&T) -> bool,
+{
+    for p in Bresenham::new(from.as_ivec2(), to).skip(1) {
+        match a.get_at(p) {
+            Some(t) if until(t) => return Some(p.as_uvec2()),
+            Some(_) => continue,
+            None => return None,
+        }
+    }
+    None
+}
+
+/// Slope as fraction (numerator, denominator > 0)
+type Slope = (i64, i64);
+
+/// Tiles of `a` visible from `origin` by symmetric shadowcasting: `origin` sees a tile iff the
+/// tile sees `origin`, walls are visible but block the view, and the visible floor area has
+/// no artifacts around pillars. Tiles for which `blocks` is true are walls, tiles outside of
+/// the map block the view. With a `radius`, only tiles within that euclidean distance are
+/// visible.
+pub fn field_of_view<T, F>(a: &Array2<T>, origin: UVec2, radius: Option<u32>, blocks: F) -> Array2<bool>
+where
+    F: Fn(&T) -> bool,
+{
+    let mut visible = Array2::from_elem(a.dim(), false);
+    if a.get_at(origin.as_ivec2()).is_none() {
+        return visible;
+    }
+    *visible.at_mut(origin) = true;
+
+    let max_depth = radius.map_or(map_size(a).max_element() as i64, |r| r as i64);
+    let in_radius = |depth: i64, col: i64| {
+        radius.is_none_or(|r| depth * depth + col * col <= (r as i64).pow(2))
+    };
+    // (depth, column) -> offset, for the quadrants north, east, south and west of the origin
+    let quadrants: [fn(i64, i64) -> IVec2; 4] = [
+        |d, c| ivec2(c as i32, -d as i32),
+        |d, c| ivec2(d as i32, c as i32),
+        |d, c| ivec2(c as i32, d as i32),
+        |d, c| ivec2(-d as i32, c as i32),
+    ];
+
+    for transform in quadrants {
+        // Rows still to scan as (depth, start slope, end slope)
+        let mut rows: Vec<(i64, Slope, Slope)> = vec![(1, (-1, 1), (1, 1))];
+        while let Some((depth, mut start, end)) = rows.pop() {
+            if depth > max_depth {
+                continue;
+            }
+            // Columns from round_ties_up(depth * start) to round_ties_down(depth * end)
+            let min_col = (2 * depth * start.0 + start.1).div_euclid(2 * start.1);
+            let max_col = -(-(2 * depth * end.0 - end.1)).div_euclid(2 * end.1);
+
+            let mut previous_wall = None;
+            for col in min_col..=max_col {
+                let p = origin.as_ivec2() + transform(depth, col);
+                let tile = a.get_at(p);
+                let wall = tile.is_none_or(&blocks);
+                let symmetric = col * start.1 >= depth * start.0 && col * end.1 <= depth * end.0;
+                if tile.is_some() && (wall || symmetric) && in_radius(depth, col) {
+                    *visible.at_mut(p.as_uvec2()) = true;
+                }
+
+                let slope = (2 * col - 1, 2 * depth);
+                match (previous_wall, wall) {
+                    (Some(true), false) => start = slope,
+                    (Some(false), true) => rows.push((depth + 1, start, slope)),
+                    _ => {}
+                }
+                previous_wall = Some(wall);
+            }
+            if previous_wall == Some(false) {
+                rows.push((depth + 1, start, end));
+            }
+        }
+    }
+    visible
+}
+
+/// Write `value` into all `positions` of `a` that are inside the map, eg. the tiles of a shape.
+pub fn stamp<T: Clone, I: IntoIterator<Item = IVec2>>(a: &mut Array2<T>, positions: I, value: T) {
+    for p in positions {
+        if a.get_at(p).is_some() {
+            *a.at_mut(p.as_uvec2()) = value.clone();
+        }
+    }
+}
+
+/// Tiles of the filled ellipse around `center` with half axes `radii` (x-major). A tile is
+/// inside if `(dx / (rx + 0.5))² + (dy / (ry + 0.5))² <= 1`, so radius 0 is a single tile and
+/// the extent along each axis is exactly `2 * radius + 1` tiles.
+pub fn ellipse(center: IVec2, radii: UVec2) -> Vec<IVec2> {
+    let r = radii.as_ivec2();
+    (-r.x..=r.x)
+        .flat_map(|x| (-r.y..=r.y).map(move |y| ivec2(x, y)))
+        .filter(|&o| in_ellipse(o, radii))
+        .map(|o| center + o)
+        .collect()
+}
+
+/// Tiles of `ellipse` that have a 4-neighbor outside of it, an 8-connected outline.
+pub fn ellipse_outline(center: IVec2, radii: UVec2) -> Vec<IVec2> {
+    ellipse(IVec2::ZERO, radii)
+        .into_iter()
+        .filter(|&o| [IVec2::X, IVec2::Y, IVec2::NEG_X, IVec2::NEG_Y].iter().any(|&d| !in_ellipse(o + d, radii)))
+        .map(|o| center + o)
+        .collect()
+}
+
+fn in_ellipse(o: IVec2, radii: UVec2) -> bool {
+    // (dx / (rx + 0.5))² + (dy / (ry + 0.5))² <= 1, multiplied by (2rx + 1)² (2ry + 1)² / 4
+    let (a, b) = (2 * radii.x as i64 + 1, 2 * radii.y as i64 + 1);
+    let (x, y) = (o.x as i64, o.y as i64);
+    4 * (x * x * b * b + y * y * a * a) <= a * a * b * b
+}
+
+/// Tiles of the filled circle around `center`, see `ellipse`.
+pub fn circle(center: IVec2, radius: u32) -> Vec<IVec2> {
+    ellipse(center, UVec2::splat(radius))
+}
+
+/// Tiles of the outline of `circle`, see `ellipse_outline`.
+pub fn circle_outline(center: IVec2, radius: u32) -> Vec<IVec2> {
+    ellipse_outline(center, UVec2::splat(radius))
+}
+
+/// Tiles whose center is inside of or on the border of the convex polygon with the given
+/// vertices (in either winding order), x-major. Empty for less than 3 vertices.
+pub fn convex_polygon(vertices: &[IVec2]) -> Vec<IVec2> {
+    if vertices.len() < 3 {
+        return Vec::new();
+    }
+    let min = vertices.iter().fold(vertices[0], |m, &v| m.min(v));
+    let max = vertices.iter().fold(vertices[0], |m, &v| m.max(v));
+    let edges: Vec<(IVec2, IVec2)> = (0..vertices.len())
+        .map(|i| (vertices[i], vertices[(i + 1) % vertices.len()]))
+        .collect();
+    let side = |p: IVec2, (a, b): (IVec2, IVec2)| (b - a).perp_dot(p - a).signum();
+
+    (min.x..=max.x)
+        .flat_map(|x| (min.y..=max.y).map(move |y| ivec2(x, y)))
+        .filter(|&p| {
+            let sides = || edges.iter().map(|&e| side(p, e));
+            sides().all(|s| s >= 0) || sides().all(|s| s <= 0)
+        })
+        .collect()
+}
+
+/// Tiles of the closed Bresenham lines along the edges of a polygon (convex or not), in
+/// order, without repeating the vertices.
+pub fn polygon_outline(vertices: &[IVec2]) -> Vec<IVec2> {
+    (0..vertices.len())
+        .flat_map(|i| Bresenham::new(vertices[i], vertices[(i + 1) % vertices.len()]).skip(1))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use glam::uvec2;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    /// Endpoint pairs in all octants, including axis-aligned, diagonal and degenerate lines
+    fn segments() -> Vec<(IVec2, IVec2)> {
+        let ends = [ivec2(0, 0), ivec2(5, 2), ivec2(-3, 7), ivec2(4, 4), ivec2(-6, -1), ivec2(0, -5), ivec2(2, -7)];
+        ends.iter().flat_map(|&a| ends.iter().map(move |&b| (a, b))).collect()
+    }
+
+    #[test]
+    fn bresenham_includes_both_endpoints() {
+        for (a, b) in segments() {
+            let line: Vec<IVec2> = Bresenham::new(a, b).collect();
+            assert_eq!((line[0], *line.last().unwrap()), (a, b));
+            assert_eq!(line.len() as i32, (b - a).abs().max_element() + 1, "{} {}", a, b);
+            assert!(line.windows(2).all(|w| (w[1] - w[0]).abs().max_element() == 1), "{} {}", a, b);
+        }
+        assert_eq!(Bresenham::new(ivec2(3, 3), ivec2(3, 3)).collect::<Vec<_>>(), vec![ivec2(3, 3)]);
+        let line: Vec<IVec2> = Bresenham::new(ivec2(0, 0), ivec2(5, 2)).collect();
+        assert_eq!(line, [(0, 0), (1, 0), (2, 1), (3, 1), (4, 2), (5, 2)].map(|(x, y)| ivec2(x, y)));
+    }
+
+    #[test]
+    fn supercover_corners() {
+        let diagonal: Vec<IVec2> = Supercover::new(ivec2(0, 0), ivec2(2, 2)).collect();
+        let expected = [(0, 0), (1, 0), (0, 1), (1, 1), (2, 1), (1, 2), (2, 2)].map(|(x, y)| ivec2(x, y));
+        assert_eq!(diagonal, expected);
+
+        // Without corners, one tile per step along either axis
+        let line: Vec<IVec2> = Supercover::new(ivec2(0, 0), ivec2(4, -2)).collect();
+        let expected = [(0, 0), (1, 0), (1, -1), (2, -1), (3, -1), (3, -2), (4, -2)].map(|(x, y)| ivec2(x, y));
+        assert_eq!(line, expected);
+
+        // (0, 0) to (3, 1) passes through the corner between (1, 0) and (2, 1)
+        let line: Vec<IVec2> = Supercover::new(ivec2(0, 0), ivec2(3, 1)).collect();
+        let expected = [(0, 0), (1, 0), (2, 0), (1, 1), (2, 1), (3, 1)].map(|(x, y)| ivec2(x, y));
+        assert_eq!(line, expected);
+
+        assert_eq!(Supercover::new(ivec2(1, 1), ivec2(1, 1)).collect::<Vec<_>>(), vec![ivec2(1, 1)]);
+        let straight: Vec<IVec2> = Supercover::new(ivec2(0, 2), ivec2(0, -1)).collect();
+        assert_eq!(straight, [2, 1, 0, -1].map(|y| ivec2(0, y)));
+    }
+
+    #[test]
+    fn supercover_contains_bresenham() {
+        for (a, b) in segments() {
+            let cover: Vec<IVec2> = Supercover::new(a, b).collect();
+            assert_eq!((cover[0], *cover.last().unwrap()), (a, b));
+            for p in Bresenham::new(a, b) {
+                assert!(cover.contains(&p), "{} {}: {}", a, b, p);
+            }
+            // 4-connected: every tile after the first has a 4-neighbor before it
+            for (i, p) in cover.iter().enumerate().skip(1) {
+                assert!(cover[..i].iter().any(|q| (*p - *q).abs().max_element() + (*p - *q).abs().min_element() == 1));
+            }
+        }
+    }
+
+    #[test]
+    fn rays() {
+        let mut a = Array2::from_elem((10, 5), '.');
+        a[[6, 2]] = '#';
+        assert_eq!(cast_ray(&a, uvec2(1, 2), ivec2(9, 2), |&t| t == '#'), Some(uvec2(6, 2)));
+        assert_eq!(cast_ray(&a, uvec2(1, 2), ivec2(5, 2), |&t| t == '#'), None);
+        assert_eq!(cast_ray(&a, uvec2(1, 2), ivec2(1, -5), |&t| t == '#'), None);
+        // The start tile is not hit
+        assert_eq!(cast_ray(&a, uvec2(6, 2), ivec2(9, 2), |&t| t == '#'), None);
+    }
+
+    /// 24x18 map with random walls (`true`)
+    fn walls(seed: u64) -> Array2<bool> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        Array2::from_shape_fn((24, 18), |_| rng.gen_bool(0.2))
+    }
+
+    #[test]
+    fn field_of_view_is_symmetric() {
+        for seed in 0..3 {
+            let a = walls(seed);
+            let floor: Vec<UVec2> = a.iter_with_positions().filter(|(_, &w)| !w).map(|(p, _)| p).collect();
+            let views: Vec<Array2<bool>> = floor.iter().map(|&p| field_of_view(&a, p, None, |&w| w)).collect();
+            for (i, &p) in floor.iter().enumerate() {
+                assert!(views[i].at(p));
+                for (j, &q) in floor.iter().enumerate() {
+                    assert_eq!(views[i].at(q), views[j].at(p), "seed {}: {} and {}", seed, p, q);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn field_of_view_walls_and_radius() {
+        let mut a = Array2::from_elem((11, 11), false);
+        let open = field_of_view(&a, uvec2(5, 5), None, |&w| w);
+        assert!(open.iter().all(|&v| v));
+
+        let r = field_of_view(&a, uvec2(5, 5), Some(3), |&w| w);
+        assert!(*r.at(uvec2(8, 5)) && !*r.at(uvec2(9, 5)) && !*r.at(uvec2(8, 8)));
+        assert!(r.iter_with_positions().all(|(p, &v)| v == ((p.as_ivec2() - ivec2(5, 5)).length_squared() <= 9)));
+
+        // A pillar is visible but hides the tiles right behind it
+        a[[7, 5]] = true;
+        let v = field_of_view(&a, uvec2(5, 5), None, |&w| w);
+        assert!(*v.at(uvec2(7, 5)));
+        assert!(!*v.at(uvec2(8, 5)) && !*v.at(uvec2(10, 5)));
+        assert!(!*v.at(uvec2(10, 4)) && *v.at(uvec2(10, 3)) && *v.at(uvec2(10, 7)));
+
+        assert!(!field_of_view(&a, uvec2(11, 0), None, |&w| w).iter().any(|&v| v));
+    }
+
+    #[test]
+    fn ellipses_and_circles() {
+        assert_eq!(circle(ivec2(3, -2), 0), vec![ivec2(3, -2)]);
+        // (1, 1) is at distance 1.41 < 1.5
+        assert_eq!(circle(IVec2::ZERO, 1).len(), 9);
+
+        let e = ellipse(ivec2(10, 10), uvec2(4, 2));
+        let min = e.iter().fold(e[0], |m, &p| m.min(p));
+        let max = e.iter().fold(e[0], |m, &p| m.max(p));
+        assert_eq!((min, max), (ivec2(6, 8), ivec2(14, 12)));
+        // Symmetric along both axes
+        assert!(e.iter().all(|&p| e.contains(&(ivec2(20, 20) - p)) && e.contains(&ivec2(20 - p.x, p.y))));
+
+        let outline = ellipse_outline(ivec2(10, 10), uvec2(4, 2));
+        assert!(outline.iter().all(|p| e.contains(p)));
+        assert!(!outline.contains(&ivec2(10, 10)));
+        assert!(outline.contains(&ivec2(6, 10)) && outline.contains(&ivec2(10, 8)));
+        assert_eq!(circle_outline(IVec2::ZERO, 0), vec![IVec2::ZERO]);
+    }
+
+    #[test]
+    fn polygons() {
+        let triangle = [ivec2(0, 0), ivec2(4, 0), ivec2(0, 4)];
+        let filled = convex_polygon(&triangle);
+        // Tiles with x + y <= 4
+        assert_eq!(filled.len(), 15);
+        assert!(filled.iter().all(|p| p.x >= 0 && p.y >= 0 && p.x + p.y <= 4));
+        let mut reversed = triangle;
+        reversed.reverse();
+        assert_eq!(convex_polygon(&reversed), filled);
+        assert!(convex_polygon(&triangle[..2]).is_empty());
+
+        let outline = polygon_outline(&triangle);
+        assert_eq!(outline.len(), 12);
+        assert!(outline.iter().all(|p| filled.contains(p)));
+        assert_eq!(*outline.last().unwrap(), ivec2(0, 0));
+
+        // Tiles outside of the map are skipped
+        let mut a = Array2::from_elem((4, 4), 0);
+        stamp(&mut a, filled, 1);
+        assert_eq!(a.sum(), 13);
+    }
+}
@@ -0,0 +1,54 @@
+// One convolution pass, see `filter::convolve` for the CPU version.
+
+struct Convolution {
+    size: vec2<i32>,
+    kernel_size: vec2<i32>,
+    // 0: fill, 1: clamp, 2: wrap, see `crop::Edge`
+    edge: i32,
+    fill: f32,
+    _padding: vec2<i32>,
+}
+
+@group(0) @binding(0) var<uniform> c: Convolution;
+// Kernel and maps are x-major
+@group(0) @binding(1) var<storage, read> weights: array<f32>;
+@group(0) @binding(2) var<storage, read> src: array<f32>;
+@group(0) @binding(3) var<storage, read_write> dst: array<f32>;
+
+// Like `rem_euclid`, with `%` of non-negative values only
+fn wrap(v: i32, n: i32) -> i32 {
+    if v < 0 {
+        return n - 1 - (-1 - v) % n;
+    }
+    return v % n;
+}
+
+fn read_tile(p: vec2<i32>) -> f32 {
+    var q = p;
+    if any(q < vec2(0)) || any(q >= c.size) {
+        if c.edge == 1 {
+            q = clamp(q, vec2(0), c.size - 1);
+        } else if c.edge == 2 {
+            q = vec2(wrap(q.x, c.size.x), wrap(q.y, c.size.y));
+        } else {
+            return c.fill;
+        }
+    }
+    return src[q.x * c.size.y + q.y];
+}
+
+@compute @workgroup_size(64)
+fn convolve(@builtin(global_invocation_id) id: vec3<u32>, @builtin(num_workgroups) groups: vec3<u32>) {
+    let t = i32(id.x + id.y * groups.x * 64u);
+    if t >= c.size.x * c.size.y {
+        return;
+    }
+    let p = vec2(t / c.size.y, t % c.size.y) - c.kernel_size / 2;
+    var v = 0.0;
+    for (var i = 0; i < c.kernel_size.x; i++) {
+        for (var j = 0; j < c.kernel_size.y; j++) {
+            v += weights[i * c.kernel_size.y + j] * read_tile(p + vec2(i, j));
+        }
+    }
+    dst[t] = v;
+}
@@ -0,0 +1,79 @@
+// Stockham FFT, one radix pass per dispatch, see `Gpu::colored_noise`.
+
+struct Pass {
+    // Length of the transformed lines
+    n: u32,
+    // Product of the radices of the previous passes
+    ns: u32,
+    radix: u32,
+    // Distance between two elements of a line and between the first elements of two lines
+    stride: u32,
+    line_stride: u32,
+    lines: u32,
+    // 1.0 for the inverse transform, -1.0 for the forward transform
+    sign: f32,
+    _padding: u32,
+}
+
+@group(0) @binding(0) var<uniform> p: Pass;
+@group(0) @binding(1) var<storage, read> src: array<vec2<f32>>;
+@group(0) @binding(2) var<storage, read_write> dst: array<vec2<f32>>;
+
+const TAU: f32 = 6.283185307179586;
+
+fn mul(a: vec2<f32>, b: vec2<f32>) -> vec2<f32> {
+    return vec2(a.x * b.x - a.y * b.y, a.x * b.y + a.y * b.x);
+}
+
+fn twiddle(k: u32, n: u32) -> vec2<f32> {
+    let angle = p.sign * TAU * f32(k) / f32(n);
+    return vec2(cos(angle), sin(angle));
+}
+
+fn thread(id: vec3<u32>, groups: vec3<u32>) -> u32 {
+    return id.x + id.y * groups.x * 64u;
+}
+
+@compute @workgroup_size(64)
+fn radix_pass(@builtin(global_invocation_id) id: vec3<u32>, @builtin(num_workgroups) groups: vec3<u32>) {
+    let m = p.n / p.radix;
+    let t = thread(id, groups);
+    if t >= p.lines * m {
+        return;
+    }
+    let base = (t / m) * p.line_stride;
+    let j = t % m;
+    let k = j % p.ns;
+
+    var v: array<vec2<f32>, 7>;
+    for (var r = 0u; r < p.radix; r++) {
+        v[r] = mul(src[base + (j + r * m) * p.stride], twiddle(r * k, p.ns * p.radix));
+    }
+    let out = (j / p.ns) * p.ns * p.radix + k;
+    for (var q = 0u; q < p.radix; q++) {
+        var sum = vec2(0.0);
+        for (var r = 0u; r < p.radix; r++) {
+            sum += mul(v[r], twiddle((r * q) % p.radix, p.radix));
+        }
+        dst[base + (out + q * p.ns) * p.stride] = sum;
+    }
+}
+
+// Complete the lines of length `n` whose first `n / 2 + 1` values are the half spectrum of a
+// real signal to the full conjugate symmetric spectrum, in place in `dst`. Like the real
+// inverse transform, ignores the imaginary parts at the zero and the Nyquist frequency.
+@compute @workgroup_size(64)
+fn hermitian(@builtin(global_invocation_id) id: vec3<u32>, @builtin(num_workgroups) groups: vec3<u32>) {
+    let t = thread(id, groups);
+    if t >= p.lines * p.n {
+        return;
+    }
+    let base = (t / p.n) * p.line_stride;
+    let y = t % p.n;
+    if y == 0u || 2u * y == p.n {
+        dst[base + y].y = 0.0;
+    } else if y > p.n / 2u {
+        let mirror = dst[base + p.n - y];
+        dst[base + y] = vec2(mirror.x, -mirror.y);
+    }
+}
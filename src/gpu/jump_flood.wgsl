@@ -0,0 +1,46 @@
+// One step of jump flooding, see `Voronoi::generate_jump_flood` for the CPU version.
+
+struct Step {
+    size: vec2<i32>,
+    step: i32,
+    _padding: i32,
+}
+
+@group(0) @binding(0) var<uniform> s: Step;
+@group(0) @binding(1) var<storage, read> centers: array<vec2<f32>>;
+// Nearest center per tile (x-major), -1 for none
+@group(0) @binding(2) var<storage, read> previous: array<i32>;
+@group(0) @binding(3) var<storage, read_write> nearest: array<i32>;
+
+fn index(p: vec2<i32>) -> i32 {
+    return p.x * s.size.y + p.y;
+}
+
+fn distance_squared(p: vec2<i32>, i: i32) -> f32 {
+    let d = vec2<f32>(p) - centers[i];
+    return d.x * d.x + d.y * d.y;
+}
+
+@compute @workgroup_size(64)
+fn jump_flood_step(@builtin(global_invocation_id) id: vec3<u32>, @builtin(num_workgroups) groups: vec3<u32>) {
+    let t = i32(id.x + id.y * groups.x * 64u);
+    if t >= s.size.x * s.size.y {
+        return;
+    }
+    let p = vec2(t / s.size.y, t % s.size.y);
+    var n = previous[t];
+    // Same order as `offsets(1, chebyshev)`, so that ties are resolved like on the CPU
+    for (var x = -1; x <= 1; x++) {
+        for (var y = -1; y <= 1; y++) {
+            let q = p + vec2(x, y) * s.step;
+            if (x == 0 && y == 0) || any(q < vec2(0)) || any(q >= s.size) {
+                continue;
+            }
+            let j = previous[index(q)];
+            if j >= 0 && (n < 0 || distance_squared(p, j) < distance_squared(p, n)) {
+                n = j;
+            }
+        }
+    }
+    nearest[t] = n;
+}
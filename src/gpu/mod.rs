@@ -0,0 +1,522 @@
+//! GPU backend (feature `wgpu`) for spectral synthesis of large maps (eg. 4k x 4k chunks for
+//! streaming), jump flooding and convolution.
+//!
+//! ```
+//! # use mapgen_2d::gpu::Backend;
+//! # use mapgen_2d::colored_noise::ColoredNoise;
+//! # use glam::uvec2;
+//! let backend = Backend::auto();
+//! let noise = ColoredNoise { size: uvec2(512, 512), ..Default::default() };
+//! let heightmap = noise.generate_on(&backend);
+//! # assert_eq!(heightmap.dim(), (512, 512));
+//! ```
+//!
+//! `Backend::auto` selects the CPU when there is no adapter. The `Gpu` methods return `None`
+//! for inputs they do not support (and on device errors), which `ColoredNoise::generate_on`,
+//! `Voronoi::generate_on`, `filter::convolve_on` and `filter::gaussian_blur_on` then generate
+//! on the CPU.
+
+use crate::colored_noise::{crop_to_size, fill_spectrum, synthesis_size, ColoredNoise};
+use crate::crop::Edge;
+use crate::filter::gaussian_passes;
+use crate::voronoi::Voronoi;
+use ndarray::Array2;
+use wgpu::util::DeviceExt;
+
+/// Threads per workgroup of all shaders
+const WORKGROUP_SIZE: usize = 64;
+
+/// Where `ColoredNoise::generate_on`, `Voronoi::generate_on` and the `filter::*_on` functions
+/// generate maps.
+pub enum Backend {
+    Cpu,
+    Gpu(Box<Gpu>),
+}
+
+impl Backend {
+    /// The GPU if there is an adapter, the CPU otherwise.
+    pub fn auto() -> Self {
+        Gpu::new().map_or(Backend::Cpu, |gpu| Backend::Gpu(Box::new(gpu)))
+    }
+}
+
+/// Device and compute pipelines.
+pub struct Gpu {
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    info: wgpu::AdapterInfo,
+    radix_pass: wgpu::ComputePipeline,
+    hermitian: wgpu::ComputePipeline,
+    jump_flood_step: wgpu::ComputePipeline,
+    convolve: wgpu::ComputePipeline,
+}
+
+impl Gpu {
+    /// Open the default adapter with its full limits, `None` if there is no adapter.
+    pub fn new() -> Option<Self> {
+        let instance = wgpu::Instance::default();
+        let adapter = pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions::default())).ok()?;
+        let descriptor = wgpu::DeviceDescriptor {
+            label: Some("mapgen-2d"),
+            required_limits: adapter.limits(),
+            ..Default::default()
+        };
+        let (device, queue) = pollster::block_on(adapter.request_device(&descriptor)).ok()?;
+
+        let fft = device.create_shader_module(wgpu::include_wgsl!("fft.wgsl"));
+        let jump_flood = device.create_shader_module(wgpu::include_wgsl!("jump_flood.wgsl"));
+        let convolve = device.create_shader_module(wgpu::include_wgsl!("convolve.wgsl"));
+        let pipeline = |module: &wgpu::ShaderModule, entry_point: &str| {
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(entry_point),
+                layout: None,
+                module,
+                entry_point: Some(entry_point),
+                compilation_options: Default::default(),
+                cache: None,
+            })
+        };
+        let radix_pass = pipeline(&fft, "radix_pass");
+        let hermitian = pipeline(&fft, "hermitian");
+        let jump_flood_step = pipeline(&jump_flood, "jump_flood_step");
+        let convolve = pipeline(&convolve, "convolve");
+
+        Some(Self {
+            info: adapter.get_info(),
+            device,
+            queue,
+            radix_pass,
+            hermitian,
+            jump_flood_step,
+            convolve,
+        })
+    }
+
+    /// Name, backend, driver etc. of the adapter
+    pub fn info(&self) -> &wgpu::AdapterInfo {
+        &self.info
+    }
+
+    /// Generate `noise` like `ColoredNoise::generate`, with a mixed radix inverse FFT in
+    /// single precision. The spectrum, cropping and normalization are computed on the CPU.
+    /// `None` if a side of the synthesized map (see `ColoredNoise::tileable`) has prime
+    /// factors other than 2, 3, 5 and 7, or the map exceeds the buffer size limits.
+    pub fn colored_noise(&self, noise: &ColoredNoise) -> Option<Array2<f64>> {
+        let size = synthesis_size(noise);
+        let (size_x, size_y) = (size.x as usize, size.y as usize);
+        let (radices_x, radices_y) = (radices(size.x)?, radices(size.y)?);
+        let len = size_x * size_y;
+        if !self.fits(len * 8) {
+            return None;
+        }
+
+        let mut f_domain = Array2::zeros((size_x, size_y / 2 + 1));
+        fill_spectrum(noise, &mut f_domain);
+        // Full spectrum (x-major), the half spectrum is completed by the `hermitian` pass
+        let mut spectrum = vec![0f32; 2 * len];
+        for ((x, y), v) in f_domain.indexed_iter() {
+            spectrum[2 * (x * size_y + y)] = v.re as f32;
+            spectrum[2 * (x * size_y + y) + 1] = v.im as f32;
+        }
+
+        let data = self.run(|| {
+            let contents: Vec<u8> = spectrum.iter().flat_map(|v| v.to_le_bytes()).collect();
+            let buffers = [self.storage(&contents), self.storage(&vec![0; contents.len()])];
+            let mut current = 0;
+            let mut encoder = self.device.create_command_encoder(&Default::default());
+
+            // Inverse transform of the columns of the half spectrum along axis 0, complete it
+            // and transform along axis 1
+            let axis_0 = Lines { n: size.x, stride: size.y, line_stride: 1, lines: size.y / 2 + 1 };
+            let axis_1 = Lines { n: size.y, stride: 1, line_stride: size.y, lines: size.x };
+            for (lines, radices, hermitian) in [(axis_0, radices_x, true), (axis_1, radices_y, false)] {
+                let mut ns = 1;
+                for radix in radices {
+                    let threads = (lines.lines * lines.n / radix) as usize;
+                    let storage = [(1, &buffers[current]), (2, &buffers[1 - current])];
+                    self.dispatch(&mut encoder, &self.radix_pass, &lines.params(ns, radix), &storage, threads);
+                    current = 1 - current;
+                    ns *= radix;
+                }
+                if hermitian {
+                    let threads = (axis_1.lines * axis_1.n) as usize;
+                    let storage = [(2, &buffers[current])];
+                    self.dispatch(&mut encoder, &self.hermitian, &axis_1.params(1, 1), &storage, threads);
+                }
+            }
+            self.read(encoder, &buffers[current])
+        })?;
+
+        // Same scaling as `ndifft` along axis 0 and `ndifft_r2c` along axis 1
+        let scale = 1.0 / (size_x * 2 * (size_y / 2)) as f64;
+        let r = Array2::from_shape_fn((size_x, size_y), |(x, y)| {
+            let i = 8 * (x * size_y + y);
+            f32::from_le_bytes([data[i], data[i + 1], data[i + 2], data[i + 3]]) as f64 * scale
+        });
+        let mut r = crop_to_size(noise, r);
+        noise.normalization.apply(&mut r);
+        Some(r)
+    }
+
+    /// Jump flooding for `voronoi` (see `Algorithm::JumpFlood`): the (index into
+    /// `voronoi.centers` of the) nearest center found per tile. The same as on the CPU, except
+    /// where single precision distances compare differently. `None` if there are no centers
+    /// or the map exceeds the buffer size limits.
+    pub fn jump_flood(&self, voronoi: &Voronoi) -> Option<Array2<Option<usize>>> {
+        let size = voronoi.size;
+        let len = size.x as usize * size.y as usize;
+        if voronoi.centers.is_empty() || !self.fits(len * 4) || !self.fits(voronoi.centers.len() * 8) {
+            return None;
+        }
+
+        let data = self.run(|| {
+            let centers: Vec<u8> = voronoi
+                .centers
+                .iter()
+                .flat_map(|c| c.position.to_array())
+                .flat_map(f32::to_le_bytes)
+                .collect();
+            let seeds: Vec<u8> = voronoi
+                .jump_flood_seeds()
+                .iter()
+                .map(|n| n.map_or(-1, |i| i as i32))
+                .flat_map(i32::to_le_bytes)
+                .collect();
+            let centers = self.storage(&centers);
+            let buffers = [self.storage(&seeds), self.storage(&seeds)];
+            let mut current = 0;
+            let mut encoder = self.device.create_command_encoder(&Default::default());
+
+            for step in voronoi.jump_flood_steps() {
+                let params = [size.x, size.y, step as u32, 0];
+                let storage = [(1, &centers), (2, &buffers[current]), (3, &buffers[1 - current])];
+                self.dispatch(&mut encoder, &self.jump_flood_step, &params, &storage, len);
+                current = 1 - current;
+            }
+            self.read(encoder, &buffers[current])
+        })?;
+
+        let nearest = data
+            .chunks_exact(4)
+            .map(|b| usize::try_from(i32::from_le_bytes([b[0], b[1], b[2], b[3]])).ok())
+            .collect();
+        Array2::from_shape_vec((size.x as usize, size.y as usize), nearest).ok()
+    }
+
+    /// Convolve `a` with `kernel` like `filter::convolve`, in single precision. `None` if `a`
+    /// is empty or exceeds the buffer size limits.
+    pub fn convolve(&self, a: &Array2<f64>, kernel: &Array2<f64>, edge: &Edge<f64>) -> Option<Array2<f64>> {
+        self.convolve_passes(a, std::slice::from_ref(kernel), edge)
+    }
+
+    /// Gaussian blur like `filter::gaussian_blur`, see `convolve`.
+    pub fn gaussian_blur(&self, a: &Array2<f64>, sigma: f64, edge: &Edge<f64>) -> Option<Array2<f64>> {
+        self.convolve_passes(a, &gaussian_passes(sigma), edge)
+    }
+
+    /// Convolve `a` with each of `kernels` in turn.
+    fn convolve_passes(&self, a: &Array2<f64>, kernels: &[Array2<f64>], edge: &Edge<f64>) -> Option<Array2<f64>> {
+        let (size_x, size_y) = a.dim();
+        let len = size_x * size_y;
+        if a.is_empty() || !self.fits(len * 4) || kernels.iter().any(|k| k.is_empty() || !self.fits(k.len() * 4)) {
+            return None;
+        }
+        // The shader indexes with `i32`
+        if i32::try_from(len).is_err() {
+            return None;
+        }
+        let (edge, fill) = match edge {
+            Edge::Fill(v) => (0, *v as f32),
+            Edge::Clamp => (1, 0.0),
+            Edge::Wrap => (2, 0.0),
+        };
+
+        let data = self.run(|| {
+            // `iter` visits tiles in x-major order, whatever the memory layout
+            let contents: Vec<u8> = a.iter().flat_map(|&v| (v as f32).to_le_bytes()).collect();
+            let buffers = [self.storage(&contents), self.storage(&contents)];
+            let mut current = 0;
+            let mut encoder = self.device.create_command_encoder(&Default::default());
+
+            for kernel in kernels {
+                let weights: Vec<u8> = kernel.iter().flat_map(|&v| (v as f32).to_le_bytes()).collect();
+                let weights = self.storage(&weights);
+                let (kx, ky) = kernel.dim();
+                let params = [size_x as u32, size_y as u32, kx as u32, ky as u32, edge, fill.to_bits(), 0, 0];
+                let storage = [(1, &weights), (2, &buffers[current]), (3, &buffers[1 - current])];
+                self.dispatch(&mut encoder, &self.convolve, &params, &storage, len);
+                current = 1 - current;
+            }
+            self.read(encoder, &buffers[current])
+        })?;
+
+        let r = data
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64)
+            .collect();
+        Array2::from_shape_vec(a.dim(), r).ok()
+    }
+
+    /// Whether a storage buffer of `bytes` is within the limits of the device
+    fn fits(&self, bytes: usize) -> bool {
+        let limits = self.device.limits();
+        let bytes = bytes as u64;
+        bytes <= limits.max_storage_buffer_binding_size && bytes <= limits.max_buffer_size
+    }
+
+    /// Run `f`, `None` if it fails or the device reports an error meanwhile.
+    fn run<T>(&self, f: impl FnOnce() -> Option<T>) -> Option<T> {
+        let validation = self.device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let out_of_memory = self.device.push_error_scope(wgpu::ErrorFilter::OutOfMemory);
+        let r = f();
+        let out_of_memory = pollster::block_on(out_of_memory.pop());
+        let validation = pollster::block_on(validation.pop());
+        match (out_of_memory, validation) {
+            (None, None) => r,
+            _ => None,
+        }
+    }
+
+    fn storage(&self, contents: &[u8]) -> wgpu::Buffer {
+        self.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: None,
+            contents,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+        })
+    }
+
+    /// Record a compute pass running `pipeline` with `threads` threads, with the uniform
+    /// `params` at binding 0 and the `storage` buffers at their bindings.
+    fn dispatch(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        pipeline: &wgpu::ComputePipeline,
+        params: &[u32],
+        storage: &[(u32, &wgpu::Buffer)],
+        threads: usize,
+    ) {
+        let params: Vec<u8> = params.iter().flat_map(|v| v.to_le_bytes()).collect();
+        let params = self.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: None,
+            contents: &params,
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+        let mut entries = vec![wgpu::BindGroupEntry { binding: 0, resource: params.as_entire_binding() }];
+        entries.extend(
+            storage
+                .iter()
+                .map(|&(binding, buffer)| wgpu::BindGroupEntry { binding, resource: buffer.as_entire_binding() }),
+        );
+        let bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: None,
+            layout: &pipeline.get_bind_group_layout(0),
+            entries: &entries,
+        });
+
+        // The shaders compute the thread index from two dimensions of workgroups
+        let groups = threads.div_ceil(WORKGROUP_SIZE).max(1) as u32;
+        let groups_x = groups.min(self.device.limits().max_compute_workgroups_per_dimension);
+        let mut pass = encoder.begin_compute_pass(&Default::default());
+        pass.set_pipeline(pipeline);
+        pass.set_bind_group(0, &bind_group, &[]);
+        pass.dispatch_workgroups(groups_x, groups.div_ceil(groups_x), 1);
+    }
+
+    /// Submit `encoder` and read back `buffer`.
+    fn read(&self, mut encoder: wgpu::CommandEncoder, buffer: &wgpu::Buffer) -> Option<Vec<u8>> {
+        let staging = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size: buffer.size(),
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        encoder.copy_buffer_to_buffer(buffer, 0, &staging, 0, buffer.size());
+        self.queue.submit([encoder.finish()]);
+
+        let (sender, receiver) = std::sync::mpsc::channel();
+        staging.map_async(wgpu::MapMode::Read, .., move |r| {
+            let _ = sender.send(r);
+        });
+        self.device.poll(wgpu::PollType::wait_indefinitely()).ok()?;
+        receiver.recv().ok()?.ok()?;
+        let data = staging.get_mapped_range(..).ok()?.to_vec();
+        Some(data)
+    }
+}
+
+/// Lines of an x-major map transformed by one FFT, see `Pass` in `fft.wgsl`
+#[derive(Clone, Copy)]
+struct Lines {
+    n: u32,
+    stride: u32,
+    line_stride: u32,
+    lines: u32,
+}
+
+impl Lines {
+    /// Uniform parameters of an inverse transform pass
+    fn params(&self, ns: u32, radix: u32) -> [u32; 8] {
+        [self.n, ns, radix, self.stride, self.line_stride, self.lines, 1f32.to_bits(), 0]
+    }
+}
+
+/// Radices of the passes of a transform of length `n`, `None` if `n` has prime factors the
+/// shader does not implement.
+fn radices(mut n: u32) -> Option<Vec<u32>> {
+    let mut r = Vec::new();
+    for radix in [7, 5, 3, 2] {
+        while n > 1 && n.is_multiple_of(radix) {
+            r.push(radix);
+            n /= radix;
+        }
+    }
+    (n == 1).then_some(r)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::colored_noise::Normalization;
+    use crate::filter;
+    use crate::voronoi::{Algorithm, VoronoiCenter};
+    use glam::{uvec2, vec2, UVec2};
+
+    fn noise(size: UVec2, tileable: bool) -> ColoredNoise {
+        ColoredNoise { size, seed: 7, ..Default::default() }
+            .tileable(tileable)
+            .padded(!tileable)
+            .normalization(Normalization::Raw)
+    }
+
+    fn max_difference(a: &Array2<f64>, b: &Array2<f64>) -> f64 {
+        assert_eq!(a.dim(), b.dim());
+        a.iter().zip(b).map(|(a, b)| (a - b).abs()).fold(0.0, f64::max)
+    }
+
+    #[test]
+    fn radices_of_lengths() {
+        assert_eq!(radices(1), Some(vec![]));
+        assert_eq!(radices(840), Some(vec![7, 5, 3, 2, 2, 2]));
+        assert_eq!(radices(22), None);
+    }
+
+    /// Tests that need an adapter return early without checking anything if there is none,
+    /// so that they run wherever a GPU is available instead of being ignored
+    fn gpu() -> Option<Gpu> {
+        Gpu::new()
+    }
+
+    #[test]
+    fn noise_matches_cpu() {
+        let gpu = match gpu() {
+            Some(gpu) => gpu,
+            None => return,
+        };
+        // Even and odd sides, the padded ones are synthesized at 45 x 105
+        for (size, tileable) in [(uvec2(64, 48), true), (uvec2(35, 21), true), (uvec2(30, 70), false)] {
+            let noise = noise(size, tileable);
+            let expected = noise.generate();
+            let scale = expected.iter().fold(0.0, |m: f64, v| m.max(v.abs()));
+            let r = gpu.colored_noise(&noise).unwrap();
+            assert!(max_difference(&r, &expected) < 1e-4 * scale, "{} {}", size, tileable);
+        }
+    }
+
+    #[test]
+    fn noise_fallback() {
+        let noise = noise(uvec2(22, 26), true);
+        assert_eq!(noise.generate_on(&Backend::Cpu), noise.generate());
+    }
+
+    #[test]
+    fn noise_gpu_fallback() {
+        // 22 = 2 * 11 has no radix pass
+        let noise = noise(uvec2(22, 26), true);
+        let gpu = match gpu() {
+            Some(gpu) => gpu,
+            None => return,
+        };
+        assert!(gpu.colored_noise(&noise).is_none());
+        assert_eq!(noise.generate_on(&Backend::Gpu(Box::new(gpu))), noise.generate());
+    }
+
+    #[test]
+    fn jump_flood_matches_cpu() {
+        // Centers on tiles, so that the distances are exact in single precision
+        let positions = [vec2(3.0, 4.0), vec2(40.0, 2.0), vec2(17.0, 20.0), vec2(33.0, 27.0), vec2(5.0, 29.0)];
+        let centers = positions
+            .iter()
+            .enumerate()
+            .map(|(index, &position)| VoronoiCenter { position, index })
+            .collect();
+        let voronoi = Voronoi::new(uvec2(45, 31), centers).algorithm(Algorithm::JumpFlood);
+        let gpu = match gpu() {
+            Some(gpu) => gpu,
+            None => return,
+        };
+        let expected = voronoi.generate();
+        let r = voronoi.generate_on(&Backend::Gpu(Box::new(gpu)));
+        assert_eq!(r.map, expected.map);
+        assert_eq!(r.distances, expected.distances);
+    }
+
+    #[test]
+    fn jump_flood_fallback() {
+        let voronoi = Voronoi::new(uvec2(8, 8), vec![]).algorithm(Algorithm::JumpFlood);
+        let r = voronoi.generate_on(&Backend::Cpu);
+        assert!(r.map.iter().all(|&i| i == crate::voronoi::OUTSIDE));
+    }
+
+    #[test]
+    fn jump_flood_gpu_fallback() {
+        let voronoi = Voronoi::new(uvec2(8, 8), vec![]).algorithm(Algorithm::JumpFlood);
+        let gpu = match gpu() {
+            Some(gpu) => gpu,
+            None => return,
+        };
+        assert!(gpu.jump_flood(&voronoi).is_none());
+        let r = voronoi.generate_on(&Backend::Gpu(Box::new(gpu)));
+        assert!(r.map.iter().all(|&i| i == crate::voronoi::OUTSIDE));
+    }
+
+    #[test]
+    fn convolution_matches_cpu() {
+        let gpu = match gpu() {
+            Some(gpu) => gpu,
+            None => return,
+        };
+        let a = noise(uvec2(37, 20), true).normalization(Normalization::UnitRange).generate();
+        let kernel = Array2::from_shape_fn((3, 4), |(i, j)| (i + 2 * j) as f64 / 10.0);
+        for edge in [Edge::Fill(0.5), Edge::Clamp, Edge::Wrap] {
+            let r = gpu.convolve(&a, &kernel, &edge).unwrap();
+            assert!(max_difference(&r, &filter::convolve(&a, &kernel, &edge)) < 1e-5);
+            let r = gpu.gaussian_blur(&a, 2.5, &edge).unwrap();
+            assert!(max_difference(&r, &filter::gaussian_blur(&a, 2.5, &edge)) < 1e-5);
+        }
+        let backend = Backend::Gpu(Box::new(gpu));
+        let r = filter::gaussian_blur_on(&backend, &a, 1.0, &Edge::Wrap);
+        assert!(max_difference(&r, &filter::gaussian_blur(&a, 1.0, &Edge::Wrap)) < 1e-5);
+    }
+
+    #[test]
+    fn convolution_fallback() {
+        let a = noise(uvec2(12, 9), true).normalization(Normalization::UnitRange).generate();
+        let kernel = Array2::from_elem((3, 3), 1.0 / 9.0);
+        let r = filter::convolve_on(&Backend::Cpu, &a, &kernel, &Edge::Clamp);
+        assert_eq!(r, filter::convolve(&a, &kernel, &Edge::Clamp));
+        let r = filter::gaussian_blur_on(&Backend::Cpu, &a, 1.5, &Edge::Wrap);
+        assert_eq!(r, filter::gaussian_blur(&a, 1.5, &Edge::Wrap));
+    }
+
+    #[test]
+    fn convolution_gpu_fallback() {
+        let gpu = match gpu() {
+            Some(gpu) => gpu,
+            None => return,
+        };
+        let empty = Array2::zeros((0, 4));
+        assert!(gpu.convolve(&empty, &Array2::ones((1, 1)), &Edge::Clamp).is_none());
+        let backend = Backend::Gpu(Box::new(gpu));
+        assert!(filter::gaussian_blur_on(&backend, &empty, 1.0, &Edge::Clamp).is_empty());
+    }
+}
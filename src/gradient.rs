@@ -0,0 +1,241 @@
+
+use crate::colored_noise::ColoredNoise;
+use crate::map_stack::MapStack;
+use crate::seed::derive_seed;
+use glam::{dvec2, uvec2, DVec2, UVec2};
+use ndarray::Array2;
+
+/// Shape of a gradient field. Positions are in tile coordinates.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GradientShape {
+    /// 0.0 at `center`, growing linearly with euclidean distance to 1.0 at `radius`
+    Radial { center: DVec2, radius: f64 },
+
+    /// 0.0 at `from`, 1.0 at `to`, linear along the line between them (clamped beyond)
+    Linear { from: DVec2, to: DVec2 },
+
+    /// 0.0 inside the rectangle spanned by `min` and `max`, growing to 1.0 at `falloff` tiles
+    /// (euclidean) outside of it
+    Rectangle { min: DVec2, max: DVec2, falloff: f64 },
+
+    /// 0.0 at `center`, growing linearly with chebyshev distance (scaled per axis) to 1.0 on
+    /// the border of the rectangle `center ± half_size`. Axes with a half size of 0 are
+    /// ignored.
+    Square { center: DVec2, half_size: DVec2 },
+
+    /// Distance in tiles to the nearest map border (0.0 on the border itself).
+    /// This one is not normalized.
+    EdgeDistance,
+
+    /// 1.0 on the map border, fading linearly to 0.0 at `width` tiles away from it
+    EdgeFade { width: f64 },
+}
+
+/// Field generator for shaping islands and fading effects towards borders.
+/// Uses the same conventions as `ColoredNoise` (`size` and `seed` fields, `generate()`).
+/// Without `perturbation` (the default) the field does not depend on `seed`.
+#[derive(Clone)]
+pub struct Gradient {
+    pub size: UVec2,
+    pub shape: GradientShape,
+    pub seed: u64,
+    /// Maximum displacement in tiles of the position every tile is evaluated at, by colored
+    /// noise derived from `seed`. Makes eg. radial island shapes less regular.
+    pub perturbation: f64,
+}
+
+impl Default for Gradient {
+    fn default() -> Self {
+        let size = uvec2(100, 100);
+        Self {
+            size,
+            shape: GradientShape::Radial { center: size.as_dvec2() / 2.0, radius: 50.0 },
+            seed: 0,
+            perturbation: 0.0,
+        }
+    }
+}
+
+impl Gradient {
+    /// Radial gradient centered in a map of the given size, reaching 1.0 at the
+    /// middle of the shorter edges.
+    pub fn centered_radial(size: UVec2) -> Self {
+        let center = (size.as_dvec2() - DVec2::ONE) / 2.0;
+        Self {
+            size,
+            shape: GradientShape::Radial { center, radius: center.min_element().max(1.0) },
+            ..Default::default()
+        }
+    }
+
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    pub fn perturbation(mut self, perturbation: f64) -> Self {
+        self.perturbation = perturbation;
+        self
+    }
+
+    pub fn generate(&self) -> Array2<f64> {
+        let shape = (self.size.x as usize, self.size.y as usize);
+        if self.perturbation == 0.0 {
+            return Array2::from_shape_fn(shape, |(x, y)| self.value(x, y));
+        }
+
+        let noise = |n| {
+            ColoredNoise { size: self.size, seed: derive_seed(self.seed, n), ..Default::default() }.generate()
+        };
+        let (dx, dy) = (noise(0), noise(1));
+        Array2::from_shape_fn(shape, |(x, y)| {
+            // Noise is in [0, 1), map to [-perturbation, perturbation)
+            let d = dvec2(dx[[x, y]], dy[[x, y]]) * 2.0 - 1.0;
+            self.value_at(dvec2(x as f64, y as f64) + d * self.perturbation)
+        })
+    }
+
+    /// Generate a gradient of the size of `layers` and store it as layer `name`.
+    pub fn generate_into_layer(&self, layers: &mut MapStack, name: &str) {
+        let g = Self { size: layers.size(), ..self.clone() };
+        layers.insert(name, g.generate());
+    }
+
+    /// Value of the field at tile `(x, y)`, without `perturbation`.
+    pub fn value(&self, x: usize, y: usize) -> f64 {
+        self.value_at(dvec2(x as f64, y as f64))
+    }
+
+    /// Value of the field at position `p` (in tile coordinates), without `perturbation`.
+    pub fn value_at(&self, p: DVec2) -> f64 {
+        match self.shape {
+            GradientShape::Radial { center, radius } => {
+                if radius <= 0.0 {
+                    return 1.0;
+                }
+                (p.distance(center) / radius).min(1.0)
+            }
+            GradientShape::Linear { from, to } => {
+                let d = to - from;
+                let l2 = d.length_squared();
+                if l2 == 0.0 {
+                    return 0.0;
+                }
+                ((p - from).dot(d) / l2).clamp(0.0, 1.0)
+            }
+            GradientShape::Rectangle { min, max, falloff } => {
+                let outside = (min - p).max(p - max).max(DVec2::ZERO).length();
+                if outside == 0.0 {
+                    0.0
+                } else if falloff <= 0.0 {
+                    1.0
+                } else {
+                    (outside / falloff).min(1.0)
+                }
+            }
+            GradientShape::Square { center, half_size } => {
+                let d = (p - center).abs();
+                let n = |d: f64, h: f64| if h > 0.0 { d / h } else { 0.0 };
+                n(d.x, half_size.x).max(n(d.y, half_size.y)).min(1.0)
+            }
+            GradientShape::EdgeDistance => self.edge_distance(p),
+            GradientShape::EdgeFade { width } => {
+                if width <= 0.0 {
+                    return 0.0;
+                }
+                (1.0 - self.edge_distance(p) / width).max(0.0)
+            }
+        }
+    }
+
+    /// Distance of `p` to the nearest map border, 0.0 on and beyond the border tiles
+    fn edge_distance(&self, p: DVec2) -> f64 {
+        let end = self.size.as_dvec2() - DVec2::ONE;
+        p.min(end - p).min_element().max(0.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gradient(size: UVec2, shape: GradientShape) -> Array2<f64> {
+        Gradient { size, shape, ..Default::default() }.generate()
+    }
+
+    fn shapes(size: UVec2) -> Vec<GradientShape> {
+        let c = (size.as_dvec2() - DVec2::ONE) / 2.0;
+        vec![
+            GradientShape::Radial { center: c, radius: 4.0 },
+            GradientShape::Linear { from: DVec2::ZERO, to: size.as_dvec2() },
+            GradientShape::Rectangle { min: c - 1.0, max: c + 1.0, falloff: 3.0 },
+            GradientShape::Square { center: c, half_size: c },
+            GradientShape::EdgeFade { width: 3.0 },
+        ]
+    }
+
+    #[test]
+    fn normalized_range() {
+        for size in [uvec2(1, 1), uvec2(1, 9), uvec2(12, 7), uvec2(20, 20)] {
+            for shape in shapes(size) {
+                let a = gradient(size, shape);
+                assert_eq!(a.dim(), (size.x as usize, size.y as usize));
+                assert!(a.iter().all(|v| (0.0..=1.0).contains(v)), "{:?} of size {}", shape, size);
+
+                let a = Gradient { size, shape, seed: 3, perturbation: 2.0 }.generate();
+                assert!(a.iter().all(|v| (0.0..=1.0).contains(v)), "{:?} of size {}", shape, size);
+            }
+        }
+    }
+
+    #[test]
+    fn radial_and_linear() {
+        let a = Gradient::centered_radial(uvec2(11, 11)).generate();
+        assert_eq!(a[[5, 5]], 0.0);
+        assert_eq!(a[[0, 5]], 1.0);
+        assert_eq!(a[[5, 10]], 1.0);
+        assert_eq!(a[[3, 5]], a[[5, 7]]);
+
+        let a = gradient(uvec2(9, 3), GradientShape::Linear { from: dvec2(2.0, 0.0), to: dvec2(6.0, 0.0) });
+        assert_eq!(a.column(1).to_vec(), vec![0.0, 0.0, 0.0, 0.25, 0.5, 0.75, 1.0, 1.0, 1.0]);
+        assert_eq!(a.column(1), a.column(0));
+    }
+
+    #[test]
+    fn rectangle_and_square() {
+        let rectangle = GradientShape::Rectangle { min: dvec2(3.0, 3.0), max: dvec2(5.0, 6.0), falloff: 2.0 };
+        let a = gradient(uvec2(10, 10), rectangle);
+        assert!(a.slice(ndarray::s![3..=5, 3..=6]).iter().all(|&v| v == 0.0));
+        assert_eq!(a[[2, 4]], 0.5);
+        assert_eq!(a[[0, 4]], 1.0);
+
+        let size = uvec2(9, 5);
+        let c = dvec2(4.0, 2.0);
+        let a = gradient(size, GradientShape::Square { center: c, half_size: c });
+        assert_eq!(a[[4, 2]], 0.0);
+        for (p, &v) in a.indexed_iter() {
+            let border = p.0 == 0 || p.1 == 0 || p.0 == 8 || p.1 == 4;
+            assert_eq!(v == 1.0, border, "{} at {:?}", v, p);
+        }
+    }
+
+    #[test]
+    fn edge_distance_and_fade() {
+        let d = gradient(uvec2(7, 5), GradientShape::EdgeDistance);
+        assert_eq!(d.column(2).to_vec(), vec![0.0, 1.0, 2.0, 2.0, 2.0, 1.0, 0.0]);
+        let f = gradient(uvec2(7, 5), GradientShape::EdgeFade { width: 2.0 });
+        assert_eq!(f, d.mapv(|d| 1.0 - d / 2.0));
+        assert!(gradient(uvec2(7, 5), GradientShape::EdgeFade { width: 0.0 }).iter().all(|&v| v == 0.0));
+    }
+
+    #[test]
+    fn perturbation_is_seeded() {
+        let g = Gradient::centered_radial(uvec2(32, 24));
+        assert_eq!(g.clone().seed(5).generate(), g.generate());
+
+        let p = g.clone().perturbation(3.0);
+        assert_eq!(p.clone().seed(1).generate(), p.clone().seed(1).generate());
+        assert_ne!(p.clone().seed(1).generate(), p.clone().seed(2).generate());
+        assert_ne!(p.seed(1).generate(), g.generate());
+    }
+}
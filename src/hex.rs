@@ -0,0 +1,245 @@
+
+//! Hex grids (pointy top).
+//!
+//! Hex maps can be stored in an `Array2` in two ways:
+//!
+//! * Axial coordinates `(q, r)`: neighbors are at fixed offsets, so all neighborhood based
+//!   machinery works unchanged with `distance` as metric, eg.
+//!   `Neighborhood::with_shape(&a, p, 1, hex::distance)` yields the 6 neighbors and
+//!   `WaveFunctionCollapseConfiguration::metric(hex::distance)` runs WFC on the hex grid.
+//!   The map covers a rhombus shaped area.
+//! * Offset coordinates (odd rows shifted right by half a hex, "odd-r"): the map covers a
+//!   rectangular area, use `offset_to_axial`/`axial_to_offset` to convert positions.
+
+use crate::coord::UCoord2Conversions;
+use glam::{dvec2, ivec2, DVec2, IVec2, UVec2};
+use ndarray::Array2;
+
+/// Axial offsets of the 6 neighbors, counter-clockwise starting east.
+pub const DIRECTIONS: [IVec2; 6] = [
+    IVec2::new(1, 0),
+    IVec2::new(1, -1),
+    IVec2::new(0, -1),
+    IVec2::new(-1, 0),
+    IVec2::new(-1, 1),
+    IVec2::new(0, 1),
+];
+
+/// Number of hex steps for an axial offset. Can be used as a `neighborhood::Metric`.
+pub fn distance(o: IVec2) -> u32 {
+    (o.x.unsigned_abs() + o.y.unsigned_abs() + (o.x + o.y).unsigned_abs()) / 2
+}
+
+/// All hexes at exactly `radius` steps from `center` (axial), counter-clockwise.
+/// For `radius` 0 this is just `center`.
+pub fn ring(center: IVec2, radius: u32) -> Vec<IVec2> {
+    if radius == 0 {
+        return vec![center];
+    }
+    let mut r = Vec::with_capacity(6 * radius as usize);
+    // Start south-west of center and walk along the 6 sides
+    let mut h = center + DIRECTIONS[4] * radius as i32;
+    for d in DIRECTIONS {
+        for _ in 0..radius {
+            r.push(h);
+            h += d;
+        }
+    }
+    r
+}
+
+/// All hexes within `radius` steps from `center` (axial), ring by ring from the inside out.
+pub fn spiral(center: IVec2, radius: u32) -> Vec<IVec2> {
+    (0..=radius).flat_map(|k| ring(center, k)).collect()
+}
+
+/// Convert odd-r offset coordinates to axial coordinates.
+pub fn offset_to_axial(p: UVec2) -> IVec2 {
+    let p = p.as_ivec2();
+    ivec2(p.x - (p.y - (p.y & 1)) / 2, p.y)
+}
+
+/// Convert axial coordinates to odd-r offset coordinates, `None` if the result would be
+/// negative.
+pub fn axial_to_offset(a: IVec2) -> Option<UVec2> {
+    let p = ivec2(a.x + (a.y - (a.y & 1)) / 2, a.y);
+    p.cmpge(IVec2::ZERO).all().then(|| p.as_uvec2())
+}
+
+/// Center of the hex at odd-r offset position `p`, in units where hexes are 1 wide.
+/// Rows are `sqrt(3) / 2` apart.
+pub fn center(p: UVec2) -> DVec2 {
+    dvec2(
+        p.x as f64 + 0.5 + 0.5 * (p.y & 1) as f64,
+        (p.y as f64 + 0.5) * 3.0_f64.sqrt() / 2.0,
+    )
+}
+
+/// Sample a square grid field (eg. noise) at the centers of an odd-r offset hex map of size
+/// `size`, so that the result is not distorted by the row shift.
+/// The field is stretched to cover all hex centers, values are interpolated bilinearly.
+/// An empty field gives NaN everywhere.
+pub fn sample(field: &Array2<f64>, size: UVec2) -> Array2<f64> {
+    if field.is_empty() {
+        return Array2::from_elem(size.as_index2(), f64::NAN);
+    }
+    let (fx, fy) = field.dim();
+    let extent = dvec2(size.x as f64 + 0.5, size.y as f64 * 3.0_f64.sqrt() / 2.0);
+    let scale = dvec2(fx as f64, fy as f64) / extent;
+
+    Array2::from_shape_fn(size.as_index2(), |(x, y)| {
+        let c = center(UVec2::new(x as u32, y as u32)) * scale - 0.5;
+        let x0 = (c.x.floor().max(0.0) as usize).min(fx - 1);
+        let y0 = (c.y.floor().max(0.0) as usize).min(fy - 1);
+        let x1 = (x0 + 1).min(fx - 1);
+        let y1 = (y0 + 1).min(fy - 1);
+        let tx = (c.x - x0 as f64).clamp(0.0, 1.0);
+        let ty = (c.y - y0 as f64).clamp(0.0, 1.0);
+
+        let top = field[[x0, y0]] * (1.0 - tx) + field[[x1, y0]] * tx;
+        let bottom = field[[x0, y1]] * (1.0 - tx) + field[[x1, y1]] * tx;
+        top * (1.0 - ty) + bottom * ty
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::automaton::CellularAutomaton;
+    use crate::neighborhood::Neighborhood;
+    use crate::tile::{Tile, TileEnum};
+    use crate::wave_function_collapse::DefaultProbabilityCallback;
+    use glam::uvec2;
+
+    crate::tile_enum! {
+        #[derive(Debug)]
+        enum Color { Red, Green, Blue }
+    }
+
+    #[test]
+    fn axial_distance() {
+        assert_eq!(distance(IVec2::ZERO), 0);
+        assert!(DIRECTIONS.iter().all(|&d| distance(d) == 1));
+        assert_eq!(distance(ivec2(1, 1)), 2);
+        assert_eq!(distance(ivec2(2, -1)), 2);
+        assert_eq!(distance(ivec2(3, -3)), 3);
+        assert_eq!(distance(ivec2(-2, -1)), 3);
+        assert_eq!(distance(ivec2(4, -7)), distance(ivec2(-4, 7)));
+    }
+
+    #[test]
+    fn rings_and_spirals() {
+        let center = ivec2(2, -3);
+        assert_eq!(ring(center, 0), vec![center]);
+        for radius in 1..5 {
+            let r = ring(center, radius);
+            assert_eq!(r.len(), 6 * radius as usize);
+            assert!(r.iter().all(|&h| distance(h - center) == radius));
+            // Consecutive hexes (and the last and the first) are neighbors
+            assert!(r.iter().zip(r.iter().cycle().skip(1)).all(|(&a, &b)| distance(b - a) == 1));
+
+            let s = spiral(center, radius);
+            assert_eq!(s.len(), 1 + 3 * (radius * (radius + 1)) as usize);
+            let mut sorted = s.clone();
+            sorted.sort_by_key(|h| (h.x, h.y));
+            sorted.dedup();
+            assert_eq!(sorted.len(), s.len());
+            assert!(s.windows(2).all(|w| distance(w[0] - center) <= distance(w[1] - center)));
+        }
+    }
+
+    #[test]
+    fn offset_axial_round_trip() {
+        for y in 0..6 {
+            for x in 0..6 {
+                let p = uvec2(x, y);
+                assert_eq!(axial_to_offset(offset_to_axial(p)), Some(p));
+            }
+        }
+        // Odd rows are shifted right: the east and north east neighbors of (1, 1) are (2, 1)
+        // and (2, 0), on even rows they are straight east and north
+        assert_eq!(axial_to_offset(offset_to_axial(uvec2(1, 1)) + DIRECTIONS[1]), Some(uvec2(2, 0)));
+        assert_eq!(axial_to_offset(offset_to_axial(uvec2(1, 2)) + DIRECTIONS[1]), Some(uvec2(1, 1)));
+        assert_eq!(axial_to_offset(ivec2(0, -1)), None);
+        assert_eq!(axial_to_offset(ivec2(-1, 1)), None);
+        assert_eq!(axial_to_offset(ivec2(-1, 2)), Some(uvec2(0, 2)));
+    }
+
+    #[test]
+    fn centers_and_sampling() {
+        assert_eq!(center(uvec2(0, 0)), dvec2(0.5, 3.0_f64.sqrt() / 4.0));
+        assert_eq!(center(uvec2(0, 1)).x, 1.0);
+        // Neighboring hex centers are 1 apart
+        let a = center(uvec2(1, 1));
+        assert!((a.distance(center(uvec2(2, 0))) - 1.0).abs() < 1e-12);
+        assert!((a.distance(center(uvec2(1, 2))) - 1.0).abs() < 1e-12);
+
+        let constant = sample(&Array2::from_elem((7, 4), 0.25), uvec2(5, 6));
+        assert_eq!(constant.dim(), (5, 6));
+        assert!(constant.iter().all(|v| (v - 0.25).abs() < 1e-12));
+        // A gradient along x stays monotonic along every row
+        let gradient = sample(&Array2::from_shape_fn((8, 8), |(x, _)| x as f64), uvec2(6, 4));
+        assert!(gradient.columns().into_iter().all(|r| r.windows(2).into_iter().all(|w| w[0] <= w[1])));
+
+        let empty = sample(&Array2::zeros((0, 3)), uvec2(2, 3));
+        assert_eq!(empty.dim(), (2, 3));
+        assert!(empty.iter().all(|v| v.is_nan()));
+        assert!(sample(&Array2::zeros((3, 3)), UVec2::ZERO).is_empty());
+    }
+
+    /// A cellular automaton with `distance` as metric grows a single cell hex by hex
+    #[test]
+    fn automaton_on_axial_map() {
+        let automaton = CellularAutomaton::new(|t: Color, n: &Neighborhood<Color>| {
+            match t == Color::Red || n.iter().flatten().any(|t| t == Color::Red) {
+                true => Color::Red,
+                false => Color::Green,
+            }
+        })
+        .with_shape(1, distance);
+        let center = ivec2(5, 5);
+        let mut a = Array2::from_elem((11, 11), Color::Green.as_numeric());
+        a[center.as_uvec2().as_index2()] = Color::Red.as_numeric();
+        for radius in 1..4 {
+            automaton.run::<Color>(&mut a, 1);
+            let red: Vec<IVec2> = a
+                .indexed_iter()
+                .filter(|(_, &t)| t == Color::Red.as_numeric())
+                .map(|((x, y), _)| ivec2(x as i32, y as i32))
+                .collect();
+            assert_eq!(red.len(), spiral(center, radius).len());
+            assert!(red.iter().all(|&h| distance(h - center) <= radius));
+        }
+    }
+
+    /// No tile equals any of its 6 neighbors
+    fn distinct(n: &Neighborhood<Color>) -> [f32; 3] {
+        let mut p = [1.0; 3];
+        for t in n.iter().flatten().filter(|t| t.is_valid()) {
+            p[t.as_usize()] = 0.0;
+        }
+        p
+    }
+
+    /// Wave function collapse with `distance` as metric colors the hex grid (axial) with 3
+    /// colors, which needs all 6 neighbors to be constrained
+    #[test]
+    fn wfc_on_axial_map() {
+        for seed in 0..5 {
+            let mut c = Color::configuration()
+                .probability(distinct as DefaultProbabilityCallback<Color, 3>)
+                .metric(distance)
+                .max_bombings(100)
+                .retries(5);
+            c.size = uvec2(8, 6);
+            c.seed = seed;
+            let mut w = c.build();
+            w.generate().unwrap();
+            for ((x, y), &t) in w.tiles.indexed_iter() {
+                let n = Neighborhood::<Color>::with_shape(&w.tiles, ivec2(x as i32, y as i32), 1, distance);
+                assert_eq!(n.iter().count(), 6);
+                assert!(n.iter().flatten().all(|u| u.as_numeric() != t), "seed {} at {}, {}", seed, x, y);
+            }
+        }
+    }
+}
@@ -0,0 +1,151 @@
+
+use crate::coord::{map_size, MapAccess};
+use crate::mask::label_components;
+use crate::neighborhood::{chebyshev, offsets};
+use float_ord::FloatOrd;
+use glam::{IVec2, UVec2};
+use ndarray::{Array2, Zip};
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+/// Result of `fill_depressions`.
+#[derive(Clone, Debug)]
+pub struct Lakes {
+    /// Heightmap with all depressions filled, so that every tile has a downhill (or, for
+    /// `epsilon` 0, flat) path to an outlet
+    pub filled: Array2<f64>,
+    /// Tiles that were below the water surface of a lake
+    pub mask: Array2<bool>,
+    /// Lake id per tile (4-connected lake tiles), 0 = no lake, lakes are numbered from 1
+    pub ids: Array2<usize>,
+    /// Water surface elevation per lake id (index 0 unused)
+    pub levels: Vec<f64>,
+}
+
+/// Fill depressions of heightmap `height` with the priority-flood algorithm.
+///
+/// Water leaves the map at the map border and at `outlets` (eg. the ocean, must have the
+/// size of the map). Every basin that has no downhill path to an outlet is filled up to its
+/// spill point and becomes a lake.
+///
+/// With `epsilon > 0`, filled areas get a tiny slope of `epsilon` per tile towards the
+/// spill point, so that flow routing (eg. rivers following steepest descent) does not stall
+/// on flat lake surfaces. Lakes are determined independently of `epsilon`.
+pub fn fill_depressions(height: &Array2<f64>, outlets: Option<&Array2<bool>>, epsilon: f64) -> Lakes {
+    if let Some(o) = outlets {
+        assert_eq!(o.dim(), height.dim());
+    }
+
+    let flat = priority_flood(height, outlets, 0.0);
+    let mask = Zip::from(&flat).and(height).map_collect(|&f, &h| f > h);
+    let (ids, sizes) = label_components(&mask);
+    let mut levels = vec![f64::NEG_INFINITY; sizes.len()];
+    Zip::from(&ids).and(&flat).for_each(|&id, &f| {
+        if id > 0 {
+            levels[id] = levels[id].max(f);
+        }
+    });
+    levels[0] = 0.0;
+
+    let filled = if epsilon > 0.0 { priority_flood(height, outlets, epsilon) } else { flat };
+    Lakes { filled, mask, ids, levels }
+}
+
+fn priority_flood(height: &Array2<f64>, outlets: Option<&Array2<bool>>, epsilon: f64) -> Array2<f64> {
+    let size = map_size(height);
+    let mut filled = height.clone();
+    let mut done = Array2::from_elem(height.dim(), false);
+
+    // Lowest first, ties in insertion order for determinism
+    let mut queue = BinaryHeap::new();
+    let mut counter = 0_u64;
+    let mut push = |queue: &mut BinaryHeap<_>, p: UVec2, h: f64| {
+        queue.push(Reverse((FloatOrd(h), counter, p.x, p.y)));
+        counter += 1;
+    };
+
+    for (p, &h) in height.iter_with_positions() {
+        let border = p.x == 0 || p.y == 0 || p.x + 1 == size.x || p.y + 1 == size.y;
+        let outlet = outlets.is_some_and(|o| o[[p.x as usize, p.y as usize]]);
+        if border || outlet {
+            done[[p.x as usize, p.y as usize]] = true;
+            push(&mut queue, p, h);
+        }
+    }
+
+    while let Some(Reverse((FloatOrd(h), _, x, y))) = queue.pop() {
+        for o in offsets(1, chebyshev) {
+            let q = IVec2::new(x as i32, y as i32) + o;
+            let qh = match height.get_at(q) {
+                Some(&qh) => qh,
+                None => continue,
+            };
+            let qi = [q.x as usize, q.y as usize];
+            if done[qi] {
+                continue;
+            }
+            done[qi] = true;
+            filled[qi] = qh.max(h + epsilon);
+            push(&mut queue, q.as_uvec2(), filled[qi]);
+        }
+    }
+    filled
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 7x5 map of height 5 with two basins: (1..3, 1..4) at height 1 and (4..6, 1..4) at
+    /// height 2, the right one spilling over a gap of height 3 at (6, 2)
+    fn basins() -> Array2<f64> {
+        Array2::from_shape_fn((7, 5), |(x, y)| match (x, y) {
+            (6, 2) => 3.0,
+            (1..=2, 1..=3) => 1.0,
+            (4..=5, 1..=3) => 2.0,
+            _ => 5.0,
+        })
+    }
+
+    #[test]
+    fn basins_are_filled_to_their_spill_point() {
+        let height = basins();
+        let lakes = fill_depressions(&height, None, 0.0);
+        assert_eq!(lakes.levels, vec![0.0, 5.0, 3.0]);
+        assert_eq!(lakes.ids[[1, 1]], 1);
+        assert_eq!(lakes.ids[[5, 3]], 2);
+        assert_eq!(lakes.mask.iter().filter(|&&m| m).count(), 12);
+        for ((p, &f), &h) in lakes.filled.indexed_iter().zip(&height) {
+            let level = lakes.levels[lakes.ids[p]];
+            assert_eq!(f, if lakes.mask[p] { level } else { h });
+        }
+    }
+
+    #[test]
+    fn outlets_drain_basins() {
+        let mut outlets = Array2::from_elem((7, 5), false);
+        outlets[[2, 2]] = true;
+        let lakes = fill_depressions(&basins(), Some(&outlets), 0.0);
+        assert_eq!(lakes.levels, vec![0.0, 3.0]);
+        assert!(!lakes.mask[[1, 1]]);
+        assert!(lakes.mask[[4, 1]]);
+        assert_eq!(lakes.filled[[1, 1]], 1.0);
+    }
+
+    #[test]
+    fn epsilon_slopes_towards_the_spill_point() {
+        let height = basins();
+        let lakes = fill_depressions(&height, None, 0.01);
+        assert_eq!(lakes.levels, fill_depressions(&height, None, 0.0).levels);
+
+        // Every inner tile has a strictly lower neighbor, so that water always flows off the map
+        let size = map_size(&height);
+        for (p, &f) in lakes.filled.iter_with_positions() {
+            if p.x == 0 || p.y == 0 || p.x + 1 == size.x || p.y + 1 == size.y {
+                continue;
+            }
+            let lower = offsets(1, chebyshev).any(|o| lakes.filled.get_at(p.as_ivec2() + o).is_some_and(|&g| g < f));
+            assert!(lower, "no downhill neighbor at {p}");
+        }
+    }
+}
@@ -0,0 +1,216 @@
+
+//! Plain text grids.
+//!
+//! Rows of the text are rows of the map (constant `y`), ie. the tile `a[[x, y]]` is the
+//! `x`-th value in line `y`.
+//!
+//! Besides delimiter separated values (CSV, TSV) there is a char grid format with one
+//! character per tile, as commonly used for hand-authored roguelike prefabs:
+//!
+//! ```text
+//! #####
+//! #..+.
+//! #####
+//! ```
+
+use ndarray::Array2;
+use std::fmt::{self, Display};
+use std::io::{self, Write};
+use std::str::FromStr;
+
+/// Error when parsing a grid. Line and column numbers are 1-based.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum GridError {
+    /// The text contains no rows
+    Empty,
+    /// Line `line` has `found` values instead of `expected`
+    Ragged { line: usize, expected: usize, found: usize },
+    /// The value at `line`, `column` could not be parsed
+    Value { line: usize, column: usize, text: String },
+}
+
+impl fmt::Display for GridError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GridError::Empty => write!(f, "empty grid"),
+            GridError::Ragged { line, expected, found } => {
+                write!(f, "line {}: expected {} values, found {}", line, expected, found)
+            }
+            GridError::Value { line, column, text } => {
+                write!(f, "line {}, column {}: invalid value '{}'", line, column, text)
+            }
+        }
+    }
+}
+
+impl std::error::Error for GridError {}
+
+/// Write `a` with values separated by `separator` (eg. `','` for CSV, `'\t'` for TSV).
+pub fn write<W: Write, T: Display>(mut w: W, a: &Array2<T>, separator: char) -> io::Result<()> {
+    let (width, height) = a.dim();
+    for y in 0..height {
+        for x in 0..width {
+            if x > 0 {
+                write!(w, "{}", separator)?;
+            }
+            write!(w, "{}", a[[x, y]])?;
+        }
+        writeln!(w)?;
+    }
+    Ok(())
+}
+
+/// `write` into a string.
+pub fn to_string<T: Display>(a: &Array2<T>, separator: char) -> String {
+    let mut r = Vec::new();
+    write(&mut r, a, separator).expect("Writing to a Vec does not fail");
+    String::from_utf8(r).expect("Display produces valid UTF-8")
+}
+
+/// Parse values separated by `separator`. Values are trimmed, empty lines are skipped and all
+/// lines must have the same number of values.
+pub fn parse<T: FromStr>(text: &str, separator: char) -> Result<Array2<T>, GridError> {
+    let rows = text
+        .lines()
+        .enumerate()
+        .filter(|(_, l)| !l.trim().is_empty())
+        .map(|(i, l)| {
+            l.split(separator)
+                .enumerate()
+                .map(|(j, v)| {
+                    let v = v.trim();
+                    v.parse().map_err(|_| GridError::Value { line: i + 1, column: j + 1, text: v.to_string() })
+                })
+                .collect::<Result<Vec<T>, _>>()
+                .map(|row| (i + 1, row))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    from_rows(rows)
+}
+
+/// Write `a` as char grid, using `f` to map tiles to characters.
+pub fn write_chars<W, T, F>(mut w: W, a: &Array2<T>, f: F) -> io::Result<()>
+where
+    W: Write,
+    F: Fn(&T) -> char,
+{
+    let (width, height) = a.dim();
+    for y in 0..height {
+        let row: String = (0..width).map(|x| f(&a[[x, y]])).collect();
+        writeln!(w, "{}", row)?;
+    }
+    Ok(())
+}
+
+/// Parse a char grid, using `f` to map characters to tiles (`None` for invalid characters).
+/// Empty lines are ignored, all lines must have the same length. Whitespace is passed to `f`
+/// like any other character, so a space can be a tile.
+pub fn parse_chars<T, F>(text: &str, f: F) -> Result<Array2<T>, GridError>
+where
+    F: Fn(char) -> Option<T>,
+{
+    let rows = text
+        .lines()
+        .enumerate()
+        .map(|(i, l)| (i, l.strip_suffix('\r').unwrap_or(l)))
+        .filter(|(_, l)| !l.is_empty())
+        .map(|(i, l)| {
+            l.chars()
+                .enumerate()
+                .map(|(j, c)| f(c).ok_or_else(|| GridError::Value { line: i + 1, column: j + 1, text: c.to_string() }))
+                .collect::<Result<Vec<T>, _>>()
+                .map(|row| (i + 1, row))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    from_rows(rows)
+}
+
+/// Assemble (line number, row) pairs into a map indexed `[[x, y]]`.
+fn from_rows<T>(rows: Vec<(usize, Vec<T>)>) -> Result<Array2<T>, GridError> {
+    let width = rows.first().ok_or(GridError::Empty)?.1.len();
+    let height = rows.len();
+    let mut values = Vec::with_capacity(width * height);
+    for (line, row) in rows {
+        if row.len() != width {
+            return Err(GridError::Ragged { line, expected: width, found: row.len() });
+        }
+        values.extend(row);
+    }
+    // Values are in row-major order, ie. (y, x)
+    let a = Array2::from_shape_vec((height, width), values).expect("Shape matches number of values");
+    Ok(a.reversed_axes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 3x2 map with tile value 10 * x + y
+    fn map() -> Array2<i32> {
+        Array2::from_shape_fn((3, 2), |(x, y)| 10 * x as i32 + y as i32)
+    }
+
+    #[test]
+    fn csv_round_trip() {
+        let a = map();
+        let text = to_string(&a, ',');
+        assert_eq!(text, "0,10,20\n1,11,21\n");
+        assert_eq!(parse::<i32>(&text, ','), Ok(a.clone()));
+        assert_eq!(parse::<i32>(&to_string(&a, '\t'), '\t'), Ok(a));
+    }
+
+    #[test]
+    fn parse_trims_and_skips_empty_lines() {
+        let a = parse::<i32>("\n 0 , 10,20\r\n\n1,11 ,21\n\n", ',').unwrap();
+        assert_eq!(a, map());
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert_eq!(parse::<i32>("", ','), Err(GridError::Empty));
+        assert_eq!(parse::<i32>(" \n\n", ','), Err(GridError::Empty));
+        assert_eq!(parse::<i32>("1,2\n3\n", ','), Err(GridError::Ragged { line: 2, expected: 2, found: 1 }));
+        assert_eq!(
+            parse::<i32>("1,2\n\n3,x\n", ','),
+            Err(GridError::Value { line: 3, column: 2, text: "x".to_string() })
+        );
+    }
+
+    fn tile(c: char) -> Option<u8> {
+        match c {
+            '#' => Some(1),
+            '.' => Some(0),
+            ' ' => Some(2),
+            _ => None,
+        }
+    }
+
+    fn char_of(t: &u8) -> char {
+        ['.', '#', ' '][*t as usize]
+    }
+
+    #[test]
+    fn chars_round_trip() {
+        let a = parse_chars("####\n#. .\r\n####\n", tile).unwrap();
+        assert_eq!(a.dim(), (4, 3));
+        assert_eq!(a[[1, 1]], 0);
+        assert_eq!(a[[2, 1]], 2);
+        assert_eq!(a.column(0).to_vec(), vec![1, 1, 1, 1]);
+
+        let mut out = Vec::new();
+        write_chars(&mut out, &a, char_of).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "####\n#. .\n####\n");
+    }
+
+    #[test]
+    fn chars_errors() {
+        assert_eq!(parse_chars("", tile), Err(GridError::Empty));
+        assert_eq!(parse_chars("##\n#\n", tile), Err(GridError::Ragged { line: 2, expected: 2, found: 1 }));
+        assert_eq!(
+            parse_chars("##\n#x\n", tile),
+            Err(GridError::Value { line: 2, column: 2, text: "x".to_string() })
+        );
+        // Trailing whitespace is part of the grid
+        assert!(matches!(parse_chars("##\n## \n", tile), Err(GridError::Ragged { .. })));
+    }
+}
@@ -0,0 +1,103 @@
+
+//! Export of generated maps for consumption by Godot.
+//!
+//! A map is exported as two parts:
+//!
+//! * A flat buffer of `i32` tile ids in row-major order, ie. the tile at `(x, y)` is found at
+//!   index `y * width + x`. As bytes this is a sequence of little-endian 32 bit integers, which
+//!   GDScript can read with `StreamPeerBuffer.get_32()` or directly turn into a
+//!   `PoolIntArray` (Godot 3) / `PackedInt32Array` (Godot 4, `to_int32_array()`).
+//! * JSON metadata describing the size, the memory layout and (optionally) names for tile ids,
+//!   loadable with `JSON.parse()`.
+
+use ndarray::Array2;
+use std::io::{self, Write};
+
+pub struct GodotExport {
+    pub width: usize,
+    pub height: usize,
+
+    /// Tile ids in row-major order (x varies fastest)
+    pub data: Vec<i32>,
+
+    /// Optional human readable names for tile ids, written to the metadata
+    pub tile_names: Vec<(i32, String)>,
+}
+
+impl GodotExport {
+    /// Convert map `a` (indexed as `a[[x, y]]`) using `f` to map tiles to ids.
+    pub fn from_map<T, F>(a: &Array2<T>, f: F) -> Self
+    where
+        F: Fn(&T) -> i32,
+    {
+        let (width, height) = a.dim();
+        let mut data = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                data.push(f(&a[[x, y]]));
+            }
+        }
+
+        Self {
+            width,
+            height,
+            data,
+            tile_names: Vec::new(),
+        }
+    }
+
+    /// Add a name for tile id `id` to the metadata.
+    pub fn tile_name(mut self, id: i32, name: &str) -> Self {
+        self.tile_names.push((id, name.to_string()));
+        self
+    }
+
+    /// The tile buffer as little-endian bytes.
+    pub fn data_bytes(&self) -> Vec<u8> {
+        self.data.iter().flat_map(|v| v.to_le_bytes()).collect()
+    }
+
+    /// Metadata as JSON string, eg.
+    /// `{"width": 3, "height": 2, "layout": "row_major", "format": "int32_le", "tiles": {"0": "Water"}}`
+    pub fn metadata_json(&self) -> String {
+        let tiles: Vec<_> = self
+            .tile_names
+            .iter()
+            .map(|(id, name)| format!("{}: {}", json_string(&id.to_string()), json_string(name)))
+            .collect();
+
+        format!(
+            "{{\"width\": {}, \"height\": {}, \"layout\": \"row_major\", \"format\": \"int32_le\", \"tiles\": {{{}}}}}",
+            self.width,
+            self.height,
+            tiles.join(", ")
+        )
+    }
+
+    pub fn write_data<W: Write>(&self, mut w: W) -> io::Result<()> {
+        w.write_all(&self.data_bytes())
+    }
+
+    pub fn write_metadata<W: Write>(&self, mut w: W) -> io::Result<()> {
+        w.write_all(self.metadata_json().as_bytes())
+    }
+}
+
+/// Quote & escape `s` as JSON string literal.
+pub(crate) fn json_string(s: &str) -> String {
+    let mut r = String::with_capacity(s.len() + 2);
+    r.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => r.push_str("\\\""),
+            '\\' => r.push_str("\\\\"),
+            '\n' => r.push_str("\\n"),
+            '\r' => r.push_str("\\r"),
+            '\t' => r.push_str("\\t"),
+            c if (c as u32) < 0x20 => r.push_str(&format!("\\u{:04x}", c as u32)),
+            c => r.push(c),
+        }
+    }
+    r.push('"');
+    r
+}
@@ -0,0 +1,145 @@
+
+//! 16 bit heightmap export and import for game engines (Unity/Unreal terrain, Bevy
+//! heightfields, ...).
+//!
+//! Heights are mapped linearly from a `HeightRange` to `0..=65535`, values outside the range
+//! are clamped. Raw files contain little-endian `u16` values in row-major order (the tile at
+//! `(x, y)` is value number `y * width + x`) without a header.
+
+use crate::io::png::{self, PngError};
+use glam::UVec2;
+use ndarray::Array2;
+use std::io::{self, Read, Write};
+
+/// Heights mapped to 0 and 65535 respectively.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct HeightRange {
+    pub min: f64,
+    pub max: f64,
+}
+
+impl Default for HeightRange {
+    /// [0, 1], the range of normalized noise
+    fn default() -> Self {
+        Self { min: 0.0, max: 1.0 }
+    }
+}
+
+impl HeightRange {
+    pub fn new(min: f64, max: f64) -> Self {
+        Self { min, max }
+    }
+
+    /// The range of the values of `a`, so that the full 16 bit precision is used.
+    pub fn of(a: &Array2<f64>) -> Self {
+        let min = a.iter().copied().fold(f64::INFINITY, f64::min);
+        let max = a.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+        Self { min, max }
+    }
+
+    pub fn to_u16(&self, h: f64) -> u16 {
+        let d = self.max - self.min;
+        let t = if d > 0.0 { (h - self.min) / d } else { 0.0 };
+        (t.clamp(0.0, 1.0) * 65535.0).round() as u16
+    }
+
+    pub fn to_f64(&self, v: u16) -> f64 {
+        self.min + (self.max - self.min) * v as f64 / 65535.0
+    }
+
+    pub fn quantize(&self, a: &Array2<f64>) -> Array2<u16> {
+        a.mapv(|h| self.to_u16(h))
+    }
+
+    pub fn dequantize(&self, a: &Array2<u16>) -> Array2<f64> {
+        a.mapv(|v| self.to_f64(v))
+    }
+}
+
+pub fn write_png<W: Write>(w: W, a: &Array2<f64>, range: HeightRange) -> io::Result<()> {
+    png::write_gray16(w, &range.quantize(a))
+}
+
+/// Read a grayscale PNG (8 bit images are read with reduced precision).
+pub fn read_png<R: Read>(r: R, range: HeightRange) -> Result<Array2<f64>, PngError> {
+    png::read_gray16(r).map(|a| range.dequantize(&a))
+}
+
+pub fn write_raw<W: Write>(mut w: W, a: &Array2<f64>, range: HeightRange) -> io::Result<()> {
+    let (width, height) = a.dim();
+    let mut bytes = Vec::with_capacity(width * height * 2);
+    for y in 0..height {
+        for x in 0..width {
+            bytes.extend_from_slice(&range.to_u16(a[[x, y]]).to_le_bytes());
+        }
+    }
+    w.write_all(&bytes)
+}
+
+/// Read a raw heightmap of the given size, the size is not stored in the file.
+/// Fails with `UnexpectedEof` if the file is too short.
+pub fn read_raw<R: Read>(mut r: R, size: UVec2, range: HeightRange) -> io::Result<Array2<f64>> {
+    let (width, height) = (size.x as usize, size.y as usize);
+    let mut bytes = vec![0_u8; width * height * 2];
+    r.read_exact(&mut bytes)?;
+    Ok(Array2::from_shape_fn((width, height), |(x, y)| {
+        let i = 2 * (y * width + x);
+        range.to_f64(u16::from_le_bytes([bytes[i], bytes[i + 1]]))
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use glam::uvec2;
+
+    /// Heights from -20 to a bit over 100, mostly between quantization steps
+    fn heights() -> Array2<f64> {
+        Array2::from_shape_fn((9, 6), |(x, y)| -20.0 + (x * 6 + y) as f64 * 120.0 / 53.0 + 0.001 * y as f64)
+    }
+
+    #[test]
+    fn quantization() {
+        let range = HeightRange::new(-10.0, 10.0);
+        assert_eq!(range.to_u16(-10.0), 0);
+        assert_eq!(range.to_u16(10.0), 65535);
+        assert_eq!(range.to_u16(-50.0), 0);
+        assert_eq!(range.to_u16(50.0), 65535);
+        assert_eq!(range.to_f64(65535), 10.0);
+        assert_eq!(HeightRange::new(1.0, 1.0).to_u16(3.0), 0);
+
+        let range = HeightRange::of(&heights());
+        assert_eq!(range.min, -20.0);
+        assert!((range.max - 100.005).abs() < 1e-9);
+    }
+
+    #[test]
+    fn png_round_trip() {
+        let a = heights();
+        let range = HeightRange::of(&a);
+        let mut bytes = Vec::new();
+        write_png(&mut bytes, &a, range).unwrap();
+        let b = read_png(&bytes[..], range).unwrap();
+        let step = (range.max - range.min) / 65535.0;
+        assert_eq!(b.dim(), a.dim());
+        assert!(a.iter().zip(b.iter()).all(|(x, y)| (x - y).abs() <= step / 2.0 + 1e-9));
+        assert_eq!(range.quantize(&b), range.quantize(&a));
+    }
+
+    #[test]
+    fn raw_round_trip() {
+        let a = heights();
+        let range = HeightRange::of(&a);
+        let mut bytes = Vec::new();
+        write_raw(&mut bytes, &a, range).unwrap();
+        assert_eq!(bytes.len(), 9 * 6 * 2);
+        // Row-major, little-endian: the second value is the tile at (1, 0)
+        assert_eq!(u16::from_le_bytes([bytes[2], bytes[3]]), range.to_u16(a[[1, 0]]));
+
+        let b = read_raw(&bytes[..], uvec2(9, 6), range).unwrap();
+        assert_eq!(range.quantize(&b), range.quantize(&a));
+
+        let e = read_raw(&bytes[..], uvec2(9, 7), range).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
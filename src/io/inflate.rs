@@ -0,0 +1,451 @@
+
+//! Minimal deflate decoder (RFC 1951) with gzip container support (RFC 1952), for reading
+//! compressed formats such as REXPaint `.xp` files without a compression dependency.
+//! Favors simplicity over speed.
+//!
+//! The decoders take a limit for the decompressed size, so that a small corrupt or malicious
+//! stream can't make them allocate unbounded memory. For writing, `gzip_stored` wraps data in
+//! uncompressed deflate blocks.
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+/// Order in which code length code lengths are stored in dynamic blocks
+const CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+const TOO_LARGE: &str = "decompressed data exceeds the size limit";
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    bit: u32,
+}
+
+impl<'a> BitReader<'a> {
+    fn bit(&mut self) -> Result<u32, &'static str> {
+        let byte = *self.data.get(self.pos).ok_or("unexpected end of data")?;
+        let r = (byte as u32 >> self.bit) & 1;
+        self.bit += 1;
+        if self.bit == 8 {
+            self.bit = 0;
+            self.pos += 1;
+        }
+        Ok(r)
+    }
+
+    /// `n` bits, least significant first
+    fn bits(&mut self, n: u8) -> Result<u32, &'static str> {
+        let mut r = 0;
+        for i in 0..n {
+            r |= self.bit()? << i;
+        }
+        Ok(r)
+    }
+
+    fn align(&mut self) {
+        if self.bit > 0 {
+            self.bit = 0;
+            self.pos += 1;
+        }
+    }
+}
+
+/// Canonical huffman code
+struct Huffman {
+    /// Number of codes per length
+    counts: [u16; 16],
+    /// Symbols ordered by code
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Self {
+        let mut counts = [0_u16; 16];
+        for &l in lengths {
+            counts[l as usize] += 1;
+        }
+        counts[0] = 0;
+
+        let mut symbols: Vec<u16> = (0..lengths.len() as u16).filter(|&s| lengths[s as usize] > 0).collect();
+        symbols.sort_by_key(|&s| lengths[s as usize]);
+        Self { counts, symbols }
+    }
+
+    fn decode(&self, r: &mut BitReader) -> Result<u16, &'static str> {
+        // Codes of each length are consecutive, starting at `first`
+        let (mut code, mut first, mut index) = (0_i32, 0_i32, 0_i32);
+        for &count in self.counts[1..].iter() {
+            code |= r.bit()? as i32;
+            let count = count as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err("invalid huffman code")
+    }
+}
+
+/// Decompress a raw deflate stream of at most `max_len` bytes.
+pub(crate) fn inflate(data: &[u8], max_len: usize) -> Result<Vec<u8>, &'static str> {
+    inflate_stream(&mut BitReader { data, pos: 0, bit: 0 }, max_len)
+}
+
+/// Decompress the deflate stream at the position of `r`, leaving `r` after its last block.
+fn inflate_stream(r: &mut BitReader, max_len: usize) -> Result<Vec<u8>, &'static str> {
+    let data = r.data;
+    let mut out = Vec::new();
+
+    loop {
+        let last = r.bit()? == 1;
+        match r.bits(2)? {
+            0 => {
+                r.align();
+                let header = data.get(r.pos..r.pos + 4).ok_or("unexpected end of data")?;
+                let len = u16::from_le_bytes([header[0], header[1]]);
+                let nlen = u16::from_le_bytes([header[2], header[3]]);
+                if len != !nlen {
+                    return Err("invalid stored block length");
+                }
+                r.pos += 4;
+                let block = data.get(r.pos..r.pos + len as usize).ok_or("unexpected end of data")?;
+                if block.len() > max_len - out.len() {
+                    return Err(TOO_LARGE);
+                }
+                out.extend_from_slice(block);
+                r.pos += len as usize;
+            }
+            1 => {
+                let mut lengths = [0_u8; 288];
+                lengths[..144].fill(8);
+                lengths[144..256].fill(9);
+                lengths[256..280].fill(7);
+                lengths[280..].fill(8);
+                inflate_block(r, &mut out, max_len, &Huffman::new(&lengths), &Huffman::new(&[5; 30]))?;
+            }
+            2 => {
+                let (literals, distances) = read_dynamic_codes(r)?;
+                inflate_block(r, &mut out, max_len, &literals, &distances)?;
+            }
+            _ => return Err("invalid block type"),
+        }
+        if last {
+            return Ok(out);
+        }
+    }
+}
+
+fn read_dynamic_codes(r: &mut BitReader) -> Result<(Huffman, Huffman), &'static str> {
+    let n_literals = r.bits(5)? as usize + 257;
+    let n_distances = r.bits(5)? as usize + 1;
+    let n_code_lengths = r.bits(4)? as usize + 4;
+
+    let mut code_lengths = [0_u8; 19];
+    for &i in CODE_LENGTH_ORDER[..n_code_lengths].iter() {
+        code_lengths[i] = r.bits(3)? as u8;
+    }
+    let code_lengths = Huffman::new(&code_lengths);
+
+    let mut lengths = Vec::with_capacity(n_literals + n_distances);
+    while lengths.len() < n_literals + n_distances {
+        let (value, repeat) = match code_lengths.decode(r)? {
+            s @ 0..=15 => (s as u8, 1),
+            16 => (*lengths.last().ok_or("repeat without previous length")?, 3 + r.bits(2)?),
+            17 => (0, 3 + r.bits(3)?),
+            _ => (0, 11 + r.bits(7)?),
+        };
+        lengths.extend(std::iter::repeat_n(value, repeat as usize));
+    }
+    if lengths.len() != n_literals + n_distances {
+        return Err("too many code lengths");
+    }
+
+    Ok((Huffman::new(&lengths[..n_literals]), Huffman::new(&lengths[n_literals..])))
+}
+
+fn inflate_block(
+    r: &mut BitReader,
+    out: &mut Vec<u8>,
+    max_len: usize,
+    literals: &Huffman,
+    distances: &Huffman,
+) -> Result<(), &'static str> {
+    loop {
+        match literals.decode(r)? {
+            s @ 0..=255 => {
+                if out.len() >= max_len {
+                    return Err(TOO_LARGE);
+                }
+                out.push(s as u8)
+            }
+            256 => return Ok(()),
+            s => {
+                let i = (s - 257) as usize;
+                if i >= LENGTH_BASE.len() {
+                    return Err("invalid length symbol");
+                }
+                let length = LENGTH_BASE[i] as usize + r.bits(LENGTH_EXTRA[i])? as usize;
+
+                let d = distances.decode(r)? as usize;
+                if d >= DISTANCE_BASE.len() {
+                    return Err("invalid distance symbol");
+                }
+                let distance = DISTANCE_BASE[d] as usize + r.bits(DISTANCE_EXTRA[d])? as usize;
+                if distance > out.len() {
+                    return Err("distance too far back");
+                }
+                if length > max_len - out.len() {
+                    return Err(TOO_LARGE);
+                }
+
+                // Copies may overlap with their own output
+                let start = out.len() - distance;
+                for k in 0..length {
+                    out.push(out[start + k]);
+                }
+            }
+        }
+    }
+}
+
+/// Decompress a gzip file (single member) of at most `max_len` bytes, checking the CRC32 and
+/// size stored after the data.
+pub(crate) fn gunzip(data: &[u8], max_len: usize) -> Result<Vec<u8>, &'static str> {
+    if data.len() < 18 || data[0..3] != [0x1f, 0x8b, 8] {
+        return Err("not a gzip file");
+    }
+    let flags = data[3];
+    let mut pos = 10;
+    let skip_zero_terminated = |pos: usize| {
+        data.get(pos..)
+            .and_then(|d| d.iter().position(|&b| b == 0))
+            .map(|n| pos + n + 1)
+            .ok_or("unexpected end of data")
+    };
+
+    if flags & 4 != 0 {
+        let extra = data.get(pos..pos + 2).ok_or("unexpected end of data")?;
+        pos += 2 + u16::from_le_bytes([extra[0], extra[1]]) as usize;
+    }
+    if flags & 8 != 0 {
+        pos = skip_zero_terminated(pos)?;
+    }
+    if flags & 16 != 0 {
+        pos = skip_zero_terminated(pos)?;
+    }
+    if flags & 2 != 0 {
+        pos += 2;
+    }
+    if pos > data.len() {
+        return Err("unexpected end of data");
+    }
+
+    let mut r = BitReader { data, pos, bit: 0 };
+    let out = inflate_stream(&mut r, max_len)?;
+    r.align();
+    let trailer = data.get(r.pos..r.pos + 8).ok_or("unexpected end of data")?;
+    if u32::from_le_bytes([trailer[0], trailer[1], trailer[2], trailer[3]]) != crc32(out.iter().copied()) {
+        return Err("checksum mismatch");
+    }
+    // The size is stored modulo 2^32
+    if u32::from_le_bytes([trailer[4], trailer[5], trailer[6], trailer[7]]) != out.len() as u32 {
+        return Err("size mismatch");
+    }
+    Ok(out)
+}
+
+/// `data` as raw deflate stream of uncompressed blocks.
+pub(crate) fn deflate_stored(data: &[u8]) -> Vec<u8> {
+    let mut r = Vec::with_capacity(data.len() + data.len() / 65535 * 5 + 5);
+    let mut blocks = data.chunks(65535).peekable();
+    if blocks.peek().is_none() {
+        r.extend_from_slice(&[1, 0, 0, 0xff, 0xff]);
+    }
+    while let Some(block) = blocks.next() {
+        r.push(blocks.peek().is_none() as u8);
+        let len = block.len() as u16;
+        r.extend_from_slice(&len.to_le_bytes());
+        r.extend_from_slice(&(!len).to_le_bytes());
+        r.extend_from_slice(block);
+    }
+    r
+}
+
+/// gzip file containing `data` in uncompressed deflate blocks.
+pub(crate) fn gzip_stored(data: &[u8]) -> Vec<u8> {
+    // No flags, no modification time, unknown OS
+    let mut r = vec![0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 255];
+    r.extend_from_slice(&deflate_stored(data));
+    r.extend_from_slice(&crc32(data.iter().copied()).to_le_bytes());
+    r.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    r
+}
+
+/// CRC-32 as used by gzip and PNG
+pub(crate) fn crc32<I: Iterator<Item = u8>>(bytes: I) -> u32 {
+    let mut crc = 0xffff_ffff_u32;
+    for b in bytes {
+        crc ^= b as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xedb8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HELLO: &[u8] = b"hello, hello, hello!";
+
+    /// `HELLO` compressed by zlib into a single fixed huffman block
+    const HELLO_FIXED: [u8; 12] = [0xcb, 0x48, 0xcd, 0xc9, 0xc9, 0xd7, 0x51, 0xc8, 0x40, 0xa2, 0x14, 0x01];
+
+    /// `HELLO` gzipped by Python with the file name "a.xp"
+    const HELLO_GZIP: [u8; 35] = [
+        0x1f, 0x8b, 0x08, 0x08, 0x00, 0x00, 0x00, 0x00, 0x02, 0xff, 0x61, 0x2e, 0x78, 0x70, 0x00, 0xcb, 0x48, 0xcd,
+        0xc9, 0xc9, 0xd7, 0x51, 0xc8, 0x40, 0xa2, 0x14, 0x01, 0xa7, 0xbb, 0xd2, 0xfe, 0x14, 0x00, 0x00, 0x00,
+    ];
+
+    /// `tiles()` compressed by zlib into a single dynamic huffman block
+    const TILES_DYNAMIC: [u8; 45] = [
+        0x45, 0x8c, 0x51, 0x12, 0x00, 0x40, 0x04, 0x42, 0x0f, 0xf3, 0xfe, 0xbb, 0xff, 0xf1, 0xd6, 0xb4, 0x05, 0x32,
+        0x54, 0x20, 0xc1, 0x40, 0x0d, 0x4c, 0x90, 0xf1, 0x16, 0xdb, 0x42, 0xc2, 0x99, 0x7b, 0x4f, 0x85, 0x8f, 0x55,
+        0xd2, 0xfa, 0xd8, 0xd6, 0xfb, 0x83, 0x9a, 0x53, 0x0f,
+    ];
+
+    /// 140 random '.' and '#' characters (xorshift32)
+    fn tiles() -> Vec<u8> {
+        let mut x = 12345_u32;
+        (0..140)
+            .map(|_| {
+                x ^= x << 13;
+                x ^= x >> 17;
+                x ^= x << 5;
+                b"..#"[x as usize % 3]
+            })
+            .collect()
+    }
+
+    /// Block type of the first block
+    fn block_type(stream: &[u8]) -> u8 {
+        (stream[0] >> 1) & 3
+    }
+
+    #[test]
+    fn stored_blocks() {
+        let data: Vec<u8> = (0..150_000).map(|i| (i % 251) as u8).collect();
+        for data in [&data[..], HELLO, &[]] {
+            let stream = deflate_stored(data);
+            assert_eq!(block_type(&stream), 0);
+            assert_eq!(inflate(&stream, data.len()).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn fixed_huffman_block() {
+        assert_eq!(block_type(&HELLO_FIXED), 1);
+        assert_eq!(inflate(&HELLO_FIXED, 100).unwrap(), HELLO);
+    }
+
+    #[test]
+    fn dynamic_huffman_block() {
+        assert_eq!(block_type(&TILES_DYNAMIC), 2);
+        assert_eq!(inflate(&TILES_DYNAMIC, 1000).unwrap(), tiles());
+    }
+
+    #[test]
+    fn size_limit() {
+        assert_eq!(inflate(&HELLO_FIXED, HELLO.len()).unwrap(), HELLO);
+        assert_eq!(inflate(&HELLO_FIXED, HELLO.len() - 1), Err(TOO_LARGE));
+        assert_eq!(inflate(&TILES_DYNAMIC, 139), Err(TOO_LARGE));
+        assert_eq!(inflate(&deflate_stored(HELLO), 3), Err(TOO_LARGE));
+        assert_eq!(gunzip(&HELLO_GZIP, 19), Err(TOO_LARGE));
+
+        let bomb = zeros(1000);
+        assert!(bomb.len() < 2000);
+        assert_eq!(inflate(&bomb, 1 << 16), Err(TOO_LARGE));
+        let out = inflate(&bomb, 1 << 20).unwrap();
+        assert_eq!(out.len(), 1 + 258 * 1000);
+        assert!(out.iter().all(|&b| b == 0));
+    }
+
+    /// Fixed huffman block of a literal 0 followed by `copies` copies of 258 bytes at distance 1
+    fn zeros(copies: usize) -> Vec<u8> {
+        let mut bits = Vec::new();
+        // Huffman codes are stored most significant bit first, other values least significant
+        let mut code = |value: u32, n: u32| bits.extend((0..n).rev().map(|i| (value >> i) & 1));
+        // Last block, type 1 (least significant bit first)
+        code(0b110, 3);
+        // Literal 0
+        code(0b0011_0000, 8);
+        for _ in 0..copies {
+            // Length symbol 285 (258 bytes) and distance symbol 0 (1 byte)
+            code(0b1100_0101, 8);
+            code(0, 5);
+        }
+        code(0, 7);
+        bits.chunks(8).map(|b| b.iter().enumerate().fold(0, |byte, (i, &bit)| byte | (bit as u8) << i)).collect()
+    }
+
+    #[test]
+    fn gzip() {
+        assert_eq!(gunzip(&HELLO_GZIP, 100).unwrap(), HELLO);
+        let data = tiles();
+        assert_eq!(gunzip(&gzip_stored(&data), 140).unwrap(), data);
+        assert_eq!(gunzip(&gzip_stored(&[]), 0).unwrap(), b"");
+    }
+
+    #[test]
+    fn truncated() {
+        for stream in [&HELLO_FIXED[..], &TILES_DYNAMIC, &deflate_stored(HELLO)] {
+            for n in 0..stream.len() {
+                assert!(inflate(&stream[..n], 1000).is_err(), "{} of {} bytes", n, stream.len());
+            }
+        }
+        for n in 0..HELLO_GZIP.len() {
+            assert!(gunzip(&HELLO_GZIP[..n], 1000).is_err(), "{} bytes", n);
+        }
+    }
+
+    #[test]
+    fn corrupt() {
+        let mut stream = deflate_stored(HELLO);
+        stream[3] ^= 1;
+        assert_eq!(inflate(&stream, 100), Err("invalid stored block length"));
+        // Reserved block type 3
+        assert_eq!(inflate(&[0x07, 0x00], 100), Err("invalid block type"));
+
+        assert_eq!(gunzip(&HELLO_GZIP[1..], 100), Err("not a gzip file"));
+        // A flipped bit in the data is caught by the checksum, a wrong size by the size
+        let mut gzip = gzip_stored(HELLO);
+        gzip[20] ^= 4;
+        assert_eq!(gunzip(&gzip, 100), Err("checksum mismatch"));
+        let mut gzip = HELLO_GZIP;
+        gzip[31] += 1;
+        assert_eq!(gunzip(&gzip, 100), Err("size mismatch"));
+
+        // Flipping any bit of a compressed stream must not panic
+        for stream in [&HELLO_FIXED[..], &TILES_DYNAMIC] {
+            for i in 0..stream.len() * 8 {
+                let mut stream = stream.to_vec();
+                stream[i / 8] ^= 1 << (i % 8);
+                let _ = inflate(&stream, 1000);
+            }
+        }
+    }
+}
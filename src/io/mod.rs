@@ -0,0 +1,9 @@
+
+pub mod godot;
+pub mod png;
+pub mod tmx;
+pub mod csv;
+pub mod rexpaint;
+pub mod prefab;
+mod inflate;
+pub mod heightmap;
@@ -0,0 +1,283 @@
+
+//! Minimal PNG encoder for previews and pipeline output.
+//!
+//! Image data is stored uncompressed (deflate "stored" blocks), so files are about as large as
+//! the raw pixels, but any PNG reader can open them and no compression library is needed.
+//! Maps are indexed as `a[[x, y]]` with `y` growing downwards in the image.
+//!
+//! Reading is supported for non-interlaced grayscale images (eg. heightmaps).
+
+use crate::io::inflate::{crc32, deflate_stored, inflate};
+use ndarray::Array2;
+use std::fmt;
+use std::io::{self, Read, Write};
+
+#[derive(Debug)]
+pub enum PngError {
+    Io(io::Error),
+    /// The file is not a valid PNG
+    Format(&'static str),
+    /// Valid PNG, but a feature that is not supported by this reader
+    Unsupported(&'static str),
+}
+
+impl fmt::Display for PngError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PngError::Io(e) => write!(f, "{}", e),
+            PngError::Format(e) => write!(f, "invalid png: {}", e),
+            PngError::Unsupported(e) => write!(f, "unsupported png: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for PngError {}
+
+impl From<io::Error> for PngError {
+    fn from(e: io::Error) -> Self {
+        PngError::Io(e)
+    }
+}
+
+/// Write `a` as 8 bit grayscale image.
+pub fn write_gray8<W: Write>(w: W, a: &Array2<u8>) -> io::Result<()> {
+    write_png(w, a.dim(), 0, 8, |x, y, row| row.push(a[[x, y]]))
+}
+
+/// Write `a` as 16 bit grayscale image.
+pub fn write_gray16<W: Write>(w: W, a: &Array2<u16>) -> io::Result<()> {
+    write_png(w, a.dim(), 0, 16, |x, y, row| row.extend_from_slice(&a[[x, y]].to_be_bytes()))
+}
+
+/// Write `a` as 8 bit RGB image.
+pub fn write_rgb8<W: Write>(w: W, a: &Array2<[u8; 3]>) -> io::Result<()> {
+    write_png(w, a.dim(), 2, 8, |x, y, row| row.extend_from_slice(&a[[x, y]]))
+}
+
+/// Write a PNG of size `(width, height)`, `pixel` appends the bytes of one pixel to the
+/// current row.
+pub(crate) fn write_png<W, F>(
+    mut w: W,
+    (width, height): (usize, usize),
+    color_type: u8,
+    bit_depth: u8,
+    pixel: F,
+) -> io::Result<()>
+where
+    W: Write,
+    F: Fn(usize, usize, &mut Vec<u8>),
+{
+    w.write_all(b"\x89PNG\r\n\x1a\n")?;
+
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&(width as u32).to_be_bytes());
+    header.extend_from_slice(&(height as u32).to_be_bytes());
+    // bit depth, color type, compression, filter, interlace
+    header.extend_from_slice(&[bit_depth, color_type, 0, 0, 0]);
+    write_chunk(&mut w, b"IHDR", &header)?;
+
+    let mut raw = Vec::new();
+    for y in 0..height {
+        // Filter type "none"
+        raw.push(0);
+        for x in 0..width {
+            pixel(x, y, &mut raw);
+        }
+    }
+    write_chunk(&mut w, b"IDAT", &zlib_stored(&raw))?;
+    write_chunk(&mut w, b"IEND", &[])
+}
+
+/// Read a non-interlaced 8 or 16 bit grayscale image. 8 bit values are scaled to the full
+/// 16 bit range.
+pub fn read_gray16<R: Read>(mut r: R) -> Result<Array2<u16>, PngError> {
+    let mut data = Vec::new();
+    r.read_to_end(&mut data)?;
+    if !data.starts_with(b"\x89PNG\r\n\x1a\n") {
+        return Err(PngError::Format("missing signature"));
+    }
+
+    let mut header = None;
+    let mut compressed = Vec::new();
+    let mut pos = 8;
+    while pos + 8 <= data.len() {
+        let len = u32::from_be_bytes([data[pos], data[pos + 1], data[pos + 2], data[pos + 3]]) as usize;
+        let kind = &data[pos + 4..pos + 8];
+        let chunk = data.get(pos + 8..pos + 8 + len).ok_or(PngError::Format("truncated chunk"))?;
+        match kind {
+            b"IHDR" if len == 13 => header = Some(chunk),
+            b"IDAT" => compressed.extend_from_slice(chunk),
+            b"IEND" => break,
+            _ => {}
+        }
+        pos += len + 12;
+    }
+
+    let header = header.ok_or(PngError::Format("missing header"))?;
+    let width = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+    let height = u32::from_be_bytes([header[4], header[5], header[6], header[7]]) as usize;
+    let (bit_depth, color_type, interlace) = (header[8], header[9], header[12]);
+    if color_type != 0 {
+        return Err(PngError::Unsupported("only grayscale images can be read"));
+    }
+    if bit_depth != 8 && bit_depth != 16 {
+        return Err(PngError::Unsupported("only 8 and 16 bit images can be read"));
+    }
+    if interlace != 0 {
+        return Err(PngError::Unsupported("interlaced images can not be read"));
+    }
+
+    let bpp = bit_depth as usize / 8;
+    let stride = width * bpp;
+    // Filter type byte and pixels per row, the decompressed data must not be larger
+    let raw_len = (stride + 1).checked_mul(height).ok_or(PngError::Format("image too large"))?;
+    // Skip the 2 byte zlib header
+    let raw = inflate(compressed.get(2..).ok_or(PngError::Format("missing image data"))?, raw_len)
+        .map_err(PngError::Format)?;
+    if raw.len() < raw_len {
+        return Err(PngError::Format("too little image data"));
+    }
+
+    let mut pixels = vec![0_u8; height * stride];
+    for y in 0..height {
+        let filter = raw[y * (stride + 1)];
+        let line = &raw[y * (stride + 1) + 1..(y + 1) * (stride + 1)];
+        let (previous, current) = pixels.split_at_mut(y * stride);
+        let up = if y > 0 { &previous[(y - 1) * stride..] } else { &[][..] };
+        let current = &mut current[..stride];
+        for i in 0..stride {
+            let a = if i >= bpp { current[i - bpp] } else { 0 };
+            let b = up.get(i).copied().unwrap_or(0);
+            let c = if i >= bpp { up.get(i - bpp).copied().unwrap_or(0) } else { 0 };
+            let predicted = match filter {
+                0 => 0,
+                1 => a,
+                2 => b,
+                3 => ((a as u16 + b as u16) / 2) as u8,
+                4 => paeth(a, b, c),
+                _ => return Err(PngError::Format("invalid filter type")),
+            };
+            current[i] = line[i].wrapping_add(predicted);
+        }
+    }
+
+    Ok(Array2::from_shape_fn((width, height), |(x, y)| match bpp {
+        1 => pixels[y * stride + x] as u16 * 257,
+        _ => u16::from_be_bytes([pixels[y * stride + 2 * x], pixels[y * stride + 2 * x + 1]]),
+    }))
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let (pa, pb, pc) = ((p - a as i16).abs(), (p - b as i16).abs(), (p - c as i16).abs());
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+fn write_chunk<W: Write>(w: &mut W, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    w.write_all(&(data.len() as u32).to_be_bytes())?;
+    w.write_all(kind)?;
+    w.write_all(data)?;
+    let crc = crc32(kind.iter().chain(data.iter()).copied());
+    w.write_all(&crc.to_be_bytes())
+}
+
+/// zlib stream containing `data` in uncompressed deflate blocks.
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut r = vec![0x78, 0x01];
+    r.extend_from_slice(&deflate_stored(data));
+    r.extend_from_slice(&adler32(data).to_be_bytes());
+    r
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1_u32, 0_u32);
+    for chunk in data.chunks(5552) {
+        for &d in chunk {
+            a += d as u32;
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+    (b << 16) | a
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ndarray::arr2;
+
+    /// The 3x2 image `[10, 20, 30] / [40, 50, 60]` with both rows filtered by the filter type
+    /// at the same index, encoded by hand
+    const FILTERED: [[[u8; 3]; 2]; 5] = [
+        [[10, 20, 30], [40, 50, 60]],
+        [[10, 10, 10], [40, 10, 10]],
+        [[10, 20, 30], [30, 30, 30]],
+        [[10, 15, 20], [35, 20, 20]],
+        [[10, 10, 10], [30, 10, 10]],
+    ];
+
+    /// Grayscale PNG of the given size and bit depth with the (filtered) image data `raw`
+    fn png(width: u32, height: u32, bit_depth: u8, raw: &[u8]) -> Vec<u8> {
+        let mut r = b"\x89PNG\r\n\x1a\n".to_vec();
+        let mut header = [width.to_be_bytes(), height.to_be_bytes()].concat();
+        header.extend_from_slice(&[bit_depth, 0, 0, 0, 0]);
+        write_chunk(&mut r, b"IHDR", &header).unwrap();
+        write_chunk(&mut r, b"IDAT", &zlib_stored(raw)).unwrap();
+        write_chunk(&mut r, b"IEND", &[]).unwrap();
+        r
+    }
+
+    #[test]
+    fn round_trips() {
+        let a = Array2::from_shape_fn((7, 5), |(x, y)| (x * 9000 + y * 1111) as u16);
+        let mut bytes = Vec::new();
+        write_gray16(&mut bytes, &a).unwrap();
+        assert_eq!(read_gray16(&bytes[..]).unwrap(), a);
+
+        let a = Array2::from_shape_fn((7, 5), |(x, y)| (x * 30 + y * 7) as u8);
+        let mut bytes = Vec::new();
+        write_gray8(&mut bytes, &a).unwrap();
+        assert_eq!(read_gray16(&bytes[..]).unwrap(), a.mapv(|v| v as u16 * 257));
+
+        let mut bytes = Vec::new();
+        write_gray16(&mut bytes, &Array2::zeros((0, 0))).unwrap();
+        assert_eq!(read_gray16(&bytes[..]).unwrap().dim(), (0, 0));
+    }
+
+    #[test]
+    fn filter_types() {
+        let expected = arr2(&[[10, 40], [20, 50], [30, 60]]).mapv(|v: u16| v * 257);
+        for (filter, rows) in FILTERED.iter().enumerate() {
+            let raw: Vec<u8> = rows.iter().flat_map(|row| [&[filter as u8], &row[..]].concat()).collect();
+            assert_eq!(read_gray16(&png(3, 2, 8, &raw)[..]).unwrap(), expected, "filter {}", filter);
+        }
+
+        // Sub with 2 bytes per pixel and wrapping: [0x0102, 0x0304, 0x0000]
+        let raw = [1, 0x01, 0x02, 0x02, 0x02, 0xfd, 0xfc];
+        assert_eq!(read_gray16(&png(3, 1, 16, &raw)[..]).unwrap(), arr2(&[[0x0102], [0x0304], [0]]));
+
+        let raw = [5, 10, 20, 30];
+        assert!(matches!(read_gray16(&png(3, 1, 8, &raw)[..]), Err(PngError::Format(_))));
+    }
+
+    #[test]
+    fn unsupported_and_invalid() {
+        let mut rgb = Vec::new();
+        write_rgb8(&mut rgb, &Array2::from_elem((2, 2), [1, 2, 3])).unwrap();
+        assert!(matches!(read_gray16(&rgb[..]), Err(PngError::Unsupported(_))));
+        assert!(matches!(read_gray16(&png(2, 1, 4, &[0, 0])[..]), Err(PngError::Unsupported(_))));
+
+        assert!(matches!(read_gray16(&b"GIF89a"[..]), Err(PngError::Format(_))));
+        let short = png(3, 2, 8, &[0, 10, 20, 30]);
+        assert!(matches!(read_gray16(&short[..]), Err(PngError::Format(_))));
+        let truncated = &png(3, 2, 8, &[0; 8])[..40];
+        assert!(matches!(read_gray16(truncated), Err(PngError::Format(_))));
+    }
+}
@@ -0,0 +1,118 @@
+
+//! Char map prefabs with a legend, eg.
+//!
+//! ```text
+//! # = wall
+//! . = floor
+//! + = door    # comments start with a '#' after the value
+//! ---
+//! #####
+//! #.+.#
+//! #####
+//! ```
+//!
+//! The legend assigns a name to every character of the grid, the names are converted to
+//! tiles by the caller. The grid follows the char grid format of `io::csv`.
+
+use crate::io::csv::{self, GridError};
+use ndarray::Array2;
+use std::fmt;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PrefabError {
+    /// No `---` line separating legend and grid
+    MissingSeparator,
+    /// Malformed legend entry (1-based line number)
+    Legend { line: usize, message: String },
+    /// Legend value that the caller could not convert to a tile
+    UnknownValue { line: usize, value: String },
+    /// Error in the grid, line numbers are relative to the file
+    Grid(GridError),
+}
+
+impl fmt::Display for PrefabError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PrefabError::MissingSeparator => write!(f, "missing '---' between legend and grid"),
+            PrefabError::Legend { line, message } => write!(f, "line {}: {}", line, message),
+            PrefabError::UnknownValue { line, value } => write!(f, "line {}: unknown value '{}'", line, value),
+            PrefabError::Grid(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for PrefabError {}
+
+/// Parse a prefab, using `f` to convert legend values to tiles.
+/// Characters in the grid that are not in the legend are an error.
+pub fn parse<T, F>(text: &str, f: F) -> Result<Array2<T>, PrefabError>
+where
+    T: Clone,
+    F: Fn(&str) -> Option<T>,
+{
+    let lines: Vec<&str> = text.lines().collect();
+    let separator = lines.iter().position(|l| l.trim() == "---").ok_or(PrefabError::MissingSeparator)?;
+
+    let mut legend = Vec::new();
+    for (i, line) in lines[..separator].iter().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let err = |message: &str| PrefabError::Legend { line: i + 1, message: message.to_string() };
+
+        // The first character is the key, it may itself be '#' or '='
+        let mut chars = line.trim_start().chars();
+        let key = chars.next().expect("Line is not empty");
+        let value = chars.as_str().trim_start().strip_prefix('=').ok_or_else(|| err("expected 'c = value'"))?;
+        let value = value.split('#').next().unwrap_or_default().trim();
+        if value.is_empty() {
+            return Err(err("missing value"));
+        }
+        if legend.iter().any(|(k, _)| *k == key) {
+            return Err(err("duplicate key"));
+        }
+        let tile = f(value).ok_or_else(|| PrefabError::UnknownValue { line: i + 1, value: value.to_string() })?;
+        legend.push((key, tile));
+    }
+
+    // Pad with empty lines so that line numbers of grid errors refer to the file
+    let grid = "\n".repeat(separator + 1) + &lines[separator + 1..].join("\n");
+    csv::parse_chars(&grid, |c| legend.iter().find(|(k, _)| *k == c).map(|(_, t)| t.clone()))
+        .map_err(PrefabError::Grid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tile(value: &str) -> Option<u8> {
+        ["floor", "wall", "door"].iter().position(|&v| v == value).map(|i| i as u8)
+    }
+
+    #[test]
+    fn legend_and_grid() {
+        let text = "# = wall\n. = floor\n\n+ = door    # comment\n---\n#####\n#.+.#\n#####\n";
+        let a = parse(text, tile).unwrap();
+        assert_eq!(a.dim(), (5, 3));
+        assert_eq!(a.column(1).to_vec(), vec![1, 0, 2, 0, 1]);
+        assert!(a.column(0).iter().all(|&t| t == 1));
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(parse("# = wall\n#\n", tile), Err(PrefabError::MissingSeparator));
+        assert!(matches!(parse("# wall\n---\n#\n", tile), Err(PrefabError::Legend { line: 1, .. })));
+        let comment_only = ". = floor\n# =   # only a comment\n---\n#\n";
+        assert!(matches!(parse(comment_only, tile), Err(PrefabError::Legend { line: 2, .. })));
+        assert!(matches!(parse("# = wall\n# = floor\n---\n#\n", tile), Err(PrefabError::Legend { line: 2, .. })));
+        assert_eq!(
+            parse("# = wall\n~ = lava\n---\n#\n", tile),
+            Err(PrefabError::UnknownValue { line: 2, value: "lava".to_string() })
+        );
+        // Grid line numbers refer to the file
+        assert_eq!(
+            parse("# = wall\n---\n##\n#.\n", tile),
+            Err(PrefabError::Grid(GridError::Value { line: 4, column: 2, text: ".".to_string() }))
+        );
+    }
+}
@@ -0,0 +1,255 @@
+
+//! Import of REXPaint `.xp` images, eg. hand-drawn vaults and prefabs.
+//!
+//! An `.xp` file is a gzip compressed stack of layers, each a grid of cells with a glyph
+//! (code point in the CP437 font), a foreground and a background color. Cells with background
+//! color `TRANSPARENT` are transparent.
+
+use crate::io::inflate::{gunzip, gzip_stored};
+use ndarray::{Array2, Zip};
+use std::fmt;
+use std::io::{self, Read, Write};
+
+/// Background color REXPaint uses for transparent cells
+pub const TRANSPARENT: [u8; 3] = [255, 0, 255];
+
+/// Largest decompressed size in bytes `XpImage::read` accepts (64 MB, eg. 2 layers of
+/// 2k x 1.5k cells), so that a corrupt or malicious file can't make it allocate unbounded
+/// memory. Use `XpImage::read_with_limit` for larger images.
+pub const MAX_BYTES: usize = 1 << 26;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct XpCell {
+    pub glyph: u32,
+    pub foreground: [u8; 3],
+    pub background: [u8; 3],
+}
+
+impl XpCell {
+    pub fn is_transparent(&self) -> bool {
+        self.background == TRANSPARENT
+    }
+
+    /// The glyph as `char`, for the printable ASCII range of CP437.
+    pub fn ascii(&self) -> Option<char> {
+        char::from_u32(self.glyph).filter(|c| c.is_ascii_graphic() || *c == ' ')
+    }
+}
+
+impl Default for XpCell {
+    /// Transparent space
+    fn default() -> Self {
+        Self { glyph: b' ' as u32, foreground: [0, 0, 0], background: TRANSPARENT }
+    }
+}
+
+#[derive(Debug)]
+pub enum XpError {
+    Io(io::Error),
+    /// The gzip stream could not be decompressed
+    Compression(&'static str),
+    /// The decompressed data is too short or has an invalid layer size
+    Format(&'static str),
+}
+
+impl fmt::Display for XpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            XpError::Io(e) => write!(f, "{}", e),
+            XpError::Compression(e) => write!(f, "decompression failed: {}", e),
+            XpError::Format(e) => write!(f, "invalid xp data: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for XpError {}
+
+impl From<io::Error> for XpError {
+    fn from(e: io::Error) -> Self {
+        XpError::Io(e)
+    }
+}
+
+struct Cursor<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn bytes(&mut self, n: usize) -> Result<&'a [u8], XpError> {
+        let r = self.data.get(self.pos..self.pos + n).ok_or(XpError::Format("unexpected end of data"))?;
+        self.pos += n;
+        Ok(r)
+    }
+
+    fn int(&mut self) -> Result<i32, XpError> {
+        self.bytes(4).map(|b| i32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+}
+
+/// A REXPaint image. Layers are indexed as `layer[[x, y]]`, the first layer is the bottom one.
+#[derive(Clone, Debug, PartialEq)]
+pub struct XpImage {
+    pub version: i32,
+    pub layers: Vec<Array2<XpCell>>,
+}
+
+impl XpImage {
+    /// Read a (gzip compressed) `.xp` file of at most `MAX_BYTES` uncompressed bytes.
+    pub fn read<R: Read>(r: R) -> Result<Self, XpError> {
+        Self::read_with_limit(r, MAX_BYTES)
+    }
+
+    /// Read a (gzip compressed) `.xp` file of at most `max_bytes` uncompressed bytes.
+    pub fn read_with_limit<R: Read>(mut r: R, max_bytes: usize) -> Result<Self, XpError> {
+        let mut data = Vec::new();
+        r.read_to_end(&mut data)?;
+        Self::from_bytes(&gunzip(&data, max_bytes).map_err(XpError::Compression)?)
+    }
+
+    /// Write a gzip compressed `.xp` file. The data is stored without compression, so files
+    /// are larger than those written by REXPaint.
+    pub fn write<W: Write>(&self, mut w: W) -> io::Result<()> {
+        w.write_all(&gzip_stored(&self.to_bytes()))
+    }
+
+    /// Uncompressed `.xp` data, see `from_bytes`.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut r = Vec::new();
+        r.extend_from_slice(&self.version.to_le_bytes());
+        r.extend_from_slice(&(self.layers.len() as i32).to_le_bytes());
+        for layer in self.layers.iter() {
+            let (width, height) = layer.dim();
+            r.extend_from_slice(&(width as i32).to_le_bytes());
+            r.extend_from_slice(&(height as i32).to_le_bytes());
+            // Column by column, see `from_bytes`
+            for c in layer.iter() {
+                r.extend_from_slice(&c.glyph.to_le_bytes());
+                r.extend_from_slice(&c.foreground);
+                r.extend_from_slice(&c.background);
+            }
+        }
+        r
+    }
+
+    /// Parse uncompressed `.xp` data.
+    pub fn from_bytes(data: &[u8]) -> Result<Self, XpError> {
+        let mut r = Cursor { data, pos: 0 };
+        let version = r.int()?;
+        let n_layers = r.int()?;
+        let mut layers = Vec::new();
+        for _ in 0..n_layers {
+            let (width, height) = (r.int()?, r.int()?);
+            if width < 0 || height < 0 {
+                return Err(XpError::Format("negative layer size"));
+            }
+            let (width, height) = (width as usize, height as usize);
+            let count = match width.checked_mul(height) {
+                Some(count) => count,
+                None => return Err(XpError::Format("layer size too large")),
+            };
+
+            // Cells are stored column by column, which is the memory order of `a[[x, y]]`
+            let mut cells = Vec::with_capacity(count.min((data.len() - r.pos) / 10));
+            for _ in 0..count {
+                let b = r.bytes(10)?;
+                cells.push(XpCell {
+                    glyph: u32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+                    foreground: [b[4], b[5], b[6]],
+                    background: [b[7], b[8], b[9]],
+                });
+            }
+            layers.push(Array2::from_shape_vec((width, height), cells).expect("Shape matches number of cells"));
+        }
+
+        if layers.windows(2).any(|l| l[0].dim() != l[1].dim()) {
+            return Err(XpError::Format("layers differ in size"));
+        }
+        Ok(Self { version, layers })
+    }
+
+    /// Combine all layers into one, taking the topmost non-transparent cell at every
+    /// position. `None` if there are no layers.
+    pub fn flatten(&self) -> Option<Array2<XpCell>> {
+        let mut r = self.layers.first()?.clone();
+        for layer in self.layers[1..].iter() {
+            Zip::from(&mut r).and(layer).for_each(|r, c| {
+                if !c.is_transparent() {
+                    *r = *c;
+                }
+            });
+        }
+        Some(r)
+    }
+
+    /// Convert the flattened image to a map with `f`, eg. mapping glyphs to tiles.
+    /// `None` if there are no layers.
+    pub fn to_map<T, F>(&self, f: F) -> Option<Array2<T>>
+    where
+        F: Fn(&XpCell) -> T,
+    {
+        self.flatten().map(|a| a.map(f))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cell(glyph: char, background: [u8; 3]) -> XpCell {
+        XpCell { glyph: glyph as u32, foreground: [1, 2, 3], background }
+    }
+
+    /// 3x2 image with a wall layer and a partially transparent item layer
+    fn image() -> XpImage {
+        let walls = Array2::from_shape_fn((3, 2), |(x, _)| cell(if x == 0 { '#' } else { '.' }, [0, 0, 0]));
+        let mut items = Array2::from_elem((3, 2), XpCell::default());
+        items[[2, 1]] = cell('$', [9, 9, 9]);
+        XpImage { version: -1, layers: vec![walls, items] }
+    }
+
+    #[test]
+    fn round_trip() {
+        let image = image();
+        let mut file = Vec::new();
+        image.write(&mut file).unwrap();
+        assert_eq!(XpImage::read(&file[..]).unwrap(), image);
+        assert_eq!(XpImage::from_bytes(&image.to_bytes()).unwrap(), image);
+        // Cells are stored column by column
+        let bytes = image.to_bytes();
+        assert_eq!(bytes[16], b'#');
+        assert_eq!(bytes[26], b'#');
+        assert_eq!(bytes[36], b'.');
+    }
+
+    #[test]
+    fn flatten() {
+        let map = image().to_map(|c| c.ascii().unwrap()).unwrap();
+        assert_eq!(map.column(0).to_vec(), vec!['#', '.', '.']);
+        assert_eq!(map.column(1).to_vec(), vec!['#', '.', '$']);
+        assert_eq!(XpImage { version: 0, layers: vec![] }.flatten(), None);
+    }
+
+    #[test]
+    fn errors() {
+        let mut file = Vec::new();
+        image().write(&mut file).unwrap();
+        let n = image().to_bytes().len();
+        assert!(XpImage::read_with_limit(&file[..], n).is_ok());
+        assert!(matches!(XpImage::read_with_limit(&file[..], n - 1), Err(XpError::Compression(_))));
+        assert!(matches!(XpImage::read(&file[..file.len() - 1]), Err(XpError::Compression(_))));
+        let last = file.len() - 9;
+        file[last] ^= 1;
+        assert!(matches!(XpImage::read(&file[..]), Err(XpError::Compression(_))));
+
+        let bytes = image().to_bytes();
+        assert!(matches!(XpImage::from_bytes(&bytes[..bytes.len() - 1]), Err(XpError::Format(_))));
+        let mut negative = bytes.clone();
+        negative[8..12].copy_from_slice(&(-3_i32).to_le_bytes());
+        assert!(matches!(XpImage::from_bytes(&negative), Err(XpError::Format(_))));
+        // A huge layer size with little data must fail without allocating the layer
+        let mut huge = bytes;
+        huge[8..16].copy_from_slice(&[0xff, 0xff, 0xff, 0x7f, 0xff, 0xff, 0xff, 0x7f]);
+        assert!(matches!(XpImage::from_bytes(&huge), Err(XpError::Format(_))));
+    }
+}
@@ -0,0 +1,109 @@
+
+//! Export of tile maps as Tiled `.tmx` files.
+//!
+//! Layers are written with CSV encoded data. Tile ids are written as global tile ids
+//! (`id + firstgid` of the tileset), so id 0 refers to the first tile of the tileset. Tiles
+//! mapped to `None` are left empty.
+
+use ndarray::Array2;
+use std::io::{self, Write};
+
+pub struct TmxExport {
+    pub width: usize,
+    pub height: usize,
+    pub tile_width: u32,
+    pub tile_height: u32,
+
+    /// Path of an external tileset (`.tsx`), relative to the map file
+    pub tileset: Option<String>,
+
+    /// (name, global tile ids in row-major order)
+    pub layers: Vec<(String, Vec<u32>)>,
+}
+
+impl TmxExport {
+    pub fn new(width: usize, height: usize, tile_width: u32, tile_height: u32) -> Self {
+        Self {
+            width,
+            height,
+            tile_width,
+            tile_height,
+            tileset: None,
+            layers: Vec::new(),
+        }
+    }
+
+    pub fn tileset(mut self, source: &str) -> Self {
+        self.tileset = Some(source.to_string());
+        self
+    }
+
+    /// Add map `a` (indexed as `a[[x, y]]`) as layer `name`, using `f` to map tiles to tile
+    /// ids. Panics if the size of `a` does not match.
+    pub fn layer<T, F>(mut self, name: &str, a: &Array2<T>, f: F) -> Self
+    where
+        F: Fn(&T) -> Option<u32>,
+    {
+        assert_eq!(a.dim(), (self.width, self.height), "Layer size mismatch for '{}'", name);
+        let mut data = Vec::with_capacity(self.width * self.height);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                data.push(f(&a[[x, y]]).map_or(0, |id| id + 1));
+            }
+        }
+        self.layers.push((name.to_string(), data));
+        self
+    }
+
+    pub fn write<W: Write>(&self, mut w: W) -> io::Result<()> {
+        writeln!(w, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>")?;
+        writeln!(
+            w,
+            "<map version=\"1.10\" orientation=\"orthogonal\" renderorder=\"right-down\" width=\"{}\" height=\"{}\" tilewidth=\"{}\" tileheight=\"{}\" infinite=\"0\" nextlayerid=\"{}\" nextobjectid=\"1\">",
+            self.width,
+            self.height,
+            self.tile_width,
+            self.tile_height,
+            self.layers.len() + 1
+        )?;
+        if let Some(source) = &self.tileset {
+            writeln!(w, " <tileset firstgid=\"1\" source={}/>", xml_attribute(source))?;
+        }
+        for (i, (name, data)) in self.layers.iter().enumerate() {
+            writeln!(
+                w,
+                " <layer id=\"{}\" name={} width=\"{}\" height=\"{}\">",
+                i + 1,
+                xml_attribute(name),
+                self.width,
+                self.height
+            )?;
+            writeln!(w, "  <data encoding=\"csv\">")?;
+            for (y, row) in data.chunks(self.width.max(1)).enumerate() {
+                let row: Vec<String> = row.iter().map(|v| v.to_string()).collect();
+                let separator = if y + 1 < self.height { "," } else { "" };
+                writeln!(w, "{}{}", row.join(","), separator)?;
+            }
+            writeln!(w, "  </data>")?;
+            writeln!(w, " </layer>")?;
+        }
+        writeln!(w, "</map>")
+    }
+}
+
+/// Quote & escape `s` as XML attribute value.
+fn xml_attribute(s: &str) -> String {
+    let mut r = String::with_capacity(s.len() + 2);
+    r.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => r.push_str("&quot;"),
+            '&' => r.push_str("&amp;"),
+            '<' => r.push_str("&lt;"),
+            '>' => r.push_str("&gt;"),
+            c => r.push(c),
+        }
+    }
+    r.push('"');
+    r
+}
@@ -6,3 +6,54 @@ pub mod neighborhood;
 pub mod coord;
 pub mod region;
 pub mod tile;
+pub mod io;
+pub mod map_stack;
+pub mod patch;
+pub mod compress;
+pub mod mask;
+pub mod gradient;
+pub mod blend;
+pub mod rect;
+pub mod quadtree;
+pub mod symmetry;
+pub mod template;
+pub mod seed;
+pub mod hex;
+pub mod bucket_queue;
+pub mod report;
+pub mod map_builder;
+pub mod config;
+pub mod palette;
+pub mod scatter;
+pub mod hydrology;
+pub mod climate;
+pub mod worms;
+pub mod territory;
+pub mod spawn;
+pub mod filter;
+#[cfg(feature = "wgpu")]
+pub mod gpu;
+pub mod veins;
+pub mod dungeon;
+pub mod progression;
+pub mod geometry;
+pub mod automaton;
+pub mod annealing;
+pub mod validate;
+pub mod testing;
+pub mod stats;
+pub mod crop;
+pub mod cliffs;
+pub mod regional;
+pub mod decorate;
+pub mod bitmask;
+pub mod versioned;
+pub mod chunk_cache;
+pub mod blocks;
+pub mod texture_synthesis;
+pub mod error;
+pub mod direction;
+pub mod transform;
+
+#[doc(hidden)]
+pub use num_traits;
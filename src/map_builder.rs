@@ -0,0 +1,291 @@
+//! High level facade for the common "heightmap, land/water, biomes, rivers" workflow.
+//!
+//! ```
+//! # use mapgen_2d::map_builder::{BiomeTable, Heightmap, MapBuilder};
+//! # use glam::uvec2;
+//! # let [OCEAN, GRASS, HILLS, PEAKS] = [0, 1, 2, 3];
+//! let map = MapBuilder::new(uvec2(64, 64))
+//!     .seed(42)
+//!     .heightmap(Heightmap::colored(-2.0))
+//!     .sea_level(0.4)
+//!     .biomes(BiomeTable::new(OCEAN).band(0.6, GRASS).band(0.8, HILLS).band(f64::INFINITY, PEAKS))
+//!     .rivers(5)
+//!     .build();
+//! # assert_eq!(map.biomes.unwrap().dim(), (64, 64));
+//! ```
+//!
+//! Everything here is assembled from the lower level modules (`colored_noise`, `mask`,
+//! `seed`), use those directly for anything the builder does not cover.
+
+use crate::colored_noise::ColoredNoise;
+use crate::coord::{map_size, MapAccess, UCoord2Conversions};
+use crate::error::MapgenError;
+use crate::hydrology::fill_depressions;
+use crate::map_stack::MapStack;
+use crate::mask::{largest_component, Falloff, MaskGenerator};
+use crate::neighborhood::{chebyshev, offsets};
+use crate::seed::derive_seed;
+use glam::UVec2;
+use ndarray::{Array2, Zip};
+use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
+
+/// Source of the heightmap.
+#[derive(Clone, Debug)]
+pub enum Heightmap {
+    /// `ColoredNoise` of the given color, seeded from the builder seed
+    Colored { color: f64 },
+    /// Use the given field, must have the size of the map
+    Custom(Array2<f64>),
+}
+
+impl Heightmap {
+    pub fn colored(color: f64) -> Self {
+        Heightmap::Colored { color }
+    }
+}
+
+/// Assigns biome ids by height. Water tiles get `water`, land tiles get the id of the first
+/// band whose upper limit is above their height.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BiomeTable {
+    pub water: u32,
+    /// (exclusive upper height limit, biome id), in ascending order of height
+    pub bands: Vec<(f64, u32)>,
+}
+
+impl BiomeTable {
+    pub fn new(water: u32) -> Self {
+        Self { water, bands: Vec::new() }
+    }
+
+    /// Add a band for land below `max_height`. Bands must be added in ascending order.
+    pub fn band(mut self, max_height: f64, biome: u32) -> Self {
+        self.bands.push((max_height, biome));
+        self
+    }
+
+    /// Biome for a tile of the given height. Land above all bands gets the last band's id
+    /// (or `water` if there are no bands).
+    pub fn biome(&self, height: f64, land: bool) -> u32 {
+        if !land {
+            return self.water;
+        }
+        self.bands
+            .iter()
+            .find(|(max, _)| height < *max)
+            .or(self.bands.last())
+            .map_or(self.water, |(_, b)| *b)
+    }
+}
+
+/// Layers produced by `MapBuilder::build`.
+#[derive(Clone, Debug)]
+pub struct GeneratedMap {
+    pub size: UVec2,
+    pub height: Array2<f64>,
+    /// `true` = land
+    pub land: Array2<bool>,
+    /// Only if `MapBuilder::biomes` was set
+    pub biomes: Option<Array2<u32>>,
+    /// Only if `MapBuilder::rivers` was set to more than 0
+    pub rivers: Option<Array2<bool>>,
+}
+
+impl GeneratedMap {
+    /// Move all layers into a `MapStack` with layers "height", "land", "biomes" and "rivers"
+    /// (the latter two only if they were generated).
+    pub fn into_map_stack(self) -> MapStack {
+        let mut layers = MapStack::new(self.size);
+        layers.insert("height", self.height);
+        layers.insert("land", self.land);
+        if let Some(biomes) = self.biomes {
+            layers.insert("biomes", biomes);
+        }
+        if let Some(rivers) = self.rivers {
+            layers.insert("rivers", rivers);
+        }
+        layers
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct MapBuilder {
+    size: UVec2,
+    seed: u64,
+    heightmap: Heightmap,
+    falloff: Falloff,
+    falloff_strength: f64,
+    sea_level: f64,
+    largest_landmass_only: bool,
+    biomes: Option<BiomeTable>,
+    rivers: usize,
+}
+
+impl MapBuilder {
+    /// Brown noise heightmap with a radial falloff and sea level 0.4, no biomes or rivers.
+    pub fn new(size: UVec2) -> Self {
+        Self {
+            size,
+            seed: 0,
+            heightmap: Heightmap::colored(-2.0),
+            falloff: Falloff::Radial,
+            falloff_strength: 0.5,
+            sea_level: 0.4,
+            largest_landmass_only: false,
+            biomes: None,
+            rivers: 0,
+        }
+    }
+
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    pub fn heightmap(mut self, heightmap: Heightmap) -> Self {
+        self.heightmap = heightmap;
+        self
+    }
+
+    /// Subtract `strength * falloff` from the heightmap, see `MaskGenerator`.
+    pub fn falloff(mut self, falloff: Falloff, strength: f64) -> Self {
+        self.falloff = falloff;
+        self.falloff_strength = strength;
+        self
+    }
+
+    /// Tiles at or above this height are land.
+    pub fn sea_level(mut self, sea_level: f64) -> Self {
+        self.sea_level = sea_level;
+        self
+    }
+
+    pub fn largest_landmass_only(mut self, largest_landmass_only: bool) -> Self {
+        self.largest_landmass_only = largest_landmass_only;
+        self
+    }
+
+    pub fn biomes(mut self, table: BiomeTable) -> Self {
+        self.biomes = Some(table);
+        self
+    }
+
+    /// Number of rivers, each flowing downhill from a random high land tile until it reaches
+    /// water or another river. Rivers flow through depressions as if they were filled with
+    /// water (see `hydrology::fill_depressions`).
+    pub fn rivers(mut self, count: usize) -> Self {
+        self.rivers = count;
+        self
+    }
+
+    /// Check the parameters for errors that would otherwise panic or produce garbage: a zero
+    /// size, a custom heightmap of the wrong size and non-finite heights or levels.
+    pub fn validate(&self) -> Result<(), MapgenError> {
+        MapgenError::check_size("size", self.size)?;
+        match &self.heightmap {
+            Heightmap::Colored { color } => {
+                ColoredNoise { size: self.size, color: *color, ..Default::default() }.validate()?
+            }
+            Heightmap::Custom(a) => {
+                if map_size(a) != self.size {
+                    return Err(MapgenError::invalid(
+                        "heightmap",
+                        format!("has size {}, the map has size {}", map_size(a), self.size),
+                    ));
+                }
+            }
+        }
+        if !self.falloff_strength.is_finite() {
+            return Err(MapgenError::invalid("falloff_strength", "must be finite"));
+        }
+        if !self.sea_level.is_finite() {
+            return Err(MapgenError::invalid("sea_level", "must be finite"));
+        }
+        Ok(())
+    }
+
+    /// Like `build`, but checks the parameters first, see `validate`.
+    pub fn try_build(&self) -> Result<GeneratedMap, MapgenError> {
+        self.validate()?;
+        Ok(self.build())
+    }
+
+    /// Panics if a custom heightmap does not have the size of the map.
+    pub fn build(&self) -> GeneratedMap {
+        let height = self.height();
+        let mut land = height.mapv(|h| h >= self.sea_level);
+        if self.largest_landmass_only {
+            land = largest_component(&land);
+        }
+
+        let biomes = self.biomes.as_ref().map(|table| {
+            Zip::from(&height).and(&land).map_collect(|&h, &l| table.biome(h, l))
+        });
+        let rivers = (self.rivers > 0).then(|| self.trace_rivers(&height, &land));
+
+        GeneratedMap { size: self.size, height, land, biomes, rivers }
+    }
+
+    fn height(&self) -> Array2<f64> {
+        match &self.heightmap {
+            Heightmap::Colored { color } => MaskGenerator {
+                noise: ColoredNoise { size: self.size, color: *color, seed: derive_seed(self.seed, 0), ..Default::default() },
+                falloff: self.falloff,
+                falloff_strength: self.falloff_strength,
+                ..Default::default()
+            }
+            .elevation(),
+            Heightmap::Custom(a) => {
+                assert_eq!(map_size(a), self.size, "Heightmap size mismatch");
+                let mut a = a.clone();
+                self.falloff.apply(&mut a, self.falloff_strength);
+                a
+            }
+        }
+    }
+
+    fn trace_rivers(&self, height: &Array2<f64>, land: &Array2<bool>) -> Array2<bool> {
+        // Follow the filled heightmap so that rivers flow through depressions rather than
+        // ending in them
+        let filled = fill_depressions(height, Some(&land.mapv(|l| !l)), 1e-6).filled;
+        let mut rivers = Array2::from_elem(height.dim(), false);
+        let mut rng = StdRng::seed_from_u64(derive_seed(self.seed, 1));
+
+        // Sources are picked from the upper half of the land height range
+        let max = land
+            .iter()
+            .zip(height.iter())
+            .filter(|(&l, _)| l)
+            .map(|(_, &h)| h)
+            .fold(f64::NEG_INFINITY, f64::max);
+        let threshold = self.sea_level + 0.5 * (max - self.sea_level);
+        let candidates: Vec<UVec2> = land
+            .iter_with_positions()
+            .filter(|&(p, &l)| l && height[p.as_index2()] >= threshold)
+            .map(|(p, _)| p)
+            .collect();
+
+        for &source in candidates.choose_multiple(&mut rng, self.rivers) {
+            let mut p = source;
+            loop {
+                if !land[p.as_index2()] || rivers[p.as_index2()] {
+                    break;
+                }
+                rivers[p.as_index2()] = true;
+
+                let next = offsets(1, chebyshev)
+                    .filter_map(|o| {
+                        let q = p.as_ivec2() + o;
+                        filled.get_at(q).map(|&h| (q.as_uvec2(), h))
+                    })
+                    .filter(|&(_, h)| h < filled[p.as_index2()])
+                    .min_by(|a, b| a.1.total_cmp(&b.1));
+                match next {
+                    Some((q, _)) => p = q,
+                    None => break,
+                }
+            }
+        }
+        rivers
+    }
+}
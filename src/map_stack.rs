@@ -0,0 +1,119 @@
+
+use crate::coord::map_size;
+use glam::UVec2;
+use ndarray::{Array2, Zip};
+use std::any::Any;
+
+/// A stack of named, equally sized map layers (terrain, moisture, objects, collision, ...).
+/// Each layer is an `Array2<T>` where `T` may differ between layers.
+/// Layers keep their insertion order. Element types must be `Send + Sync` so that stacks can
+/// be shared between threads (eg. as guidance of a shared wave function collapse configuration).
+pub struct MapStack {
+    size: UVec2,
+    layers: Vec<(String, Box<dyn Any + Send + Sync>)>,
+}
+
+impl MapStack {
+    pub fn new(size: UVec2) -> Self {
+        Self {
+            size,
+            layers: Vec::new(),
+        }
+    }
+
+    pub fn size(&self) -> UVec2 {
+        self.size
+    }
+
+    /// Insert layer `name`, replacing any previous layer of the same name (regardless of its
+    /// element type).
+    /// Panics if the size of `layer` does not match the size of the stack.
+    pub fn insert<T: Send + Sync + 'static>(&mut self, name: &str, layer: Array2<T>) {
+        assert_eq!(map_size(&layer), self.size, "Layer size mismatch for '{}'", name);
+
+        match self.layers.iter_mut().find(|(n, _)| n == name) {
+            Some((_, l)) => *l = Box::new(layer),
+            None => self.layers.push((name.to_string(), Box::new(layer))),
+        }
+    }
+
+    /// Insert a new layer of the given name filled with `value`.
+    pub fn insert_filled<T: Clone + Send + Sync + 'static>(&mut self, name: &str, value: T) {
+        let layer = Array2::from_elem((self.size.x as usize, self.size.y as usize), value);
+        self.insert(name, layer);
+    }
+
+    /// Remove layer `name` and return it if it exists and has element type `T`.
+    /// If it exists with a different element type, it is left untouched.
+    pub fn remove<T: 'static>(&mut self, name: &str) -> Option<Array2<T>> {
+        let i = self.layers.iter().position(|(n, l)| n == name && l.is::<Array2<T>>())?;
+        let (_, l) = self.layers.remove(i);
+        l.downcast().ok().map(|b| *b)
+    }
+
+    /// Layer `name`, if it exists and has element type `T`.
+    pub fn get<T: 'static>(&self, name: &str) -> Option<&Array2<T>> {
+        self.layers
+            .iter()
+            .find(|(n, _)| n == name)
+            .and_then(|(_, l)| l.downcast_ref())
+    }
+
+    pub fn get_mut<T: 'static>(&mut self, name: &str) -> Option<&mut Array2<T>> {
+        self.layers
+            .iter_mut()
+            .find(|(n, _)| n == name)
+            .and_then(|(_, l)| l.downcast_mut())
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.layers.iter().any(|(n, _)| n == name)
+    }
+
+    /// Layer names in insertion order
+    pub fn names(&self) -> impl Iterator<Item = &str> + '_ {
+        self.layers.iter().map(|(n, _)| n.as_str())
+    }
+
+    pub fn len(&self) -> usize {
+        self.layers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.layers.is_empty()
+    }
+
+    /// Compute layer `output` element-wise from layers `a` and `b`.
+    /// Returns `false` (and does nothing) if either input layer does not exist
+    /// with the requested element type.
+    pub fn combine<A, B, C, F>(&mut self, a: &str, b: &str, output: &str, f: F) -> bool
+    where
+        A: 'static,
+        B: 'static,
+        C: Send + Sync + 'static,
+        F: Fn(&A, &B) -> C,
+    {
+        let r = match (self.get::<A>(a), self.get::<B>(b)) {
+            (Some(la), Some(lb)) => Zip::from(la).and(lb).map_collect(f),
+            _ => return false,
+        };
+        self.insert(output, r);
+        true
+    }
+
+    /// Compute layer `output` element-wise from layer `input`.
+    /// Returns `false` (and does nothing) if `input` does not exist with element type `A`.
+    pub fn map<A, C, F>(&mut self, input: &str, output: &str, f: F) -> bool
+    where
+        A: 'static,
+        C: Send + Sync + 'static,
+        F: Fn(&A) -> C,
+    {
+        let r = match self.get::<A>(input) {
+            Some(l) => l.map(f),
+            None => return false,
+        };
+        self.insert(output, r);
+        true
+    }
+}
@@ -0,0 +1,420 @@
+
+use crate::colored_noise::ColoredNoise;
+use crate::coord::{map_size, UCoord2Conversions};
+use crate::gradient::{Gradient, GradientShape};
+use crate::neighborhood::{chebyshev, manhattan, offsets};
+use glam::{ivec2, DVec2, IVec2, UVec2};
+use ndarray::{Array2, Zip};
+
+/// Shape that is subtracted from the noise to push land towards the map center: a `Gradient`
+/// fitted to the map size.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(rename_all = "snake_case"))]
+pub enum Falloff {
+    None,
+    /// Grows with euclidean distance from the center, reaches 1.0 at the middle of the shorter
+    /// edges (see `Gradient::centered_radial`)
+    Radial,
+    /// Grows with chebyshev distance from the center, reaches 1.0 on the whole border
+    Square,
+    /// 1.0 on the map border, fading to 0.0 at `width` tiles away from the border
+    Edge { width: f64 },
+}
+
+impl Falloff {
+    /// The gradient of this falloff for a map of `size`, `None` for `Falloff::None`.
+    pub fn gradient(&self, size: UVec2) -> Option<Gradient> {
+        let center = (size.as_dvec2() - DVec2::ONE) / 2.0;
+        let shape = match *self {
+            Falloff::None => return None,
+            Falloff::Radial => return Some(Gradient::centered_radial(size)),
+            Falloff::Square => GradientShape::Square { center, half_size: center },
+            Falloff::Edge { width } => GradientShape::EdgeFade { width },
+        };
+        Some(Gradient { size, shape, ..Default::default() })
+    }
+
+    /// Falloff values for a map of `size`, in [0, 1].
+    pub fn generate(&self, size: UVec2) -> Array2<f64> {
+        match self.gradient(size) {
+            Some(gradient) => gradient.generate(),
+            None => Array2::zeros(size.as_index2()),
+        }
+    }
+
+    /// Subtract `strength` times the falloff from the map `a`.
+    pub fn apply(&self, a: &mut Array2<f64>, strength: f64) {
+        if *self == Falloff::None {
+            return;
+        }
+        let falloff = self.generate(map_size(a));
+        Zip::from(a).and(&falloff).for_each(|v, &f| *v -= strength * f);
+    }
+}
+
+/// Generates land/water masks (`true` = land) for islands and continents
+/// by thresholding colored noise that has been shaped by a falloff.
+#[derive(Clone)]
+pub struct MaskGenerator {
+    pub noise: ColoredNoise,
+    pub falloff: Falloff,
+
+    /// How much of the falloff is subtracted from the noise
+    pub falloff_strength: f64,
+
+    /// Tiles with (noise - falloff_strength * falloff) >= sea_level are land
+    pub sea_level: f64,
+
+    /// Only keep the largest connected (4-neighborhood) landmass
+    pub largest_landmass_only: bool,
+}
+
+impl Default for MaskGenerator {
+    fn default() -> Self {
+        Self {
+            noise: Default::default(),
+            falloff: Falloff::Radial,
+            falloff_strength: 1.0,
+            sea_level: 0.0,
+            largest_landmass_only: false,
+        }
+    }
+}
+
+impl MaskGenerator {
+    /// The elevation field the mask is thresholded from.
+    pub fn elevation(&self) -> Array2<f64> {
+        let mut a = self.noise.generate();
+        self.falloff.apply(&mut a, self.falloff_strength);
+        a
+    }
+
+    pub fn generate(&self) -> Array2<bool> {
+        let mask = self.elevation().mapv(|v| v >= self.sea_level);
+
+        match self.largest_landmass_only {
+            true => largest_component(&mask),
+            false => mask,
+        }
+    }
+}
+
+const NEIGHBORS4: [IVec2; 4] = [ivec2(1, 0), ivec2(0, 1), ivec2(-1, 0), ivec2(0, -1)];
+
+/// Label 4-connected components of `true` tiles.
+/// Returns the label map (0 = not in mask, components are numbered from 1) and the sizes of
+/// the components (index 0 unused).
+pub fn label_components(mask: &Array2<bool>) -> (Array2<usize>, Vec<usize>) {
+    let (sx, sy) = mask.dim();
+    let mut labels = Array2::zeros((sx, sy));
+    let mut sizes = vec![0];
+    let mut stack = Vec::new();
+
+    for ((x, y), &m) in mask.indexed_iter() {
+        if !m || labels[[x, y]] != 0 {
+            continue;
+        }
+
+        let label = sizes.len();
+        let mut size = 0;
+        labels[[x, y]] = label;
+        stack.push(ivec2(x as i32, y as i32));
+
+        while let Some(p) = stack.pop() {
+            size += 1;
+            for o in NEIGHBORS4 {
+                let q = p + o;
+                if q.x < 0 || q.y < 0 || q.x >= sx as i32 || q.y >= sy as i32 {
+                    continue;
+                }
+                let idx = [q.x as usize, q.y as usize];
+                if mask[idx] && labels[idx] == 0 {
+                    labels[idx] = label;
+                    stack.push(q);
+                }
+            }
+        }
+        sizes.push(size);
+    }
+
+    (labels, sizes)
+}
+
+/// Reduce `mask` to its largest 4-connected component.
+pub fn largest_component(mask: &Array2<bool>) -> Array2<bool> {
+    let (labels, sizes) = label_components(mask);
+    let largest = match (1..sizes.len()).max_by_key(|&i| sizes[i]) {
+        Some(l) => l,
+        None => return mask.clone(),
+    };
+    labels.mapv(|l| l == largest)
+}
+
+/// Distance (8-neighborhood chamfer approximation of euclidean distance, in tiles) from every
+/// tile to the nearest `true` tile of `mask`. `f64::INFINITY` everywhere if `mask` is empty.
+pub fn distance_transform(mask: &Array2<bool>) -> Array2<f64> {
+    let (sx, sy) = mask.dim();
+    let mut d = mask.mapv(|m| if m { 0.0 } else { f64::INFINITY });
+    let diagonal = std::f64::consts::SQRT_2;
+
+    // Forward pass looks at already visited neighbors (west, north), backward pass at the rest
+    let forward = [(-1, 0, 1.0), (0, -1, 1.0), (-1, -1, diagonal), (1, -1, diagonal)];
+    for y in 0..sy {
+        for x in 0..sx {
+            relax(&mut d, x, y, &forward);
+        }
+    }
+    let backward = forward.map(|(dx, dy, c)| (-dx, -dy, c));
+    for y in (0..sy).rev() {
+        for x in (0..sx).rev() {
+            relax(&mut d, x, y, &backward);
+        }
+    }
+    d
+}
+
+fn relax(d: &mut Array2<f64>, x: usize, y: usize, neighbors: &[(i32, i32, f64)]) {
+    let (sx, sy) = d.dim();
+    for &(dx, dy, cost) in neighbors {
+        let (nx, ny) = (x as i32 + dx, y as i32 + dy);
+        if nx < 0 || ny < 0 || nx >= sx as i32 || ny >= sy as i32 {
+            continue;
+        }
+        let v = d[[nx as usize, ny as usize]] + cost;
+        if v < d[[x, y]] {
+            d[[x, y]] = v;
+        }
+    }
+}
+
+/// Surrounding tiles in cyclic order, starting east
+const RING: [IVec2; 8] = [
+    ivec2(1, 0),
+    ivec2(1, -1),
+    ivec2(0, -1),
+    ivec2(-1, -1),
+    ivec2(-1, 0),
+    ivec2(-1, 1),
+    ivec2(0, 1),
+    ivec2(1, 1),
+];
+
+/// Whether flipping tile `(x, y)` leaves the topology of `mask` unchanged, ie. does not
+/// create, remove, split or merge any 4-connected component of `true` tiles or 8-connected
+/// component of `false` tiles. Tiles outside of the map count as `false`.
+pub fn is_simple(mask: &Array2<bool>, x: usize, y: usize) -> bool {
+    let (sx, sy) = mask.dim();
+    let ring = RING.map(|o| {
+        let q = ivec2(x as i32, y as i32) + o;
+        q.x >= 0 && q.y >= 0 && q.x < sx as i32 && q.y < sy as i32 && mask[[q.x as usize, q.y as usize]]
+    });
+
+    // `true` components only touch the center if they contain one of its 4-neighbors
+    ring_components(&ring, true, |a, b| manhattan(a - b) == 1, |i| i % 2 == 0) == 1
+        && ring_components(&ring, false, |a, b| chebyshev(a - b) == 1, |_| true) == 1
+}
+
+/// Number of components of tiles with `value` among the 8 ring tiles, counting only
+/// components that contain a tile for which `touches` is true.
+fn ring_components(
+    ring: &[bool; 8],
+    value: bool,
+    adjacent: fn(IVec2, IVec2) -> bool,
+    touches: fn(usize) -> bool,
+) -> usize {
+    fn find(parent: &[usize; 8], mut i: usize) -> usize {
+        while parent[i] != i {
+            i = parent[i];
+        }
+        i
+    }
+
+    let mut parent: [usize; 8] = std::array::from_fn(|i| i);
+    for i in 0..8 {
+        for j in i + 1..8 {
+            if ring[i] == value && ring[j] == value && adjacent(RING[i], RING[j]) {
+                let (a, b) = (find(&parent, i), find(&parent, j));
+                parent[a] = b;
+            }
+        }
+    }
+
+    let mut roots: Vec<usize> = (0..8)
+        .filter(|&i| ring[i] == value && touches(i))
+        .map(|i| find(&parent, i))
+        .collect();
+    roots.sort_unstable();
+    roots.dedup();
+    roots.len()
+}
+
+/// Kind of tile produced by `Coastline`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Shore {
+    Water,
+    /// Land within `beach_width` of water
+    Beach,
+    Land,
+}
+
+/// Post-processing of land/water masks: smooths the coastline and marks a beach band along it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Coastline {
+    /// Iterations of the majority filter. A tile flips if at least 5 of its 8 neighbors
+    /// differ from it and flipping does not change the topology (see `is_simple`), so
+    /// islands, lakes and straits survive smoothing.
+    pub smoothing_iterations: u32,
+    /// Land tiles with a distance to water of at most this are beach (0.0 = no beach)
+    pub beach_width: f64,
+}
+
+impl Default for Coastline {
+    fn default() -> Self {
+        Self { smoothing_iterations: 2, beach_width: 1.5 }
+    }
+}
+
+impl Coastline {
+    /// Smooth `mask` (`true` = land).
+    pub fn smooth(&self, mask: &Array2<bool>) -> Array2<bool> {
+        let (sx, sy) = mask.dim();
+        let mut mask = mask.clone();
+        for _ in 0..self.smoothing_iterations {
+            let mut changed = false;
+            // In place, so that topology checks always see the current state
+            for y in 0..sy {
+                for x in 0..sx {
+                    let m = mask[[x, y]];
+                    let differing = offsets(1, chebyshev)
+                        .filter(|&o| {
+                            let q = ivec2(x as i32, y as i32) + o;
+                            let v = q.x >= 0 && q.y >= 0 && q.x < sx as i32 && q.y < sy as i32
+                                && mask[[q.x as usize, q.y as usize]];
+                            v != m
+                        })
+                        .count();
+                    if differing >= 5 && is_simple(&mask, x, y) {
+                        mask[[x, y]] = !m;
+                        changed = true;
+                    }
+                }
+            }
+            if !changed {
+                break;
+            }
+        }
+        mask
+    }
+
+    /// Smooth `mask` (`true` = land) and classify every tile.
+    pub fn generate(&self, mask: &Array2<bool>) -> Array2<Shore> {
+        let land = self.smooth(mask);
+        let water_distance = distance_transform(&land.mapv(|l| !l));
+        Zip::from(&land).and(&water_distance).map_collect(|&l, &d| match (l, d <= self.beach_width) {
+            (false, _) => Shore::Water,
+            (true, true) => Shore::Beach,
+            (true, false) => Shore::Land,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use glam::uvec2;
+
+    #[test]
+    fn falloff_presets() {
+        let size = uvec2(9, 7);
+        assert!(Falloff::None.generate(size).iter().all(|&v| v == 0.0));
+        for falloff in [Falloff::Radial, Falloff::Square, Falloff::Edge { width: 2.0 }] {
+            let a = falloff.generate(size);
+            assert_eq!(a.dim(), (9, 7));
+            assert!(a.iter().all(|v| (0.0..=1.0).contains(v)), "{:?}", falloff);
+            assert_eq!(a[[0, 3]], 1.0, "{:?}", falloff);
+            assert!(a[[4, 3]] < a[[1, 3]], "{:?}", falloff);
+        }
+        let square = Falloff::Square.generate(size);
+        assert_eq!(square[[4, 3]], 0.0);
+        assert!(square.row(0).iter().chain(square.column(6)).all(|&v| v == 1.0));
+    }
+
+    #[test]
+    fn falloff_pushes_land_to_center() {
+        let mut m = MaskGenerator { falloff_strength: 2.0, sea_level: 0.2, ..Default::default() };
+        m.noise.size = uvec2(40, 30);
+        let mut land = 0;
+        for seed in 0..5 {
+            m.noise.seed = seed;
+            let mask = m.generate();
+            assert!(mask.row(0).iter().chain(mask.row(39)).all(|&l| !l));
+            assert!(mask.column(0).iter().chain(mask.column(29)).all(|&l| !l));
+            land += mask.iter().filter(|&&l| l).count();
+        }
+        assert!(land > 0);
+    }
+
+    /// Parse a mask from rows of `#` (land) and `.` (water)
+    fn mask(rows: &[&str]) -> Array2<bool> {
+        Array2::from_shape_fn((rows[0].len(), rows.len()), |(x, y)| rows[y].as_bytes()[x] == b'#')
+    }
+
+    #[test]
+    fn simple_tiles() {
+        let m = mask(&["...", ".#.", "..."]);
+        // Removing the only land tile of an island removes a component
+        assert!(!is_simple(&m, 1, 1));
+        // Adding a tile next to it only grows it
+        assert!(is_simple(&m, 1, 0));
+        // A diagonal neighbor would be a new component for the 4-neighborhood
+        assert!(!is_simple(&m, 0, 0));
+
+        let m = mask(&["###", "#.#", "###"]);
+        // Filling the lake removes a water component
+        assert!(!is_simple(&m, 1, 1));
+    }
+
+    #[test]
+    fn smoothing_preserves_topology() {
+        let m = mask(&[
+            "..........",
+            ".######...",
+            ".######.#.",
+            ".##.###...",
+            ".######...",
+            "..........",
+        ]);
+        let smooth = Coastline { smoothing_iterations: 5, beach_width: 0.0 }.smooth(&m);
+        let (_, land) = label_components(&smooth);
+        let (_, water) = label_components(&smooth.mapv(|l| !l));
+        // The island at (8, 2) and the lake at (3, 3) survive
+        assert!(smooth[[8, 2]]);
+        assert!(!smooth[[3, 3]]);
+        assert_eq!(land.len() - 1, 2);
+        assert_eq!(water.len() - 1, 2);
+    }
+
+    #[test]
+    fn smoothing_removes_bumps() {
+        // Square island with a one tile bump on top and a one tile notch at the bottom
+        let mut m = Array2::from_shape_fn((12, 12), |(x, y)| (2..10).contains(&x) && (2..10).contains(&y));
+        m[[5, 1]] = true;
+        m[[6, 9]] = false;
+        let smooth = Coastline { smoothing_iterations: 3, beach_width: 0.0 }.smooth(&m);
+        assert!(!smooth[[5, 1]], "bump survived");
+        assert!(smooth[[6, 9]], "notch survived");
+        assert!(smooth[[5, 5]]);
+    }
+
+    #[test]
+    fn beach_band() {
+        let m = Array2::from_shape_fn((12, 12), |(x, y)| (2..10).contains(&x) && (2..10).contains(&y));
+        let shore = Coastline { smoothing_iterations: 0, beach_width: 2.0 }.generate(&m);
+        assert_eq!(shore[[0, 5]], Shore::Water);
+        assert_eq!(shore[[2, 5]], Shore::Beach);
+        assert_eq!(shore[[3, 5]], Shore::Beach);
+        assert_eq!(shore[[4, 5]], Shore::Land);
+        assert_eq!(shore[[5, 5]], Shore::Land);
+        assert_eq!(shore.iter().filter(|&&s| s == Shore::Water).count(), 144 - 64);
+    }
+}
@@ -1,13 +1,136 @@
-use crate::coord::{UCoord2, UCoord2Conversions};
-use glam::{ivec2, uvec2, IVec2, UVec2};
-use ndarray::{Array2, s};
+use crate::coord::{map_size, MapAccess, UCoord2Conversions};
+use glam::{ivec2, ivec3, BVec2, BVec3, IVec2, IVec3, UVec2, UVec3};
+use ndarray::{Array2, Array3};
 use std::cmp::Ord;
 use crate::tile::Tile;
+use crate::direction::Direction8;
+
+/// Distance function that determines the shape of a neighborhood:
+/// the tile at offset `o` is part of the neighborhood of radius `r` iff `0 < metric(o) <= r`.
+pub type Metric = fn(IVec2) -> u32;
+
+/// |x| + |y|, radius 1 are the 4 direct neighbors.
+pub fn manhattan(o: IVec2) -> u32 {
+    o.x.unsigned_abs() + o.y.unsigned_abs()
+}
+
+/// max(|x|, |y|), radius 1 are the 8 surrounding tiles.
+pub fn chebyshev(o: IVec2) -> u32 {
+    o.x.unsigned_abs().max(o.y.unsigned_abs())
+}
+
+/// Euclidean length, rounded up. Radius r covers all tiles within a circle of radius r.
+pub fn euclidean(o: IVec2) -> u32 {
+    let d2 = o.x.unsigned_abs().pow(2) + o.y.unsigned_abs().pow(2);
+    let mut r = (d2 as f64).sqrt() as u32;
+    while r * r < d2 {
+        r += 1;
+    }
+    r
+}
+
+/// All offsets of a neighborhood of the given radius and metric (x-major order).
+pub fn offsets(radius: u32, metric: Metric) -> impl Iterator<Item = IVec2> {
+    let r = radius as i32;
+    (-r..=r)
+        .flat_map(move |x| (-r..=r).map(move |y| ivec2(x, y)))
+        .filter(move |&o| o != IVec2::ZERO && metric(o) <= radius)
+}
+
+/// All offsets at exactly distance `r` in the given metric (x-major order), the zero offset
+/// for `r == 0`.
+pub fn ring_offsets(r: u32, metric: Metric) -> impl Iterator<Item = IVec2> {
+    let ri = r as i32;
+    (-ri..=ri)
+        .flat_map(move |x| (-ri..=ri).map(move |y| ivec2(x, y)))
+        .filter(move |&o| metric(o) == r)
+}
+
+/// All offsets of a neighborhood of the given radius and metric (like `offsets`), ordered by
+/// increasing distance, ie. ring by ring.
+pub fn spiral_offsets(radius: u32, metric: Metric) -> impl Iterator<Item = IVec2> {
+    (1..=radius).flat_map(move |r| ring_offsets(r, metric))
+}
+
+/// Map positions (and their tiles) at distance 0 to `radius` from `from`, ring by ring.
+fn spiral_in<T>(a: &Array2<T>, from: UVec2, radius: u32, metric: Metric) -> impl Iterator<Item = (UVec2, &T)> {
+    (0..=radius)
+        .flat_map(move |r| ring_offsets(r, metric))
+        .filter_map(move |o| {
+            let p = from.as_ivec2() + o;
+            a.get_at(p).map(|t| (p.as_uvec2(), t))
+        })
+}
+
+/// Position of a tile of `a` that satisfies `pred` with the smallest distance to `from` (in
+/// the given metric, `from` itself included), found by searching ring by ring.
+/// Ties are broken by x-major order of the offsets. `None` if no tile satisfies `pred`.
+pub fn find_nearest<T, F>(a: &Array2<T>, from: UVec2, pred: F, metric: Metric) -> Option<UVec2>
+where
+    F: Fn(&T) -> bool,
+{
+    // The farthest tile is one of the corners
+    let size = map_size(a).as_ivec2();
+    let corners = [ivec2(0, 0), ivec2(size.x - 1, 0), ivec2(0, size.y - 1), size - 1];
+    let max = corners.iter().map(|&c| metric(c - from.as_ivec2())).max()?;
+    spiral_in(a, from, max, metric).find(|(_, t)| pred(t)).map(|(p, _)| p)
+}
+
+/// Positions of all tiles of `a` within distance `radius` of `from` (in the given metric,
+/// `from` itself included) that satisfy `pred`, by increasing distance.
+pub fn find_all_within<T, F>(a: &Array2<T>, from: UVec2, radius: u32, pred: F, metric: Metric) -> Vec<UVec2>
+where
+    F: Fn(&T) -> bool,
+{
+    spiral_in(a, from, radius, metric).filter(|(_, t)| pred(t)).map(|(p, _)| p).collect()
+}
+
+/// Read access shared by `Neighborhood` and `Neighborhood3`, so that rules (eg. probability
+/// callbacks) can be written once for 2D and 3D maps.
+pub trait Neighbors<T: Tile> {
+    /// `IVec2` or `IVec3`
+    type Offset;
+    /// `UVec2` or `UVec3`
+    type Position;
+
+    /// Tile at `offset` relative to the position, `None` if outside of the map.
+    fn get(&self, offset: Self::Offset) -> Option<T>;
+
+    /// Iterate all neighors with their positions.
+    /// Yields `None` for positions outside of the array area.
+    fn iter_with_positions(&self) -> impl Iterator<Item = Option<(Self::Position, T)>> + '_;
+
+    /// Iterate tiles in the neighborhood.
+    /// Yields `None` for positions outside of the array area.
+    fn iter(&self) -> impl Iterator<Item = Option<T>> + '_ {
+        self.iter_with_positions().map(|o| o.map(|(_p, v)| v))
+    }
+
+    /// Count the number of tiles of type `x` in the neighborhood.
+    fn count(&self, x: T) -> usize {
+        self.iter().filter(|n| *n == Some(x)).count()
+    }
+
+    /// min/max tile value in the neighborhood.
+    /// Ignore invalid tiles.
+    /// If there are no valid tiles in the neighborhood, return `None`.
+    fn range(&self) -> Option<(T, T)> {
+        self.iter().flatten().filter(|n| n.is_valid()).fold(None, |r, n| match r {
+            None => Some((n, n)),
+            Some((a, b)) => Some((
+                a.as_numeric().min(n.as_numeric()).into(),
+                b.as_numeric().max(n.as_numeric()).into(),
+            )),
+        })
+    }
+}
 
 /// Represents the 2d neighborhood around a tile located
 /// at a certain positon in a given array.
 /// Generally, methods here will refer to the tiles around the given
 /// position, not including that tile itself.
+/// By default, the neighborhood consists of the 4 direct neighbors
+/// (radius 1, `manhattan` metric), see `with_shape`.
 pub struct Neighborhood<'a, T>
 where
     T: Tile,
@@ -15,6 +138,9 @@ where
     a: &'a Array2<T::Numeric>,
     position: IVec2,
     size: UVec2,
+    radius: u32,
+    metric: Metric,
+    wrap: BVec2,
 }
 
 impl<'a, T> Neighborhood<'a, T>
@@ -24,74 +150,82 @@ where
     /// Constructor.
     /// Note that position is signed, ie. it is allowed to be outside the array area.
     pub fn new(a: &'a Array2<T::Numeric>, position: IVec2) -> Self {
-        let size = uvec2(a.shape()[0] as u32, a.shape()[1] as u32);
+        Self::with_shape(a, position, 1, manhattan)
+    }
+
+    /// Neighborhood consisting of all tiles with `0 < metric(offset) <= radius`.
+    pub fn with_shape(a: &'a Array2<T::Numeric>, position: IVec2, radius: u32, metric: Metric) -> Self {
+        let size = map_size(a);
 
         Self {
             position,
             a,
             size,
+            radius,
+            metric,
+            wrap: BVec2::FALSE,
         }
     }
 
+    /// Wrap around the map along the x and/or y axis (as on a torus), so that tiles on the
+    /// opposite side of the map are neighbors.
+    pub fn wrapping(mut self, wrap: BVec2) -> Self {
+        self.wrap = wrap;
+        self
+    }
+
     pub fn position(&self) -> IVec2 { self.position }
 
+    pub fn radius(&self) -> u32 { self.radius }
+
+    pub fn metric(&self) -> Metric { self.metric }
+
+    pub fn wrap(&self) -> BVec2 { self.wrap }
+
+    /// Tile at `offset` relative to the position, `None` if outside of the map.
+    /// `offset` may be any offset within the bounding square of the neighborhood.
     pub fn get(&self, offset: IVec2) -> Option<T> {
-        assert!(offset.x >= -1 && offset.x <= 1);
-        assert!(offset.y >= -1 && offset.y <= 1);
+        let r = self.radius.max(1) as i32;
+        assert!(offset.x >= -r && offset.x <= r);
+        assert!(offset.y >= -r && offset.y <= r);
 
-        let p = self.position + offset;
-        match self.in_map(p) {
-            true => Some(self.a[p.as_uvec2().as_index2()].into()),
-            false => None,
-        }
+        self.resolve(offset).map(|p| self.a[p.as_index2()].into())
     }
 
-    /// min/max tile value in the neighborhood.
-    /// Ignore invalid tiles.
-    /// If there are no valid tiles in the neighborhood, return `None`.
-    pub fn range(&self) -> Option<(T, T)> {
-        let mut r = None;
-        for neighbor in self.iter() {
-            if let Some(n) = neighbor {
-                if n.is_valid() {
-                    r = match r {
-                        None => Some((n, n)),
-                        Some((a, b)) => Some((
-                            a.as_numeric().min(n.as_numeric()).into(),
-                            b.as_numeric().max(n.as_numeric()).into(),
-                        )),
-                    }
-                }
-            }
-        }
+    /// Tile of the neighbor in direction `d` (a `Direction` or `Direction8`), `None` if
+    /// outside of the map, eg. `n.get_dir(Direction::North)`.
+    pub fn get_dir<D: Into<Direction8>>(&self, d: D) -> Option<T> {
+        self.get(d.into().offset())
+    }
 
-        // Post condition
-        for neighbor in self.iter() {
-            if let Some(n) = neighbor {
-                if n.is_valid() {
-                    assert!(n.as_numeric() >= r.unwrap().0.as_numeric());
-                    assert!(n.as_numeric() <= r.unwrap().1.as_numeric());
-                }
-            }
+    /// Map position at `offset` relative to the position (taking wrapping into account),
+    /// `None` if outside of the map.
+    fn resolve(&self, offset: IVec2) -> Option<UVec2> {
+        let mut p = self.position + offset;
+        let size = self.size.as_ivec2();
+        if self.wrap.x && size.x > 0 {
+            p.x = p.x.rem_euclid(size.x);
+        }
+        if self.wrap.y && size.y > 0 {
+            p.y = p.y.rem_euclid(size.y);
         }
+        self.in_map(p).then(|| p.as_uvec2())
+    }
 
-        r
+    /// min/max tile value in the neighborhood, see `Neighbors::range`.
+    pub fn range(&self) -> Option<(T, T)> {
+        Neighbors::range(self)
     }
 
     /// Count the number of tiles of type `x` in the neighborhood.
     pub fn count(&self, x: T) -> usize {
-        self.iter()
-            .map(|neighbor| match neighbor {
-                Some(n) if n == x => 1,
-                _ => 0,
-            })
-            .sum()
+        Neighbors::count(self, x)
     }
 
     /// Iterate all neighors with their positions.
     /// Yields `None` for positions outside of the array area.
     pub fn iter_with_positions(&self) -> impl Iterator<Item = Option<(UVec2, T)>> + '_ {
-        NeighborhoodIterator::new(&self)
+        NeighborhoodIterator::new(self)
     }
 
     /// Iterate tiles in the neighborhood.
@@ -106,6 +240,21 @@ where
             .filter_map(|o| o.map(|(p, _v)| p))
     }
 
+    /// Tiles at exactly distance `r` from the position (which need not be within the
+    /// radius of the neighborhood), with their positions, see `ring_offsets`.
+    /// Positions outside of the map are skipped.
+    pub fn iter_ring(&self, r: u32) -> impl Iterator<Item = (UVec2, T)> + '_ {
+        ring_offsets(r, self.metric)
+            .filter_map(move |o| self.resolve(o).map(|p| (p, self.a[p.as_index2()].into())))
+    }
+
+    /// Tiles of the neighborhood with their positions, by increasing distance from the
+    /// position, see `spiral_offsets`. Useful to find the nearest tile with some property.
+    /// Positions outside of the map are skipped.
+    pub fn iter_spiral(&self) -> impl Iterator<Item = (UVec2, T)> + '_ {
+        (1..=self.radius).flat_map(move |r| self.iter_ring(r))
+    }
+
     fn in_map_of_size(p: IVec2, size: UVec2) -> bool {
         p.x >= 0 && p.y >= 0 && p.x < (size.x as i32) && p.y < (size.y as i32)
     }
@@ -115,15 +264,25 @@ where
     }
 }
 
-const INVALID_OFFSET: IVec2 = IVec2::new(0, 0);
-const FIRST_OFFSET: IVec2 = IVec2::new(0, 1);
-const LAST_OFFSET: IVec2 = IVec2::new(-1, 0);
+impl<T: Tile> Neighbors<T> for Neighborhood<'_, T> {
+    type Offset = IVec2;
+    type Position = UVec2;
+
+    fn get(&self, offset: IVec2) -> Option<T> {
+        Neighborhood::get(self, offset)
+    }
+
+    fn iter_with_positions(&self) -> impl Iterator<Item = Option<(UVec2, T)>> + '_ {
+        Neighborhood::iter_with_positions(self)
+    }
+}
 
 pub struct NeighborhoodIterator<'a, T>
 where
     T: Tile,
 {
     neighborhood: &'a Neighborhood<'a, T>,
+    /// Next offset to check, x-major
     offset: IVec2,
 }
 
@@ -132,9 +291,10 @@ where
     T: Tile,
 {
     pub fn new(neighborhood: &'a Neighborhood<'a, T>) -> Self {
+        let r = neighborhood.radius as i32;
         Self {
             neighborhood,
-            offset: INVALID_OFFSET,
+            offset: ivec2(-r, -r),
         }
     }
 }
@@ -147,26 +307,236 @@ where
     type Item = Option<(UVec2, T)>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let mut o = self.offset;
+        let n = self.neighborhood;
+        let r = n.radius as i32;
+
+        while self.offset.x <= r {
+            let o = self.offset;
+            self.offset = if o.y < r { ivec2(o.x, o.y + 1) } else { ivec2(o.x + 1, -r) };
 
-        if o == LAST_OFFSET {
-            return None;
+            if o != IVec2::ZERO && (n.metric)(o) <= n.radius {
+                return Some(n.resolve(o).map(|p| (p, n.a[p.as_index2()].into())));
+            }
         }
+        None
+    }
+}
+
+/// Offsets and bits of the 4-neighbor autotile mask: north (negative y) = 1, east = 2,
+/// south = 4, west = 8.
+pub const AUTOTILE4: [(IVec2, u8); 4] = [
+    (IVec2::new(0, -1), 1),
+    (IVec2::new(1, 0), 2),
+    (IVec2::new(0, 1), 4),
+    (IVec2::new(-1, 0), 8),
+];
+
+/// Offsets and bits of the 8-neighbor ("blob") autotile mask, row by row from north-west:
+/// NW = 1, N = 2, NE = 4, W = 8, E = 16, SW = 32, S = 64, SE = 128.
+pub const AUTOTILE8: [(IVec2, u8); 8] = [
+    (IVec2::new(-1, -1), 1),
+    (IVec2::new(0, -1), 2),
+    (IVec2::new(1, -1), 4),
+    (IVec2::new(-1, 0), 8),
+    (IVec2::new(1, 0), 16),
+    (IVec2::new(-1, 1), 32),
+    (IVec2::new(0, 1), 64),
+    (IVec2::new(1, 1), 128),
+];
+
+fn autotile_mask<T, F>(a: &Array2<T>, neighbors: &[(IVec2, u8)], is_same: F) -> Array2<u8>
+where
+    F: Fn(&T, &T) -> bool,
+{
+    let size = map_size(a).as_ivec2();
+    Array2::from_shape_fn(a.dim(), |(x, y)| {
+        let p = ivec2(x as i32, y as i32);
+        neighbors
+            .iter()
+            .filter(|(o, _)| {
+                let q = p + *o;
+                // Neighbors outside of the map count as the same, so that no edges are drawn
+                // along the map border
+                q.cmplt(IVec2::ZERO).any()
+                    || q.cmpge(size).any()
+                    || is_same(&a[[x, y]], &a[[q.x as usize, q.y as usize]])
+            })
+            .fold(0, |m, (_, bit)| m | bit)
+    })
+}
+
+/// 4-neighbor autotile bitmask (16 values, see `AUTOTILE4`) for every tile: a bit is set if
+/// the neighbor in that direction `is_same` as the tile. Used to pick edge sprites.
+pub fn autotile<T, F>(a: &Array2<T>, is_same: F) -> Array2<u8>
+where
+    F: Fn(&T, &T) -> bool,
+{
+    autotile_mask(a, &AUTOTILE4, is_same)
+}
+
+/// 8-neighbor autotile bitmask (see `AUTOTILE8`) for every tile, for "blob" tilesets with
+/// edge and corner sprites.
+/// Corner bits are only kept if both adjacent edges are set (a corner does not change the
+/// sprite otherwise), which leaves 47 distinct masks, see `blob_index`.
+pub fn autotile_blob<T, F>(a: &Array2<T>, is_same: F) -> Array2<u8>
+where
+    F: Fn(&T, &T) -> bool,
+{
+    let mut r = autotile_mask(a, &AUTOTILE8, is_same);
+    r.mapv_inplace(reduce_blob_mask);
+    r
+}
+
+/// Clear corner bits of an `AUTOTILE8` mask whose adjacent edges are not both set.
+pub fn reduce_blob_mask(m: u8) -> u8 {
+    let (n, w, e, s) = (m & 2 != 0, m & 8 != 0, m & 16 != 0, m & 64 != 0);
+    let mut r = m & (2 | 8 | 16 | 64);
+    for (corner, keep) in [(1, n && w), (4, n && e), (32, s && w), (128, s && e)] {
+        if keep {
+            r |= m & corner;
+        }
+    }
+    r
+}
+
+/// Index (0..47) of a reduced blob mask in ascending order of the 47 possible masks, eg. for
+/// a tileset with the blob tiles laid out in that order. Non-reduced masks are reduced first.
+pub fn blob_index(mask: u8) -> usize {
+    let mask = reduce_blob_mask(mask);
+    (0..mask).filter(|&m| reduce_blob_mask(m) == m).count()
+}
+
+/// 3D version of `Metric`.
+pub type Metric3 = fn(IVec3) -> u32;
+
+pub fn manhattan3(o: IVec3) -> u32 {
+    o.x.unsigned_abs() + o.y.unsigned_abs() + o.z.unsigned_abs()
+}
+
+pub fn chebyshev3(o: IVec3) -> u32 {
+    o.x.unsigned_abs().max(o.y.unsigned_abs()).max(o.z.unsigned_abs())
+}
+
+/// All offsets of a 3D neighborhood of the given radius and metric (x-major order).
+pub fn offsets3(radius: u32, metric: Metric3) -> impl Iterator<Item = IVec3> {
+    let r = radius as i32;
+    (-r..=r)
+        .flat_map(move |x| (-r..=r).flat_map(move |y| (-r..=r).map(move |z| ivec3(x, y, z))))
+        .filter(move |&o| o != IVec3::ZERO && metric(o) <= radius)
+}
+
+/// 3D version of `Neighborhood`, on an `Array3` indexed `[x, y, z]`.
+/// By default the neighborhood consists of the 6 face neighbors (radius 1, `manhattan3`).
+pub struct Neighborhood3<'a, T>
+where
+    T: Tile,
+{
+    a: &'a Array3<T::Numeric>,
+    position: IVec3,
+    size: UVec3,
+    radius: u32,
+    metric: Metric3,
+    wrap: BVec3,
+}
+
+impl<'a, T> Neighborhood3<'a, T>
+where
+    T: Tile,
+{
+    /// Note that position is signed, ie. it is allowed to be outside the array area.
+    pub fn new(a: &'a Array3<T::Numeric>, position: IVec3) -> Self {
+        Self::with_shape(a, position, 1, manhattan3)
+    }
+
+    /// Neighborhood consisting of all tiles with `0 < metric(offset) <= radius`.
+    pub fn with_shape(a: &'a Array3<T::Numeric>, position: IVec3, radius: u32, metric: Metric3) -> Self {
+        let (sx, sy, sz) = a.dim();
+        Self {
+            a,
+            position,
+            size: UVec3::new(sx as u32, sy as u32, sz as u32),
+            radius,
+            metric,
+            wrap: BVec3::FALSE,
+        }
+    }
+
+    /// Wrap around the map along the given axes, see `Neighborhood::wrapping`.
+    pub fn wrapping(mut self, wrap: BVec3) -> Self {
+        self.wrap = wrap;
+        self
+    }
+
+    pub fn position(&self) -> IVec3 { self.position }
+
+    pub fn radius(&self) -> u32 { self.radius }
+
+    pub fn metric(&self) -> Metric3 { self.metric }
+
+    pub fn wrap(&self) -> BVec3 { self.wrap }
+
+    /// Tile at `offset` relative to the position, `None` if outside of the map.
+    /// `offset` may be any offset within the bounding cube of the neighborhood.
+    pub fn get(&self, offset: IVec3) -> Option<T> {
+        let r = self.radius.max(1) as i32;
+        assert!(offset.abs().max_element() <= r);
+
+        self.resolve(offset).map(|p| self.a[[p.x as usize, p.y as usize, p.z as usize]].into())
+    }
+
+    /// Map position at `offset` relative to the position (taking wrapping into account),
+    /// `None` if outside of the map.
+    fn resolve(&self, offset: IVec3) -> Option<UVec3> {
+        let mut p = self.position + offset;
+        let size = self.size.as_ivec3();
+        let wrap: [bool; 3] = self.wrap.into();
+        for axis in 0..3 {
+            if wrap[axis] && size[axis] > 0 {
+                p[axis] = p[axis].rem_euclid(size[axis]);
+            }
+        }
+        (p.cmpge(IVec3::ZERO).all() && p.cmplt(size).all()).then(|| p.as_uvec3())
+    }
+
+    /// min/max tile value in the neighborhood, see `Neighbors::range`.
+    pub fn range(&self) -> Option<(T, T)> {
+        Neighbors::range(self)
+    }
+
+    /// Count the number of tiles of type `x` in the neighborhood.
+    pub fn count(&self, x: T) -> usize {
+        Neighbors::count(self, x)
+    }
+
+    /// Iterate all neighors with their positions.
+    /// Yields `None` for positions outside of the array area.
+    pub fn iter_with_positions(&self) -> impl Iterator<Item = Option<(UVec3, T)>> + '_ {
+        offsets3(self.radius, self.metric).map(move |o| {
+            self.resolve(o).map(|p| (p, self.a[[p.x as usize, p.y as usize, p.z as usize]].into()))
+        })
+    }
+
+    /// Iterate tiles in the neighborhood.
+    /// Yields `None` for positions outside of the array area.
+    pub fn iter(&self) -> impl Iterator<Item = Option<T>> + '_ {
+        self.iter_with_positions().map(|o| o.map(|(_p, v)| v))
+    }
+
+    /// All generated positions will be inside the map area
+    pub fn iter_positions(&self) -> impl Iterator<Item = UVec3> + '_ {
+        self.iter_with_positions().filter_map(|o| o.map(|(p, _v)| p))
+    }
+}
+
+impl<T: Tile> Neighbors<T> for Neighborhood3<'_, T> {
+    type Offset = IVec3;
+    type Position = UVec3;
+
+    fn get(&self, offset: IVec3) -> Option<T> {
+        Neighborhood3::get(self, offset)
+    }
 
-        o = if o == INVALID_OFFSET {
-            FIRST_OFFSET
-        } else {
-            // Rotate by 90 degrees (CW in a RH CS)
-            // 0, 1 -> 1, 0 -> 0, -1 -> -1, 0
-            ivec2(o.y, -o.x)
-            // TODO: Actually want (optional) 45
-            // Due to the rescaling this is actually not a rotation, but we need to do a case
-            // distinction
-        };
-
-        self.offset = o;
-
-        let p = self.neighborhood.position + o;
-        Some(self.neighborhood.get(o).map(|t| (p.as_uvec2(), t) ))
+    fn iter_with_positions(&self) -> impl Iterator<Item = Option<(UVec3, T)>> + '_ {
+        Neighborhood3::iter_with_positions(self)
     }
 }
@@ -0,0 +1,188 @@
+//! Color mapping for previews and visualization.
+//!
+//! `Palette` maps continuous values (heightmaps, noise) to colors by interpolating between
+//! color stops, `DiscretePalette` maps tile values to fixed colors.
+
+use ndarray::{Array2, Zip};
+
+pub type Rgb = [u8; 3];
+
+/// Color gradient defined by stops `(value, color)` in ascending order of value.
+/// Values between stops are interpolated linearly, values outside are clamped.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Palette {
+    pub stops: Vec<(f64, Rgb)>,
+}
+
+impl Palette {
+    /// Panics if `stops` is empty.
+    pub fn new(stops: Vec<(f64, Rgb)>) -> Self {
+        assert!(!stops.is_empty());
+        Self { stops }
+    }
+
+    /// Black to white over [0, 1]
+    pub fn grayscale() -> Self {
+        Self::new(vec![(0.0, [0, 0, 0]), (1.0, [255, 255, 255])])
+    }
+
+    /// Deep water, shallow water, beach, grass, rock, snow over [0, 1], with the coast at
+    /// about 0.4.
+    pub fn terrain() -> Self {
+        Self::new(vec![
+            (0.0, [10, 20, 80]),
+            (0.35, [40, 90, 180]),
+            (0.4, [220, 210, 150]),
+            (0.45, [80, 150, 60]),
+            (0.7, [40, 100, 40]),
+            (0.8, [110, 95, 70]),
+            (0.9, [140, 140, 140]),
+            (1.0, [250, 250, 250]),
+        ])
+    }
+
+    /// Perceptually uniform "viridis" colormap over [0, 1]
+    pub fn viridis() -> Self {
+        Self::new(vec![
+            (0.0, [68, 1, 84]),
+            (0.125, [71, 44, 122]),
+            (0.25, [59, 81, 139]),
+            (0.375, [44, 113, 142]),
+            (0.5, [33, 144, 141]),
+            (0.625, [39, 173, 129]),
+            (0.75, [92, 200, 99]),
+            (0.875, [170, 220, 50]),
+            (1.0, [253, 231, 37]),
+        ])
+    }
+
+    /// Stretch the stops so that they cover [min, max] instead of their current range.
+    pub fn rescale(&self, min: f64, max: f64) -> Self {
+        let (lo, hi) = (self.stops[0].0, self.stops[self.stops.len() - 1].0);
+        let d = if hi > lo { hi - lo } else { 1.0 };
+        Self::new(
+            self.stops
+                .iter()
+                .map(|&(v, c)| (min + (v - lo) / d * (max - min), c))
+                .collect(),
+        )
+    }
+
+    pub fn color(&self, v: f64) -> Rgb {
+        let i = self.stops.partition_point(|&(s, _)| s <= v);
+        if i == 0 {
+            return self.stops[0].1;
+        }
+        if i == self.stops.len() {
+            return self.stops[i - 1].1;
+        }
+
+        let ((v0, c0), (v1, c1)) = (self.stops[i - 1], self.stops[i]);
+        let t = (v - v0) / (v1 - v0);
+        let mut r = [0; 3];
+        for k in 0..3 {
+            r[k] = (c0[k] as f64 + (c1[k] as f64 - c0[k] as f64) * t).round() as u8;
+        }
+        r
+    }
+}
+
+/// Color every tile of `a` with `palette`.
+pub fn apply_palette(a: &Array2<f64>, palette: &Palette) -> Array2<Rgb> {
+    a.mapv(|v| palette.color(v))
+}
+
+/// Fixed colors for tile values, eg. enum tiles or voronoi cell ids.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DiscretePalette<T> {
+    pub colors: Vec<(T, Rgb)>,
+    /// Color for values that are not in `colors`
+    pub default: Rgb,
+}
+
+impl<T: PartialEq> DiscretePalette<T> {
+    pub fn new(default: Rgb) -> Self {
+        Self { colors: Vec::new(), default }
+    }
+
+    pub fn with(mut self, value: T, color: Rgb) -> Self {
+        self.colors.push((value, color));
+        self
+    }
+
+    pub fn color(&self, value: &T) -> Rgb {
+        self.colors
+            .iter()
+            .find(|(v, _)| v == value)
+            .map_or(self.default, |(_, c)| *c)
+    }
+
+    pub fn apply(&self, a: &Array2<T>) -> Array2<Rgb> {
+        a.map(|v| self.color(v))
+    }
+}
+
+/// `n` well distinguishable colors, eg. for coloring voronoi cells or regions.
+/// Hues are spaced by the golden angle, so any prefix of the sequence is well spread as well.
+pub fn distinct_colors(n: usize) -> Vec<Rgb> {
+    (0..n)
+        .map(|i| {
+            let hue = (i as f64 * 0.618_033_988_75).fract() * 6.0;
+            let lightness = [0.8, 0.6, 0.7][i % 3];
+            hsv(hue, 0.65, lightness)
+        })
+        .collect()
+}
+
+/// `hue` in [0, 6), saturation and value in [0, 1]
+fn hsv(hue: f64, saturation: f64, value: f64) -> Rgb {
+    let c = value * saturation;
+    let x = c * (1.0 - (hue % 2.0 - 1.0).abs());
+    let (r, g, b) = match hue as u32 {
+        0 => (c, x, 0.0),
+        1 => (x, c, 0.0),
+        2 => (0.0, c, x),
+        3 => (0.0, x, c),
+        4 => (x, 0.0, c),
+        _ => (c, 0.0, x),
+    };
+    let m = value - c;
+    [r, g, b].map(|v| ((v + m) * 255.0).round() as u8)
+}
+
+/// Illumination of heightmap `a` by a distant light, in [0, 1] (0 = facing away from the
+/// light). `azimuth` is the direction the light comes from in degrees clockwise from north
+/// (negative y), `altitude` its angle above the horizon in degrees; 315 / 45 is the
+/// conventional choice.
+/// Heights are in tile units, scale `a` first to exaggerate the relief of eg. normalized noise.
+pub fn hillshade(a: &Array2<f64>, azimuth: f64, altitude: f64) -> Array2<f64> {
+    let (sx, sy) = a.dim();
+    let (azimuth, altitude) = (azimuth.to_radians(), altitude.to_radians());
+    let light = [
+        azimuth.sin() * altitude.cos(),
+        -azimuth.cos() * altitude.cos(),
+        altitude.sin(),
+    ];
+
+    Array2::from_shape_fn((sx, sy), |(x, y)| {
+        // Central differences, one-sided at the map border
+        let (x0, x1) = (x.saturating_sub(1), (x + 1).min(sx - 1));
+        let (y0, y1) = (y.saturating_sub(1), (y + 1).min(sy - 1));
+        let dx = if x1 > x0 { (a[[x1, y]] - a[[x0, y]]) / (x1 - x0) as f64 } else { 0.0 };
+        let dy = if y1 > y0 { (a[[x, y1]] - a[[x, y0]]) / (y1 - y0) as f64 } else { 0.0 };
+
+        // Surface normal is (-dx, -dy, 1)
+        let shade = (-dx * light[0] - dy * light[1] + light[2]) / (dx * dx + dy * dy + 1.0).sqrt();
+        shade.max(0.0)
+    })
+}
+
+/// Color `a` with `palette` and darken/brighten it by `hillshade`, so that flat terrain keeps
+/// its palette color. `relief` scales the heights for shading (see `hillshade`).
+pub fn shaded_relief(a: &Array2<f64>, palette: &Palette, relief: f64, azimuth: f64, altitude: f64) -> Array2<Rgb> {
+    let flat = altitude.to_radians().sin().max(f64::EPSILON);
+    let shade = hillshade(&(a * relief), azimuth, altitude);
+    Zip::from(a).and(&shade).map_collect(|&v, &s| {
+        palette.color(v).map(|c| (c as f64 * s / flat).round().min(255.0) as u8)
+    })
+}
@@ -0,0 +1,146 @@
+
+use glam::{uvec2, UVec2};
+use ndarray::Array2;
+use crate::coord::{map_size, UCoord2Conversions};
+
+/// A single changed tile.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Change<T> {
+    pub position: UVec2,
+    pub old: T,
+    pub new: T,
+}
+
+/// The set of changes that transforms one map into another one of the same size.
+/// Produced by `diff`, consumed by `apply`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MapPatch<T> {
+    pub size: UVec2,
+    pub changes: Vec<Change<T>>,
+}
+
+impl<T> MapPatch<T>
+where
+    T: Clone + PartialEq,
+{
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.changes.len()
+    }
+
+    /// The patch that undoes this one.
+    pub fn inverse(&self) -> Self {
+        Self {
+            size: self.size,
+            changes: self
+                .changes
+                .iter()
+                .map(|c| Change { position: c.position, old: c.new.clone(), new: c.old.clone() })
+                .collect(),
+        }
+    }
+
+    /// True iff `a` has the right size and all tiles touched by this patch
+    /// currently hold their `old` value.
+    pub fn applies_to(&self, a: &Array2<T>) -> bool {
+        map_size(a) == self.size
+            && self.changes.iter().all(|c| a[c.position.as_index2()] == c.old)
+    }
+}
+
+/// Compute the patch that transforms `from` into `to`.
+/// Changes are listed in iteration order of the arrays.
+/// Panics if the array sizes differ.
+pub fn diff<T>(from: &Array2<T>, to: &Array2<T>) -> MapPatch<T>
+where
+    T: Clone + PartialEq,
+{
+    assert_eq!(from.dim(), to.dim());
+    let (sx, sy) = from.dim();
+
+    let changes = from
+        .indexed_iter()
+        .zip(to.iter())
+        .filter(|((_, old), new)| old != new)
+        .map(|(((x, y), old), new)| Change {
+            position: (x, y).as_uvec2(),
+            old: old.clone(),
+            new: new.clone(),
+        })
+        .collect();
+
+    MapPatch { size: uvec2(sx as u32, sy as u32), changes }
+}
+
+/// Write the `new` values of `patch` into `a`.
+/// Panics if the size of `a` does not match the patch.
+pub fn apply<T>(a: &mut Array2<T>, patch: &MapPatch<T>)
+where
+    T: Clone + PartialEq,
+{
+    assert_eq!(map_size(a), patch.size);
+
+    for c in patch.changes.iter() {
+        a[c.position.as_index2()] = c.new.clone();
+    }
+}
+
+/// Undo `patch` on `a`, ie. write the `old` values of `patch` back into `a`.
+pub fn revert<T>(a: &mut Array2<T>, patch: &MapPatch<T>)
+where
+    T: Clone + PartialEq,
+{
+    apply(a, &patch.inverse());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn maps() -> (Array2<u8>, Array2<u8>) {
+        let from = Array2::from_shape_fn((5, 4), |(x, y)| (x + y) as u8 % 3);
+        let mut to = from.clone();
+        to[[0, 0]] = 9;
+        to[[4, 1]] = 7;
+        to[[2, 3]] = 8;
+        (from, to)
+    }
+
+    #[test]
+    fn diff_lists_changes_in_order() {
+        let (from, to) = maps();
+        let patch = diff(&from, &to);
+        assert_eq!(patch.size, uvec2(5, 4));
+        assert_eq!(patch.len(), 3);
+        let positions: Vec<UVec2> = patch.changes.iter().map(|c| c.position).collect();
+        assert_eq!(positions, vec![uvec2(0, 0), uvec2(2, 3), uvec2(4, 1)]);
+        assert_eq!(patch.changes[1], Change { position: uvec2(2, 3), old: 2, new: 8 });
+        assert!(diff(&from, &from).is_empty());
+    }
+
+    #[test]
+    fn apply_and_revert_round_trip() {
+        let (from, to) = maps();
+        let patch = diff(&from, &to);
+        assert!(patch.applies_to(&from));
+        assert!(!patch.applies_to(&to));
+        assert!(patch.inverse().applies_to(&to));
+
+        let mut a = from.clone();
+        apply(&mut a, &patch);
+        assert_eq!(a, to);
+        revert(&mut a, &patch);
+        assert_eq!(a, from);
+        assert_eq!(patch.inverse().inverse(), patch);
+    }
+
+    #[test]
+    fn applies_to_checks_size() {
+        let (from, to) = maps();
+        let patch = diff(&from, &to);
+        assert!(!patch.applies_to(&Array2::zeros((4, 5))));
+    }
+}
@@ -0,0 +1,296 @@
+//! Progression constraints for room graphs (see `dungeon::RoomGraph`): locks with keys and
+//! one-way edges, placed such that the graph stays completable from the entrance, and a solver
+//! that simulates a player to check completability and to order rooms by difficulty.
+
+use crate::dungeon::{RoomEdge, RoomGraph};
+use crate::seed::derive_seed;
+use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
+use std::collections::VecDeque;
+
+/// Result of `solve`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Walkthrough {
+    /// Number of key collection rounds needed before each room can be reached, `None` for
+    /// rooms that are never reached
+    pub stage: Vec<Option<u32>>,
+    /// Reached rooms in the order a player reaches them: stage by stage, breadth first within
+    /// a stage
+    pub order: Vec<usize>,
+    /// Lock ids in the order they are opened
+    pub opened: Vec<u32>,
+    /// The exit can be reached and the player can never get stuck on the way
+    pub completable: bool,
+}
+
+impl Walkthrough {
+    /// Difficulty of each room in [0, 1], the position in `order` normalized, made
+    /// non-decreasing along the critical path of `graph`. Unreached rooms get 1.
+    pub fn difficulty(&self, graph: &RoomGraph) -> Vec<f64> {
+        let mut difficulty = vec![1.0; graph.rooms];
+        let last = self.order.len().saturating_sub(1).max(1) as f64;
+        for (i, &r) in self.order.iter().enumerate() {
+            difficulty[r] = i as f64 / last;
+        }
+        let mut max: f64 = 0.0;
+        for r in graph.critical_path() {
+            max = max.max(difficulty[r]);
+            difficulty[r] = max;
+        }
+        difficulty
+    }
+}
+
+/// Simulate a player starting at the entrance of `graph` without keys.
+///
+/// In every round the player explores everything reachable with the opened locks and collects
+/// all keys found there, keys that become reachable at the same time are collected together.
+/// Collecting the key of a lock opens it. The player is stuck if some reached room (other than
+/// the exit) has no way back to the entrance, eg. behind a one-way edge.
+pub fn solve(graph: &RoomGraph) -> Walkthrough {
+    let mut walkthrough = Walkthrough {
+        stage: vec![None; graph.rooms],
+        order: Vec::new(),
+        opened: Vec::new(),
+        completable: false,
+    };
+    if graph.entrance >= graph.rooms {
+        return walkthrough;
+    }
+
+    let mut stuck = false;
+    for round in 0.. {
+        let opened = &walkthrough.opened;
+        let open = |e: &RoomEdge| e.lock.is_none_or(|l| opened.contains(&l));
+        let reached = breadth_first(graph, graph.entrance, open);
+        stuck |= reached
+            .iter()
+            .any(|&r| r != graph.exit && !graph.reachable(r, open)[graph.entrance]);
+        for &r in &reached {
+            if walkthrough.stage[r].is_none() {
+                walkthrough.stage[r] = Some(round);
+                walkthrough.order.push(r);
+            }
+        }
+
+        let mut keys: Vec<u32> = graph
+            .keys
+            .iter()
+            .filter(|&&(l, room)| walkthrough.stage[room].is_some() && !walkthrough.opened.contains(&l))
+            .map(|&(l, _)| l)
+            .collect();
+        if keys.is_empty() {
+            break;
+        }
+        keys.sort_unstable();
+        keys.dedup();
+        walkthrough.opened.extend(keys);
+    }
+
+    walkthrough.completable = !stuck && walkthrough.stage.get(graph.exit).is_some_and(|s| s.is_some());
+    walkthrough
+}
+
+/// Rooms reachable from `from` in breadth first order.
+fn breadth_first<F: Fn(&RoomEdge) -> bool>(graph: &RoomGraph, from: usize, passable: F) -> Vec<usize> {
+    let mut seen = vec![false; graph.rooms];
+    let mut order = vec![from];
+    let mut queue = VecDeque::from([from]);
+    seen[from] = true;
+    while let Some(r) = queue.pop_front() {
+        for (e, other) in graph.neighbors(r) {
+            let edge = &graph.edges[e];
+            if !seen[other] && edge.passable_from(r) && passable(edge) {
+                seen[other] = true;
+                order.push(other);
+                queue.push_back(other);
+            }
+        }
+    }
+    order
+}
+
+/// Places locks, keys and one-way edges in a room graph (replacing existing ones), keeping it
+/// completable (see `solve`).
+///
+/// Locks are spread evenly over the edges of the critical path that can not be bypassed (so
+/// fewer than `locks` locks may be placed in graphs with many cycles), lock `i` gets id `i`,
+/// counting from the entrance. Its key is placed in a random room that can be reached with the
+/// keys of the earlier locks only, so locks are opened in order and later rooms on the critical
+/// path are harder to reach.
+/// One-way edges are chosen among the remaining edges and point away from the entrance, every
+/// candidate is kept only if the graph stays completable, so fewer than `one_way` edges may
+/// be placed (eg. in a tree, every one-way edge that does not lead into the exit would trap the
+/// player).
+#[derive(Clone, Debug)]
+pub struct Progression {
+    pub seed: u64,
+    /// Number of locked edges on the critical path
+    pub locks: usize,
+    /// Number of one-way edges
+    pub one_way: usize,
+}
+
+impl Default for Progression {
+    fn default() -> Self {
+        Self { seed: 0, locks: 2, one_way: 1 }
+    }
+}
+
+impl Progression {
+    /// Returns the walkthrough of the resulting graph.
+    pub fn apply(&self, graph: &mut RoomGraph) -> Walkthrough {
+        let mut rng = StdRng::seed_from_u64(derive_seed(self.seed, 0));
+        graph.keys.clear();
+        for e in graph.edges.iter_mut() {
+            e.lock = None;
+            e.one_way = false;
+        }
+        if graph.entrance >= graph.rooms || graph.exit >= graph.rooms {
+            return solve(graph);
+        }
+
+        // Only edges that can not be bypassed (bridges between entrance and exit) gate progress
+        let path = graph.critical_path();
+        let gates: Vec<usize> = path
+            .windows(2)
+            .filter_map(|w| graph.edges.iter().position(|e| (e.a, e.b) == (w[0], w[1]) || (e.a, e.b) == (w[1], w[0])))
+            .filter(|&g| !graph.reachable(graph.entrance, |e| !std::ptr::eq(e, &graph.edges[g]))[graph.exit])
+            .collect();
+        let locks = self.locks.min(gates.len());
+        for i in 0..locks {
+            graph.edges[gates[(2 * i + 1) * gates.len() / (2 * locks)]].lock = Some(i as u32);
+        }
+        for i in 0..locks as u32 {
+            let reachable = graph.reachable(graph.entrance, |e| e.lock.is_none_or(|l| l < i));
+            let rooms: Vec<usize> = (0..graph.rooms).filter(|&r| reachable[r]).collect();
+            graph.keys.push((i, *rooms.choose(&mut rng).unwrap()));
+        }
+
+        // Orient candidates away from the entrance, by breadth first order ignoring locks
+        let mut rank = vec![usize::MAX; graph.rooms];
+        for (i, r) in breadth_first(graph, graph.entrance, |_| true).into_iter().enumerate() {
+            rank[r] = i;
+        }
+        let mut candidates: Vec<usize> = (0..graph.edges.len()).filter(|&e| graph.edges[e].lock.is_none()).collect();
+        candidates.shuffle(&mut rng);
+        let mut placed = 0;
+        for e in candidates {
+            if placed == self.one_way {
+                break;
+            }
+            let previous = graph.edges[e].clone();
+            let edge = &mut graph.edges[e];
+            if rank[edge.b] < rank[edge.a] {
+                std::mem::swap(&mut edge.a, &mut edge.b);
+            }
+            edge.one_way = true;
+            match solve(graph).completable {
+                true => placed += 1,
+                false => graph.edges[e] = previous,
+            }
+        }
+        solve(graph)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dungeon::DungeonGenerator;
+
+    /// Rooms `0..n` in a row, from the entrance 0 to the exit `n - 1`
+    fn chain(n: usize) -> RoomGraph {
+        let edges = (1..n).map(|i| RoomEdge::new(i - 1, i)).collect();
+        RoomGraph { rooms: n, edges, entrance: 0, exit: n - 1, keys: Vec::new() }
+    }
+
+    #[test]
+    fn locks_on_a_chain() {
+        let mut graph = chain(5);
+        let walkthrough = Progression { seed: 1, locks: 2, one_way: 0 }.apply(&mut graph);
+        let locks: Vec<Option<u32>> = graph.edges.iter().map(|e| e.lock).collect();
+        assert_eq!(locks, vec![None, Some(0), None, Some(1)]);
+        // Every key is in front of its own lock
+        assert!([0, 1].contains(&graph.keys[0].1));
+        assert!(graph.keys[1].1 <= 3);
+
+        assert!(walkthrough.completable);
+        assert_eq!(walkthrough.opened, vec![0, 1]);
+        assert_eq!(walkthrough.stage[..4], [Some(0), Some(0), Some(1), Some(1)]);
+        // Both keys may be found before the first lock
+        let last = if graph.keys[1].1 <= 1 { 1 } else { 2 };
+        assert_eq!(walkthrough.stage[4], Some(last));
+        assert_eq!(walkthrough.order, vec![0, 1, 2, 3, 4]);
+        assert_eq!(walkthrough.difficulty(&graph), vec![0.0, 0.25, 0.5, 0.75, 1.0]);
+    }
+
+    #[test]
+    fn one_way_edges() {
+        // In a tree only the edge into the exit can be one-way
+        let mut graph = chain(4);
+        graph.edges.push(RoomEdge::new(1, 4));
+        graph.rooms = 5;
+        let walkthrough = Progression { seed: 2, locks: 0, one_way: 3 }.apply(&mut graph);
+        assert!(walkthrough.completable);
+        let one_way: Vec<&RoomEdge> = graph.edges.iter().filter(|e| e.one_way).collect();
+        assert_eq!(one_way, vec![&RoomEdge { a: 2, b: 3, lock: None, one_way: true }]);
+
+        // In a cycle all but one edge can be one-way as well, all point away from the entrance
+        let mut graph = chain(4);
+        graph.edges.push(RoomEdge::new(0, 2));
+        let walkthrough = Progression { seed: 2, locks: 0, one_way: 3 }.apply(&mut graph);
+        assert!(walkthrough.completable);
+        let one_way: Vec<&RoomEdge> = graph.edges.iter().filter(|e| e.one_way).collect();
+        assert_eq!(one_way.len(), 3);
+        assert!(one_way.iter().all(|e| e.a < e.b));
+    }
+
+    #[test]
+    fn unsolvable_graphs() {
+        // The key is behind its own lock
+        let mut graph = chain(3);
+        graph.edges[0].lock = Some(0);
+        graph.keys.push((0, 1));
+        let walkthrough = solve(&graph);
+        assert!(!walkthrough.completable);
+        assert_eq!(walkthrough.stage, vec![Some(0), None, None]);
+        assert!(walkthrough.opened.is_empty());
+
+        // A one-way edge into a dead end traps the player
+        let mut graph = chain(3);
+        graph.edges.push(RoomEdge { a: 1, b: 3, lock: None, one_way: true });
+        graph.rooms = 4;
+        assert!(!solve(&graph).completable);
+        graph.edges[2].one_way = false;
+        assert!(solve(&graph).completable);
+    }
+
+    /// In generated dungeons the key of every lock is found before the lock is reached, so the
+    /// exit is always reached
+    #[test]
+    fn keys_are_found_before_their_locks() {
+        for seed in 0..20 {
+            let generator = DungeonGenerator { seed, rooms: 16, locks: 3, ..Default::default() };
+            let graph = generator.generate().graph;
+            let walkthrough = solve(&graph);
+            assert!(walkthrough.completable, "seed {}", seed);
+            let mut opened = walkthrough.opened.clone();
+            opened.sort_unstable();
+            let locks = graph.edges.iter().filter(|e| e.lock.is_some()).count() as u32;
+            assert_eq!(opened, (0..locks).collect::<Vec<_>>(), "seed {}", seed);
+
+            let stage = |room: usize| walkthrough.stage[room].unwrap();
+            for e in graph.edges.iter() {
+                if let Some(lock) = e.lock {
+                    let key = graph.keys.iter().find(|k| k.0 == lock).unwrap().1;
+                    assert!(stage(key) <= lock, "seed {}: key {} in {}", seed, lock, key);
+                    assert!(stage(key) < stage(e.a).max(stage(e.b)), "seed {}: lock {}", seed, lock);
+                }
+            }
+
+            let difficulty = walkthrough.difficulty(&graph);
+            let path = graph.critical_path();
+            assert!(path.windows(2).all(|w| difficulty[w[0]] <= difficulty[w[1]]), "seed {}", seed);
+        }
+    }
+}
@@ -0,0 +1,199 @@
+
+use crate::coord::{map_size, UCoord2Conversions};
+use crate::rect::Rect;
+use glam::{uvec2, UVec2};
+use ndarray::Array2;
+
+enum Node<T> {
+    Leaf(Rect, T),
+    Branch(Rect, Vec<Node<T>>),
+}
+
+impl<T> Node<T> {
+    fn rect(&self) -> &Rect {
+        match self {
+            Node::Leaf(r, _) => r,
+            Node::Branch(r, _) => r,
+        }
+    }
+}
+
+/// Quadtree view of a map where uniform areas are merged into single leaves.
+/// Leaves always cover rectangles, their union covers the whole map.
+pub struct Quadtree<T> {
+    size: UVec2,
+    root: Node<T>,
+}
+
+impl<T> Quadtree<T>
+where
+    T: Clone + PartialEq,
+{
+    /// Panics if `a` is empty.
+    pub fn new(a: &Array2<T>) -> Self {
+        assert!(!a.is_empty());
+        let size = map_size(a);
+        Self {
+            size,
+            root: Self::build(a, Rect::from_size(size)),
+        }
+    }
+
+    fn build(a: &Array2<T>, rect: Rect) -> Node<T> {
+        if rect.area() <= 1 {
+            return Node::Leaf(rect, a[rect.anchor.as_index2()].clone());
+        }
+
+        // Split every axis that is longer than 1 tile
+        let half = uvec2(rect.size.x.div_ceil(2), rect.size.y.div_ceil(2));
+        let xs: &[(u32, u32)] = &[(0, half.x), (half.x, rect.size.x - half.x)];
+        let ys: &[(u32, u32)] = &[(0, half.y), (half.y, rect.size.y - half.y)];
+
+        let children: Vec<_> = xs
+            .iter()
+            .flat_map(|&x| ys.iter().map(move |&y| (x, y)))
+            .filter(|&((_, w), (_, h))| w > 0 && h > 0)
+            .map(|((ox, w), (oy, h))| Self::build(a, Rect::new(rect.anchor + uvec2(ox, oy), uvec2(w, h))))
+            .collect();
+
+        // Merge if all children are leaves with the same value
+        let uniform = match &children[0] {
+            Node::Leaf(_, v) => children.iter().all(|c| matches!(c, Node::Leaf(_, w) if w == v)),
+            Node::Branch(..) => false,
+        };
+
+        if uniform {
+            match children.into_iter().next() {
+                Some(Node::Leaf(_, v)) => Node::Leaf(rect, v),
+                _ => unreachable!(),
+            }
+        } else {
+            Node::Branch(rect, children)
+        }
+    }
+
+    pub fn size(&self) -> UVec2 {
+        self.size
+    }
+
+    /// Value at `p`, `None` if `p` is outside of the map.
+    pub fn value_at(&self, p: UVec2) -> Option<&T> {
+        let mut node = &self.root;
+        if !node.rect().contains(p) {
+            return None;
+        }
+
+        loop {
+            match node {
+                Node::Leaf(_, v) => return Some(v),
+                Node::Branch(_, children) => {
+                    node = children.iter().find(|c| c.rect().contains(p))?;
+                }
+            }
+        }
+    }
+
+    /// All leaves (rect and value) that intersect `rect`.
+    pub fn regions_intersecting(&self, rect: &Rect) -> Vec<(Rect, &T)> {
+        let mut r = Vec::new();
+        let mut stack = vec![&self.root];
+
+        while let Some(node) = stack.pop() {
+            if !node.rect().intersects(rect) {
+                continue;
+            }
+            match node {
+                Node::Leaf(lr, v) => r.push((*lr, v)),
+                Node::Branch(_, children) => stack.extend(children.iter().rev()),
+            }
+        }
+        r
+    }
+
+    /// Iterate all leaves (rect and value).
+    pub fn leaves(&self) -> impl Iterator<Item = (Rect, &T)> + '_ {
+        let mut stack = vec![&self.root];
+        std::iter::from_fn(move || {
+            while let Some(node) = stack.pop() {
+                match node {
+                    Node::Leaf(r, v) => return Some((*r, v)),
+                    Node::Branch(_, children) => stack.extend(children.iter().rev()),
+                }
+            }
+            None
+        })
+    }
+
+    pub fn leaf_count(&self) -> usize {
+        self.leaves().count()
+    }
+
+    /// Expand back into a full map.
+    pub fn to_array(&self) -> Array2<T> {
+        let mut a = Array2::from_elem(self.size.as_index2(), self.root_value());
+        for (rect, v) in self.leaves() {
+            for p in rect.iter() {
+                a[p.as_index2()] = v.clone();
+            }
+        }
+        a
+    }
+
+    fn root_value(&self) -> T {
+        let mut node = &self.root;
+        loop {
+            match node {
+                Node::Leaf(_, v) => return v.clone(),
+                Node::Branch(_, children) => node = &children[0],
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 8x6 map with a uniform 4x3 block of 1 in the corner (one quadrant), 0 elsewhere, and a
+    /// single 2
+    fn map() -> Array2<u8> {
+        let mut a = Array2::from_shape_fn((8, 6), |(x, y)| (x < 4 && y < 3) as u8);
+        a[[7, 5]] = 2;
+        a
+    }
+
+    #[test]
+    fn round_trip() {
+        for a in [map(), Array2::from_elem((1, 1), 3), Array2::from_shape_fn((3, 8), |(x, y)| (x * y) as u8)] {
+            let q = Quadtree::new(&a);
+            assert_eq!(q.size(), map_size(&a));
+            assert_eq!(q.to_array(), a);
+            for (p, v) in a.indexed_iter() {
+                assert_eq!(q.value_at(p.as_uvec2()), Some(v));
+            }
+        }
+    }
+
+    #[test]
+    fn uniform_areas_are_merged() {
+        assert_eq!(Quadtree::new(&Array2::from_elem((16, 9), 5)).leaf_count(), 1);
+
+        let q = Quadtree::new(&map());
+        assert!(q.leaves().any(|(r, &v)| r == Rect::from_size(uvec2(4, 3)) && v == 1));
+        assert!(q.leaf_count() < 8 * 6);
+        // Leaves cover the map without overlap
+        assert_eq!(q.leaves().map(|(r, _)| r.area()).sum::<usize>(), 8 * 6);
+    }
+
+    #[test]
+    fn queries() {
+        let q = Quadtree::new(&map());
+        assert_eq!(q.value_at(uvec2(8, 0)), None);
+        assert_eq!(q.value_at(uvec2(7, 5)), Some(&2));
+
+        let hits = q.regions_intersecting(&Rect::new(uvec2(6, 4), uvec2(2, 2)));
+        assert!(hits.iter().any(|&(r, &v)| r.contains(uvec2(7, 5)) && v == 2));
+        assert!(hits.iter().all(|(r, _)| r.intersects(&Rect::new(uvec2(6, 4), uvec2(2, 2)))));
+        assert!(q.regions_intersecting(&Rect::new(uvec2(9, 9), uvec2(2, 2))).is_empty());
+    }
+}
@@ -0,0 +1,319 @@
+
+use glam::{ivec2, uvec2, uvec3, IVec2, UVec2, UVec3};
+use ndarray::{s, Array2, ArrayView2, ArrayViewMut2};
+
+/// Axis aligned rectangle of tiles.
+/// `anchor` is the tile with the smallest coordinates, `size` the extent (in tiles).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Rect {
+    pub anchor: UVec2,
+    pub size: UVec2,
+}
+
+impl Rect {
+    pub fn new(anchor: UVec2, size: UVec2) -> Self {
+        Self { anchor, size }
+    }
+
+    /// Rect of the given size anchored at (0, 0), ie. covering a whole map.
+    pub fn from_size(size: UVec2) -> Self {
+        Self { anchor: UVec2::ZERO, size }
+    }
+
+    /// Smallest rect containing both `a` and `b`.
+    pub fn from_corners(a: UVec2, b: UVec2) -> Self {
+        let anchor = a.min(b);
+        Self { anchor, size: a.max(b) - anchor + UVec2::ONE }
+    }
+
+    /// One past the last tile in each direction
+    pub fn end(&self) -> UVec2 {
+        self.anchor + self.size
+    }
+
+    pub fn area(&self) -> usize {
+        self.size.x as usize * self.size.y as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.size.x == 0 || self.size.y == 0
+    }
+
+    pub fn contains(&self, p: UVec2) -> bool {
+        p.cmpge(self.anchor).all() && p.cmplt(self.end()).all()
+    }
+
+    /// True iff `self` and `other` have at least one tile in common.
+    pub fn intersects(&self, other: &Rect) -> bool {
+        !self.is_empty()
+            && !other.is_empty()
+            && self.anchor.cmplt(other.end()).all()
+            && other.anchor.cmplt(self.end()).all()
+    }
+
+    /// The tiles covered by both `self` and `other`, `None` if they have no tile in common.
+    pub fn intersect(&self, other: &Rect) -> Option<Rect> {
+        let anchor = self.anchor.max(other.anchor);
+        let end = self.end().min(other.end());
+        match self.intersects(other) {
+            true => Some(Rect::new(anchor, end - anchor)),
+            false => None,
+        }
+    }
+
+    /// Convert the map position `pos` to coordinates relative to the anchor.
+    /// `None` if `pos` is outside of the rect.
+    pub fn to_local(&self, pos: UVec2) -> Option<UVec2> {
+        self.contains(pos).then(|| pos - self.anchor)
+    }
+
+    /// Convert coordinates relative to the anchor to a map position.
+    pub fn to_global(&self, local: UVec2) -> UVec2 {
+        self.anchor + local
+    }
+
+    /// View of the part of `a` covered by the rect, indexed in local coordinates.
+    /// Panics if the rect is not inside `a`.
+    pub fn view<'a, T>(&self, a: &'a Array2<T>) -> ArrayView2<'a, T> {
+        let (a0, e) = (self.anchor, self.end());
+        a.slice(s![a0.x as usize..e.x as usize, a0.y as usize..e.y as usize])
+    }
+
+    /// Mutable version of `view`.
+    pub fn view_mut<'a, T>(&self, a: &'a mut Array2<T>) -> ArrayViewMut2<'a, T> {
+        let (a0, e) = (self.anchor, self.end());
+        a.slice_mut(s![a0.x as usize..e.x as usize, a0.y as usize..e.y as usize])
+    }
+
+    /// Iterate all positions in the rect, x-major (same order as iterating an `Array2`).
+    pub fn iter(&self) -> impl Iterator<Item = UVec2> {
+        let (a, e) = (self.anchor, self.end());
+        (a.x..e.x).flat_map(move |x| (a.y..e.y).map(move |y| uvec2(x, y)))
+    }
+
+    /// Iterate all positions back to front for isometric rendering (see
+    /// `coord::Isometric`), ie. by diagonals `x + y` ascending, each diagonal with x ascending.
+    /// Drawing tiles in this order lets closer tiles overdraw the ones behind them.
+    pub fn iter_isometric(&self) -> impl Iterator<Item = UVec2> {
+        let (a, s) = (self.anchor, self.size);
+        let diagonals = if self.is_empty() { 0 } else { s.x + s.y - 1 };
+        (0..diagonals).flat_map(move |d| {
+            let x0 = d.saturating_sub(s.y - 1);
+            let x1 = d.min(s.x - 1);
+            (x0..=x1).map(move |x| a + uvec2(x, d - x))
+        })
+    }
+}
+
+/// Axis aligned rectangle of tiles with signed coordinates, eg. the neighborhood of a tile at
+/// the map border, which extends past the map until it is clamped to it (see `clamp`).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct IRect {
+    pub anchor: IVec2,
+    pub size: UVec2,
+}
+
+impl IRect {
+    pub fn new(anchor: IVec2, size: UVec2) -> Self {
+        Self { anchor, size }
+    }
+
+    /// Square of all tiles with a chebyshev distance of at most `radius` to `center`.
+    pub fn around(center: IVec2, radius: u32) -> Self {
+        Self { anchor: center - radius as i32, size: UVec2::splat(2 * radius + 1) }
+    }
+
+    /// One past the last tile in each direction
+    pub fn end(&self) -> IVec2 {
+        self.anchor + self.size.as_ivec2()
+    }
+
+    pub fn area(&self) -> usize {
+        self.size.x as usize * self.size.y as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.size.x == 0 || self.size.y == 0
+    }
+
+    pub fn contains(&self, p: IVec2) -> bool {
+        p.cmpge(self.anchor).all() && p.cmplt(self.end()).all()
+    }
+
+    /// True iff `self` and `other` have at least one tile in common.
+    pub fn intersects(&self, other: &IRect) -> bool {
+        !self.is_empty()
+            && !other.is_empty()
+            && self.anchor.cmplt(other.end()).all()
+            && other.anchor.cmplt(self.end()).all()
+    }
+
+    /// The tiles covered by both `self` and `other`, `None` if they have no tile in common.
+    pub fn intersect(&self, other: &IRect) -> Option<IRect> {
+        let anchor = self.anchor.max(other.anchor);
+        let end = self.end().min(other.end());
+        match self.intersects(other) {
+            true => Some(IRect::new(anchor, (end - anchor).as_uvec2())),
+            false => None,
+        }
+    }
+
+    /// The part of the rect inside of `bounds` (eg. `Rect::from_size(map size)`), `None` if
+    /// they have no tile in common.
+    pub fn clamp(&self, bounds: &Rect) -> Option<Rect> {
+        self.intersect(&IRect::from(*bounds))
+            .map(|r| Rect::new(r.anchor.as_uvec2(), r.size))
+    }
+
+    /// The same rect with unsigned coordinates, `None` if it extends to negative coordinates.
+    pub fn to_rect(&self) -> Option<Rect> {
+        match self.anchor.cmpge(IVec2::ZERO).all() {
+            true => Some(Rect::new(self.anchor.as_uvec2(), self.size)),
+            false => None,
+        }
+    }
+
+    /// Iterate all positions in the rect, x-major (same order as iterating an `Array2`).
+    pub fn iter(&self) -> impl Iterator<Item = IVec2> {
+        let (a, e) = (self.anchor, self.end());
+        (a.x..e.x).flat_map(move |x| (a.y..e.y).map(move |y| ivec2(x, y)))
+    }
+}
+
+impl From<Rect> for IRect {
+    fn from(r: Rect) -> Self {
+        Self { anchor: r.anchor.as_ivec2(), size: r.size }
+    }
+}
+
+/// Axis aligned box of voxels, the 3D version of `Rect`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Rect3 {
+    pub anchor: UVec3,
+    pub size: UVec3,
+}
+
+impl Rect3 {
+    pub fn new(anchor: UVec3, size: UVec3) -> Self {
+        Self { anchor, size }
+    }
+
+    pub fn from_size(size: UVec3) -> Self {
+        Self { anchor: UVec3::ZERO, size }
+    }
+
+    /// Smallest box containing both `a` and `b`.
+    pub fn from_corners(a: UVec3, b: UVec3) -> Self {
+        let anchor = a.min(b);
+        Self { anchor, size: a.max(b) - anchor + UVec3::ONE }
+    }
+
+    /// One past the last voxel in each direction
+    pub fn end(&self) -> UVec3 {
+        self.anchor + self.size
+    }
+
+    pub fn volume(&self) -> usize {
+        self.size.x as usize * self.size.y as usize * self.size.z as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.size.cmpeq(UVec3::ZERO).any()
+    }
+
+    pub fn contains(&self, p: UVec3) -> bool {
+        p.cmpge(self.anchor).all() && p.cmplt(self.end()).all()
+    }
+
+    pub fn intersects(&self, other: &Rect3) -> bool {
+        !self.is_empty()
+            && !other.is_empty()
+            && self.anchor.cmplt(other.end()).all()
+            && other.anchor.cmplt(self.end()).all()
+    }
+
+    /// The voxels covered by both `self` and `other`, `None` if they have no voxel in common.
+    pub fn intersect(&self, other: &Rect3) -> Option<Rect3> {
+        let anchor = self.anchor.max(other.anchor);
+        let end = self.end().min(other.end());
+        match self.intersects(other) {
+            true => Some(Rect3::new(anchor, end - anchor)),
+            false => None,
+        }
+    }
+
+    /// The 2D rect covered by this box (ie. dropping z).
+    pub fn xy(&self) -> Rect {
+        Rect::new(self.anchor.truncate(), self.size.truncate())
+    }
+
+    /// Iterate all positions in the box, x-major (same order as iterating an `Array3`).
+    pub fn iter(&self) -> impl Iterator<Item = UVec3> {
+        let (a, e) = (self.anchor, self.end());
+        (a.x..e.x).flat_map(move |x| (a.y..e.y).flat_map(move |y| (a.z..e.z).map(move |z| uvec3(x, y, z))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rect(x: u32, y: u32, w: u32, h: u32) -> Rect {
+        Rect::new(uvec2(x, y), uvec2(w, h))
+    }
+
+    #[test]
+    fn intersect_touching_edges() {
+        let a = rect(0, 0, 4, 4);
+        assert_eq!(a.intersect(&rect(4, 0, 2, 4)), None);
+        assert_eq!(a.intersect(&rect(0, 4, 4, 2)), None);
+        assert_eq!(a.intersect(&rect(4, 4, 1, 1)), None);
+        assert_eq!(rect(4, 0, 2, 4).intersect(&a), None);
+    }
+
+    #[test]
+    fn intersect_containment() {
+        let outer = rect(1, 2, 10, 8);
+        let inner = rect(3, 4, 2, 3);
+        assert_eq!(outer.intersect(&inner), Some(inner));
+        assert_eq!(inner.intersect(&outer), Some(inner));
+        assert_eq!(outer.intersect(&outer), Some(outer));
+    }
+
+    #[test]
+    fn intersect_partial_overlap() {
+        let a = rect(0, 0, 5, 4);
+        let b = rect(3, 2, 5, 5);
+        assert_eq!(a.intersect(&b), Some(rect(3, 2, 2, 2)));
+        assert_eq!(b.intersect(&a), Some(rect(3, 2, 2, 2)));
+        // Overlapping along x only
+        assert_eq!(a.intersect(&rect(2, 6, 2, 2)), None);
+    }
+
+    #[test]
+    fn intersect_empty() {
+        let a = rect(0, 0, 5, 5);
+        assert_eq!(a.intersect(&rect(2, 2, 0, 3)), None);
+        assert_eq!(a.intersect(&rect(2, 2, 3, 0)), None);
+        assert_eq!(rect(2, 2, 0, 0).intersect(&a), None);
+        assert_eq!(rect(2, 2, 0, 0).intersect(&rect(2, 2, 0, 0)), None);
+    }
+
+    #[test]
+    fn irect_intersect_negative() {
+        let a = IRect::new(ivec2(-3, -2), uvec2(4, 4));
+        assert_eq!(a.intersect(&IRect::new(ivec2(0, 0), uvec2(3, 3))), Some(IRect::new(ivec2(0, 0), uvec2(1, 2))));
+        assert_eq!(a.intersect(&IRect::new(ivec2(1, 0), uvec2(3, 3))), None);
+        assert_eq!(a.clamp(&rect(0, 0, 8, 8)), Some(rect(0, 0, 1, 2)));
+    }
+
+    #[test]
+    fn intersect_3d() {
+        let a = Rect3::new(uvec3(0, 0, 0), uvec3(4, 4, 4));
+        assert_eq!(a.intersect(&Rect3::new(uvec3(2, 3, 4), uvec3(4, 4, 4))), None);
+        assert_eq!(
+            a.intersect(&Rect3::new(uvec3(2, 3, 1), uvec3(4, 4, 1))),
+            Some(Rect3::new(uvec3(2, 3, 1), uvec3(2, 1, 1)))
+        );
+    }
+}
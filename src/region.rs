@@ -1,6 +1,22 @@
 
-use glam::{UVec2, uvec2};
-use ndarray::Array2;
+use crate::coord::MapAccess;
+use crate::rect::{Rect, Rect3};
+use glam::{ivec2, uvec3, IVec2, IVec3, UVec2, UVec3};
+use ndarray::{Array2, Array3};
+use rand::seq::SliceRandom;
+use rand::Rng;
+
+/// Directions of the 8 neighbors, clockwise (with y pointing down) starting west.
+const MOORE: [IVec2; 8] = [
+    IVec2::new(-1, 0),
+    IVec2::new(-1, -1),
+    IVec2::new(0, -1),
+    IVec2::new(1, -1),
+    IVec2::new(1, 0),
+    IVec2::new(1, 1),
+    IVec2::new(0, 1),
+    IVec2::new(-1, 1),
+];
 
 pub struct Region<T>
     where T: Eq+Copy
@@ -11,3 +27,308 @@ pub struct Region<T>
     //pub(crate) a: &'a Array2<T>,
 }
 
+impl<T> Region<T>
+    where T: Eq+Copy
+{
+    /// Bounding box of the region
+    pub fn rect(&self) -> Rect {
+        Rect::new(self.anchor, self.size)
+    }
+
+    /// The map value of all cells of this region
+    pub fn reference(&self) -> T {
+        self.reference
+    }
+
+    /// True iff `p` is a cell of this region in map `a`.
+    pub fn contains(&self, a: &Array2<T>, p: IVec2) -> bool {
+        p.cmpge(self.anchor.as_ivec2()).all()
+            && p.cmplt(self.rect().end().as_ivec2()).all()
+            && a.get_at(p) == Some(&self.reference)
+    }
+
+    /// All cells of this region in map `a`, x-major.
+    pub fn cells(&self, a: &Array2<T>) -> Vec<UVec2> {
+        self.rect()
+            .iter()
+            .filter(|p| a.at(*p) == &self.reference)
+            .collect()
+    }
+
+    /// `n` distinct cells of the region, chosen uniformly at random.
+    /// Returns all cells (in random order) if the region has less than `n` cells.
+    pub fn sample_positions<R: Rng>(&self, a: &Array2<T>, n: usize, rng: &mut R) -> Vec<UVec2> {
+        self.cells(a).choose_multiple(rng, n).cloned().collect()
+    }
+
+    /// `n` distinct cells of the region, chosen with probability proportional to `weights`
+    /// (which must have the size of `a`). Cells with a weight <= 0.0 are never chosen, so
+    /// less than `n` cells may be returned.
+    pub fn sample_positions_weighted<R: Rng>(
+        &self,
+        a: &Array2<T>,
+        weights: &Array2<f64>,
+        n: usize,
+        rng: &mut R,
+    ) -> Vec<UVec2> {
+        assert_eq!(a.dim(), weights.dim());
+        let cells: Vec<UVec2> = self
+            .cells(a)
+            .into_iter()
+            .filter(|p| *weights.at(*p) > 0.0)
+            .collect();
+
+        cells
+            .choose_multiple_weighted(rng, n, |p| *weights.at(*p))
+            .map(|it| it.cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// All cells of the region that have a 4-neighbor outside of the region (or are at the
+    /// map border), x-major.
+    pub fn border_cells(&self, a: &Array2<T>) -> Vec<UVec2> {
+        border_cells(self.cells(a), |p| self.contains(a, p))
+    }
+
+    /// Ordered trace of the boundary cells, clockwise, starting at the top-most (then
+    /// left-most) cell, using Moore neighbor tracing.
+    /// Only the connected part (8-connectivity) containing the start cell is traced, holes
+    /// are not traced. Empty if the region has no cells in `a`.
+    pub fn outline_polyline(&self, a: &Array2<T>) -> Vec<UVec2> {
+        outline_polyline(&self.cells(a), |p| self.contains(a, p))
+    }
+
+    /// Copy of this region that holds its own cells, so it can be queried without `a` (eg.
+    /// after `a` was modified or dropped).
+    pub fn materialize(&self, a: &Array2<T>) -> OwnedRegion<T> {
+        let rect = self.rect();
+        let cells = self.cells(a);
+        let mut mask = Array2::from_elem((rect.size.x as usize, rect.size.y as usize), false);
+        for p in &cells {
+            *mask.at_mut(*p - rect.anchor) = true;
+        }
+        OwnedRegion { rect, reference: self.reference, mask, cells }
+    }
+}
+
+/// A region that owns its cells (see `Region::materialize`), with the same queries as `Region`
+/// but without the map.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OwnedRegion<T> {
+    rect: Rect,
+    reference: T,
+    /// Cells of the region within `rect`, in coordinates relative to `rect.anchor`
+    mask: Array2<bool>,
+    /// x-major
+    cells: Vec<UVec2>,
+}
+
+impl<T: Copy> OwnedRegion<T> {
+    /// Bounding box of the region
+    pub fn rect(&self) -> Rect {
+        self.rect
+    }
+
+    /// The map value the region was created from
+    pub fn reference(&self) -> T {
+        self.reference
+    }
+
+    /// Membership of the cells of `rect()`, indexed relative to its anchor.
+    pub fn mask(&self) -> &Array2<bool> {
+        &self.mask
+    }
+
+    /// True iff `p` is a cell of this region.
+    pub fn contains(&self, p: IVec2) -> bool {
+        let local = p - self.rect.anchor.as_ivec2();
+        self.mask.get_at(local) == Some(&true)
+    }
+
+    /// All cells of this region, x-major.
+    pub fn cells(&self) -> &[UVec2] {
+        &self.cells
+    }
+
+    pub fn len(&self) -> usize {
+        self.cells.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cells.is_empty()
+    }
+
+    /// See `Region::sample_positions`.
+    pub fn sample_positions<R: Rng>(&self, n: usize, rng: &mut R) -> Vec<UVec2> {
+        self.cells.choose_multiple(rng, n).cloned().collect()
+    }
+
+    /// See `Region::sample_positions_weighted`, `weights` is indexed with map coordinates.
+    pub fn sample_positions_weighted<R: Rng>(&self, weights: &Array2<f64>, n: usize, rng: &mut R) -> Vec<UVec2> {
+        let cells: Vec<UVec2> = self
+            .cells
+            .iter()
+            .copied()
+            .filter(|p| weights.get_at(p.as_ivec2()).is_some_and(|&w| w > 0.0))
+            .collect();
+
+        cells
+            .choose_multiple_weighted(rng, n, |p| *weights.at(*p))
+            .map(|it| it.cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// See `Region::border_cells`.
+    pub fn border_cells(&self) -> Vec<UVec2> {
+        border_cells(self.cells.clone(), |p| self.contains(p))
+    }
+
+    /// See `Region::outline_polyline`.
+    pub fn outline_polyline(&self) -> Vec<UVec2> {
+        outline_polyline(&self.cells, |p| self.contains(p))
+    }
+}
+
+/// 3D version of `Region`: the cells of an `Array3` with a given value, within a bounding box.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Region3<T>
+    where T: Eq+Copy
+{
+    pub(crate) anchor: UVec3,
+    pub(crate) size: UVec3,
+    pub(crate) reference: T,
+}
+
+impl<T> Region3<T>
+    where T: Eq+Copy
+{
+    /// The region of all cells of `a` with value `reference`, `None` if there are none.
+    pub fn of(a: &Array3<T>, reference: T) -> Option<Self> {
+        let mut bounds: Option<(UVec3, UVec3)> = None;
+        for ((x, y, z), v) in a.indexed_iter() {
+            if *v == reference {
+                let p = uvec3(x as u32, y as u32, z as u32);
+                bounds = Some(match bounds {
+                    None => (p, p),
+                    Some((lo, hi)) => (lo.min(p), hi.max(p)),
+                });
+            }
+        }
+        bounds.map(|(lo, hi)| Self { anchor: lo, size: hi - lo + UVec3::ONE, reference })
+    }
+
+    /// Bounding box of the region
+    pub fn rect(&self) -> Rect3 {
+        Rect3::new(self.anchor, self.size)
+    }
+
+    /// The map value of all cells of this region
+    pub fn reference(&self) -> T {
+        self.reference
+    }
+
+    /// True iff `p` is a cell of this region in map `a`.
+    pub fn contains(&self, a: &Array3<T>, p: IVec3) -> bool {
+        p.cmpge(IVec3::ZERO).all()
+            && self.rect().contains(p.as_uvec3())
+            && a.get([p.x as usize, p.y as usize, p.z as usize]) == Some(&self.reference)
+    }
+
+    /// All cells of this region in map `a`, x-major.
+    pub fn cells(&self, a: &Array3<T>) -> Vec<UVec3> {
+        self.rect()
+            .iter()
+            .filter(|p| a[[p.x as usize, p.y as usize, p.z as usize]] == self.reference)
+            .collect()
+    }
+
+    /// All cells of the region that have a face neighbor outside of the region (or are at the
+    /// map border), x-major.
+    pub fn border_cells(&self, a: &Array3<T>) -> Vec<UVec3> {
+        self.cells(a)
+            .into_iter()
+            .filter(|p| {
+                [IVec3::X, IVec3::NEG_X, IVec3::Y, IVec3::NEG_Y, IVec3::Z, IVec3::NEG_Z]
+                    .iter()
+                    .any(|&d| !self.contains(a, p.as_ivec3() + d))
+            })
+            .collect()
+    }
+}
+
+/// The `cells` that have a 4-neighbor for which `contains` is false.
+fn border_cells<F: Fn(IVec2) -> bool>(cells: Vec<UVec2>, contains: F) -> Vec<UVec2> {
+    cells
+        .into_iter()
+        .filter(|p| {
+            [ivec2(1, 0), ivec2(-1, 0), ivec2(0, 1), ivec2(0, -1)]
+                .iter()
+                .any(|&d| !contains(p.as_ivec2() + d))
+        })
+        .collect()
+}
+
+/// Moore neighbor tracing of the region given by `cells` and `contains`.
+fn outline_polyline<F: Fn(IVec2) -> bool>(cells: &[UVec2], contains: F) -> Vec<UVec2> {
+    let start = match cells.iter().min_by_key(|p| (p.y, p.x)) {
+        Some(p) => p.as_ivec2(),
+        None => return Vec::new(),
+    };
+
+    // We enter `start` from the west, which is outside of the region as `start` is the
+    // first cell in scanline order
+    let start_back = start + MOORE[0];
+    let (mut p, mut back) = (start, start_back);
+    let mut outline = vec![start.as_uvec2()];
+
+    loop {
+        let first = MOORE.iter().position(|&d| d == back - p).unwrap();
+        let next = (1..=8)
+            .map(|i| (first + i) % 8)
+            .find(|&i| contains(p + MOORE[i]));
+
+        let i = match next {
+            Some(i) => i,
+            // Single isolated cell
+            None => break,
+        };
+
+        back = p + MOORE[(i + 7) % 8];
+        p += MOORE[i];
+
+        if p == start && back == start_back {
+            break;
+        }
+        outline.push(p.as_uvec2());
+    }
+    outline
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn region3() {
+        let mut a = Array3::from_elem((4, 3, 5), 0u8);
+        a[[1, 0, 2]] = 1;
+        a[[2, 2, 4]] = 1;
+        a[[2, 1, 3]] = 1;
+
+        let r = Region3::of(&a, 1).unwrap();
+        assert_eq!(r.rect(), Rect3::new(uvec3(1, 0, 2), uvec3(2, 3, 3)));
+        assert_eq!(r.cells(&a), vec![uvec3(1, 0, 2), uvec3(2, 1, 3), uvec3(2, 2, 4)]);
+        assert!(r.contains(&a, IVec3::new(2, 1, 3)));
+        assert!(!r.contains(&a, IVec3::new(2, 1, 2)));
+        assert!(!r.contains(&a, IVec3::new(-1, 0, 2)));
+        assert_eq!(r.border_cells(&a).len(), 3);
+        assert_eq!(Region3::of(&a, 2), None);
+
+        // Only the center of a solid 3x3x3 cube is not at its border
+        let cube = Array3::from_elem((3, 3, 3), 1u8);
+        let r = Region3::of(&cube, 1).unwrap();
+        assert_eq!(r.cells(&cube).len(), 27);
+        assert!(!r.border_cells(&cube).contains(&uvec3(1, 1, 1)));
+        assert_eq!(r.border_cells(&cube).len(), 26);
+    }
+}
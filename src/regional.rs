@@ -0,0 +1,97 @@
+//! Run a generator independently inside each region of a map (eg. a different wave function
+//! collapse tileset per biome cell of a voronoi map) and stitch the results into one map.
+
+use crate::coord::MapAccess;
+use crate::neighborhood::{chebyshev, find_nearest, offsets};
+use crate::region::Region;
+use crate::seed::derive_seed;
+use glam::UVec2;
+use ndarray::Array2;
+
+/// A generator that is run once per region by `generate_per_region`.
+/// Implemented for closures `FnMut(usize, &Array2<bool>, u64) -> Array2<T>`.
+pub trait RegionGenerator<T> {
+    /// Generate the tiles of region number `region`. `mask` covers the bounding box of the
+    /// region and marks its tiles, the result must have the size of `mask`. Tiles outside of
+    /// the mask are discarded.
+    fn generate(&mut self, region: usize, mask: &Array2<bool>, seed: u64) -> Array2<T>;
+}
+
+impl<T, F: FnMut(usize, &Array2<bool>, u64) -> Array2<T>> RegionGenerator<T> for F {
+    fn generate(&mut self, region: usize, mask: &Array2<bool>, seed: u64) -> Array2<T> {
+        self(region, mask, seed)
+    }
+}
+
+/// How `generate_per_region` fills tiles that belong to no region (eg. voronoi border tiles).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BorderRule<T> {
+    /// Use this value
+    Fill(T),
+    /// Use the value of the nearest generated tile (chebyshev distance)
+    Nearest,
+    /// Use the most frequent value among the generated 8 neighbors, or the nearest generated
+    /// tile if there are none. Blends borders into the surrounding regions.
+    Majority,
+}
+
+/// Run `generator` for every region of `map` (eg. `VoronoiResult::regions` and `map`) with
+/// seed `derive_seed(seed, region index)` and stitch the results into one map of the size of
+/// `map`. Tiles not covered by any region are filled according to `border`.
+/// Panics if no tile was generated and `border` is not `Fill`.
+pub fn generate_per_region<T, R, G>(
+    regions: &[Region<R>],
+    map: &Array2<R>,
+    seed: u64,
+    mut generator: G,
+    border: &BorderRule<T>,
+) -> Array2<T>
+where
+    T: Clone + PartialEq,
+    R: Eq + Copy,
+    G: RegionGenerator<T>,
+{
+    let mut stitched: Array2<Option<T>> = Array2::from_elem(map.dim(), None);
+    for (i, region) in regions.iter().enumerate() {
+        let rect = region.rect();
+        let mask = rect.view(map).mapv(|v| v == region.reference());
+        let generated = generator.generate(i, &mask, derive_seed(seed, i as u64));
+        assert_eq!(generated.dim(), mask.dim());
+        for (p, &m) in mask.iter_with_positions() {
+            if m {
+                *stitched.at_mut(rect.anchor + p) = Some(generated.at(p).clone());
+            }
+        }
+    }
+
+    let nearest = |p: UVec2| {
+        let q = find_nearest(&stitched, p, |t| t.is_some(), chebyshev).expect("no tiles were generated");
+        stitched.at(q).clone().unwrap()
+    };
+    Array2::from_shape_fn(map.dim(), |(x, y)| {
+        let p = UVec2::new(x as u32, y as u32);
+        if let Some(t) = stitched.at(p) {
+            return t.clone();
+        }
+        match border {
+            BorderRule::Fill(v) => v.clone(),
+            BorderRule::Nearest => nearest(p),
+            BorderRule::Majority => majority(&stitched, p).unwrap_or_else(|| nearest(p)),
+        }
+    })
+}
+
+/// Most frequent value among the 8 neighbors of `p` that are set, ties are broken by neighbor
+/// order.
+fn majority<T: Clone + PartialEq>(a: &Array2<Option<T>>, p: UVec2) -> Option<T> {
+    let values: Vec<&T> = offsets(1, chebyshev)
+        .filter_map(|o| a.get_at(p.as_ivec2() + o).and_then(|t| t.as_ref()))
+        .collect();
+    let count = |v: &T| values.iter().filter(|w| **w == v).count();
+    values
+        .iter()
+        .enumerate()
+        .max_by_key(|&(i, v)| (count(v), usize::MAX - i))
+        .map(|(_, v)| (*v).clone())
+}
+
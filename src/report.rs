@@ -0,0 +1,96 @@
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// Min/max/mean/standard deviation of the values of a generated map.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ValueStats {
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64,
+    /// Population standard deviation
+    pub std_dev: f64,
+}
+
+impl ValueStats {
+    /// `None` if `values` is empty.
+    pub fn of<'a, I: IntoIterator<Item = &'a f64>>(values: I) -> Option<Self> {
+        let mut n = 0_usize;
+        let mut r = Self { min: f64::INFINITY, max: f64::NEG_INFINITY, mean: 0.0, std_dev: 0.0 };
+        // Welford's online algorithm, `std_dev` holds the sum of squared deviations
+        for &v in values {
+            r.min = r.min.min(v);
+            r.max = r.max.max(v);
+            n += 1;
+            let delta = v - r.mean;
+            r.mean += delta / n as f64;
+            r.std_dev += delta * (v - r.mean);
+        }
+        (n > 0).then(|| Self { std_dev: (r.std_dev / n as f64).sqrt(), ..r })
+    }
+}
+
+/// Timing and statistics of a generator run, for tuning parameters.
+/// Generators only fill in the fields that apply to them.
+#[derive(Clone, Debug, Default)]
+pub struct GenerationReport {
+    /// Time spent per phase, in order of first occurrence
+    pub phases: Vec<(&'static str, Duration)>,
+    /// Number of cells decided (eg. collapsed by WFC)
+    pub cells_processed: usize,
+    pub contradictions: usize,
+    pub bombings: usize,
+    /// Mean distance the centers moved, per relaxation step
+    pub movement: Vec<f32>,
+    pub values: Option<ValueStats>,
+}
+
+impl GenerationReport {
+    /// Run `f` and add the time it took to `phase`.
+    pub fn time<R, F: FnOnce() -> R>(&mut self, phase: &'static str, f: F) -> R {
+        let start = Instant::now();
+        let r = f();
+        self.add_time(phase, start.elapsed());
+        r
+    }
+
+    pub fn add_time(&mut self, phase: &'static str, duration: Duration) {
+        match self.phases.iter_mut().find(|(p, _)| *p == phase) {
+            Some((_, d)) => *d += duration,
+            None => self.phases.push((phase, duration)),
+        }
+    }
+
+    /// Time spent in `phase`, zero if the phase did not occur.
+    pub fn phase(&self, phase: &str) -> Duration {
+        self.phases
+            .iter()
+            .find(|(p, _)| *p == phase)
+            .map_or(Duration::ZERO, |(_, d)| *d)
+    }
+
+    pub fn total(&self) -> Duration {
+        self.phases.iter().map(|(_, d)| *d).sum()
+    }
+}
+
+impl fmt::Display for GenerationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (phase, d) in self.phases.iter() {
+            writeln!(f, "{}: {:?}", phase, d)?;
+        }
+        writeln!(f, "total: {:?}", self.total())?;
+        if self.cells_processed > 0 {
+            writeln!(f, "cells processed: {}", self.cells_processed)?;
+        }
+        if self.contradictions > 0 || self.bombings > 0 {
+            writeln!(f, "contradictions: {}, bombings: {}", self.contradictions, self.bombings)?;
+        }
+        if !self.movement.is_empty() {
+            writeln!(f, "movement per step: {:?}", self.movement)?;
+        }
+        if let Some(v) = self.values {
+            writeln!(f, "values: min {} max {} mean {} std dev {}", v.min, v.max, v.mean, v.std_dev)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,139 @@
+
+use crate::seed::derive_seed;
+use glam::{ivec2, vec2, IVec2, UVec2, Vec2};
+use ndarray::Array2;
+use rand::{distributions::Uniform, rngs::StdRng, Rng, SeedableRng};
+use std::collections::HashMap;
+
+/// A placed object.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Placement<K> {
+    pub kind: K,
+    /// Position in tile coordinates (tile `(x, y)` covers `[x, x + 1) x [y, y + 1)`)
+    pub position: Vec2,
+}
+
+/// An object type to scatter.
+#[derive(Clone, Debug)]
+pub struct ScatterLayer<'a, K> {
+    pub kind: K,
+    /// Probability in [0, 1] of accepting a candidate position on each tile; 1.0 places
+    /// objects as densely as `radius` allows. Must have the size of the map.
+    pub density: &'a Array2<f64>,
+    /// Minimum distance to other objects. Two objects are at least the larger of their radii
+    /// apart.
+    pub radius: f32,
+}
+
+/// Places decorations (trees, rocks, ...) with blue noise spacing according to density maps
+/// (eg. noise layers or masks converted to `f64`).
+///
+/// Layers are placed in the order they were added, so earlier layers take precedence:
+/// eg. large trees first, then bushes in the remaining gaps.
+pub struct Scatter<'a, K> {
+    pub size: UVec2,
+    pub seed: u64,
+    /// Candidate positions per layer and area `radius^2` (more is closer to a maximal
+    /// packing but slower)
+    pub attempts: u32,
+    pub layers: Vec<ScatterLayer<'a, K>>,
+}
+
+impl<'a, K: Clone> Scatter<'a, K> {
+    pub fn new(size: UVec2, seed: u64) -> Self {
+        Self { size, seed, attempts: 8, layers: Vec::new() }
+    }
+
+    /// Panics if `density` does not have the size of the map or `radius` is not positive.
+    pub fn layer(mut self, kind: K, density: &'a Array2<f64>, radius: f32) -> Self {
+        assert_eq!(density.dim(), (self.size.x as usize, self.size.y as usize));
+        assert!(radius > 0.0);
+        self.layers.push(ScatterLayer { kind, density, radius });
+        self
+    }
+
+    pub fn generate(&self) -> Vec<Placement<K>> {
+        let cell = self.layers.iter().map(|l| l.radius).fold(0.0, f32::max);
+        let mut grid: HashMap<IVec2, Vec<usize>> = HashMap::new();
+        let mut placed: Vec<(Placement<K>, f32)> = Vec::new();
+        let size = self.size.as_vec2();
+
+        for (i, layer) in self.layers.iter().enumerate() {
+            let mut rng = StdRng::seed_from_u64(derive_seed(self.seed, i as u64));
+            let ux = Uniform::from(0.0..size.x.max(f32::MIN_POSITIVE));
+            let uy = Uniform::from(0.0..size.y.max(f32::MIN_POSITIVE));
+            let n = (size.x * size.y / (layer.radius * layer.radius) * self.attempts as f32).ceil() as usize;
+
+            for _ in 0..n {
+                let p = vec2(rng.sample(ux), rng.sample(uy));
+                let density = layer.density[[p.x as usize, p.y as usize]];
+                if rng.gen::<f64>() >= density {
+                    continue;
+                }
+
+                // Any conflicting object is within `cell` and thus in the surrounding cells
+                let c = (p / cell).floor().as_ivec2();
+                let free = (-1..=1)
+                    .flat_map(|dx| (-1..=1).map(move |dy| ivec2(dx, dy)))
+                    .filter_map(|o| grid.get(&(c + o)))
+                    .flatten()
+                    .all(|&j| {
+                        let (other, radius) = &placed[j];
+                        other.position.distance(p) >= radius.max(layer.radius)
+                    });
+                if free {
+                    grid.entry(c).or_default().push(placed.len());
+                    placed.push((Placement { kind: layer.kind.clone(), position: p }, layer.radius));
+                }
+            }
+        }
+
+        placed.into_iter().map(|(p, _)| p).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use glam::uvec2;
+
+    #[test]
+    fn objects_keep_their_distance() {
+        let trees = Array2::from_shape_fn((30, 20), |(x, _)| if x < 10 { 0.0 } else { 1.0 });
+        let bushes = Array2::from_elem((30, 20), 1.0);
+        let scatter = Scatter::new(uvec2(30, 20), 4).layer('T', &trees, 3.0).layer('b', &bushes, 1.0);
+        let placed = scatter.generate();
+        assert_eq!(placed, scatter.generate());
+
+        let radius = |p: &Placement<char>| if p.kind == 'T' { 3.0 } else { 1.0 };
+        for (i, a) in placed.iter().enumerate() {
+            assert!(a.position.cmpge(Vec2::ZERO).all() && a.position.cmplt(vec2(30.0, 20.0)).all());
+            for b in &placed[i + 1..] {
+                assert!(a.position.distance(b.position) >= f32::max(radius(a), radius(b)));
+            }
+        }
+
+        // Trees are placed first and only where their density is positive, bushes fill the gaps
+        let trees = placed.iter().take_while(|p| p.kind == 'T').count();
+        assert!(trees > 10);
+        assert!(placed[..trees].iter().all(|p| p.position.x >= 10.0));
+        assert!(placed[trees..].iter().all(|p| p.kind == 'b'));
+        assert!(placed[trees..].iter().filter(|p| p.position.x < 10.0).count() > 20);
+    }
+
+    #[test]
+    fn density_thins_out_objects() {
+        let dense = Array2::from_elem((40, 40), 1.0);
+        let sparse = Array2::from_elem((40, 40), 0.05);
+        let count = |density| Scatter::new(uvec2(40, 40), 1).layer((), density, 2.0).generate().len();
+        assert!(count(&sparse) < count(&dense) / 2);
+        assert_eq!(count(&Array2::zeros((40, 40))), 0);
+        assert!(Scatter::<()>::new(uvec2(0, 0), 1).generate().is_empty());
+    }
+
+    #[test]
+    #[should_panic]
+    fn density_must_have_map_size() {
+        let _ = Scatter::new(uvec2(4, 4), 0).layer(0, &Array2::zeros((4, 5)), 1.0);
+    }
+}
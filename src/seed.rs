@@ -0,0 +1,10 @@
+
+/// Derive an independent seed from `seed` and a sequence number `n` (eg. attempt number,
+/// region index, chunk index).
+/// Uses the SplitMix64 finalizer so that consecutive `n` give uncorrelated seeds.
+pub fn derive_seed(seed: u64, n: u64) -> u64 {
+    let mut z = seed.wrapping_add(n.wrapping_add(1).wrapping_mul(0x9e37_79b9_7f4a_7c15));
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
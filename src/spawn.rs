@@ -0,0 +1,151 @@
+
+use crate::coord::MapAccess;
+use crate::region::Region;
+use crate::seed::derive_seed;
+use ndarray::Array2;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+/// Parameters for `spawn_tables`. Budgets of a region are
+/// `area * density * (1 + scaling * difficulty) * jitter`, with `jitter` uniform in
+/// `[1 - variation, 1 + variation]`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SpawnTable {
+    /// Encounter budget per tile at difficulty 0
+    pub encounter_density: f64,
+    /// Relative encounter budget increase at difficulty 1
+    pub encounter_scaling: f64,
+    /// Loot budget per tile at difficulty 0
+    pub loot_density: f64,
+    pub loot_scaling: f64,
+    /// Random variation of the budgets in [0, 1]
+    pub variation: f64,
+}
+
+impl Default for SpawnTable {
+    fn default() -> Self {
+        Self {
+            encounter_density: 0.02,
+            encounter_scaling: 2.0,
+            loot_density: 0.01,
+            loot_scaling: 3.0,
+            variation: 0.25,
+        }
+    }
+}
+
+/// Budgets assigned to one region by `spawn_tables`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RegionSpawns {
+    /// Index of the region in the slice passed to `spawn_tables`
+    pub region: usize,
+    /// Number of tiles of the region
+    pub area: usize,
+    /// Mean difficulty of the region's tiles in [0, 1]
+    pub difficulty: f64,
+    pub encounter_budget: f64,
+    pub loot_budget: f64,
+}
+
+/// Assign encounter and loot budgets to `regions` (eg. rooms) of map `a`.
+///
+/// `difficulty` must have the size of `a`, eg. the dijkstra distance from the entrance. It is
+/// normalized by its largest finite value, non-finite values (unreachable tiles) count as
+/// difficulty 1.
+/// Each region gets its own rng derived from `seed` and its index, so adding regions at the end
+/// does not change the budgets of the others.
+pub fn spawn_tables<T: Eq + Copy>(
+    regions: &[Region<T>],
+    a: &Array2<T>,
+    difficulty: &Array2<f64>,
+    table: &SpawnTable,
+    seed: u64,
+) -> Vec<RegionSpawns> {
+    assert_eq!(a.dim(), difficulty.dim());
+    let max = difficulty
+        .iter()
+        .filter(|d| d.is_finite())
+        .fold(0.0, |m: f64, &d| m.max(d));
+    let normalized = |d: f64| match d.is_finite() {
+        true if max > 0.0 => (d / max).clamp(0.0, 1.0),
+        true => 0.0,
+        false => 1.0,
+    };
+
+    regions
+        .iter()
+        .enumerate()
+        .map(|(i, region)| {
+            let cells = region.cells(a);
+            let area = cells.len();
+            let difficulty = match area {
+                0 => 0.0,
+                _ => cells.iter().map(|&p| normalized(*difficulty.at(p))).sum::<f64>() / area as f64,
+            };
+
+            let mut rng = StdRng::seed_from_u64(derive_seed(seed, i as u64));
+            let mut jitter = || 1.0 + table.variation * rng.gen_range(-1.0..=1.0);
+            let budget = |density: f64, scaling: f64| area as f64 * density * (1.0 + scaling * difficulty);
+
+            RegionSpawns {
+                region: i,
+                area,
+                difficulty,
+                encounter_budget: budget(table.encounter_density, table.encounter_scaling) * jitter(),
+                loot_budget: budget(table.loot_density, table.loot_scaling) * jitter(),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use glam::{uvec2, UVec2};
+
+    /// 8x4 map with two 4x4 rooms 0 and 1 and difficulty `x`, (6, 0) is unreachable
+    fn rooms() -> (Vec<Region<u8>>, Array2<u8>, Array2<f64>) {
+        let a = Array2::from_shape_fn((8, 4), |(x, _)| (x >= 4) as u8);
+        let mut difficulty = Array2::from_shape_fn((8, 4), |(x, _)| x as f64);
+        difficulty[[6, 0]] = f64::INFINITY;
+        let regions = (0..2)
+            .map(|i| Region { anchor: uvec2(4 * i as u32, 0), size: uvec2(4, 4), reference: i })
+            .collect();
+        (regions, a, difficulty)
+    }
+
+    #[test]
+    fn budgets_scale_with_area_and_difficulty() {
+        let (regions, a, difficulty) = rooms();
+        let table = SpawnTable { variation: 0.0, ..Default::default() };
+        let spawns = spawn_tables(&regions, &a, &difficulty, &table, 0);
+        assert_eq!(spawns.len(), 2);
+        assert_eq!((spawns[0].region, spawns[0].area), (0, 16));
+        assert!((spawns[0].difficulty - 1.5 / 7.0).abs() < 1e-12);
+        assert!((spawns[1].difficulty - 89.0 / 112.0).abs() < 1e-12);
+        for s in &spawns {
+            assert!((s.encounter_budget - 16.0 * 0.02 * (1.0 + 2.0 * s.difficulty)).abs() < 1e-12);
+            assert!((s.loot_budget - 16.0 * 0.01 * (1.0 + 3.0 * s.difficulty)).abs() < 1e-12);
+        }
+
+        let empty = [Region { anchor: UVec2::ZERO, size: uvec2(8, 4), reference: 2 }];
+        let spawns = spawn_tables(&empty, &a, &difficulty, &table, 0);
+        assert_eq!((spawns[0].area, spawns[0].difficulty, spawns[0].loot_budget), (0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn variation_is_per_region() {
+        let (mut regions, a, difficulty) = rooms();
+        let table = SpawnTable { variation: 0.5, ..Default::default() };
+        let exact = spawn_tables(&regions, &a, &difficulty, &SpawnTable { variation: 0.0, ..table.clone() }, 0);
+        let spawns = spawn_tables(&regions, &a, &difficulty, &table, 7);
+        assert_ne!(spawns, spawn_tables(&regions, &a, &difficulty, &table, 8));
+        for (s, e) in spawns.iter().zip(&exact) {
+            let ratio = s.encounter_budget / e.encounter_budget;
+            assert!((0.5..=1.5).contains(&ratio));
+        }
+
+        // Adding a region does not change the budgets of the others
+        regions.push(Region { anchor: uvec2(2, 0), size: uvec2(4, 2), reference: 1 });
+        assert_eq!(spawn_tables(&regions, &a, &difficulty, &table, 7)[..2], spawns[..]);
+    }
+}
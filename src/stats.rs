@@ -0,0 +1,78 @@
+
+use crate::report::ValueStats;
+use float_ord::FloatOrd;
+use ndarray::{Array2, Axis};
+use std::collections::HashMap;
+use std::hash::Hash;
+
+/// Number of tiles per tile value.
+pub fn tile_histogram<T: Eq + Hash + Clone>(a: &Array2<T>) -> HashMap<T, usize> {
+    let mut r = HashMap::new();
+    for t in a.iter() {
+        *r.entry(t.clone()).or_insert(0) += 1;
+    }
+    r
+}
+
+/// Number of tiles for which `pred` is true in each row (index y).
+pub fn row_counts<T, F: Fn(&T) -> bool>(a: &Array2<T>, pred: F) -> Vec<usize> {
+    a.axis_iter(Axis(1)).map(|row| row.iter().filter(|t| pred(t)).count()).collect()
+}
+
+/// Number of tiles for which `pred` is true in each column (index x).
+pub fn column_counts<T, F: Fn(&T) -> bool>(a: &Array2<T>, pred: F) -> Vec<usize> {
+    a.axis_iter(Axis(0)).map(|column| column.iter().filter(|t| pred(t)).count()).collect()
+}
+
+/// Min/max/mean/standard deviation of the values of `a`, `None` if `a` is empty.
+pub fn value_stats(a: &Array2<f64>) -> Option<ValueStats> {
+    ValueStats::of(a)
+}
+
+/// Value below which the fraction `p` (in [0, 1]) of the values of `a` lie, interpolated
+/// linearly between neighboring values. NaNs are ignored, `None` if there are no other values.
+pub fn percentile(a: &Array2<f64>, p: f64) -> Option<f64> {
+    let mut values: Vec<f64> = a.iter().copied().filter(|v| !v.is_nan()).collect();
+    if values.is_empty() {
+        return None;
+    }
+    values.sort_unstable_by_key(|&v| FloatOrd(v));
+    let i = p.clamp(0.0, 1.0) * (values.len() - 1) as f64;
+    let (lo, hi) = (i.floor() as usize, i.ceil() as usize);
+    Some(values[lo] + (values[hi] - values[lo]) * (i - lo as f64))
+}
+
+/// Result of `value_histogram`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Histogram {
+    pub min: f64,
+    pub max: f64,
+    /// Number of values per bin, bin `i` covers `[min + i * width, min + (i + 1) * width)`
+    /// (the last bin includes `max`)
+    pub counts: Vec<usize>,
+}
+
+impl Histogram {
+    pub fn bin_width(&self) -> f64 {
+        (self.max - self.min) / self.counts.len().max(1) as f64
+    }
+}
+
+/// Histogram of the finite values of `a` with `bins` equally wide bins between their min and
+/// max. `None` if there are no finite values or `bins` is 0.
+pub fn value_histogram(a: &Array2<f64>, bins: usize) -> Option<Histogram> {
+    let stats = ValueStats::of(a.iter().filter(|v| v.is_finite()))?;
+    if bins == 0 {
+        return None;
+    }
+    let mut counts = vec![0; bins];
+    let width = stats.max - stats.min;
+    for &v in a.iter().filter(|v| v.is_finite()) {
+        let bin = match width > 0.0 {
+            true => ((v - stats.min) / width * bins as f64) as usize,
+            false => 0,
+        };
+        counts[bin.min(bins - 1)] += 1;
+    }
+    Some(Histogram { min: stats.min, max: stats.max, counts })
+}
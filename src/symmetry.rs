@@ -0,0 +1,107 @@
+
+use crate::coord::{map_size, UCoord2Conversions};
+use crate::transform::Transform;
+use glam::{uvec2, UVec2};
+use ndarray::Array2;
+
+/// Symmetry of a map.
+/// Every position belongs to an orbit of positions that are mapped onto each other by the
+/// symmetry; the orbit member with the smallest (x, y) (lexicographically) is its source,
+/// the set of all sources is the fundamental domain.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Symmetry {
+    /// Mirrored along the vertical center line, ie. x -> size.x - 1 - x
+    MirrorX,
+    /// Mirrored along the horizontal center line, ie. y -> size.y - 1 - y
+    MirrorY,
+    /// Mirrored along both center lines
+    MirrorXY,
+    /// Invariant under rotation by 180 degrees around the center
+    Rotate2,
+    /// Invariant under rotation by 90 degrees around the center. Requires a square map.
+    Rotate4,
+}
+
+impl Symmetry {
+    /// All positions that `p` is mapped to by this symmetry (including `p` itself),
+    /// without duplicates.
+    pub fn orbit(&self, p: UVec2, size: UVec2) -> Vec<UVec2> {
+        let m = size - UVec2::ONE;
+        let mut r = vec![p];
+        let images = match self {
+            Symmetry::MirrorX => vec![uvec2(m.x - p.x, p.y)],
+            Symmetry::MirrorY => vec![uvec2(p.x, m.y - p.y)],
+            Symmetry::MirrorXY => vec![
+                uvec2(m.x - p.x, p.y),
+                uvec2(p.x, m.y - p.y),
+                uvec2(m.x - p.x, m.y - p.y),
+            ],
+            Symmetry::Rotate2 => vec![uvec2(m.x - p.x, m.y - p.y)],
+            Symmetry::Rotate4 => {
+                assert_eq!(size.x, size.y, "Rotate4 symmetry requires a square map");
+                vec![
+                    uvec2(m.x - p.y, p.x),
+                    uvec2(m.x - p.x, m.y - p.y),
+                    uvec2(p.y, m.y - p.x),
+                ]
+            }
+        };
+
+        for q in images {
+            if !r.contains(&q) {
+                r.push(q);
+            }
+        }
+        r
+    }
+
+    /// The transforms the map is invariant under (including `Transform::Identity`), eg. to
+    /// generate all variants of a block or prefab that are allowed on a symmetric map.
+    pub fn transforms(&self) -> Vec<Transform> {
+        match self {
+            Symmetry::MirrorX => vec![Transform::Identity, Transform::FlipX],
+            Symmetry::MirrorY => vec![Transform::Identity, Transform::FlipY],
+            Symmetry::MirrorXY => vec![Transform::Identity, Transform::FlipX, Transform::FlipY, Transform::Rotate180],
+            Symmetry::Rotate2 => vec![Transform::Identity, Transform::Rotate180],
+            Symmetry::Rotate4 => vec![
+                Transform::Identity,
+                Transform::Rotate90,
+                Transform::Rotate180,
+                Transform::Rotate270,
+            ],
+        }
+    }
+
+    /// The representative of the orbit of `p`.
+    pub fn source(&self, p: UVec2, size: UVec2) -> UVec2 {
+        self.orbit(p, size)
+            .into_iter()
+            .min_by_key(|q| (q.x, q.y))
+            .unwrap()
+    }
+
+    pub fn is_fundamental(&self, p: UVec2, size: UVec2) -> bool {
+        self.source(p, size) == p
+    }
+
+    /// Mask of the fundamental domain, ie. the positions that determine the whole map.
+    pub fn fundamental_domain(&self, size: UVec2) -> Array2<bool> {
+        Array2::from_shape_fn(size.as_index2(), |(x, y)| {
+            self.is_fundamental(uvec2(x as u32, y as u32), size)
+        })
+    }
+
+    /// Make `a` symmetric by copying the values of the fundamental domain onto the rest of the
+    /// map. Can be used on the output of any generator.
+    pub fn apply<T: Clone>(&self, a: &mut Array2<T>) {
+        let size = map_size(a);
+        for x in 0..size.x as usize {
+            for y in 0..size.y as usize {
+                let s = self.source(uvec2(x as u32, y as u32), size);
+                if s != uvec2(x as u32, y as u32) {
+                    a[[x, y]] = a[s.as_index2()].clone();
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,85 @@
+
+use crate::colored_noise::ColoredNoise;
+use crate::coord::map_size;
+use crate::seed::derive_seed;
+use glam::{uvec2, UVec2};
+use ndarray::Array2;
+
+/// Expands a coarse, hand-authored template (eg. an 8x8 map of Ocean/Land/Mountain tags)
+/// to full resolution.
+/// Every template cell covers `scale` x `scale` output tiles, the borders between cells are
+/// made organic by displacing the lookup position of every output tile by a noise vector.
+#[derive(Clone)]
+pub struct TemplateExpansion<T> {
+    pub template: Array2<T>,
+    pub scale: u32,
+
+    /// Maximum displacement of the template lookup in tiles
+    pub perturbation: f64,
+
+    /// Noise used for displacement and refinement detail. `size` is ignored, the output size
+    /// is `template size * scale`.
+    pub noise: ColoredNoise,
+}
+
+impl<T> TemplateExpansion<T>
+where
+    T: Clone,
+{
+    /// Panics if the template is empty or `scale` is 0.
+    pub fn new(template: Array2<T>, scale: u32) -> Self {
+        assert!(!template.is_empty() && scale > 0);
+        Self {
+            template,
+            scale,
+            perturbation: scale as f64 / 2.0,
+            noise: Default::default(),
+        }
+    }
+
+    pub fn output_size(&self) -> UVec2 {
+        map_size(&self.template) * self.scale
+    }
+
+    fn noise_layer(&self, seed_offset: u64) -> Array2<f64> {
+        ColoredNoise {
+            size: self.output_size(),
+            seed: derive_seed(self.noise.seed, seed_offset),
+            ..self.noise.clone()
+        }
+        .generate()
+    }
+
+    /// Upscale the template with noise-perturbed cell borders.
+    pub fn expand(&self) -> Array2<T> {
+        let size = self.output_size();
+        let (tx, ty) = self.template.dim();
+        let dx = self.noise_layer(0);
+        let dy = self.noise_layer(1);
+        let scale = self.scale as f64;
+
+        Array2::from_shape_fn((size.x as usize, size.y as usize), |(x, y)| {
+            // Noise is in [0, 1), map to [-perturbation, perturbation)
+            let px = x as f64 + 0.5 + (dx[[x, y]] * 2.0 - 1.0) * self.perturbation;
+            let py = y as f64 + 0.5 + (dy[[x, y]] * 2.0 - 1.0) * self.perturbation;
+
+            let cx = ((px / scale).floor().max(0.0) as usize).min(tx - 1);
+            let cy = ((py / scale).floor().max(0.0) as usize).min(ty - 1);
+            self.template[[cx, cy]].clone()
+        })
+    }
+
+    /// Expand the template, then refine every tile with `rule`, which gets the tag of the
+    /// tile, its position and a per-tile detail noise value in [0, 1).
+    pub fn generate<U, F>(&self, mut rule: F) -> Array2<U>
+    where
+        F: FnMut(&T, UVec2, f64) -> U,
+    {
+        let expanded = self.expand();
+        let detail = self.noise_layer(2);
+
+        Array2::from_shape_fn(expanded.dim(), |(x, y)| {
+            rule(&expanded[[x, y]], uvec2(x as u32, y as u32), detail[[x, y]])
+        })
+    }
+}
@@ -0,0 +1,189 @@
+
+use crate::coord::{map_size, MapAccess};
+use crate::map_stack::MapStack;
+use crate::neighborhood::{chebyshev, offsets};
+use crate::voronoi::VoronoiCenter;
+use float_ord::FloatOrd;
+use glam::{UVec2, Vec2};
+use ndarray::Array2;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+/// Owner of tiles no territory could reach (impassable or cut off).
+pub const UNCLAIMED: u32 = u32::MAX;
+
+/// A territory seed, eg. a capital, a voronoi center or a point of interest.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TerritorySource {
+    pub position: UVec2,
+    /// Growth speed relative to other territories: the cost of entering a tile is divided by
+    /// it, so territories with higher aggressiveness grow over more expensive terrain
+    pub aggressiveness: f64,
+}
+
+/// Result of `Territories::generate`.
+#[derive(Clone, Debug)]
+pub struct TerritoryMap {
+    /// Index of the owning source per tile, `UNCLAIMED` if no source reached it
+    pub owner: Array2<u32>,
+    /// Order in which tiles were claimed (0 = first), `u32::MAX` if unclaimed.
+    /// Thresholding this gives the partition at an earlier point of the growth.
+    pub order: Array2<u32>,
+    /// Accumulated (weighted) cost from the owning source, infinite if unclaimed
+    pub distance: Array2<f64>,
+    /// Number of tiles per source
+    pub areas: Vec<usize>,
+}
+
+/// Grows territories from sources over a cost surface until the map is partitioned.
+///
+/// All territories expand simultaneously, always claiming the unclaimed tile that is cheapest
+/// to reach (multi-source dijkstra, 8 neighbors). Unlike a voronoi map, borders follow the
+/// terrain: mountains (high cost) slow territories down, and tiles with infinite cost are never
+/// claimed.
+#[derive(Clone, Debug, Default)]
+pub struct Territories {
+    pub sources: Vec<TerritorySource>,
+}
+
+impl Territories {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// One source with aggressiveness 1.0 per voronoi center (at its rounded position), in the
+    /// order of `centers`.
+    pub fn from_centers(centers: &[VoronoiCenter]) -> Self {
+        centers
+            .iter()
+            .fold(Self::new(), |t, c| t.source(c.position.round().max(Vec2::ZERO).as_uvec2(), 1.0))
+    }
+
+    /// Panics if `aggressiveness` is not positive and finite.
+    pub fn source(mut self, position: UVec2, aggressiveness: f64) -> Self {
+        assert!(aggressiveness > 0.0 && aggressiveness.is_finite(), "aggressiveness must be positive");
+        self.sources.push(TerritorySource { position, aggressiveness });
+        self
+    }
+
+    /// `cost` is the cost of entering each tile (scaled by the step length, so diagonal steps
+    /// cost more). Sources outside of the map or on impassable tiles claim nothing.
+    /// Panics if the aggressiveness of a source is not positive and finite.
+    pub fn generate(&self, cost: &Array2<f64>) -> TerritoryMap {
+        let valid = |s: &TerritorySource| s.aggressiveness > 0.0 && s.aggressiveness.is_finite();
+        assert!(self.sources.iter().all(valid), "aggressiveness must be positive");
+        let mut owner = Array2::from_elem(cost.dim(), UNCLAIMED);
+        let mut order = Array2::from_elem(cost.dim(), u32::MAX);
+        let mut distance = Array2::from_elem(cost.dim(), f64::INFINITY);
+        let mut areas = vec![0; self.sources.len()];
+
+        // Ties are broken by source index for determinism
+        let mut queue = BinaryHeap::new();
+        for (i, s) in self.sources.iter().enumerate() {
+            match cost.get_at(s.position.as_ivec2()) {
+                Some(c) if c.is_finite() => queue.push(Reverse((FloatOrd(0.0), i, s.position.x, s.position.y))),
+                _ => continue,
+            }
+        }
+
+        let mut claimed = 0;
+        while let Some(Reverse((FloatOrd(d), i, x, y))) = queue.pop() {
+            let p = UVec2::new(x, y);
+            if *owner.at(p) != UNCLAIMED {
+                continue;
+            }
+            *owner.at_mut(p) = i as u32;
+            *order.at_mut(p) = claimed;
+            *distance.at_mut(p) = d;
+            areas[i] += 1;
+            claimed += 1;
+
+            for o in offsets(1, chebyshev) {
+                let q = p.as_ivec2() + o;
+                let c = match cost.get_at(q) {
+                    Some(&c) if c.is_finite() => c,
+                    _ => continue,
+                };
+                let q = q.as_uvec2();
+                if *owner.at(q) == UNCLAIMED {
+                    let dq = d + c * o.as_dvec2().length() / self.sources[i].aggressiveness;
+                    queue.push(Reverse((FloatOrd(dq), i, q.x, q.y)));
+                }
+            }
+        }
+
+        TerritoryMap { owner, order, distance, areas }
+    }
+
+    /// Grow territories over the `f64` cost layer `cost` and store the owners as layer `name`.
+    /// Returns `false` (and does nothing) if the cost layer does not exist.
+    pub fn generate_into_layer(&self, layers: &mut MapStack, cost: &str, name: &str) -> bool {
+        let owner = match layers.get::<f64>(cost) {
+            Some(c) => {
+                assert_eq!(map_size(c), layers.size());
+                self.generate(c).owner
+            }
+            None => return false,
+        };
+        layers.insert(name, owner);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use glam::{uvec2, vec2};
+
+    #[test]
+    fn equal_sources_split_the_map() {
+        let t = Territories::new().source(uvec2(0, 0), 1.0).source(uvec2(9, 0), 1.0);
+        let r = t.generate(&Array2::ones((10, 3)));
+        assert_eq!(r.areas, vec![15, 15]);
+        assert!(r.owner.indexed_iter().all(|((x, _), &o)| o == (x >= 5) as u32));
+        assert_eq!(r.order[[0, 0]], 0);
+        assert_eq!(r.order[[9, 0]], 1);
+        assert_eq!(r.distance[[0, 0]], 0.0);
+        assert_eq!(r.distance[[3, 0]], 3.0);
+        assert!((r.distance[[1, 1]] - 2_f64.sqrt()).abs() < 1e-12);
+
+        let mut orders: Vec<u32> = r.order.iter().copied().collect();
+        orders.sort();
+        assert_eq!(orders, (0..30).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn aggressiveness_and_cost() {
+        let cost = Array2::ones((10, 1));
+        let r = Territories::new().source(uvec2(0, 0), 3.0).source(uvec2(9, 0), 1.0).generate(&cost);
+        assert_eq!(r.areas, vec![7, 3]);
+
+        // A wall at x = 2 with a single expensive gap, a source on the wall and one outside of
+        // the map
+        let mut cost = Array2::ones((7, 5));
+        cost.row_mut(2).fill(f64::INFINITY);
+        cost[[2, 2]] = 10.0;
+        let centers = [vec2(0.8, 0.2), vec2(6.0, 4.0)].map(|position| VoronoiCenter { position, index: 0 });
+        let t = Territories::from_centers(&centers).source(uvec2(2, 0), 1.0).source(uvec2(20, 0), 1.0);
+        assert_eq!(t.sources[0].position, uvec2(1, 0));
+        let r = t.generate(&cost);
+        assert_eq!(r.areas, vec![11, 20, 0, 0]);
+        assert_eq!(r.distance[[2, 2]], 12.0);
+        assert!(r.owner.row(2).iter().enumerate().all(|(y, &o)| o == if y == 2 { 0 } else { UNCLAIMED }));
+        assert_eq!(r.distance[[2, 0]], f64::INFINITY);
+        assert_eq!(r.order[[2, 0]], u32::MAX);
+    }
+
+    #[test]
+    #[should_panic(expected = "aggressiveness must be positive")]
+    fn zero_aggressiveness() {
+        let _ = Territories::new().source(uvec2(0, 0), 0.0);
+    }
+
+    #[test]
+    #[should_panic(expected = "aggressiveness must be positive")]
+    fn negative_aggressiveness() {
+        let source = TerritorySource { position: uvec2(0, 0), aggressiveness: -1.0 };
+        let _ = Territories { sources: vec![source] }.generate(&Array2::ones((3, 3)));
+    }
+}
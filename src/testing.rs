@@ -0,0 +1,562 @@
+//! Random values of this crate's types for property based tests of downstream code (eg.
+//! probability callbacks and post-processors).
+//!
+//! The generators here only need an `Rng`. With the `proptest` feature, `strategies` provides
+//! the same values as proptest strategies (which shrink), and `Rect`, the generator configs
+//! (`config::NoiseConfig`, ...), `Normalization`, `Direction`, `Direction8` and `Transform`
+//! implement `Arbitrary`:
+//!
+//! ```
+//! # #[cfg(feature = "proptest")]
+//! # {
+//! # use glam::uvec2;
+//! # use mapgen_2d::testing::strategies::{map, size};
+//! # use proptest::prelude::*;
+//! # #[derive(Clone, Copy, Debug)]
+//! # enum Cell { Wall, Floor }
+//! # use Cell::*;
+//! # fn smooth(a: &ndarray::Array2<Cell>) -> ndarray::Array2<Cell> { a.clone() }
+//! proptest! {
+//!     fn smoothing_keeps_size(map in map(size(uvec2(32, 32)), vec![Wall, Floor])) {
+//!         prop_assert_eq!(smooth(&map).dim(), map.dim());
+//!     }
+//! }
+//! # smoothing_keeps_size();
+//! # }
+//! ```
+//!
+//! With the `quickcheck` feature, `Rect`, `Normalization`, `Direction`, `Direction8` and
+//! `Transform` implement quickcheck's `Arbitrary`, and `arbitrary` has wrappers for sizes,
+//! seeds and tiles:
+//!
+//! ```
+//! # #[cfg(feature = "quickcheck")]
+//! # {
+//! # use mapgen_2d::testing::arbitrary::{Seed, Size};
+//! # use mapgen_2d::testing::random_map;
+//! # use quickcheck::quickcheck;
+//! # use rand::{rngs::StdRng, SeedableRng};
+//! # #[derive(Clone, Copy, Debug)]
+//! # enum Cell { Wall, Floor }
+//! # use Cell::*;
+//! # fn smooth(a: &ndarray::Array2<Cell>) -> ndarray::Array2<Cell> { a.clone() }
+//! fn smoothing_keeps_size(size: Size, seed: Seed) -> bool {
+//!     let map = random_map(&mut StdRng::seed_from_u64(seed.0), size.0, &[Wall, Floor]);
+//!     smooth(&map).dim() == map.dim()
+//! }
+//! quickcheck(smoothing_keeps_size as fn(Size, Seed) -> bool);
+//! # }
+//! ```
+
+use crate::rect::Rect;
+use crate::tile::Tile;
+use glam::{uvec2, UVec2};
+use ndarray::Array2;
+use rand::{seq::SliceRandom, Rng};
+
+/// Map size with each axis in `1..=max` (`max` is clamped to at least 1).
+pub fn random_size<R: Rng>(rng: &mut R, max: UVec2) -> UVec2 {
+    let max = max.max(UVec2::ONE);
+    uvec2(rng.gen_range(1..=max.x), rng.gen_range(1..=max.y))
+}
+
+/// Non-empty rect that lies completely within a map of size `size`, or an empty rect at
+/// (0, 0) if `size` is empty.
+pub fn random_rect<R: Rng>(rng: &mut R, size: UVec2) -> Rect {
+    if size.x == 0 || size.y == 0 {
+        return Rect::from_size(UVec2::ZERO);
+    }
+    let a = uvec2(rng.gen_range(0..size.x), rng.gen_range(0..size.y));
+    let b = uvec2(rng.gen_range(0..size.x), rng.gen_range(0..size.y));
+    Rect::from_corners(a, b)
+}
+
+/// Random valid tile, assuming that the numeric values `0..T::MAX` are the valid tiles (as for
+/// the tiles of a wave function collapse). `T::invalid()` if there are no valid tiles.
+pub fn random_tile<T: Tile, R: Rng>(rng: &mut R) -> T {
+    match T::MAX {
+        0 => T::invalid(),
+        n => T::from(rng.gen_range(0..n)),
+    }
+}
+
+/// Map of the given size with tiles chosen uniformly from `tiles`, an empty map (of size
+/// 0 x 0) if `tiles` is empty.
+pub fn random_map<T: Clone, R: Rng>(rng: &mut R, size: UVec2, tiles: &[T]) -> Array2<T> {
+    if tiles.is_empty() {
+        return Array2::from_shape_vec((0, 0), Vec::new()).expect("Empty shape");
+    }
+    Array2::from_shape_fn((size.x as usize, size.y as usize), |_| tiles.choose(rng).unwrap().clone())
+}
+
+/// Map of the given size with values uniform in `[0, 1)`, like the output of the noise
+/// generators.
+pub fn random_values<R: Rng>(rng: &mut R, size: UVec2) -> Array2<f64> {
+    Array2::from_shape_fn((size.x as usize, size.y as usize), |_| rng.gen())
+}
+
+/// Proptest strategies for the values of the `random_*` functions. The `Arbitrary`
+/// implementations only produce configs that pass their `validate()`.
+#[cfg(feature = "proptest")]
+pub mod strategies {
+    use super::*;
+    use crate::colored_noise::Normalization;
+    use crate::config::{NoiseConfig, VoronoiConfig, WfcConfig};
+    use crate::direction::{Direction, Direction8};
+    use crate::transform::Transform;
+    use crate::voronoi::Algorithm;
+    use crate::wave_function_collapse::{CellSelection, Strategy as WfcStrategy};
+    use proptest::prelude::*;
+    use proptest::sample::select;
+    use std::fmt::Debug;
+
+    /// Maximum size along each axis of arbitrary rects and configs
+    const MAX_SIZE: u32 = 64;
+
+    /// Map size with each axis in `1..=max` (`max` is clamped to at least 1).
+    pub fn size(max: UVec2) -> impl Strategy<Value = UVec2> {
+        let max = max.max(UVec2::ONE);
+        (1..=max.x, 1..=max.y).prop_map(|(x, y)| uvec2(x, y))
+    }
+
+    /// Non-empty rect within a map of size `size`, see `random_rect`.
+    pub fn rect(size: UVec2) -> BoxedStrategy<Rect> {
+        if size.x == 0 || size.y == 0 {
+            return Just(Rect::from_size(UVec2::ZERO)).boxed();
+        }
+        (0..size.x, 0..size.y, 0..size.x, 0..size.y)
+            .prop_map(|(ax, ay, bx, by)| Rect::from_corners(uvec2(ax, ay), uvec2(bx, by)))
+            .boxed()
+    }
+
+    /// Valid tile, see `random_tile`.
+    pub fn tile<T: Tile + Debug + 'static>() -> BoxedStrategy<T> {
+        match T::MAX {
+            0 => Just(T::invalid()).boxed(),
+            n => (0..n).prop_map(T::from).boxed(),
+        }
+    }
+
+    /// Map of a size drawn from `size` with tiles from `tiles`, see `random_map`.
+    pub fn map<T, S>(size: S, tiles: Vec<T>) -> BoxedStrategy<Array2<T>>
+    where
+        T: Clone + Debug + 'static,
+        S: Strategy<Value = UVec2> + 'static,
+    {
+        if tiles.is_empty() {
+            return Just(Array2::from_shape_vec((0, 0), Vec::new()).expect("Empty shape")).boxed();
+        }
+        size.prop_flat_map(move |s| {
+            proptest::collection::vec(select(tiles.clone()), (s.x * s.y) as usize).prop_map(move |v| {
+                Array2::from_shape_vec((s.x as usize, s.y as usize), v).expect("Shape matches number of tiles")
+            })
+        })
+        .boxed()
+    }
+
+    /// Map of a size drawn from `size` with values in `[0, 1)`, see `random_values`.
+    pub fn values<S: Strategy<Value = UVec2> + 'static>(size: S) -> BoxedStrategy<Array2<f64>> {
+        size.prop_flat_map(|s| {
+            proptest::collection::vec(0.0..1.0, (s.x * s.y) as usize).prop_map(move |v| {
+                Array2::from_shape_vec((s.x as usize, s.y as usize), v).expect("Shape matches number of values")
+            })
+        })
+        .boxed()
+    }
+
+    /// Rects with anchor and size below `MAX_SIZE`, including empty ones.
+    impl Arbitrary for Rect {
+        type Parameters = ();
+        type Strategy = BoxedStrategy<Rect>;
+
+        fn arbitrary_with(_: ()) -> Self::Strategy {
+            (0..MAX_SIZE, 0..MAX_SIZE, 0..MAX_SIZE, 0..MAX_SIZE)
+                .prop_map(|(x, y, w, h)| Rect::new(uvec2(x, y), uvec2(w, h)))
+                .boxed()
+        }
+    }
+
+    impl Arbitrary for Normalization {
+        type Parameters = ();
+        type Strategy = BoxedStrategy<Normalization>;
+
+        fn arbitrary_with(_: ()) -> Self::Strategy {
+            prop_oneof![
+                Just(Normalization::UnitRange),
+                Just(Normalization::Raw),
+                Just(Normalization::ZScore),
+                Just(Normalization::Equalize),
+                (-1.0..1.0, 0.01..2.0).prop_map(|(min, d)| Normalization::Clamp { min, max: min + d }),
+            ]
+            .boxed()
+        }
+    }
+
+    impl Arbitrary for NoiseConfig {
+        type Parameters = ();
+        type Strategy = BoxedStrategy<NoiseConfig>;
+
+        fn arbitrary_with(_: ()) -> Self::Strategy {
+            let flags = (any::<bool>(), any::<bool>());
+            (size(UVec2::splat(MAX_SIZE)), -3.0..1.0, any::<u64>(), flags, any::<Normalization>())
+                .prop_map(|(size, color, seed, (tileable, padded), normalization)| NoiseConfig {
+                    size,
+                    color,
+                    seed,
+                    tileable,
+                    padded,
+                    normalization,
+                })
+                .boxed()
+        }
+    }
+
+    impl Arbitrary for VoronoiConfig {
+        type Parameters = ();
+        type Strategy = BoxedStrategy<VoronoiConfig>;
+
+        fn arbitrary_with(_: ()) -> Self::Strategy {
+            let algorithm = prop_oneof![Just(Algorithm::KdTree), Just(Algorithm::JumpFlood)];
+            (size(UVec2::splat(MAX_SIZE)), 1..=32_usize, any::<u64>(), algorithm, 0.0..3.0_f32)
+                .prop_map(|(size, centers, seed, algorithm, border_width)| VoronoiConfig {
+                    size,
+                    centers,
+                    seed,
+                    algorithm,
+                    border_width,
+                })
+                .boxed()
+        }
+    }
+
+    impl Arbitrary for WfcConfig {
+        type Parameters = ();
+        type Strategy = BoxedStrategy<WfcConfig>;
+
+        fn arbitrary_with(_: ()) -> Self::Strategy {
+            let cell_selection = prop_oneof![
+                Just(CellSelection::MaxEntropy),
+                Just(CellSelection::MinEntropy),
+                Just(CellSelection::Scanline),
+                Just(CellSelection::RandomTies),
+                (0.0..2.0_f32).prop_map(|weight| CellSelection::CenterDistance { weight }),
+            ];
+            let strategy = prop_oneof![
+                Just(WfcStrategy::Bomb),
+                (1..=200_usize).prop_map(|max_depth| WfcStrategy::Backtrack { max_depth }),
+            ];
+            (any::<u64>(), size(UVec2::splat(MAX_SIZE)), 1..=3_u32, cell_selection, 0..=5_u32, strategy, 0..=200_u32)
+                .prop_map(
+                    |(seed, size, neighborhood_size, cell_selection, retries, strategy, max_bombings)| WfcConfig {
+                        seed,
+                        size,
+                        neighborhood_size,
+                        cell_selection,
+                        retries,
+                        strategy,
+                        max_bombings,
+                    },
+                )
+                .boxed()
+        }
+    }
+
+    impl Arbitrary for Direction {
+        type Parameters = ();
+        type Strategy = BoxedStrategy<Direction>;
+
+        fn arbitrary_with(_: ()) -> Self::Strategy {
+            select(Direction::ALL.to_vec()).boxed()
+        }
+    }
+
+    impl Arbitrary for Direction8 {
+        type Parameters = ();
+        type Strategy = BoxedStrategy<Direction8>;
+
+        fn arbitrary_with(_: ()) -> Self::Strategy {
+            select(Direction8::ALL.to_vec()).boxed()
+        }
+    }
+
+    impl Arbitrary for Transform {
+        type Parameters = ();
+        type Strategy = BoxedStrategy<Transform>;
+
+        fn arbitrary_with(_: ()) -> Self::Strategy {
+            select(Transform::ALL.to_vec()).boxed()
+        }
+    }
+}
+
+/// Quickcheck `Arbitrary` implementations. Sizes, seeds and tiles are foreign or generic
+/// types, they are wrapped in `Size`, `Seed` and `AnyTile`.
+#[cfg(feature = "quickcheck")]
+pub mod arbitrary {
+    use super::*;
+    use crate::colored_noise::Normalization;
+    use crate::direction::{Direction, Direction8};
+    use crate::transform::Transform;
+    use quickcheck::{Arbitrary, Gen};
+    use std::fmt::Debug;
+
+    /// Maximum size along each axis of arbitrary sizes and rects
+    const MAX_SIZE: u32 = 64;
+
+    /// Value in `0..n`, `n` must not be zero.
+    fn below(g: &mut Gen, n: u32) -> u32 {
+        u32::arbitrary(g) % n
+    }
+
+    /// Map size with each axis in `1..=64`, shrinks towards 1 x 1.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct Size(pub UVec2);
+
+    impl Arbitrary for Size {
+        fn arbitrary(g: &mut Gen) -> Self {
+            Size(uvec2(1 + below(g, MAX_SIZE), 1 + below(g, MAX_SIZE)))
+        }
+
+        fn shrink(&self) -> Box<dyn Iterator<Item = Self>> {
+            let s = self.0;
+            let candidates = [
+                uvec2(s.x / 2, s.y),
+                uvec2(s.x, s.y / 2),
+                uvec2(s.x.saturating_sub(1), s.y),
+                uvec2(s.x, s.y.saturating_sub(1)),
+            ];
+            Box::new(candidates.into_iter().filter(move |&c| c.cmpge(UVec2::ONE).all() && c != s).map(Size))
+        }
+    }
+
+    /// Seed for a generator or `StdRng`, shrinks towards 0.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct Seed(pub u64);
+
+    impl Arbitrary for Seed {
+        fn arbitrary(g: &mut Gen) -> Self {
+            Seed(u64::arbitrary(g))
+        }
+
+        fn shrink(&self) -> Box<dyn Iterator<Item = Self>> {
+            Box::new(self.0.shrink().map(Seed))
+        }
+    }
+
+    /// Valid tile, see `random_tile`. Shrinks towards the tile with index 0.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct AnyTile<T>(pub T);
+
+    impl<T: Tile + Debug + 'static> Arbitrary for AnyTile<T> {
+        fn arbitrary(g: &mut Gen) -> Self {
+            match T::MAX {
+                0 => AnyTile(T::invalid()),
+                n => AnyTile(T::from(usize::arbitrary(g) % n)),
+            }
+        }
+
+        fn shrink(&self) -> Box<dyn Iterator<Item = Self>> {
+            let n = match self.0.is_valid() {
+                true => self.0.as_usize(),
+                false => 0,
+            };
+            Box::new((0..n).map(|i| AnyTile(T::from(i))))
+        }
+    }
+
+    /// Map of size `size` with tiles chosen uniformly from `tiles`, see `random_map`.
+    pub fn map<T: Clone>(g: &mut Gen, size: UVec2, tiles: &[T]) -> Array2<T> {
+        if tiles.is_empty() {
+            return Array2::from_shape_vec((0, 0), Vec::new()).expect("Empty shape");
+        }
+        Array2::from_shape_fn((size.x as usize, size.y as usize), |_| g.choose(tiles).unwrap().clone())
+    }
+
+    /// Rects with anchor and size below 64, including empty ones. Shrinks towards the origin
+    /// and towards smaller sizes.
+    impl Arbitrary for Rect {
+        fn arbitrary(g: &mut Gen) -> Self {
+            let anchor = uvec2(below(g, MAX_SIZE), below(g, MAX_SIZE));
+            Rect::new(anchor, uvec2(below(g, MAX_SIZE), below(g, MAX_SIZE)))
+        }
+
+        fn shrink(&self) -> Box<dyn Iterator<Item = Self>> {
+            let r = *self;
+            let candidates = [
+                Rect::new(r.anchor / 2, r.size),
+                Rect::new(r.anchor, uvec2(r.size.x / 2, r.size.y)),
+                Rect::new(r.anchor, uvec2(r.size.x, r.size.y / 2)),
+            ];
+            Box::new(candidates.into_iter().filter(move |&c| c != r))
+        }
+    }
+
+    impl Arbitrary for Normalization {
+        fn arbitrary(g: &mut Gen) -> Self {
+            match below(g, 5) {
+                0 => Normalization::UnitRange,
+                1 => Normalization::Raw,
+                2 => Normalization::ZScore,
+                3 => Normalization::Equalize,
+                _ => {
+                    let min = below(g, 2000) as f64 / 1000.0 - 1.0;
+                    let d = 0.01 + below(g, 200) as f64 / 100.0;
+                    Normalization::Clamp { min, max: min + d }
+                }
+            }
+        }
+    }
+
+    impl Arbitrary for Direction {
+        fn arbitrary(g: &mut Gen) -> Self {
+            *g.choose(&Direction::ALL).unwrap()
+        }
+    }
+
+    impl Arbitrary for Direction8 {
+        fn arbitrary(g: &mut Gen) -> Self {
+            *g.choose(&Direction8::ALL).unwrap()
+        }
+    }
+
+    impl Arbitrary for Transform {
+        fn arbitrary(g: &mut Gen) -> Self {
+            *g.choose(&Transform::ALL).unwrap()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    #[test]
+    fn empty_inputs() {
+        let mut rng = StdRng::seed_from_u64(0);
+        assert!(random_rect(&mut rng, uvec2(0, 5)).is_empty());
+        assert!(random_rect(&mut rng, uvec2(5, 0)).is_empty());
+        assert_eq!(random_map::<u8, _>(&mut rng, uvec2(3, 3), &[]).dim(), (0, 0));
+        assert_eq!(random_map(&mut rng, uvec2(0, 3), &[1, 2]).dim(), (0, 3));
+        assert_eq!(random_values(&mut rng, uvec2(4, 0)).dim(), (4, 0));
+    }
+
+    #[test]
+    fn rects_within_map() {
+        let mut rng = StdRng::seed_from_u64(1);
+        for _ in 0..100 {
+            let size = random_size(&mut rng, uvec2(7, 3));
+            let rect = random_rect(&mut rng, size);
+            assert!(!rect.is_empty());
+            assert!(rect.end().cmple(size).all());
+        }
+    }
+
+    #[cfg(feature = "proptest")]
+    mod properties {
+        use super::super::strategies::*;
+        use super::*;
+        use crate::config::{Config, NoiseConfig, VoronoiConfig, WfcConfig};
+        use proptest::prelude::*;
+
+        proptest! {
+            #[test]
+            fn rect_within_map(size in size(uvec2(7, 3)), seed in any::<u64>()) {
+                let rect = random_rect(&mut StdRng::seed_from_u64(seed), size);
+                prop_assert!(!rect.is_empty());
+                prop_assert!(rect.end().cmple(size).all());
+            }
+
+            #[test]
+            fn rect_strategy_within_map(rect in (1..10_u32, 1..10_u32).prop_flat_map(|(x, y)| rect(uvec2(x, y)))) {
+                prop_assert!(!rect.is_empty());
+                prop_assert!(rect.end().cmple(uvec2(10, 10)).all());
+            }
+
+            #[test]
+            fn map_size_and_tiles(map in map(size(uvec2(7, 3)), vec!['#', '.'])) {
+                prop_assert!(map.dim().0 <= 7 && map.dim().1 <= 3);
+                prop_assert!(map.iter().all(|&c| c == '#' || c == '.'));
+            }
+
+            #[test]
+            fn values_in_unit_range(values in values(size(uvec2(8, 8)))) {
+                prop_assert!(values.iter().all(|v| (0.0..1.0).contains(v)));
+            }
+
+            #[test]
+            fn arbitrary_intersection_is_contained(a in any::<Rect>(), b in any::<Rect>()) {
+                if let Some(r) = a.intersect(&b) {
+                    prop_assert!(!r.is_empty());
+                    prop_assert!(r.iter().all(|p| a.contains(p) && b.contains(p)));
+                }
+            }
+
+            #[test]
+            fn arbitrary_configs_are_valid(
+                noise in any::<NoiseConfig>(),
+                voronoi in any::<VoronoiConfig>(),
+                wfc in any::<WfcConfig>(),
+            ) {
+                prop_assert!(noise.validate().is_ok());
+                prop_assert!(voronoi.validate().is_ok());
+                prop_assert!(wfc.validate().is_ok());
+            }
+        }
+    }
+
+    #[cfg(feature = "quickcheck")]
+    mod quickcheck_properties {
+        use super::super::arbitrary::*;
+        use super::*;
+        use crate::direction::Direction;
+        use quickcheck::{quickcheck, Arbitrary, Gen};
+
+        crate::tile_enum! {
+            #[derive(Debug)]
+            enum Cell { Wall, Floor, Door }
+        }
+
+        quickcheck! {
+            fn sizes_in_range(size: Size) -> bool {
+                size.0.cmpge(UVec2::ONE).all() && size.0.cmple(UVec2::splat(64)).all()
+            }
+
+            fn seeded_rect_within_map(size: Size, seed: Seed) -> bool {
+                let rect = random_rect(&mut StdRng::seed_from_u64(seed.0), size.0);
+                !rect.is_empty() && rect.end().cmple(size.0).all()
+            }
+
+            fn tiles_are_valid(tile: AnyTile<Cell>) -> bool {
+                tile.0.is_valid() && tile.0.as_usize() < 3
+            }
+
+            fn intersection_is_contained(a: Rect, b: Rect) -> bool {
+                match a.intersect(&b) {
+                    Some(r) => !r.is_empty() && r.iter().all(|p| a.contains(p) && b.contains(p)),
+                    None => true,
+                }
+            }
+
+            fn opposite_is_involution(d: Direction) -> bool {
+                d.opposite().opposite() == d
+            }
+        }
+
+        #[test]
+        fn shrinking_stays_in_range() {
+            assert!(Size(uvec2(5, 1)).shrink().all(|s| s.0.cmpge(UVec2::ONE).all() && s.0 != uvec2(5, 1)));
+            assert_eq!(Size(uvec2(1, 1)).shrink().count(), 0);
+            assert_eq!(AnyTile(Cell::Door).shrink().map(|t| t.0).collect::<Vec<_>>(), vec![Cell::Wall, Cell::Floor]);
+        }
+
+        #[test]
+        fn map_tiles() {
+            let mut g = Gen::new(10);
+            let m = map(&mut g, uvec2(4, 3), &['#', '.']);
+            assert_eq!(m.dim(), (4, 3));
+            assert!(m.iter().all(|&c| c == '#' || c == '.'));
+            assert_eq!(map::<char>(&mut g, uvec2(4, 3), &[]).dim(), (0, 0));
+        }
+    }
+}
@@ -0,0 +1,159 @@
+use crate::coord::{map_size, MapAccess};
+use crate::neighborhood::{chebyshev, offsets, Metric};
+use glam::{ivec2, uvec2, IVec2, UVec2};
+use ndarray::Array2;
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+
+/// Example based texture synthesis (Efros–Leung, with the coherent candidates of Ashikhmin):
+/// grows a map tile by tile in scanline order, copying for every tile the example tile whose
+/// neighborhood best matches the already generated neighborhood.
+///
+/// Unlike a wave function collapse, nothing is enforced: patterns of the example are
+/// reproduced approximately, which gives fuzzier and more organic results (eg. for terrain
+/// textures) and never fails.
+/// Uses the same conventions as `ColoredNoise` (`size` field, `generate()`).
+#[derive(Clone, Debug)]
+pub struct TextureSynthesis {
+    pub size: UVec2,
+    pub seed: u64,
+    /// Radius of the matched neighborhood (default: 2)
+    pub radius: u32,
+    /// Shape of the matched neighborhood together with `radius`, see `neighborhood::Metric`
+    /// (default: `chebyshev`, ie. squares)
+    pub metric: Metric,
+    /// Example positions whose mismatch is at most `best * (1 + tolerance)` are chosen from at
+    /// random. Larger values give more variation but reproduce the example less faithfully.
+    pub tolerance: f32,
+    /// Number of random example positions compared per tile, in addition to the positions
+    /// that continue the example regions the neighbors were copied from. `None` compares all
+    /// positions of the example, which is exact but slow for large examples.
+    pub samples: Option<usize>,
+    /// Treat the example as tileable, ie. neighborhoods wrap around its edges. Otherwise,
+    /// neighbors outside of the example count as mismatches.
+    pub wrap_example: bool,
+}
+
+impl Default for TextureSynthesis {
+    fn default() -> Self {
+        Self {
+            size: uvec2(64, 64),
+            seed: 0,
+            radius: 2,
+            metric: chebyshev,
+            tolerance: 0.1,
+            samples: Some(32),
+            wrap_example: false,
+        }
+    }
+}
+
+impl TextureSynthesis {
+    pub fn new(size: UVec2, seed: u64) -> Self {
+        Self { size, seed, ..Default::default() }
+    }
+
+    pub fn radius(mut self, radius: u32) -> Self {
+        self.radius = radius;
+        self
+    }
+
+    pub fn metric(mut self, metric: Metric) -> Self {
+        self.metric = metric;
+        self
+    }
+
+    pub fn tolerance(mut self, tolerance: f32) -> Self {
+        self.tolerance = tolerance;
+        self
+    }
+
+    pub fn samples(mut self, samples: Option<usize>) -> Self {
+        self.samples = samples;
+        self
+    }
+
+    pub fn wrap_example(mut self, wrap: bool) -> Self {
+        self.wrap_example = wrap;
+        self
+    }
+
+    /// Synthesize a map of size `size` from `example` (which must not be empty).
+    pub fn generate<T: Clone + PartialEq>(&self, example: &Array2<T>) -> Array2<T> {
+        self.generate_with_sources(example).0
+    }
+
+    /// Like `generate`, also returns for every tile the example position it was copied from.
+    pub fn generate_with_sources<T: Clone + PartialEq>(&self, example: &Array2<T>) -> (Array2<T>, Array2<UVec2>) {
+        let example_size = map_size(example);
+        assert!(example_size.x > 0 && example_size.y > 0, "empty example");
+        let mut rng = StdRng::seed_from_u64(self.seed);
+        let window: Vec<IVec2> = offsets(self.radius, self.metric).filter(|&o| o != IVec2::ZERO).collect();
+        let all: Vec<UVec2> = example.iter_with_positions().map(|(p, _)| p).collect();
+
+        let dim = (self.size.x as usize, self.size.y as usize);
+        let mut sources: Array2<Option<UVec2>> = Array2::from_elem(dim, None);
+        let wrap = |p: IVec2| ivec2(p.x.rem_euclid(example_size.x as i32), p.y.rem_euclid(example_size.y as i32));
+        let example_at = |p: IVec2| -> Option<&T> {
+            match self.wrap_example {
+                true => Some(example.at(wrap(p).as_uvec2())),
+                false => example.get_at(p),
+            }
+        };
+
+        for y in 0..self.size.y {
+            for x in 0..self.size.x {
+                let p = uvec2(x, y);
+                // Generated neighbors as (offset, tile, source)
+                let known: Vec<(IVec2, &T, UVec2)> = window
+                    .iter()
+                    .filter_map(|&o| {
+                        let s = (*sources.get_at(p.as_ivec2() + o)?)?;
+                        Some((o, example.at(s), s))
+                    })
+                    .collect();
+
+                let candidates: Vec<UVec2> = match self.samples {
+                    Some(n) if !known.is_empty() => {
+                        let mut c: Vec<UVec2> = (0..n).map(|_| *all.choose(&mut rng).unwrap()).collect();
+                        // Continue the patches the neighbors were copied from
+                        for &(o, _, s) in known.iter() {
+                            let mut q = s.as_ivec2() - o;
+                            if self.wrap_example {
+                                q = wrap(q);
+                            }
+                            if example.get_at(q).is_some() {
+                                c.push(q.as_uvec2());
+                            }
+                        }
+                        c.sort_by_key(|p| (p.x, p.y));
+                        c.dedup();
+                        c
+                    }
+                    _ => all.clone(),
+                };
+
+                let mismatches: Vec<usize> = candidates
+                    .iter()
+                    .map(|c| {
+                        known
+                            .iter()
+                            .filter(|(o, t, _)| example_at(c.as_ivec2() + *o) != Some(*t))
+                            .count()
+                    })
+                    .collect();
+                let best = mismatches.iter().copied().min().unwrap_or(0);
+                let limit = best as f32 * (1.0 + self.tolerance.max(0.0));
+                let good: Vec<UVec2> = candidates
+                    .iter()
+                    .zip(mismatches.iter())
+                    .filter(|(_, &m)| m as f32 <= limit)
+                    .map(|(c, _)| *c)
+                    .collect();
+                *sources.at_mut(p) = Some(good[rng.gen_range(0..good.len())]);
+            }
+        }
+
+        let sources = sources.mapv(|s| s.unwrap());
+        (sources.mapv(|s| example.at(s).clone()), sources)
+    }
+}
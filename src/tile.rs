@@ -1,3 +1,6 @@
+use crate::wave_function_collapse::{DefaultProbabilityCallback, WaveFunctionCollapseConfiguration};
+use ndarray::Array2;
+
 
 pub trait Tile: Copy+Eq+From<usize>+From<Self::Numeric> {
     type Numeric: Ord+Clone+Copy+PartialEq+Eq;
@@ -8,3 +11,164 @@ pub trait Tile: Copy+Eq+From<usize>+From<Self::Numeric> {
     fn as_usize(&self) -> usize;
     fn as_numeric(&self) -> Self::Numeric;
 }
+
+/// Tile of two layers that are collapsed together, eg. terrain and objects. The domain is the
+/// cartesian product of both tile sets, tile `(a, b)` has index `a * B::MAX + b`.
+/// Both `A::MAX` and `B::MAX` must be the number of valid tiles of their type.
+/// A layered tile is valid iff both of its parts are valid.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Layered<A, B>(pub A, pub B);
+
+impl<A: Tile, B: Tile> Layered<A, B> {
+    /// Number of combined tiles, the `N` of a layered wave function collapse
+    pub const COUNT: usize = A::MAX * B::MAX;
+
+    /// Combined probabilities for independent per-layer probabilities `a` and `b`, to be
+    /// adjusted for combinations that are not allowed (eg. trees on water).
+    /// Panics if `N != N1 * N2`.
+    pub fn product<const N1: usize, const N2: usize, const N: usize>(a: [f32; N1], b: [f32; N2]) -> [f32; N] {
+        assert_eq!(N, N1 * N2);
+        let mut r = [0.0; N];
+        for (i, pa) in a.iter().enumerate() {
+            for (j, pb) in b.iter().enumerate() {
+                r[i * N2 + j] = pa * pb;
+            }
+        }
+        r
+    }
+
+    /// Split a map of layered tiles into its two layers.
+    pub fn split(a: &Array2<usize>) -> (Array2<A>, Array2<B>) {
+        let first = a.mapv(|t| Self::from(t).0);
+        let second = a.mapv(|t| Self::from(t).1);
+        (first, second)
+    }
+}
+
+impl<A: Tile, B: Tile> From<usize> for Layered<A, B> {
+    fn from(n: usize) -> Self {
+        match n {
+            usize::MAX => Self::invalid(),
+            n => Layered(A::from(n / B::MAX), B::from(n % B::MAX)),
+        }
+    }
+}
+
+impl<A: Tile, B: Tile> Tile for Layered<A, B> {
+    type Numeric = usize;
+    const MAX: usize = A::MAX * B::MAX;
+
+    fn invalid() -> Self {
+        Layered(A::invalid(), B::invalid())
+    }
+
+    fn is_valid(&self) -> bool {
+        self.0.is_valid() && self.1.is_valid()
+    }
+
+    fn as_usize(&self) -> usize {
+        self.0.as_usize() * B::MAX + self.1.as_usize()
+    }
+
+    fn as_numeric(&self) -> usize {
+        match self.is_valid() {
+            true => self.as_usize(),
+            false => usize::MAX,
+        }
+    }
+}
+
+/// Enum of `N` tiles, usually implemented with `tile_enum!`. The number of tiles is part of
+/// the trait, so a wave function collapse configuration can infer it:
+/// `Color::configuration().probability(|n| [...])` instead of
+/// `WaveFunctionCollapseConfiguration::<Color, _, 8>::default()`.
+pub trait TileEnum<const N: usize>: Tile {
+    /// Number of valid tiles (`Tile::MAX`)
+    const COUNT: usize = N;
+    /// All valid tiles, in the order of their indices
+    const ALL: [Self; N];
+
+    /// Default wave function collapse configuration for this tile type.
+    fn configuration() -> WaveFunctionCollapseConfiguration<Self, DefaultProbabilityCallback<Self, N>, N> {
+        WaveFunctionCollapseConfiguration::default()
+    }
+}
+
+/// Define a fieldless enum and implement `Tile`, `TileEnum` and `num_traits::FromPrimitive`
+/// for it. An additional variant `Invalid` is added for undetermined cells, the other variants
+/// get the indices `0..COUNT` in the order they are listed.
+///
+/// ```
+/// # use mapgen_2d::neighborhood::Neighborhood;
+/// # use mapgen_2d::tile::TileEnum;
+/// # use mapgen_2d::tile_enum;
+/// tile_enum! {
+///     #[derive(Debug)]
+///     pub enum Color { Red, Green, Blue }
+/// }
+/// let mut c = Color::configuration().probability(|n: &Neighborhood<Color>| [1.0, 1.0, n.count(Color::Red) as f32]);
+/// c.size = glam::uvec2(16, 16);
+/// let mut wfc = c.build();
+/// wfc.generate().unwrap();
+/// assert_eq!(Color::COUNT, 3);
+/// ```
+#[macro_export]
+macro_rules! tile_enum {
+    ($(#[$meta:meta])* $vis:vis enum $name:ident { $($variant:ident),+ $(,)? }) => {
+        $(#[$meta])*
+        #[derive(Clone, Copy, PartialEq, Eq, Hash)]
+        $vis enum $name {
+            $($variant,)+
+            Invalid,
+        }
+
+        impl From<usize> for $name {
+            fn from(n: usize) -> Self {
+                <$name as $crate::tile::TileEnum<{ [$($name::$variant),+].len() }>>::ALL
+                    .get(n)
+                    .copied()
+                    .unwrap_or($name::Invalid)
+            }
+        }
+
+        impl $crate::tile::Tile for $name {
+            type Numeric = usize;
+            const MAX: usize = [$($name::$variant),+].len();
+
+            fn invalid() -> Self {
+                $name::Invalid
+            }
+
+            fn is_valid(&self) -> bool {
+                *self != $name::Invalid
+            }
+
+            fn as_usize(&self) -> usize {
+                *self as usize
+            }
+
+            fn as_numeric(&self) -> usize {
+                match self.is_valid() {
+                    true => *self as usize,
+                    false => usize::MAX,
+                }
+            }
+        }
+
+        impl $crate::tile::TileEnum<{ [$($name::$variant),+].len() }> for $name {
+            const ALL: [Self; [$($name::$variant),+].len()] = [$($name::$variant),+];
+        }
+
+        impl $crate::num_traits::FromPrimitive for $name {
+            fn from_i64(n: i64) -> Option<Self> {
+                usize::try_from(n).ok().and_then(|n| Self::from_u64(n as u64))
+            }
+
+            fn from_u64(n: u64) -> Option<Self> {
+                let n = usize::try_from(n).ok()?;
+                let tile = <Self as From<usize>>::from(n);
+                $crate::tile::Tile::is_valid(&tile).then_some(tile)
+            }
+        }
+    };
+}
@@ -0,0 +1,183 @@
+//! Rotations and reflections of maps (eg. prefabs or stamps before placing them).
+//! Rotations are clockwise as seen on screen, ie. with y pointing down: `rotate90` moves the
+//! top left tile to the top right.
+//!
+//! The allocating functions work on any map, the `_inplace` variants swap tiles and only
+//! support transforms that keep the shape (rotations by 90° and transposition require a
+//! square map).
+
+use crate::coord::map_size;
+use glam::{uvec2, UVec2};
+use ndarray::Array2;
+
+/// One of the 8 rotations and reflections of a rectangular grid.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Transform {
+    Identity,
+    /// Clockwise by 90°
+    Rotate90,
+    Rotate180,
+    /// Clockwise by 270°, ie. counterclockwise by 90°
+    Rotate270,
+    /// Mirrored along the vertical center line, ie. x -> size.x - 1 - x
+    FlipX,
+    /// Mirrored along the horizontal center line, ie. y -> size.y - 1 - y
+    FlipY,
+    /// Mirrored along the main diagonal, ie. (x, y) -> (y, x)
+    Transpose,
+    /// Mirrored along the other diagonal
+    AntiTranspose,
+}
+
+impl Transform {
+    pub const ALL: [Transform; 8] = [
+        Transform::Identity,
+        Transform::Rotate90,
+        Transform::Rotate180,
+        Transform::Rotate270,
+        Transform::FlipX,
+        Transform::FlipY,
+        Transform::Transpose,
+        Transform::AntiTranspose,
+    ];
+
+    /// True iff the transform exchanges the x and y axes (and thus width and height).
+    pub fn swaps_axes(self) -> bool {
+        matches!(self, Transform::Rotate90 | Transform::Rotate270 | Transform::Transpose | Transform::AntiTranspose)
+    }
+
+    /// Size of a map of size `size` after the transform.
+    pub fn size(self, size: UVec2) -> UVec2 {
+        match self.swaps_axes() {
+            true => uvec2(size.y, size.x),
+            false => size,
+        }
+    }
+
+    /// Position of the tile at `p` of a map of size `size` after the transform.
+    pub fn apply(self, p: UVec2, size: UVec2) -> UVec2 {
+        let m = size - UVec2::ONE;
+        match self {
+            Transform::Identity => p,
+            Transform::Rotate90 => uvec2(m.y - p.y, p.x),
+            Transform::Rotate180 => m - p,
+            Transform::Rotate270 => uvec2(p.y, m.x - p.x),
+            Transform::FlipX => uvec2(m.x - p.x, p.y),
+            Transform::FlipY => uvec2(p.x, m.y - p.y),
+            Transform::Transpose => uvec2(p.y, p.x),
+            Transform::AntiTranspose => uvec2(m.y - p.y, m.x - p.x),
+        }
+    }
+
+    /// The transform that undoes this one.
+    pub fn inverse(self) -> Self {
+        match self {
+            Transform::Rotate90 => Transform::Rotate270,
+            Transform::Rotate270 => Transform::Rotate90,
+            t => t,
+        }
+    }
+}
+
+/// `a` transformed by `t`, see the module documentation.
+pub fn transform<T: Clone>(a: &Array2<T>, t: Transform) -> Array2<T> {
+    let size = map_size(a);
+    let out = t.size(size);
+    let inverse = t.inverse();
+    Array2::from_shape_fn((out.x as usize, out.y as usize), |(x, y)| {
+        let p = inverse.apply(uvec2(x as u32, y as u32), out);
+        a[[p.x as usize, p.y as usize]].clone()
+    })
+}
+
+pub fn rotate90<T: Clone>(a: &Array2<T>) -> Array2<T> {
+    transform(a, Transform::Rotate90)
+}
+
+pub fn rotate180<T: Clone>(a: &Array2<T>) -> Array2<T> {
+    transform(a, Transform::Rotate180)
+}
+
+pub fn rotate270<T: Clone>(a: &Array2<T>) -> Array2<T> {
+    transform(a, Transform::Rotate270)
+}
+
+pub fn flip_x<T: Clone>(a: &Array2<T>) -> Array2<T> {
+    transform(a, Transform::FlipX)
+}
+
+pub fn flip_y<T: Clone>(a: &Array2<T>) -> Array2<T> {
+    transform(a, Transform::FlipY)
+}
+
+/// Transform `a` in place. Panics if `t` swaps the axes and `a` is not square.
+pub fn transform_inplace<T>(a: &mut Array2<T>, t: Transform) {
+    if t.swaps_axes() {
+        let (sx, sy) = a.dim();
+        assert_eq!(sx, sy, "{:?} in place requires a square map", t);
+    }
+    match t {
+        Transform::Identity => {}
+        Transform::Rotate90 => {
+            transpose_square(a);
+            flip_x_inplace(a);
+        }
+        Transform::Rotate180 => {
+            flip_x_inplace(a);
+            flip_y_inplace(a);
+        }
+        Transform::Rotate270 => {
+            transpose_square(a);
+            flip_y_inplace(a);
+        }
+        Transform::FlipX => flip_x_inplace(a),
+        Transform::FlipY => flip_y_inplace(a),
+        Transform::Transpose => transpose_square(a),
+        Transform::AntiTranspose => {
+            transpose_square(a);
+            flip_x_inplace(a);
+            flip_y_inplace(a);
+        }
+    }
+}
+
+/// Rotate the square map `a` clockwise by 90° in place. Panics if `a` is not square.
+pub fn rotate90_inplace<T>(a: &mut Array2<T>) {
+    transform_inplace(a, Transform::Rotate90)
+}
+
+pub fn rotate180_inplace<T>(a: &mut Array2<T>) {
+    transform_inplace(a, Transform::Rotate180)
+}
+
+/// Rotate the square map `a` clockwise by 270° in place. Panics if `a` is not square.
+pub fn rotate270_inplace<T>(a: &mut Array2<T>) {
+    transform_inplace(a, Transform::Rotate270)
+}
+
+pub fn flip_x_inplace<T>(a: &mut Array2<T>) {
+    let (sx, sy) = a.dim();
+    for x in 0..sx / 2 {
+        for y in 0..sy {
+            a.swap([x, y], [sx - 1 - x, y]);
+        }
+    }
+}
+
+pub fn flip_y_inplace<T>(a: &mut Array2<T>) {
+    let (sx, sy) = a.dim();
+    for x in 0..sx {
+        for y in 0..sy / 2 {
+            a.swap([x, y], [x, sy - 1 - y]);
+        }
+    }
+}
+
+fn transpose_square<T>(a: &mut Array2<T>) {
+    let n = a.dim().0;
+    for x in 0..n {
+        for y in x + 1..n {
+            a.swap([x, y], [y, x]);
+        }
+    }
+}
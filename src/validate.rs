@@ -0,0 +1,230 @@
+
+use crate::coord::{map_size, MapAccess};
+use crate::seed::derive_seed;
+use glam::{IVec2, UVec2};
+use ndarray::Array2;
+use std::fmt;
+
+/// User check for `Check::Custom`, returns a description of the problem if it fails.
+pub type CustomCheck<T> = Box<dyn Fn(&Array2<T>) -> Result<(), String>>;
+
+/// A check of a `Validator`.
+pub enum Check<T> {
+    /// The fraction of tiles in `tiles` is in `[min, max]`
+    Fraction { tiles: Vec<T>, min: f64, max: f64 },
+    /// The tiles in `tiles` form a single 4-connected component (or there are none)
+    Connected { tiles: Vec<T> },
+    /// All pairs of tiles in `tiles` (eg. stairs, spawns) are at least `distance` apart
+    /// (euclidean). Compares all pairs, so meant for sparse features.
+    MinDistance { tiles: Vec<T>, distance: f64 },
+    /// The fraction of tiles at the map border that are in `tiles` is in `[min, max]`, eg.
+    /// to require islands surrounded by water
+    Border { tiles: Vec<T>, min: f64, max: f64 },
+    /// Named user check
+    Custom { name: String, check: CustomCheck<T> },
+}
+
+/// Result of one check.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CheckResult {
+    /// Index of the check in `Validator::checks`
+    pub check: usize,
+    pub passed: bool,
+    /// What was measured, eg. "fraction 0.31 not in [0.1, 0.3]"
+    pub message: String,
+}
+
+/// Result of `Validator::validate`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ValidationReport {
+    pub results: Vec<CheckResult>,
+}
+
+impl ValidationReport {
+    pub fn passed(&self) -> bool {
+        self.results.iter().all(|r| r.passed)
+    }
+
+    pub fn failures(&self) -> impl Iterator<Item = &CheckResult> {
+        self.results.iter().filter(|r| !r.passed)
+    }
+}
+
+impl fmt::Display for ValidationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for r in self.results.iter() {
+            let status = if r.passed { "ok" } else { "FAILED" };
+            writeln!(f, "check {}: {}: {}", r.check, status, r.message)?;
+        }
+        Ok(())
+    }
+}
+
+/// Declarative checks of generated maps, replacing ad hoc assertions.
+///
+/// ```
+/// # use mapgen_2d::validate::Validator;
+/// # use mapgen_2d::colored_noise::ColoredNoise;
+/// # use mapgen_2d::mask::largest_component;
+/// # use glam::uvec2;
+/// const FLOOR: bool = true;
+/// let generate = |seed| {
+///     let noise = ColoredNoise { size: uvec2(32, 32), seed, ..Default::default() }.generate();
+///     largest_component(&noise.mapv(|v| v > 0.3))
+/// };
+/// let validator = Validator::new().fraction(&[FLOOR], 0.2, 0.8).connected(&[FLOOR]);
+/// let (map, report) = validator.generate_validated(7, 10, generate).expect("A map passes");
+/// # assert!(report.passed() && map.dim() == (32, 32));
+/// ```
+pub struct Validator<T> {
+    pub checks: Vec<Check<T>>,
+}
+
+impl<T> Default for Validator<T> {
+    fn default() -> Self {
+        Self { checks: Vec::new() }
+    }
+}
+
+impl<T: Eq + Copy> Validator<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn check(mut self, check: Check<T>) -> Self {
+        self.checks.push(check);
+        self
+    }
+
+    pub fn fraction(self, tiles: &[T], min: f64, max: f64) -> Self {
+        self.check(Check::Fraction { tiles: tiles.to_vec(), min, max })
+    }
+
+    pub fn connected(self, tiles: &[T]) -> Self {
+        self.check(Check::Connected { tiles: tiles.to_vec() })
+    }
+
+    pub fn min_distance(self, tiles: &[T], distance: f64) -> Self {
+        self.check(Check::MinDistance { tiles: tiles.to_vec(), distance })
+    }
+
+    pub fn border(self, tiles: &[T], min: f64, max: f64) -> Self {
+        self.check(Check::Border { tiles: tiles.to_vec(), min, max })
+    }
+
+    pub fn custom<F>(self, name: &str, check: F) -> Self
+    where
+        F: Fn(&Array2<T>) -> Result<(), String> + 'static,
+    {
+        self.check(Check::Custom { name: name.to_string(), check: Box::new(check) })
+    }
+
+    /// Run all checks on `a`.
+    pub fn validate(&self, a: &Array2<T>) -> ValidationReport {
+        let results = self
+            .checks
+            .iter()
+            .enumerate()
+            .map(|(i, c)| {
+                let (passed, message) = run(c, a);
+                CheckResult { check: i, passed, message }
+            })
+            .collect();
+        ValidationReport { results }
+    }
+
+    /// Call `generate` with seeds derived from `seed` until the map passes all checks, at most
+    /// `retries + 1` times. Returns the map and its report, or the report of the last attempt
+    /// if no map passed.
+    pub fn generate_validated<F>(
+        &self,
+        seed: u64,
+        retries: u32,
+        mut generate: F,
+    ) -> Result<(Array2<T>, ValidationReport), ValidationReport>
+    where
+        F: FnMut(u64) -> Array2<T>,
+    {
+        let mut report = ValidationReport::default();
+        for attempt in 0..=retries {
+            let a = generate(derive_seed(seed, attempt as u64));
+            report = self.validate(&a);
+            if report.passed() {
+                return Ok((a, report));
+            }
+        }
+        Err(report)
+    }
+}
+
+fn run<T: Eq + Copy>(check: &Check<T>, a: &Array2<T>) -> (bool, String) {
+    let range = |what: &str, v: f64, min: f64, max: f64| {
+        let passed = v >= min && v <= max;
+        let relation = if passed { "in" } else { "not in" };
+        (passed, format!("{} {:.3} {} [{}, {}]", what, v, relation, min, max))
+    };
+
+    match check {
+        Check::Fraction { tiles, min, max } => {
+            let count = a.iter().filter(|t| tiles.contains(t)).count();
+            range("fraction", count as f64 / a.len().max(1) as f64, *min, *max)
+        }
+        Check::Connected { tiles } => {
+            let components = components(a, |t| tiles.contains(t));
+            (components <= 1, format!("{} components", components))
+        }
+        Check::MinDistance { tiles, distance } => {
+            let features: Vec<UVec2> = a
+                .iter_with_positions()
+                .filter(|(_, t)| tiles.contains(t))
+                .map(|(p, _)| p)
+                .collect();
+            let closest = features
+                .iter()
+                .enumerate()
+                .flat_map(|(i, p)| features[i + 1..].iter().map(move |q| p.as_vec2().distance(q.as_vec2()) as f64))
+                .fold(f64::INFINITY, f64::min);
+            let passed = closest >= *distance;
+            let relation = if passed { ">=" } else { "<" };
+            (passed, format!("closest distance {:.3} {} {}", closest, relation, distance))
+        }
+        Check::Border { tiles, min, max } => {
+            let size = map_size(a);
+            let border: Vec<&T> = a
+                .iter_with_positions()
+                .filter(|(p, _)| p.x == 0 || p.y == 0 || p.x + 1 == size.x || p.y + 1 == size.y)
+                .map(|(_, t)| t)
+                .collect();
+            let count = border.iter().filter(|t| tiles.contains(t)).count();
+            range("border fraction", count as f64 / border.len().max(1) as f64, *min, *max)
+        }
+        Check::Custom { name, check } => match check(a) {
+            Ok(()) => (true, name.clone()),
+            Err(e) => (false, format!("{}: {}", name, e)),
+        },
+    }
+}
+
+/// Number of 4-connected components of the tiles for which `member` is true.
+fn components<T, F: Fn(&T) -> bool>(a: &Array2<T>, member: F) -> usize {
+    let mut seen = Array2::from_elem(a.dim(), false);
+    let mut count = 0;
+    for (p, t) in a.iter_with_positions() {
+        if *seen.at(p) || !member(t) {
+            continue;
+        }
+        count += 1;
+        *seen.at_mut(p) = true;
+        let mut stack = vec![p];
+        while let Some(p) = stack.pop() {
+            for d in [IVec2::X, IVec2::Y, IVec2::NEG_X, IVec2::NEG_Y] {
+                let q = p.as_ivec2() + d;
+                if a.get_at(q).is_some_and(&member) && !seen.get_at(q).unwrap() {
+                    *seen.at_mut(q.as_uvec2()) = true;
+                    stack.push(q.as_uvec2());
+                }
+            }
+        }
+    }
+    count
+}
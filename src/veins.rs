@@ -0,0 +1,99 @@
+
+use crate::coord::MapAccess;
+use crate::seed::derive_seed;
+use glam::{IVec2, UVec2};
+use ndarray::Array2;
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+
+/// The 8 step directions, clockwise starting north (negative y)
+const DIRECTIONS: [IVec2; 8] = [
+    IVec2::new(0, -1),
+    IVec2::new(1, -1),
+    IVec2::new(1, 0),
+    IVec2::new(1, 1),
+    IVec2::new(0, 1),
+    IVec2::new(-1, 1),
+    IVec2::new(-1, 0),
+    IVec2::new(-1, -1),
+];
+
+/// Places elongated clusters (ore veins, flower patches, ...) of a value by random walks that
+/// tend to keep their direction.
+#[derive(Clone, Debug)]
+pub struct Veins {
+    pub seed: u64,
+    /// Number of veins
+    pub count: usize,
+    /// Steps per vein
+    pub length: usize,
+    /// Probability in [0, 1] to keep the direction in each step, otherwise the walk turns by
+    /// 45 degrees. Higher values give straighter veins.
+    pub persistence: f64,
+    /// Probability in [0, 1] per step to also fill a random 4-neighbor, which makes veins
+    /// thicker
+    pub spread: f64,
+}
+
+impl Default for Veins {
+    fn default() -> Self {
+        Self { seed: 0, count: 10, length: 12, persistence: 0.7, spread: 0.3 }
+    }
+}
+
+impl Veins {
+    /// Write `value` into the tiles of `a` covered by veins. Veins start at random tiles of
+    /// `mask` (which must have the size of `a`) and never leave it, a vein that runs into the
+    /// edge of the mask turns, or ends if it can not continue.
+    /// Returns the tiles of each vein.
+    pub fn generate<T: Clone>(&self, a: &mut Array2<T>, mask: &Array2<bool>, value: T) -> Vec<Vec<UVec2>> {
+        assert_eq!(a.dim(), mask.dim());
+        let mut rng = StdRng::seed_from_u64(derive_seed(self.seed, 0));
+        let allowed = |p: IVec2| mask.get_at(p) == Some(&true);
+        let starts: Vec<UVec2> = mask.iter_with_positions().filter(|(_, &m)| m).map(|(p, _)| p).collect();
+
+        let mut veins = Vec::with_capacity(self.count);
+        for _ in 0..self.count {
+            let mut p = match starts.choose(&mut rng) {
+                Some(p) => p.as_ivec2(),
+                None => break,
+            };
+            let mut direction = rng.gen_range(0..DIRECTIONS.len());
+            let mut cells = vec![p.as_uvec2()];
+
+            for _ in 0..self.length {
+                if rng.gen_bool(self.spread.clamp(0.0, 1.0)) {
+                    let q = p + DIRECTIONS[rng.gen_range(0..4) * 2];
+                    if allowed(q) {
+                        cells.push(q.as_uvec2());
+                    }
+                }
+
+                if !rng.gen_bool(self.persistence.clamp(0.0, 1.0)) {
+                    direction = match rng.gen() {
+                        true => (direction + 1) % 8,
+                        false => (direction + 7) % 8,
+                    };
+                }
+                // Turn further until the vein can continue, give up when turned around
+                let turn = match rng.gen() {
+                    true => 1,
+                    false => 7,
+                };
+                match (0..8).map(|i| (direction + i * turn) % 8).find(|&d| allowed(p + DIRECTIONS[d])) {
+                    Some(d) => direction = d,
+                    None => break,
+                }
+                p += DIRECTIONS[direction];
+                cells.push(p.as_uvec2());
+            }
+
+            cells.sort_by_key(|p| (p.x, p.y));
+            cells.dedup();
+            for &c in &cells {
+                *a.at_mut(c) = value.clone();
+            }
+            veins.push(cells);
+        }
+        veins
+    }
+}
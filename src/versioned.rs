@@ -0,0 +1,308 @@
+//! Versioned container for saved maps: a version tag chosen by the game, named layers and the
+//! layers compressed with `compress` (palette + RLE). Migrations registered per version
+//! upgrade old saves when the game's tile types change between releases.
+//!
+//! Layout of the byte stream (integers as LEB128 varints unless noted):
+//!
+//! ```text
+//! magic "MG2V" (4 bytes) | format version (1 byte) | game version | layer count
+//! | (name length | name (utf-8) | payload length | payload (see `compress`))*
+//! ```
+//!
+//! ```
+//! # use mapgen_2d::versioned::{LoadError, Migrations, SavedMap};
+//! # let terrain = ndarray::arr2(&[[0_u8, 2], [1, 2]]);
+//! let bytes = SavedMap::new(3).layer("terrain", terrain).encode();
+//! // Version 4 split WATER (2) into SHALLOW (2) and DEEP (5), old water was deep
+//! let migrations = Migrations::new(4).migration(3, |map| {
+//!     map.remap("terrain", |t| if t == 2 { 5 } else { t });
+//!     Ok(())
+//! });
+//! let map = migrations.load(&bytes)?;
+//! assert_eq!(map.version, 4);
+//! assert_eq!(map.get("terrain"), Some(&ndarray::arr2(&[[0_u8, 5], [1, 5]])));
+//! # Ok::<(), LoadError>(())
+//! ```
+
+use crate::compress::{compress, decompress, next_varint, write_varint, DecodeError, PaletteValue};
+use ndarray::Array2;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::hash::Hash;
+
+const MAGIC: &[u8; 4] = b"MG2V";
+pub const FORMAT_VERSION: u8 = 1;
+
+/// Named layers of a generated map with the version of the game's tile types.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SavedMap<T> {
+    pub version: u32,
+    pub layers: Vec<(String, Array2<T>)>,
+}
+
+impl<T> SavedMap<T> {
+    pub fn new(version: u32) -> Self {
+        Self { version, layers: Vec::new() }
+    }
+
+    /// Add a layer, replacing an existing layer of the same name.
+    pub fn layer(mut self, name: &str, a: Array2<T>) -> Self {
+        self.insert(name, a);
+        self
+    }
+
+    /// Add a layer, replacing an existing layer of the same name.
+    pub fn insert(&mut self, name: &str, a: Array2<T>) {
+        match self.layers.iter_mut().find(|(n, _)| n == name) {
+            Some((_, layer)) => *layer = a,
+            None => self.layers.push((name.to_string(), a)),
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Array2<T>> {
+        self.layers.iter().find(|(n, _)| n == name).map(|(_, a)| a)
+    }
+
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut Array2<T>> {
+        self.layers.iter_mut().find(|(n, _)| n == name).map(|(_, a)| a)
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<Array2<T>> {
+        let i = self.layers.iter().position(|(n, _)| n == name)?;
+        Some(self.layers.remove(i).1)
+    }
+
+    pub fn rename(&mut self, name: &str, new_name: &str) -> bool {
+        match self.layers.iter_mut().find(|(n, _)| n == name) {
+            Some((n, _)) => {
+                *n = new_name.to_string();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Map all tiles of layer `name` with `f`, eg. to renumber tiles. Returns false if there
+    /// is no such layer.
+    pub fn remap<F: FnMut(T) -> T>(&mut self, name: &str, mut f: F) -> bool
+    where
+        T: Clone,
+    {
+        match self.get_mut(name) {
+            Some(a) => {
+                a.mapv_inplace(&mut f);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl<T: Eq + Hash + Clone + PaletteValue> SavedMap<T> {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(MAGIC);
+        out.push(FORMAT_VERSION);
+        write_varint(&mut out, self.version as u64);
+        write_varint(&mut out, self.layers.len() as u64);
+        for (name, a) in self.layers.iter() {
+            write_varint(&mut out, name.len() as u64);
+            out.extend_from_slice(name.as_bytes());
+            let payload = compress(a);
+            write_varint(&mut out, payload.len() as u64);
+            out.extend_from_slice(&payload);
+        }
+        out
+    }
+
+    /// Decode a map written by `encode` without migrating it, see `Migrations::load`.
+    pub fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        if bytes.len() < MAGIC.len() + 1 {
+            return Err(DecodeError::UnexpectedEnd);
+        }
+        if &bytes[..MAGIC.len()] != MAGIC {
+            return Err(DecodeError::BadMagic);
+        }
+        let format = bytes[MAGIC.len()];
+        if format != FORMAT_VERSION {
+            return Err(DecodeError::UnsupportedVersion(format));
+        }
+
+        let mut pos = MAGIC.len() + 1;
+        let version = u32::try_from(next_varint(bytes, &mut pos)?).map_err(|_| DecodeError::InvalidValue)?;
+        let count = next_varint(bytes, &mut pos)? as usize;
+        let mut layers = Vec::with_capacity(count.min(bytes.len()));
+        for _ in 0..count {
+            let name = slice(bytes, &mut pos)?;
+            let name = String::from_utf8(name.to_vec()).map_err(|_| DecodeError::InvalidValue)?;
+            let a = decompress(slice(bytes, &mut pos)?)?;
+            layers.push((name, a));
+        }
+        Ok(Self { version, layers })
+    }
+}
+
+/// Read a length prefixed byte slice at `*pos` and advance `pos` past it.
+fn slice<'a>(bytes: &'a [u8], pos: &mut usize) -> Result<&'a [u8], DecodeError> {
+    let len = next_varint(bytes, pos)? as usize;
+    let end = pos.checked_add(len).filter(|&end| end <= bytes.len()).ok_or(DecodeError::UnexpectedEnd)?;
+    let s = &bytes[*pos..end];
+    *pos = end;
+    Ok(s)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LoadError {
+    Decode(DecodeError),
+    /// The save is newer than the current version
+    NewerVersion(u32),
+    /// No migration from this version is registered
+    MissingMigration(u32),
+    /// The migration from `from` failed
+    Migration { from: u32, message: String },
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::Decode(e) => write!(f, "{}", e),
+            LoadError::NewerVersion(v) => write!(f, "saved map has newer version {}", v),
+            LoadError::MissingMigration(v) => write!(f, "no migration from version {}", v),
+            LoadError::Migration { from, message } => write!(f, "migration from version {} failed: {}", from, message),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+impl From<DecodeError> for LoadError {
+    fn from(e: DecodeError) -> Self {
+        LoadError::Decode(e)
+    }
+}
+
+/// Upgrades a saved map from version `from` to `from + 1`.
+pub type Migration<T> = Box<dyn Fn(&mut SavedMap<T>) -> Result<(), String>>;
+
+/// Registry of migrations that bring saved maps to the current version.
+pub struct Migrations<T> {
+    pub current: u32,
+    pub steps: BTreeMap<u32, Migration<T>>,
+}
+
+impl<T> Migrations<T> {
+    pub fn new(current: u32) -> Self {
+        Self { current, steps: BTreeMap::new() }
+    }
+
+    /// Register the migration from version `from` to `from + 1`.
+    pub fn migration<F>(mut self, from: u32, f: F) -> Self
+    where
+        F: Fn(&mut SavedMap<T>) -> Result<(), String> + 'static,
+    {
+        self.steps.insert(from, Box::new(f));
+        self
+    }
+
+    /// Apply migrations until `map` has the current version.
+    pub fn migrate(&self, map: &mut SavedMap<T>) -> Result<(), LoadError> {
+        if map.version > self.current {
+            return Err(LoadError::NewerVersion(map.version));
+        }
+        while map.version < self.current {
+            let from = map.version;
+            let step = self.steps.get(&from).ok_or(LoadError::MissingMigration(from))?;
+            step(map).map_err(|message| LoadError::Migration { from, message })?;
+            map.version = from + 1;
+        }
+        Ok(())
+    }
+}
+
+impl<T: Eq + Hash + Clone + PaletteValue> Migrations<T> {
+    /// Decode a map written by `SavedMap::encode` and migrate it to the current version.
+    pub fn load(&self, bytes: &[u8]) -> Result<SavedMap<T>, LoadError> {
+        let mut map = SavedMap::decode(bytes)?;
+        self.migrate(&mut map)?;
+        Ok(map)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ndarray::arr2;
+
+    fn map() -> SavedMap<u16> {
+        SavedMap::new(1)
+            .layer("terrain", arr2(&[[1, 2, 2], [300, 2, 1]]))
+            .layer("objects", Array2::zeros((0, 4)))
+            .layer("height", Array2::from_elem((5, 5), 7))
+    }
+
+    #[test]
+    fn encode_round_trip() {
+        let bytes = map().encode();
+        assert_eq!(&bytes[..4], b"MG2V");
+        assert_eq!(bytes[4], FORMAT_VERSION);
+        assert_eq!(SavedMap::decode(&bytes), Ok(map()));
+    }
+
+    #[test]
+    fn layers() {
+        let mut m = map().layer("terrain", Array2::zeros((1, 1)));
+        assert_eq!(m.layers.len(), 3);
+        assert_eq!(m.get("terrain"), Some(&Array2::zeros((1, 1))));
+
+        assert!(m.rename("height", "elevation"));
+        assert!(!m.rename("height", "elevation"));
+        assert!(m.remap("elevation", |h| h * 2));
+        assert!(!m.remap("height", |h| h * 2));
+        assert_eq!(m.remove("elevation"), Some(Array2::from_elem((5, 5), 14)));
+        assert_eq!(m.remove("elevation"), None);
+        let names: Vec<&str> = m.layers.iter().map(|(n, _)| n.as_str()).collect();
+        assert_eq!(names, vec!["terrain", "objects"]);
+    }
+
+    #[test]
+    fn migrations_run_in_order() {
+        let migrations = Migrations::new(3)
+            .migration(2, |m: &mut SavedMap<u16>| {
+                m.rename("elevation", "height");
+                Ok(())
+            })
+            .migration(1, |m| {
+                assert!(m.rename("height", "elevation"));
+                m.remap("terrain", |t| t + 1);
+                Ok(())
+            });
+        let m = migrations.load(&map().encode()).unwrap();
+        assert_eq!(m.version, 3);
+        assert_eq!(m.get("terrain"), Some(&arr2(&[[2, 3, 3], [301, 3, 2]])));
+        assert_eq!(m.get("height"), map().get("height"));
+
+        // Maps of the current version are not changed
+        let mut current = map();
+        current.version = 3;
+        assert_eq!(migrations.load(&current.encode()), Ok(current));
+    }
+
+    #[test]
+    fn load_errors() {
+        let migrations = Migrations::new(3).migration(2, |_: &mut SavedMap<u16>| Err("broken".to_string()));
+        assert_eq!(migrations.load(&map().encode()), Err(LoadError::MissingMigration(1)));
+
+        let mut m = map();
+        m.version = 2;
+        let failed = LoadError::Migration { from: 2, message: "broken".to_string() };
+        assert_eq!(migrations.load(&m.encode()), Err(failed));
+        m.version = 4;
+        assert_eq!(migrations.load(&m.encode()), Err(LoadError::NewerVersion(4)));
+
+        let bytes = map().encode();
+        assert_eq!(SavedMap::<u16>::decode(&bytes[..3]), Err(DecodeError::UnexpectedEnd));
+        assert_eq!(SavedMap::<u16>::decode(&bytes[..bytes.len() - 1]), Err(DecodeError::UnexpectedEnd));
+        assert_eq!(SavedMap::<u16>::decode(b"MG2X\x01"), Err(DecodeError::BadMagic));
+        assert_eq!(SavedMap::<u16>::decode(b"MG2V\x02"), Err(DecodeError::UnsupportedVersion(2)));
+    }
+}
@@ -1,104 +1,798 @@
 
-use ndarray::Array2;
-use glam::{UVec2, Vec2, uvec2};
+use ndarray::{Array2, Zip};
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
+use float_ord::FloatOrd;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashSet};
+use crate::neighborhood::{chebyshev, manhattan, offsets};
+use glam::{ivec2, IVec2, UVec2, Vec2, uvec2, vec2};
 use kd_tree::{KdTree, KdPoint};
 use typenum;
 use crate::region::Region;
-use std::cmp::{min, max};
+use crate::map_stack::MapStack;
+use crate::colored_noise::ColoredNoise;
+use crate::report::GenerationReport;
+use crate::seed::derive_seed;
+use crate::coord::{map_size, MapAccess, UCoord2Conversions};
+use crate::error::MapgenError;
+use std::ops::{Index, IndexMut};
+#[cfg(feature = "wgpu")]
+use crate::gpu::Backend;
+
+/// Value of `VoronoiResult::map` for tiles on the border between cells (see
+/// `Voronoi::border_width`).
+pub const BORDER: usize = usize::MAX;
+
+/// Value of `VoronoiResult::map` for tiles that do not belong to any cell, eg. because
+/// they are outside of the domain mask (see `Voronoi::generate_masked`).
+pub const OUTSIDE: usize = usize::MAX - 1;
+
+/// A tile of a voronoi map, see `VoronoiResult::tile`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum VoronoiTile {
+    /// Index of the cell (ie. of its center)
+    Cell(usize),
+    Border,
+    Outside,
+}
+
+impl From<usize> for VoronoiTile {
+    fn from(v: usize) -> Self {
+        match v {
+            BORDER => VoronoiTile::Border,
+            OUTSIDE => VoronoiTile::Outside,
+            i => VoronoiTile::Cell(i),
+        }
+    }
+}
+
+/// Distance used by `Voronoi::generate_masked` to assign tiles to centers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DomainDistance {
+    /// Straight-line distance, as in `generate`. Cells may be split by holes in the mask.
+    Euclidean,
+    /// Length of the shortest path inside the mask (8-connected), so cells do not extend
+    /// across gaps in the mask. Tiles that can not be reached from any center are `OUTSIDE`.
+    Geodesic,
+}
+
+/// Settings for `Voronoi::generate_balanced`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CapacityConstraint {
+    /// Maximum relative deviation of any cell area from the mean area
+    pub tolerance: f32,
+    pub max_iterations: u32,
+}
+
+impl Default for CapacityConstraint {
+    fn default() -> Self {
+        Self {
+            tolerance: 0.05,
+            max_iterations: 100,
+        }
+    }
+}
+
+/// Size and shape of one cell, see `VoronoiResult::cell_stats`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CellStats {
+    /// Number of tiles
+    pub area: usize,
+    /// Mean position of the tiles, the center itself if the cell has no tiles
+    pub centroid: Vec2,
+    /// Number of tile edges between the cell and other tiles (or the map border)
+    pub perimeter: usize,
+    /// `4π area / perimeter²`: π/4 for squares, about 0.6 for round cells (perimeters along
+    /// tile edges are longer than smooth ones) and close to 0 for slivers; 0 for empty cells
+    pub compactness: f32,
+    /// Indices of the adjacent cells (also across border tiles), ascending
+    pub neighbors: Vec<usize>,
+}
+
+/// How `Voronoi::generate` assigns tiles to centers.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(rename_all = "snake_case"))]
+pub enum Algorithm {
+    /// Exact nearest center per tile via a kd-tree, with borders of `Voronoi::border_width`
+    /// tiles between cells.
+    #[default]
+    KdTree,
+    /// Jump flooding: a fixed amount of work per tile (O(tiles * log(size))) independent of
+    /// the number of centers, without a kd-tree. The result is approximate (a few tiles near
+    /// cell borders may be assigned to the second-nearest center), has no border tiles and
+    /// includes the distance field (`VoronoiResult::distances`).
+    JumpFlood,
+}
 
 #[derive(Clone)]
 pub struct Voronoi {
     // TODO: turn into a builder, hide VoronoiCenter
     pub size: UVec2,
     pub centers: Vec<VoronoiCenter>,
+    pub algorithm: Algorithm,
+    /// Width of the borders between cells in tiles, 0 for no borders. Only
+    /// `Algorithm::KdTree` produces borders.
+    pub border_width: f32,
 }
 
 pub struct VoronoiResult {
     pub input_configuration: Voronoi,
     pub output_configuration: Voronoi,
+    /// Cell index per tile, `BORDER` for border tiles
     pub map: Array2<usize>,
     pub regions: Vec<Region<usize>>,
+    /// Additive weight per cell (power diagram), all 0.0 for a plain voronoi diagram
+    pub weights: Vec<f32>,
+    /// Distance of every tile to the center of its cell, only computed by
+    /// `Algorithm::JumpFlood`
+    pub distances: Option<Array2<f32>>,
+    /// Tree of the centers of `output_configuration`, only kept for results of `generate`
+    /// (see `move_centers`)
+    kdtree: Option<KdTree<VoronoiCenter>>,
 }
 
-impl Voronoi {
+impl Index<UVec2> for VoronoiResult {
+    type Output = usize;
 
-    pub fn generate(&self) -> VoronoiResult {
-        let kdtree = KdTree::build_by_ordered_float(
-            self.centers.clone()
-        );
+    fn index(&self, p: UVec2) -> &usize {
+        &self.map[p.as_index2()]
+    }
+}
+
+impl IndexMut<UVec2> for VoronoiResult {
+    fn index_mut(&mut self, p: UVec2) -> &mut usize {
+        &mut self.map[p.as_index2()]
+    }
+}
+
+impl VoronoiResult {
+    /// Iterate all cell indices with their positions, x-major.
+    pub fn iter_with_positions(&self) -> impl Iterator<Item = (UVec2, &usize)> {
+        self.map.iter_with_positions()
+    }
+
+    pub fn tile(&self, p: UVec2) -> VoronoiTile {
+        self[p].into()
+    }
+
+    /// Plain integer labeling of the cells, border tiles are `u32::MAX`.
+    pub fn id_map(&self) -> Array2<u32> {
+        self.into_tiles(|t| match t {
+            VoronoiTile::Cell(i) => i as u32,
+            VoronoiTile::Border | VoronoiTile::Outside => u32::MAX,
+        })
+    }
 
-        // TODO: Allow providing this from outside?
-        let mut a = Array2::zeros((self.size.x as usize, self.size.y as usize));
+    /// Move the centers of `output_configuration` to `positions` (one per center) and update
+    /// the map.
+    /// Only centers that moved by more than `threshold` are moved, and only the tiles that
+    /// have one of them among their 3 nearest centers (before or after the move) are
+    /// reassigned. This makes repeated small adjustments (eg. `lloyd_step`) much cheaper than
+    /// generating from scratch.
+    /// Panics if this result is not from `Voronoi::generate`.
+    pub fn move_centers(&mut self, positions: &[Vec2], threshold: f32) {
+        assert_eq!(positions.len(), self.output_configuration.centers.len());
+        let old_tree = self
+            .kdtree
+            .take()
+            .expect("move_centers requires a result of Voronoi::generate");
 
-        let mut regions: Vec<_> = self.centers.iter().map(|c| {
-            Region {
-                anchor: c.position.as_uvec2(),
-                size: uvec2(1, 1),
-                reference: c.index.into(),
-                // TODO XXX: we would like to reference this array but that is being moved
-                // at the end of the function so the ref lifetime is too short, what can we do?
-                //a: &a
+        let moved: Vec<usize> = (0..positions.len())
+            .filter(|&i| self.output_configuration.centers[i].position.distance(positions[i]) > threshold)
+            .collect();
+        if moved.is_empty() {
+            self.kdtree = Some(old_tree);
+            return;
+        }
+
+        // Every tile is in the area of influence of 3 centers and we check before and after
+        // the move, so when many centers move it is cheaper to reassign everything
+        let incremental = moved.len() * 6 < positions.len();
+
+        let mut affected = Array2::from_elem(self.map.dim(), !incremental);
+        for &i in moved.iter() {
+            if incremental {
+                self.mark_influence(&old_tree, i, &mut affected);
             }
-        }).collect();
+            self.output_configuration.centers[i].position = positions[i];
+        }
 
-        for ix in 0..self.size.x {
-            for iy in 0..self.size.y {
-                let found = kdtree.nearests(&[ix as f32, iy as f32], 3);
+        let kdtree = KdTree::build_by_ordered_float(self.output_configuration.centers.clone());
+        if incremental {
+            for &i in moved.iter() {
+                self.mark_influence(&kdtree, i, &mut affected);
+            }
+        }
 
-                let index = found[0].item.index;
+        for (p, &a) in affected.iter_with_positions() {
+            if a {
+                self.map[p.as_index2()] = Voronoi::assign(&kdtree, p.as_vec2(), self.output_configuration.border_width);
+            }
+        }
 
-                // This is needed for the "smooth" wall.
-                // TODO: Make this more configurable
-                let d1 = found[1].squared_distance - found[0].squared_distance;
-                let d2 = found[2].squared_distance - found[0].squared_distance;
+        self.regions = Voronoi::regions_of(&self.map, positions.len());
+        self.kdtree = Some(kdtree);
+    }
 
-                // TODO: Make configurable / dependent on expected cell size
-                if d1 * d2 >= 5000000.0 {
-                    a[[ix as usize, iy as usize]] = index;
+    /// Mark all tiles that have center `i` among their 3 nearest centers in `kdtree`.
+    /// These form a star-shaped area around the center, so a flood fill finds all of them.
+    fn mark_influence(&self, kdtree: &KdTree<VoronoiCenter>, i: usize, marked: &mut Array2<bool>) {
+        let size = map_size(&self.map);
+        let start = self.output_configuration.centers[i]
+            .position
+            .round()
+            .as_ivec2()
+            .clamp(IVec2::ZERO, size.as_ivec2() - 1);
 
-                    let region = &mut regions[index];
-                    assert!(region.reference == index);
-                    region.anchor = uvec2(
-                        min(region.anchor.x, ix),
-                        min(region.anchor.y, iy),
-                    );
+        let mut visited = HashSet::new();
+        let mut stack = vec![start];
+        visited.insert(start);
 
-                    if ix > region.anchor.x {
-                        region.size.x = max(region.size.x, (ix - region.anchor.x) as u32);
+        while let Some(p) = stack.pop() {
+            let found = kdtree.nearests(&[p.x as f32, p.y as f32], 3);
+            if !found.iter().any(|f| f.item.index == i) {
+                continue;
+            }
+            marked[p.as_uvec2().as_index2()] = true;
+
+            for o in offsets(1, chebyshev) {
+                let q = p + o;
+                if q.cmpge(IVec2::ZERO).all() && q.cmplt(size.as_ivec2()).all() && visited.insert(q) {
+                    stack.push(q);
+                }
+            }
+        }
+    }
+
+    /// Move every center to the centroid of its cell (border tiles are not counted), see
+    /// `move_centers`.
+    /// Returns the mean distance the centers moved, useful as a convergence measure.
+    pub fn lloyd_step(&mut self, threshold: f32) -> f32 {
+        let n = self.output_configuration.centers.len();
+        let mut sums = vec![(Vec2::ZERO, 0_usize); n];
+        for (p, &i) in self.map.iter_with_positions() {
+            if i < n {
+                sums[i].0 += p.as_vec2();
+                sums[i].1 += 1;
+            }
+        }
+
+        let positions: Vec<Vec2> = sums
+            .iter()
+            .zip(self.output_configuration.centers.iter())
+            .map(|(&(sum, count), c)| if count > 0 { sum / count as f32 } else { c.position })
+            .collect();
+        let movement = positions
+            .iter()
+            .zip(self.output_configuration.centers.iter())
+            .map(|(p, c)| p.distance(c.position))
+            .sum::<f32>()
+            / n.max(1) as f32;
+        self.move_centers(&positions, threshold);
+        movement
+    }
+
+    /// Run `steps` Lloyd steps, reporting the time taken and the movement per step.
+    pub fn relax(&mut self, steps: usize, threshold: f32) -> GenerationReport {
+        let mut report = GenerationReport::default();
+        for _ in 0..steps {
+            let movement = report.time("lloyd", || self.lloyd_step(threshold));
+            report.movement.push(movement);
+        }
+        report.cells_processed = self.map.len() * steps;
+        report
+    }
+
+    /// Area, centroid, perimeter, compactness and neighbors of every cell, eg. to reject
+    /// layouts with slivers or to pick the largest cell.
+    pub fn cell_stats(&self) -> Vec<CellStats> {
+        let centers = &self.output_configuration.centers;
+        let n = centers.len();
+        let mut sums = vec![(Vec2::ZERO, 0_usize, 0_usize); n];
+        let mut neighbors = vec![Vec::new(); n];
+        // Cells separated by borders are neighbors if they are both near the same border tile
+        let reach = self.output_configuration.border_width.ceil().max(1.0) as u32;
+
+        for (p, &i) in self.map.iter_with_positions() {
+            if i == BORDER {
+                let near: Vec<usize> = offsets(reach, chebyshev)
+                    .filter_map(|o| self.map.get_at(p.as_ivec2() + o).copied())
+                    .filter(|&j| j < n)
+                    .collect();
+                for &a in near.iter() {
+                    neighbors[a].extend(near.iter().copied().filter(|&b| b != a));
+                }
+                continue;
+            }
+            if i >= n {
+                continue;
+            }
+            sums[i].0 += p.as_vec2();
+            sums[i].1 += 1;
+            for o in offsets(1, manhattan) {
+                match self.map.get_at(p.as_ivec2() + o) {
+                    Some(&j) if j == i => {}
+                    Some(&j) => {
+                        sums[i].2 += 1;
+                        if j < n {
+                            neighbors[i].push(j);
+                        }
                     }
-                    if iy > region.anchor.x {
-                        region.size.y = max(region.size.y, (iy - region.anchor.y) as u32);
+                    None => sums[i].2 += 1,
+                }
+            }
+        }
+
+        sums.into_iter()
+            .zip(neighbors)
+            .zip(centers)
+            .map(|(((sum, area, perimeter), mut neighbors), c)| {
+                neighbors.sort_unstable();
+                neighbors.dedup();
+                CellStats {
+                    area,
+                    centroid: if area > 0 { sum / area as f32 } else { c.position },
+                    perimeter,
+                    compactness: match perimeter {
+                        0 => 0.0,
+                        _ => 4.0 * std::f32::consts::PI * area as f32 / (perimeter * perimeter) as f32,
+                    },
+                    neighbors,
+                }
+            })
+            .collect()
+    }
+
+    /// True for all border tiles.
+    pub fn border_mask(&self) -> Array2<bool> {
+        self.into_tiles(|t| t == VoronoiTile::Border)
+    }
+
+    /// Convert every tile with `f`, eg. to map cells to terrain types.
+    pub fn into_tiles<T, F>(&self, f: F) -> Array2<T>
+    where
+        F: Fn(VoronoiTile) -> T,
+    {
+        self.map.mapv(|v| f(v.into()))
+    }
+}
+
+impl Voronoi {
+    pub fn new(size: UVec2, centers: Vec<VoronoiCenter>) -> Self {
+        Self {
+            size,
+            centers,
+            algorithm: Algorithm::default(),
+            border_width: 1.0,
+        }
+    }
+
+    pub fn algorithm(mut self, algorithm: Algorithm) -> Self {
+        self.algorithm = algorithm;
+        self
+    }
+
+    pub fn border_width(mut self, width: f32) -> Self {
+        self.border_width = width;
+        self
+    }
+
+    /// Check the parameters: the size must not be zero, there must be at least one center,
+    /// center positions must be finite and the border width finite and not negative.
+    pub fn validate(&self) -> Result<(), MapgenError> {
+        MapgenError::check_size("size", self.size)?;
+        if self.centers.is_empty() {
+            return Err(MapgenError::invalid("centers", "no centers"));
+        }
+        if let Some(c) = self.centers.iter().find(|c| !c.position.is_finite()) {
+            return Err(MapgenError::invalid("centers", format!("center {} is at {}", c.index, c.position)));
+        }
+        if let Some(c) = self.centers.iter().find(|c| c.index >= self.centers.len()) {
+            let n = self.centers.len();
+            return Err(MapgenError::invalid("centers", format!("center index {} is not below {}", c.index, n)));
+        }
+        if !(self.border_width.is_finite() && self.border_width >= 0.0) {
+            return Err(MapgenError::invalid("border_width", "must be finite and not negative"));
+        }
+        Ok(())
+    }
+
+    /// Like `generate`, but checks the parameters first, see `validate`.
+    pub fn try_generate(&self) -> Result<VoronoiResult, MapgenError> {
+        self.validate()?;
+        Ok(self.generate())
+    }
+
+    pub fn generate(&self) -> VoronoiResult {
+        if self.algorithm == Algorithm::JumpFlood {
+            return self.generate_jump_flood();
+        }
+
+        let kdtree = KdTree::build_by_ordered_float(self.centers.clone());
+        let map = self.rasterize(&kdtree, None, |p| p.as_vec2());
+        VoronoiResult {
+            kdtree: Some(kdtree),
+            ..self.result(map)
+        }
+    }
+
+    /// Like `generate`, but on `backend`. Only `Algorithm::JumpFlood` runs on the GPU, other
+    /// algorithms and maps the GPU does not support (see `Gpu::jump_flood`) fall back to the
+    /// CPU.
+    #[cfg(feature = "wgpu")]
+    pub fn generate_on(&self, backend: &Backend) -> VoronoiResult {
+        match (backend, self.algorithm) {
+            (Backend::Gpu(gpu), Algorithm::JumpFlood) => match gpu.jump_flood(self) {
+                Some(nearest) => self.jump_flood_result(nearest),
+                None => self.generate(),
+            },
+            _ => self.generate(),
+        }
+    }
+
+    fn generate_jump_flood(&self) -> VoronoiResult {
+        let size = self.size.as_ivec2();
+        let mut nearest = self.jump_flood_seeds();
+        let mut previous = nearest.clone();
+        for step in self.jump_flood_steps() {
+            std::mem::swap(&mut previous, &mut nearest);
+            let f = |(x, y): (usize, usize), n: &mut Option<usize>| {
+                let p = ivec2(x as i32, y as i32);
+                *n = previous[[x, y]];
+                for o in offsets(1, chebyshev) {
+                    let q = p + o * step;
+                    if q.cmplt(IVec2::ZERO).any() || q.cmpge(size).any() {
+                        continue;
+                    }
+                    if let Some(j) = previous[q.as_uvec2().as_index2()] {
+                        if n.is_none_or(|i| self.jump_flood_distance(p, j) < self.jump_flood_distance(p, i)) {
+                            *n = Some(j);
+                        }
                     }
                 }
+            };
+
+            #[cfg(feature = "rayon")]
+            Zip::indexed(&mut nearest).par_for_each(f);
+            #[cfg(not(feature = "rayon"))]
+            Zip::indexed(&mut nearest).for_each(f);
+        }
+        self.jump_flood_result(nearest)
+    }
+
+    /// Squared distance of `p` to center `i` as compared by jump flooding
+    fn jump_flood_distance(&self, p: IVec2, i: usize) -> f32 {
+        p.as_vec2().distance_squared(self.centers[i].position)
+    }
+
+    /// Initial state of jump flooding: the (index into `centers` of the) nearest center at
+    /// the tile of each center.
+    pub(crate) fn jump_flood_seeds(&self) -> Array2<Option<usize>> {
+        let size = self.size.as_ivec2();
+        let mut nearest: Array2<Option<usize>> = Array2::from_elem(self.size.as_index2(), None);
+        for (i, c) in self.centers.iter().enumerate() {
+            let p = c.position.round().as_ivec2().clamp(IVec2::ZERO, size - 1);
+            let closer = match nearest[p.as_uvec2().as_index2()] {
+                Some(j) => self.jump_flood_distance(p, i) < self.jump_flood_distance(p, j),
+                None => true,
+            };
+            if closer {
+                nearest[p.as_uvec2().as_index2()] = Some(i);
             }
         }
+        nearest
+    }
+
+    /// Step widths of the jump flooding passes, halving from half the size down to 1
+    pub(crate) fn jump_flood_steps(&self) -> impl Iterator<Item = i32> {
+        let first = self.size.max_element().next_power_of_two() as i32 / 2;
+        std::iter::successors(Some(first), |step| Some(step / 2)).take_while(|&step| step >= 1)
+    }
+
+    /// Result of jump flooding from the nearest center found per tile.
+    fn jump_flood_result(&self, nearest: Array2<Option<usize>>) -> VoronoiResult {
+        let map = nearest.mapv(|n| n.map_or(OUTSIDE, |i| self.centers[i].index));
+        let distances = Array2::from_shape_fn(self.size.as_index2(), |(x, y)| match nearest[[x, y]] {
+            Some(i) => self.jump_flood_distance(uvec2(x as u32, y as u32).as_ivec2(), i).sqrt(),
+            None => f32::INFINITY,
+        });
 
+        VoronoiResult {
+            distances: Some(distances),
+            ..self.result(map)
+        }
+    }
+
+    /// Like `generate`, but every tile is assigned to the center nearest to its position
+    /// displaced by a noise vector of up to `amplitude` tiles, which makes the cell borders
+    /// organic ("wobbly"). The two displacement components are generated from `noise`
+    /// (with seeds derived from `noise.seed`, see `seed::derive_seed`), `noise.size` is ignored.
+    pub fn generate_perturbed(&self, noise: &ColoredNoise, amplitude: f32) -> VoronoiResult {
+        let layer = |seed_offset: u64| {
+            ColoredNoise {
+                size: self.size,
+                seed: derive_seed(noise.seed, seed_offset),
+                ..noise.clone()
+            }
+            .generate()
+        };
+        let (dx, dy) = (layer(0), layer(1));
+
+        let kdtree = KdTree::build_by_ordered_float(self.centers.clone());
+        self.result(self.rasterize(&kdtree, None, |p| {
+            // Noise is in [0, 1), map to [-amplitude, amplitude)
+            let d = vec2(*dx.at(p) as f32, *dy.at(p) as f32) * 2.0 - 1.0;
+            p.as_vec2() + d * amplitude
+        }))
+    }
+
+    fn result(&self, map: Array2<usize>) -> VoronoiResult {
         VoronoiResult {
             output_configuration: self.clone(),
             input_configuration: self.clone(),
-            map: a,
-            regions
+            regions: Self::regions_of(&map, self.centers.len()),
+            map,
+            weights: vec![0.0; self.centers.len()],
+            kdtree: None,
+            distances: None,
         }
+    }
 
+    /// Assign every tile (where `mask` is set, if given) to the center nearest to `query(tile)`.
+    /// Other tiles are `OUTSIDE`.
+    /// With the `rayon` feature, tiles are assigned in parallel.
+    fn rasterize<Q>(&self, kdtree: &KdTree<VoronoiCenter>, mask: Option<&Array2<bool>>, query: Q) -> Array2<usize>
+    where
+        Q: Fn(UVec2) -> Vec2 + Sync,
+    {
+        let mut a = Array2::from_elem(self.size.as_index2(), OUTSIDE);
+        let f = |(x, y): (usize, usize), v: &mut usize| {
+            if mask.is_none_or(|m| m[[x, y]]) {
+                *v = Self::assign(kdtree, query(uvec2(x as u32, y as u32)), self.border_width);
+            }
+        };
+
+        #[cfg(feature = "rayon")]
+        Zip::indexed(&mut a).par_for_each(f);
+        #[cfg(not(feature = "rayon"))]
+        Zip::indexed(&mut a).for_each(f);
+
+        a
     }
 
-    pub fn lloyd_step(&mut self, _a: &mut Array2<u32>) {
-        // TODO: lloyd step
-        todo!()
+    /// The cell index for a tile at position `q`, `BORDER` if it is within the border band of
+    /// width `border_width` around the boundary of its cell.
+    ///
+    /// The distance of `q` to the boundary between its nearest center `c0` and another center
+    /// `c` is its distance to their bisector, `(|q - c|² - |q - c0|²) / (2 |c - c0|)`. The
+    /// band is half-open (`[-width / 2, width / 2)` across the bisector, oriented by the
+    /// center indices), so it contains exactly `border_width` tiles along any row or column
+    /// crossing an axis-aligned boundary, and `border_width` tiles measured across the
+    /// boundary in general, independent of the cell sizes.
+    fn assign(kdtree: &KdTree<VoronoiCenter>, q: Vec2, border_width: f32) -> usize {
+        let found = kdtree.nearests(&[q.x, q.y], 3);
+        if found.is_empty() {
+            return OUTSIDE;
+        }
+
+        let nearest = &found[0].item;
+        let half = border_width / 2.0;
+        let on_border = found[1..].iter().any(|f| {
+            let other = &f.item;
+            let spacing = other.position.distance(nearest.position);
+            if spacing == 0.0 {
+                return other.index != nearest.index;
+            }
+            let d = (f.squared_distance - found[0].squared_distance) / (2.0 * spacing);
+            match nearest.index < other.index {
+                true => d < half,
+                false => d <= half,
+            }
+        });
+
+        match on_border && border_width > 0.0 {
+            true => BORDER,
+            false => nearest.index,
+        }
     }
 
-    /*
-    pub fn add_walls(&self, a: &mut Array2<u32>) {
-        for ix in 0..self.size.x as usize {
-            for iy in 0..self.size.y as usize {
-                for dx in [-1, 1] {
-                    for dy in [-1, 1] {
-                    }
+    /// Generate cells only on tiles where `mask` (which must have the size of the map) is set,
+    /// all other tiles are `OUTSIDE`. Centers outside of the mask get no tiles.
+    /// Only `DomainDistance::Euclidean` produces border tiles.
+    pub fn generate_masked(&self, mask: &Array2<bool>, distance: DomainDistance) -> VoronoiResult {
+        assert_eq!(map_size(mask), self.size);
+
+        let map = match distance {
+            DomainDistance::Euclidean => {
+                let centers: Vec<VoronoiCenter> = self
+                    .centers
+                    .iter()
+                    .filter(|c| self.center_in_mask(c, mask))
+                    .cloned()
+                    .collect();
+                self.rasterize(&KdTree::build_by_ordered_float(centers), Some(mask), |p| p.as_vec2())
+            }
+            DomainDistance::Geodesic => self.geodesic_map(mask),
+        };
+        self.result(map)
+    }
+
+    fn center_in_mask(&self, c: &VoronoiCenter, mask: &Array2<bool>) -> bool {
+        let p = c.position.round().as_ivec2();
+        mask.get_at(p) == Some(&true)
+    }
+
+    /// Multi-source dijkstra from all centers inside the mask
+    fn geodesic_map(&self, mask: &Array2<bool>) -> Array2<usize> {
+        let mut a = Array2::from_elem(self.size.as_index2(), OUTSIDE);
+        let mut distances = Array2::from_elem(self.size.as_index2(), f32::INFINITY);
+        let mut queue = BinaryHeap::new();
+
+        for c in self.centers.iter().filter(|c| self.center_in_mask(c, mask)) {
+            let p = c.position.round().as_uvec2();
+            if distances.at(p) > &0.0 {
+                *distances.at_mut(p) = 0.0;
+                queue.push(Reverse((FloatOrd(0.0_f32), p.x, p.y, c.index)));
+            }
+        }
+
+        while let Some(Reverse((FloatOrd(d), x, y, index))) = queue.pop() {
+            let p = uvec2(x, y);
+            if d > *distances.at(p) || a.at(p) != &OUTSIDE {
+                continue;
+            }
+            *a.at_mut(p) = index;
+
+            for o in offsets(1, chebyshev) {
+                let q = p.as_ivec2() + o;
+                if mask.get_at(q) != Some(&true) {
+                    continue;
+                }
+                let q = q.as_uvec2();
+                let dq = d + o.as_vec2().length();
+                if dq < *distances.at(q) {
+                    *distances.at_mut(q) = dq;
+                    queue.push(Reverse((FloatOrd(dq), q.x, q.y, index)));
                 }
             }
         }
+        a
+    }
+
+    /// Generate a capacity-constrained tessellation, ie. a map where all cells have
+    /// approximately the same area.
+    /// Every center gets an additive weight, tiles belong to the center with the smallest
+    /// `squared distance - weight`. Weights are adjusted iteratively until all cell areas are
+    /// within `constraint.tolerance` of the mean area, or `constraint.max_iterations` is
+    /// reached.
+    /// Unlike `generate`, the result has no border tiles.
+    /// Panics on invalid parameters, see `try_generate_balanced`.
+    pub fn generate_balanced(&self, constraint: &CapacityConstraint) -> VoronoiResult {
+        let n = self.centers.len();
+        assert!(n > 0, "no centers");
+        assert!(self.centers.iter().enumerate().all(|(i, c)| c.index == i), "center indices are not 0..n");
+
+        let target = self.size.x as f32 * self.size.y as f32 / n as f32;
+        let mut weights = vec![0.0_f32; n];
+        let mut a = self.power_map(&weights);
+
+        for _ in 0..constraint.max_iterations {
+            let mut areas = vec![0_usize; n];
+            for &i in a.iter() {
+                areas[i] += 1;
+            }
+
+            let max_deviation = areas
+                .iter()
+                .map(|&area| (area as f32 - target).abs() / target)
+                .fold(0.0, f32::max);
+            if max_deviation <= constraint.tolerance {
+                break;
+            }
+
+            // The area of a cell reacts to its weight roughly 1:1, but larger steps make
+            // neighboring cells swallow each other and oscillate.
+            for (w, &area) in weights.iter_mut().zip(areas.iter()) {
+                *w += (target - area as f32) * 0.1;
+            }
+            a = self.power_map(&weights);
+        }
+
+        VoronoiResult {
+            output_configuration: self.clone(),
+            input_configuration: self.clone(),
+            regions: Self::regions_of(&a, n),
+            map: a,
+            weights,
+            kdtree: None,
+            distances: None,
+        }
+    }
+
+    /// Like `generate_balanced`, but checks the parameters first: in addition to `validate`,
+    /// the center indices must be `0..centers.len()` in order and the tolerance must be finite
+    /// and not negative.
+    pub fn try_generate_balanced(&self, constraint: &CapacityConstraint) -> Result<VoronoiResult, MapgenError> {
+        self.validate()?;
+        if let Some((i, c)) = self.centers.iter().enumerate().find(|(i, c)| c.index != *i) {
+            return Err(MapgenError::invalid("centers", format!("center {} has index {}", i, c.index)));
+        }
+        if !(constraint.tolerance.is_finite() && constraint.tolerance >= 0.0) {
+            return Err(MapgenError::invalid("tolerance", "must be finite and not negative"));
+        }
+        Ok(self.generate_balanced(constraint))
+    }
+
+    /// Assign every tile to the center with the smallest power distance.
+    fn power_map(&self, weights: &[f32]) -> Array2<usize> {
+        Array2::from_shape_fn(self.size.as_index2(), |(x, y)| {
+            let p = Vec2::new(x as f32, y as f32);
+            self.centers
+                .iter()
+                .zip(weights)
+                .map(|(c, w)| (c.index, p.distance_squared(c.position) - w))
+                .min_by(|a, b| a.1.total_cmp(&b.1))
+                .unwrap()
+                .0
+        })
+    }
+
+    /// Bounding boxes of the cells `0..n` in `a`.
+    /// Cells that do not occur in `a` get an empty region at (0, 0).
+    /// With the `rayon` feature, bands of rows are scanned in parallel and merged.
+    fn regions_of(a: &Array2<usize>, n: usize) -> Vec<Region<usize>> {
+        type Bounds = Vec<Option<(UVec2, UVec2)>>;
+
+        let scan_row = |mut bounds: Bounds, y: u32| {
+            for x in 0..a.dim().0 as u32 {
+                let p = uvec2(x, y);
+                let i = *a.at(p);
+                if i == BORDER || i == OUTSIDE {
+                    continue;
+                }
+                bounds[i] = Some(match bounds[i] {
+                    None => (p, p),
+                    Some((lo, hi)) => (lo.min(p), hi.max(p)),
+                });
+            }
+            bounds
+        };
+
+        let rows = 0..a.dim().1 as u32;
+
+        #[cfg(feature = "rayon")]
+        let bounds = rows.into_par_iter().fold(|| vec![None; n], scan_row).reduce(
+            || vec![None; n],
+            |a, b| {
+                a.into_iter()
+                    .zip(b)
+                    .map(|bs| match bs {
+                        (Some((lo1, hi1)), Some((lo2, hi2))) => Some((lo1.min(lo2), hi1.max(hi2))),
+                        (x, None) | (None, x) => x,
+                    })
+                    .collect()
+            },
+        );
+        #[cfg(not(feature = "rayon"))]
+        let bounds = rows.fold(vec![None; n], scan_row);
+
+        bounds
+            .into_iter()
+            .enumerate()
+            .map(|(i, b)| match b {
+                Some((lo, hi)) => Region { anchor: lo, size: hi - lo + UVec2::ONE, reference: i },
+                None => Region { anchor: UVec2::ZERO, size: UVec2::ZERO, reference: i },
+            })
+            .collect()
+    }
+
+    /// Generate and store the resulting map as layer `name` in `layers`.
+    pub fn generate_into_layer(&self, layers: &mut MapStack, name: &str) -> Vec<Region<usize>> {
+        let r = self.generate();
+        layers.insert(name, r.map);
+        r.regions
     }
-    */
 }
 
 #[derive(Clone)]
@@ -116,3 +810,393 @@ impl KdPoint for VoronoiCenter {
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    /// `n` centers at random positions on a map of `size`
+    fn scattered(size: UVec2, n: usize, seed: u64) -> Voronoi {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let centers = (0..n)
+            .map(|index| {
+                let position = vec2(rng.gen_range(0.0..size.x as f32), rng.gen_range(0.0..size.y as f32));
+                VoronoiCenter { position, index }
+            })
+            .collect();
+        Voronoi::new(size, centers)
+    }
+
+    /// Two cells side by side with the boundary at x = 10, or above each other with the
+    /// boundary at y = 10 if `vertical`
+    fn two_cells(border_width: f32, vertical: bool) -> Array2<usize> {
+        let positions = match vertical {
+            false => [vec2(5.0, 7.0), vec2(15.0, 7.0)],
+            true => [vec2(7.0, 5.0), vec2(7.0, 15.0)],
+        };
+        let centers = positions
+            .iter()
+            .enumerate()
+            .map(|(index, &position)| VoronoiCenter { position, index })
+            .collect();
+        Voronoi::new(uvec2(20, 20), centers).border_width(border_width).generate().map
+    }
+
+    /// The border tiles of every row crossing the boundary, which must be contiguous
+    fn border_bands(a: &Array2<usize>) -> Vec<Vec<usize>> {
+        a.columns()
+            .into_iter()
+            .map(|row| {
+                let band: Vec<usize> = (0..row.len()).filter(|&x| row[x] == BORDER).collect();
+                assert!(band.windows(2).all(|w| w[1] == w[0] + 1), "border is not contiguous: {:?}", band);
+                band
+            })
+            .collect()
+    }
+
+    #[test]
+    fn border_width_one() {
+        let a = two_cells(1.0, false);
+        for band in border_bands(&a) {
+            assert_eq!(band, vec![10]);
+        }
+        assert!((0..10).all(|x| a[[x, 3]] == 0));
+        assert!((11..20).all(|x| a[[x, 3]] == 1));
+    }
+
+    #[test]
+    fn border_width_two() {
+        let a = two_cells(2.0, false);
+        for band in border_bands(&a) {
+            assert_eq!(band, vec![10, 11]);
+        }
+        assert!((0..10).all(|x| a[[x, 3]] == 0));
+        assert!((12..20).all(|x| a[[x, 3]] == 1));
+    }
+
+    #[test]
+    fn border_width_vertical_boundary() {
+        for width in [1, 2] {
+            let a = two_cells(width as f32, true);
+            for x in 0..20 {
+                let band: Vec<usize> = (0..20).filter(|&y| a[[x, y]] == BORDER).collect();
+                assert_eq!(band.len(), width);
+                assert_eq!(band[0], 10);
+            }
+        }
+    }
+
+    #[test]
+    fn border_width_zero() {
+        let a = two_cells(0.0, false);
+        assert!(a.iter().all(|&i| i != BORDER));
+    }
+
+    /// Cells left and right of x = 3.5 on a 7x3 map, which transposed would not fit
+    fn non_square(algorithm: Algorithm) -> VoronoiResult {
+        let centers = vec![
+            VoronoiCenter { position: vec2(6.0, 1.0), index: 0 },
+            VoronoiCenter { position: vec2(0.0, 1.0), index: 1 },
+        ];
+        Voronoi::new(uvec2(7, 3), centers).algorithm(algorithm).border_width(0.0).generate()
+    }
+
+    #[test]
+    fn non_square_kd_tree() {
+        let r = non_square(Algorithm::KdTree);
+        assert_eq!(r.map.dim(), (7, 3));
+        for (p, &i) in r.map.iter_with_positions() {
+            if p.x != 3 {
+                assert_eq!(i, if p.x > 3 { 0 } else { 1 }, "{}", p);
+            }
+        }
+        assert_eq!(r.regions[0].rect().anchor.y, 0);
+        assert_eq!(r.regions[0].rect().end(), uvec2(7, 3));
+        assert_eq!(r.regions[1].rect().anchor, uvec2(0, 0));
+        assert_eq!(r.regions[1].rect().end().y, 3);
+    }
+
+    #[test]
+    fn non_square_jump_flood() {
+        let r = non_square(Algorithm::JumpFlood);
+        assert_eq!(r.map.dim(), (7, 3));
+        assert_eq!(r.distances.as_ref().unwrap().dim(), (7, 3));
+        for (p, &i) in r.map.iter_with_positions() {
+            if p.x != 3 {
+                assert_eq!(i, if p.x > 3 { 0 } else { 1 }, "{}", p);
+            }
+        }
+        assert_eq!(r.distances.unwrap()[[6, 1]], 0.0);
+    }
+
+    /// Centers crowded into one corner of a 60x40 map, which an unweighted tessellation gives
+    /// very different areas
+    fn crowded() -> Voronoi {
+        let positions = [vec2(2.0, 3.0), vec2(5.0, 2.0), vec2(3.0, 6.0), vec2(8.0, 8.0), vec2(50.0, 30.0)];
+        let centers = positions
+            .iter()
+            .enumerate()
+            .map(|(index, &position)| VoronoiCenter { position, index })
+            .collect();
+        Voronoi::new(uvec2(60, 40), centers)
+    }
+
+    #[test]
+    fn balanced_areas_within_tolerance() {
+        let constraint = CapacityConstraint { tolerance: 0.05, max_iterations: 500 };
+        let r = crowded().try_generate_balanced(&constraint).unwrap();
+        let target = 60.0 * 40.0 / 5.0;
+        for i in 0..5 {
+            let area = r.map.iter().filter(|&&j| j == i).count() as f32;
+            assert!((area - target).abs() / target <= constraint.tolerance, "cell {} has area {}", i, area);
+        }
+    }
+
+    #[test]
+    fn balanced_invalid_parameters() {
+        let constraint = CapacityConstraint::default();
+        let empty = Voronoi::new(uvec2(10, 10), vec![]);
+        assert!(matches!(empty.try_generate_balanced(&constraint), Err(MapgenError::InvalidParameter { .. })));
+
+        let mut v = crowded();
+        v.centers[1].index = 7;
+        assert!(matches!(v.try_generate_balanced(&constraint), Err(MapgenError::InvalidParameter { .. })));
+
+        let nan = CapacityConstraint { tolerance: f32::NAN, ..constraint };
+        assert!(matches!(crowded().try_generate_balanced(&nan), Err(MapgenError::InvalidParameter { .. })));
+    }
+
+    #[test]
+    fn index_out_of_range() {
+        for algorithm in [Algorithm::KdTree, Algorithm::JumpFlood] {
+            let mut v = crowded().algorithm(algorithm);
+            v.centers[2].index = 5;
+            assert!(matches!(v.try_generate(), Err(MapgenError::InvalidParameter { .. })));
+            v.centers[2].index = 0;
+            assert!(v.try_generate().is_ok());
+        }
+    }
+
+    #[test]
+    fn perturbed_borders() {
+        let v = scattered(uvec2(48, 32), 8, 1);
+        let noise = ColoredNoise { seed: 5, ..Default::default() };
+        assert_eq!(v.generate_perturbed(&noise, 0.0).map, v.generate().map);
+
+        let amplitude = 4.0;
+        let r = v.generate_perturbed(&noise, amplitude);
+        assert_eq!(r.map, v.generate_perturbed(&noise, amplitude).map);
+        assert_ne!(r.map, v.generate().map);
+        assert_ne!(r.map, v.generate_perturbed(&ColoredNoise { seed: 6, ..noise }, amplitude).map);
+
+        // A tile is displaced by at most amplitude * sqrt(2), so its cell can only be one whose
+        // center is at most twice that farther away than the nearest center
+        for (p, &i) in r.iter_with_positions().filter(|(_, &i)| i != BORDER) {
+            let nearest = v.centers.iter().map(|c| c.position.distance(p.as_vec2())).fold(f32::INFINITY, f32::min);
+            let distance = v.centers[i].position.distance(p.as_vec2());
+            assert!(distance <= nearest + 2.0 * amplitude * std::f32::consts::SQRT_2, "{} in cell {}", p, i);
+        }
+    }
+
+    /// Map of a fresh `generate` with the current centers of `r`
+    fn regenerated(r: &VoronoiResult) -> Array2<usize> {
+        r.output_configuration.generate().map
+    }
+
+    #[test]
+    fn move_centers_matches_generate() {
+        let v = scattered(uvec2(64, 48), 30, 2);
+        let positions: Vec<Vec2> = v.centers.iter().map(|c| c.position).collect();
+
+        // Few centers move: incremental update
+        let mut r = v.generate();
+        let mut moved = positions.clone();
+        moved[3] += vec2(4.0, -3.0);
+        moved[17] = vec2(1.0, 1.0);
+        r.move_centers(&moved, 0.0);
+        assert_eq!(r.output_configuration.centers[17].position, vec2(1.0, 1.0));
+        assert_eq!(r.map, regenerated(&r));
+        let rects = |regions: &[Region<usize>]| regions.iter().map(|r| r.rect()).collect::<Vec<_>>();
+        assert_eq!(rects(&r.regions), rects(&r.output_configuration.generate().regions));
+
+        // Many centers move: everything is reassigned
+        let shifted: Vec<Vec2> = positions.iter().map(|&p| (p + vec2(2.5, 1.5)).min(vec2(63.0, 47.0))).collect();
+        r.move_centers(&shifted, 0.0);
+        assert_eq!(r.map, regenerated(&r));
+
+        // Moves up to the threshold are skipped
+        let nudged: Vec<Vec2> = shifted.iter().map(|&p| p + vec2(0.1, 0.0)).collect();
+        let before = r.map.clone();
+        r.move_centers(&nudged, 0.5);
+        assert_eq!(r.map, before);
+        assert_eq!(r.output_configuration.centers[0].position, shifted[0]);
+        assert_eq!(r.input_configuration.centers[0].position, positions[0]);
+    }
+
+    #[test]
+    fn lloyd_steps_converge() {
+        let v = scattered(uvec2(64, 48), 12, 3);
+        let mut r = v.generate();
+        let first = r.lloyd_step(0.0);
+        assert_eq!(r.map, regenerated(&r));
+
+        let report = r.relax(20, 0.0);
+        assert_eq!(report.movement.len(), 20);
+        assert!(report.movement[19] < first / 4.0, "{} {:?}", first, report.movement);
+        assert_eq!(r.map, regenerated(&r));
+
+        // Relaxed centers are close to the centroids of their cells
+        for (stats, c) in r.cell_stats().iter().zip(r.output_configuration.centers.iter()) {
+            assert!(stats.centroid.distance(c.position) < 1.0, "{} {}", stats.centroid, c.position);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "move_centers requires a result of Voronoi::generate")]
+    fn move_centers_needs_generate() {
+        let v = scattered(uvec2(16, 16), 3, 4);
+        let mut r = v.generate_perturbed(&ColoredNoise::default(), 1.0);
+        r.lloyd_step(0.0);
+    }
+
+    /// 20x10 map with a wall at x = 10 that is open only at y = 9, centers 0 left and 1 right
+    /// of it, and center 2 inside the wall
+    fn walled() -> (Voronoi, Array2<bool>) {
+        let mask = Array2::from_shape_fn((20, 10), |(x, y)| x != 10 || y == 9);
+        let positions = [vec2(5.0, 1.0), vec2(18.0, 8.0), vec2(10.0, 4.0)];
+        let centers = positions
+            .iter()
+            .enumerate()
+            .map(|(index, &position)| VoronoiCenter { position, index })
+            .collect();
+        (Voronoi::new(uvec2(20, 10), centers).border_width(0.0), mask)
+    }
+
+    #[test]
+    fn masked_euclidean() {
+        let (v, mask) = walled();
+        let r = v.generate_masked(&mask, DomainDistance::Euclidean);
+        let without_wall_center = Voronoi { centers: v.centers[..2].to_vec(), ..v.clone() }.generate();
+        for (p, &i) in r.iter_with_positions() {
+            match *mask.at(p) {
+                true => assert_eq!(i, without_wall_center[p], "{}", p),
+                false => assert_eq!(i, OUTSIDE, "{}", p),
+            }
+        }
+        assert_eq!(r[uvec2(11, 1)], 0);
+        assert_eq!(r.regions[2].size, UVec2::ZERO);
+    }
+
+    #[test]
+    fn masked_geodesic() {
+        let (v, mut mask) = walled();
+        let r = v.generate_masked(&mask, DomainDistance::Geodesic);
+        // Right behind the wall, but the path from center 0 goes around through the gap
+        assert_eq!(r[uvec2(11, 1)], 1);
+        assert!((0..8).all(|y| r[uvec2(9, y)] == 0));
+        assert!((0..9).all(|y| r[uvec2(10, y)] == OUTSIDE));
+        assert!(r.iter_with_positions().all(|(p, &i)| *mask.at(p) == (i != OUTSIDE)));
+
+        // Without the gap, the right side is only reachable from center 1
+        mask[[10, 9]] = false;
+        mask[[18, 8]] = false;
+        let r = v.generate_masked(&mask, DomainDistance::Geodesic);
+        assert!((11..20).all(|x| r[uvec2(x, 0)] == OUTSIDE));
+        assert!((0..10).all(|x| r[uvec2(x, 0)] == 0));
+    }
+
+    #[test]
+    fn tile_conversions() {
+        let centers = vec![
+            VoronoiCenter { position: vec2(5.0, 7.0), index: 0 },
+            VoronoiCenter { position: vec2(15.0, 7.0), index: 1 },
+        ];
+        let r = Voronoi::new(uvec2(20, 20), centers).generate();
+        assert_eq!(r.tile(uvec2(3, 3)), VoronoiTile::Cell(0));
+        assert_eq!(r.tile(uvec2(10, 3)), VoronoiTile::Border);
+        assert_eq!(r.tile(uvec2(15, 3)), VoronoiTile::Cell(1));
+
+        let ids = r.id_map();
+        assert_eq!(ids[[3, 3]], 0);
+        assert_eq!(ids[[10, 3]], u32::MAX);
+        assert_eq!(ids[[15, 3]], 1);
+
+        let border = r.border_mask();
+        assert_eq!(border.iter().filter(|&&b| b).count(), 20);
+        assert!((0..20).all(|y| border[[10, y]]));
+
+        let (v, mask) = walled();
+        let r = v.generate_masked(&mask, DomainDistance::Geodesic);
+        let chars = r.into_tiles(|t| match t {
+            VoronoiTile::Cell(i) => (b'a' + i as u8) as char,
+            VoronoiTile::Border => '+',
+            VoronoiTile::Outside => '#',
+        });
+        assert_eq!((chars[[0, 0]], chars[[10, 0]], chars[[19, 0]]), ('a', '#', 'b'));
+        assert_eq!(r.id_map()[[10, 0]], u32::MAX);
+    }
+
+    #[test]
+    fn cell_stats_of_two_cells() {
+        let centers = vec![
+            VoronoiCenter { position: vec2(5.0, 7.0), index: 0 },
+            VoronoiCenter { position: vec2(15.0, 7.0), index: 1 },
+            VoronoiCenter { position: vec2(-50.0, -50.0), index: 2 },
+        ];
+        let v = Voronoi::new(uvec2(20, 20), centers);
+        // Cell 0 is x in 0..10, the border is x = 10 and cell 1 is x in 11..20; cell 2 is empty
+        let stats = v.generate().cell_stats();
+        assert_eq!(stats[0].area, 200);
+        assert_eq!(stats[0].centroid, vec2(4.5, 9.5));
+        assert_eq!(stats[0].perimeter, 60);
+        assert_eq!(stats[0].neighbors, vec![1]);
+        assert_eq!(stats[1].area, 180);
+        assert_eq!(stats[1].perimeter, 58);
+        assert_eq!(stats[1].neighbors, vec![0]);
+        assert!((stats[0].compactness - 4.0 * std::f32::consts::PI * 200.0 / 3600.0).abs() < 1e-6);
+        let centroid = vec2(-50.0, -50.0);
+        let empty = CellStats { area: 0, centroid, perimeter: 0, compactness: 0.0, neighbors: vec![] };
+        assert_eq!(stats[2], empty);
+
+        // Without borders the cells touch directly
+        let stats = v.border_width(0.0).generate().cell_stats();
+        assert_eq!(stats[0].area + stats[1].area, 400);
+        assert_eq!(stats[0].neighbors, vec![1]);
+        assert_eq!(stats[1].neighbors, vec![0]);
+    }
+
+    #[test]
+    fn cell_stats_neighbors_are_symmetric() {
+        let r = scattered(uvec2(64, 48), 20, 5).border_width(2.0).generate();
+        let stats = r.cell_stats();
+        assert_eq!(stats.iter().map(|s| s.area).sum::<usize>(), r.map.iter().filter(|&&i| i != BORDER).count());
+        for (i, s) in stats.iter().enumerate() {
+            assert!(s.neighbors.windows(2).all(|w| w[0] < w[1]));
+            assert!(s.compactness > 0.0 && s.compactness <= std::f32::consts::FRAC_PI_4 + 1e-6, "{}", s.compactness);
+            for &j in s.neighbors.iter() {
+                assert!(stats[j].neighbors.contains(&i), "{} {}", i, j);
+            }
+        }
+    }
+
+    /// With the `rayon` feature this checks the parallel rasterization and merging of region
+    /// bounds against a plain sequential pass.
+    #[test]
+    fn rasterization_matches_sequential() {
+        let v = scattered(uvec2(200, 150), 40, 6);
+        let r = v.generate();
+        let kdtree = KdTree::build_by_ordered_float(v.centers.clone());
+        for (p, &i) in r.iter_with_positions() {
+            assert_eq!(i, Voronoi::assign(&kdtree, p.as_vec2(), v.border_width), "{}", p);
+        }
+
+        let mut bounds = vec![(UVec2::splat(u32::MAX), UVec2::ZERO); 40];
+        for (p, &i) in r.iter_with_positions().filter(|(_, &i)| i < 40) {
+            bounds[i] = (bounds[i].0.min(p), bounds[i].1.max(p));
+        }
+        for (region, (lo, hi)) in r.regions.iter().zip(bounds) {
+            assert_eq!((region.anchor, region.size), (lo, hi - lo + UVec2::ONE), "cell {}", region.reference);
+        }
+    }
+}
@@ -1,204 +1,3586 @@
-use crate::neighborhood::Neighborhood;
-use crate::coord::{UCoord2, UCoord2Conversions};
-use std::time::{Instant, Duration};
-use glam::{uvec2, UVec2, IVec2};
-use ndarray::{arr1, s, Array2, Array3, ArrayBase, Ix1, ViewRepr, Zip};
+use crate::neighborhood::{self, Metric, Metric3, Neighborhood, Neighborhood3};
+use crate::coord::{map_size, UCoord2Conversions};
+use glam::{uvec2, uvec3, BVec2, BVec3, IVec2, IVec3, UVec2, UVec3};
+use ndarray::{arr1, Array2, Array3};
 use rand::{
     distributions::{Distribution, Uniform},
-    SeedableRng,
+    rngs::StdRng,
+    Rng, SeedableRng,
 };
 //use soil_protocol::Tile;
+use std::collections::VecDeque;
+use std::fmt;
 use std::marker::PhantomData;
+use std::ops::Index;
+use std::sync::Arc;
+use std::time::Instant;
 //use ndarray::parallel::prelude::*;
 use priority_queue::priority_queue::PriorityQueue;
 use float_ord::FloatOrd;
-use crate::tile::Tile;
+use crate::tile::{Layered, Tile};
+use crate::map_stack::MapStack;
+use crate::symmetry::Symmetry;
+use crate::seed::derive_seed;
+use crate::rect::{IRect, Rect, Rect3};
+use crate::bucket_queue::BucketQueue;
+use crate::report::GenerationReport;
+use crate::error::MapgenError;
 
-pub trait ProbabilityCallback<T, const N: usize>: FnMut(&Neighborhood<T>) -> [f32; N] {}
+/// Probability callback that only looks at the neighborhood.
+/// Callbacks are `Fn` so that a configuration can be shared between generators (see
+/// `WaveFunctionCollapse::new`), use `ProbabilityContext::rng` for random numbers.
+pub trait ProbabilityCallback<T, const N: usize>: Fn(&Neighborhood<T>) -> [f32; N] {}
 
 impl<F, T, const N: usize> ProbabilityCallback<T, N> for F where
-    F: FnMut(&Neighborhood<T>) -> [f32; N]
+    F: Fn(&Neighborhood<T>) -> [f32; N]
 {
 }
 
-type DefaultProbabilityCallback<T, const N: usize> = fn(&Neighborhood<T>) -> [f32; N];
+/// Type of the probability callback of default configurations, plain functions can be cast to
+/// it to name the type of a configuration, eg.
+/// `probability(adjacency as DefaultProbabilityCallback<Terrain, 3>)`.
+pub type DefaultProbabilityCallback<T, const N: usize> = fn(&Neighborhood<T>) -> [f32; N];
 
-// TODO: Consistent Lingo, over in map.rs we call these builders "settings"
-pub struct WaveFunctionCollapseConfiguration<T, F, const N: usize>
+/// Additional information for probability callbacks that need more than the neighborhood,
+/// see `WithContext`.
+pub struct ProbabilityContext<'a> {
+    /// Position of the cell whose probabilities are computed
+    pub position: UVec2,
+    /// Fraction of the area that is collapsed, in [0, 1]
+    pub progress: f32,
+    /// Random numbers, deterministic per seed and attempt
+    pub rng: &'a mut StdRng,
+    /// Guidance layers, see `WaveFunctionCollapseConfiguration::guidance`
+    pub layers: Option<&'a MapStack>,
+}
+
+impl ProbabilityContext<'_> {
+    /// Value of guidance layer `name` at the cell, `None` if there is no such layer (of
+    /// element type `V`).
+    pub fn value<V: Copy + 'static>(&self, name: &str) -> Option<V> {
+        self.layers?.get::<V>(name).map(|a| a[self.position.as_index2()])
+    }
+}
+
+/// Probability callback that also gets a `ProbabilityContext`.
+/// Implemented for all `ProbabilityCallback`s (which ignore the context) and for closures
+/// wrapped in `WithContext`.
+pub trait ContextProbabilityCallback<T: Tile, const N: usize> {
+    fn probabilities(&self, neighborhood: &Neighborhood<T>, context: &mut ProbabilityContext) -> [f32; N];
+
+    /// Check that the callback can be used for a map of size `size`, called by
+    /// `WaveFunctionCollapseConfiguration::validate`.
+    fn validate(&self, _size: UVec2) -> Result<(), MapgenError> {
+        Ok(())
+    }
+}
+
+impl<F, T, const N: usize> ContextProbabilityCallback<T, N> for F
 where
     F: ProbabilityCallback<T, N>,
+    T: Tile,
 {
-    // TODO: Consider builder pattern here rather than making these pub
-    pub seed: u64,
-    pub size: UVec2,
-    pub probability: F,
-
-    // TODO: Hide this again
-    pub _tile: PhantomData<T>,
+    fn probabilities(&self, neighborhood: &Neighborhood<T>, _context: &mut ProbabilityContext) -> [f32; N] {
+        self(neighborhood)
+    }
 }
 
-pub struct WaveFunctionCollapse<T, F, const N: usize>
+/// Wraps a closure `|neighborhood, context| -> [f32; N]` to use it as probability callback,
+/// eg. `.probability(WithContext(|n, ctx| ...))`.
+pub struct WithContext<F>(pub F);
+
+impl<F, T, const N: usize> ContextProbabilityCallback<T, N> for WithContext<F>
 where
-    F: ProbabilityCallback<T, N>,
+    F: Fn(&Neighborhood<T>, &mut ProbabilityContext) -> [f32; N],
     T: Tile,
 {
-    pub configuration: WaveFunctionCollapseConfiguration<T, F, N>,
-    pub tiles: Array2<T::Numeric>,
-    probabilities: Array3<f32>,
-    entropy: PriorityQueue<UVec2, FloatOrd<f32>>,
+    fn probabilities(&self, neighborhood: &Neighborhood<T>, context: &mut ProbabilityContext) -> [f32; N] {
+        (self.0)(neighborhood, context)
+    }
 }
 
-pub const NO_PROBABILITY: f32 = -1.0;
+/// Probability callback that gives all tiles the weight 1, the default inner callback of
+/// `GuidedWeights`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct UniformWeights;
 
-impl<T, F, const N: usize> WaveFunctionCollapse<T, F, N>
+impl<T: Tile, const N: usize> ContextProbabilityCallback<T, N> for UniformWeights {
+    fn probabilities(&self, _neighborhood: &Neighborhood<T>, _context: &mut ProbabilityContext) -> [f32; N] {
+        [1.0; N]
+    }
+}
+
+/// Probability callback that multiplies the weights of an inner callback by per tile guidance
+/// layers: the weight of tile `i` at `p` is scaled by `layers[i][p]`, eg. an elevation map for
+/// rock and snow tiles so that they are favored at high cells.
+/// The layers must have the size of the map (checked by
+/// `WaveFunctionCollapseConfiguration::validate`), negative values count as 0.
+///
+/// ```
+/// # use mapgen_2d::neighborhood::Neighborhood;
+/// # use mapgen_2d::wave_function_collapse::{GuidedWeights, WaveFunctionCollapseConfiguration};
+/// # use ndarray::Array2;
+/// # mapgen_2d::tile_enum! { enum Terrain { Lowland, Rock, Snow } }
+/// # let size = glam::uvec2(16, 12);
+/// # let elevation = Array2::from_shape_fn((16, 12), |(x, _)| x as f64 / 16.0);
+/// # let lowland = elevation.mapv(|e| 1.0 - e);
+/// // Neighboring tiles differ by at most one step
+/// let adjacency = |n: &Neighborhood<Terrain>| match n.range() {
+///     Some((lo, hi)) => [0, 1, 2].map(|i| (i + 1 >= hi as usize && i <= lo as usize + 1) as u8 as f32),
+///     None => [1.0; 3],
+/// };
+/// let guided = GuidedWeights::new([lowland, elevation.clone(), elevation]).inner(adjacency);
+/// let mut c = WaveFunctionCollapseConfiguration::default().probability(guided).max_bombings(100);
+/// c.size = size;
+/// let mut wfc = c.build();
+/// wfc.generate().unwrap();
+/// ```
+pub struct GuidedWeights<F, const N: usize> {
+    pub layers: [Array2<f64>; N],
+    pub inner: F,
+}
+
+impl<const N: usize> GuidedWeights<UniformWeights, N> {
+    pub fn new(layers: [Array2<f64>; N]) -> Self {
+        Self { layers, inner: UniformWeights }
+    }
+}
+
+impl<F, const N: usize> GuidedWeights<F, N> {
+    /// Scale the weights of `inner` instead of uniform weights.
+    pub fn inner<G>(self, inner: G) -> GuidedWeights<G, N> {
+        GuidedWeights { layers: self.layers, inner }
+    }
+}
+
+impl<F, T, const N: usize> ContextProbabilityCallback<T, N> for GuidedWeights<F, N>
 where
-    F: ProbabilityCallback<T, N>,
+    F: ContextProbabilityCallback<T, N>,
     T: Tile,
 {
+    fn probabilities(&self, neighborhood: &Neighborhood<T>, context: &mut ProbabilityContext) -> [f32; N] {
+        let mut ps = self.inner.probabilities(neighborhood, context);
+        for (p, layer) in ps.iter_mut().zip(self.layers.iter()) {
+            // Keep NO_PROBABILITY
+            if *p > 0.0 {
+                *p *= layer[context.position.as_index2()].max(0.0) as f32;
+            }
+        }
+        ps
+    }
 
-    pub fn generate(&mut self) {
-        let mut rng = rand::rngs::StdRng::seed_from_u64(self.configuration.seed);
+    fn validate(&self, size: UVec2) -> Result<(), MapgenError> {
+        if let Some((i, layer)) = self.layers.iter().enumerate().find(|(_, l)| map_size(l) != size) {
+            return Err(MapgenError::invalid(
+                "probability",
+                format!("guidance layer {} has size {}, the map has size {}", i, map_size(layer), size),
+            ));
+        }
+        self.inner.validate(size)
+    }
+}
 
-        // 1. compute all them probabilities
-        self.compute_probabilities();
+/// Tuples of tile types that can be collapsed together, see `WaveFunctionCollapse::layered`.
+pub trait Layers {
+    type Tile: Tile;
+}
 
-        // 2. compute all entropies, find max
-        self.compute_entropies();
+impl<A: Tile, B: Tile> Layers for (A, B) {
+    type Tile = Layered<A, B>;
+}
 
-        loop {
-            // 5. Find max entropy
-            let (target, _) = match self.entropy.pop() {
-                None => break, // done :)
-                Some(x) => x,
-            };
+/// Score of a cell in the entropy queue, highest is collapsed first.
+/// The second component breaks ties between equal scores.
+type Priority = (FloatOrd<f32>, u64);
+
+/// Heuristic for choosing the next cell to collapse.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(rename_all = "snake_case"))]
+pub enum CellSelection {
+    /// Cell with the highest entropy first. This was the behavior of earlier versions and
+    /// tends to produce noisier structures than `MinEntropy`.
+    MaxEntropy,
+    /// Cell with the lowest entropy first (the choice of reference WFC implementations).
+    /// This is the default.
+    MinEntropy,
+    /// Row by row, ignoring entropy
+    Scanline,
+    /// Lowest entropy first, ties are broken randomly (but deterministically per seed)
+    /// rather than in scanline order
+    RandomTies,
+    /// Lowest entropy first, penalized by `weight` per tile of distance from the map center,
+    /// which makes the map grow outwards from the center
+    CenterDistance { weight: f32 },
+}
 
-            // 3. Choose tile for target location
-            let mut p_sum = 0.0;
-            let roll = Uniform::<f32>::from(0.0..1.0).sample(&mut rng);
-            let mut tile = None;
-            for (i, p) in self.get_probabilities(target).iter().enumerate() {
-                p_sum += p;
-                if roll <= p_sum {
-                    // We shouldnt select a tile with zero probability, ever.
-                    assert!(*p != 0.0);
+/// Data structure for selecting the next cell to collapse.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EntropyQueue {
+    /// Exact ordering by score and tie breaker (default)
+    Exact,
+    /// Scores are quantized to steps of `resolution` (in bits of entropy), cells within the
+    /// same step are only ordered by their tie breaker. Considerably faster on large maps.
+    Bucketed { resolution: f32 },
+}
 
-                    tile = Some(i);
-                    break;
-                }
+/// Queue of cells that are still to be collapsed, see `EntropyQueue`
+#[derive(Clone)]
+enum CellQueue {
+    Exact(PriorityQueue<UVec2, Priority>),
+    Bucketed(BucketQueue<(u32, u32)>),
+}
+
+impl CellQueue {
+    fn new(kind: EntropyQueue) -> Self {
+        match kind {
+            EntropyQueue::Exact => CellQueue::Exact(PriorityQueue::new()),
+            EntropyQueue::Bucketed { resolution } => CellQueue::Bucketed(BucketQueue::new(resolution)),
+        }
+    }
+
+    /// Insert `pos` or update its priority
+    fn push(&mut self, pos: UVec2, priority: Priority) {
+        match self {
+            CellQueue::Exact(q) => {
+                q.push(pos, priority);
             }
+            CellQueue::Bucketed(q) => q.push((pos.x, pos.y), priority.0 .0, priority.1),
+        }
+    }
 
-            // 4. Set tile & update surroundings
-            match tile {
-                Some(t) => self.set_tile(target, t.into()),
-                None => {
-                    println!("p {:?}", self.get_probabilities(target));
-                    panic!();
+    /// Update the priority of `pos` if it is queued
+    fn change_priority(&mut self, pos: UVec2, priority: Priority) {
+        match self {
+            CellQueue::Exact(q) => {
+                q.change_priority(&pos, priority);
+            }
+            CellQueue::Bucketed(q) => {
+                if q.contains(&(pos.x, pos.y)) {
+                    q.push((pos.x, pos.y), priority.0 .0, priority.1);
                 }
             }
+        }
+    }
 
+    fn pop(&mut self) -> Option<UVec2> {
+        match self {
+            CellQueue::Exact(q) => q.pop().map(|(p, _)| p),
+            CellQueue::Bucketed(q) => q.pop().map(|(x, y)| uvec2(x, y)),
         }
     }
 
+    fn remove(&mut self, pos: UVec2) {
+        match self {
+            CellQueue::Exact(q) => {
+                q.remove(&pos);
+            }
+            CellQueue::Bucketed(q) => q.remove(&(pos.x, pos.y)),
+        }
+    }
 
-    fn set_tile(&mut self, pos: UVec2, tile: T) {
-        assert!(tile.is_valid());
-        assert!(!T::from(self.tiles[pos.as_index2()]).is_valid());
+    fn clear(&mut self) {
+        match self {
+            CellQueue::Exact(q) => q.clear(),
+            CellQueue::Bucketed(q) => q.clear(),
+        }
+    }
+}
 
-        self.tiles[pos.as_index2()] = tile.as_numeric();
+/// Precision of the per-cell tile probabilities kept during generation.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ProbabilityStorage {
+    #[default]
+    F32,
+    /// Quantized to 16 bit, which halves the memory needed for the probabilities (the
+    /// largest part of the generator state for large maps and many tiles).
+    U16,
+}
 
-        let neighborhood = Neighborhood::<T>::new(&self.tiles, pos.as_ivec2());
+/// Probabilities of all cells, see `ProbabilityStorage`
+enum Probabilities<const N: usize> {
+    F32(Array3<f32>),
+    U16(Array3<u16>),
+}
 
-        // We need to recompute probabilities & entropies for all neighbors
-        for neigh in neighborhood.iter_positions() {
-            if T::from(self.tiles[neigh.as_index2()]).is_valid() {
-                // We only care for invalid (== not-yet-determined) tiles
-                continue;
-            }
+impl<const N: usize> Clone for Probabilities<N> {
+    fn clone(&self) -> Self {
+        match self {
+            Probabilities::F32(a) => Probabilities::F32(a.clone()),
+            Probabilities::U16(a) => Probabilities::U16(a.clone()),
+        }
+    }
 
-            Self::compute_probability(neigh, &self.tiles, &mut self.configuration.probability, &mut self.probabilities);
-            Self::compute_entropy(neigh, &self.probabilities, &mut self.entropy);
+    fn clone_from(&mut self, source: &Self) {
+        match (self, source) {
+            (Probabilities::F32(a), Probabilities::F32(b)) => a.clone_from(b),
+            (Probabilities::U16(a), Probabilities::U16(b)) => a.clone_from(b),
+            (p, source) => *p = source.clone(),
         }
+    }
+}
 
-        // Probability for this field is 1.0 for the tile we set, 0 for everything else
-        let mut ps = self
-            .probabilities
-            .slice_mut(pos.as_slice3d());
-        ps.fill(0.0);
-        ps[tile.as_usize()] = 1.0;
+impl<const N: usize> Probabilities<N> {
+    fn new(storage: ProbabilityStorage, size: UVec2) -> Self {
+        match storage {
+            ProbabilityStorage::F32 => Probabilities::F32(Array3::from_elem(size.as_index3(N), NO_PROBABILITY)),
+            ProbabilityStorage::U16 => Probabilities::U16(Array3::zeros(size.as_index3(N))),
+        }
     }
 
-    fn get_probabilities(&self, pos: UVec2) -> ArrayBase<ViewRepr<&f32>, Ix1> {
-        self.probabilities.slice(pos.as_slice3d())
+    fn get(&self, pos: UVec2) -> [f32; N] {
+        let mut r = [0.0; N];
+        match self {
+            Probabilities::F32(a) => {
+                for (r, &p) in r.iter_mut().zip(a.slice(pos.as_slice3d())) {
+                    *r = p;
+                }
+            }
+            Probabilities::U16(a) => {
+                for (r, &p) in r.iter_mut().zip(a.slice(pos.as_slice3d())) {
+                    *r = p as f32 / u16::MAX as f32;
+                }
+            }
+        }
+        r
     }
 
-    fn compute_probabilities(&mut self) {
-        for ix in 0..self.configuration.size.x {
-            for iy in 0..self.configuration.size.y {
-                Self::compute_probability((ix, iy).as_uvec2(), &self.tiles, &mut self.configuration.probability, &mut self.probabilities);
+    fn set(&mut self, pos: UVec2, ps: &[f32]) {
+        match self {
+            Probabilities::F32(a) => a.slice_mut(pos.as_slice3d()).assign(&arr1(ps)),
+            Probabilities::U16(a) => {
+                for (q, &p) in a.slice_mut(pos.as_slice3d()).iter_mut().zip(ps) {
+                    *q = (p.clamp(0.0, 1.0) * u16::MAX as f32).round() as u16;
+                }
             }
         }
     }
 
-    fn compute_probability(pos: UVec2, tiles: &Array2<T::Numeric>, f: &mut F, probabilities: &mut Array3<f32>) {
-        let neighborhood = Neighborhood::new(tiles, pos.as_ivec2());
-        let ps = &((f)(&neighborhood));
+    /// Reset all probabilities to "not computed"
+    fn clear(&mut self) {
+        match self {
+            Probabilities::F32(a) => a.fill(NO_PROBABILITY),
+            Probabilities::U16(a) => a.fill(0),
+        }
+    }
+}
+
+/// How to recover from contradictions.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(rename_all = "snake_case"))]
+pub enum Strategy {
+    /// Reset ("bomb") a square area around the contradiction and continue from there.
+    /// The radius starts at 1 and doubles with every bombing that is required close to the
+    /// previous one.
+    /// Generation fails when more than `max_bombings` would be necessary.
+    Bomb,
 
-        let s: f32 = ps.iter().sum();
-        if ps[0] == NO_PROBABILITY || s <= 0.0 {
-            // TODO: if any(ps == NO_PROBABILITY) or all(ps == 0.0), backtrack!
-            todo!("Backtrack!");
+    /// Undo the most recent decisions (at most the last `max_depth` ones) and ban the choices
+    /// that led to the contradiction, so that only the conflicting part of the map is redone.
+    /// Falls back to bombing when undoing `max_depth` decisions is not enough.
+    Backtrack { max_depth: usize },
+}
+
+/// An area that was reset to resolve a contradiction (a cube for `WaveFunctionCollapse3`).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Bombing<P = UVec2> {
+    pub center: P,
+    pub radius: u32,
+}
+
+/// Condition for one edge of the map.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Border<T> {
+    /// No constraint, neighborhoods just see fewer cells at the border (default)
+    Any,
+    /// All cells along the edge get this tile (unless preset otherwise)
+    Tile(T),
+    /// Neighborhoods wrap around to the opposite edge, which makes the map tileable along
+    /// this axis. Applies to both opposite edges, mark both for clarity.
+    Wrap,
+}
+
+/// Global limit for the number of cells with a specific tile, see
+/// `WaveFunctionCollapseConfiguration::quota`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TileQuota {
+    /// Index of the tile (`Tile::as_usize`)
+    pub tile: usize,
+    pub min: usize,
+    pub max: usize,
+}
+
+/// State of a cell before it was modified by a decision
+#[derive(Clone)]
+struct CellChange<N> {
+    pos: UVec2,
+    tile: N,
+    probabilities: Vec<f32>,
+    banned: Vec<bool>,
+}
+
+/// A collapsed cell along with everything that changed due to it
+#[derive(Clone)]
+struct Decision<N> {
+    target: UVec2,
+    tile: usize,
+    changes: Vec<CellChange<N>>,
+}
+
+// TODO: Consistent Lingo, over in map.rs we call these builders "settings"
+pub struct WaveFunctionCollapseConfiguration<T, F, const N: usize>
+where
+    F: ContextProbabilityCallback<T, N>,
+    T: Tile,
+{
+    // TODO: Consider builder pattern here rather than making these pub
+    pub seed: u64,
+    pub size: UVec2,
+    pub probability: F,
+
+    /// If set, only the fundamental domain of the symmetry is collapsed, every decision is
+    /// mirrored onto the rest of the orbit so that neighborhoods along the symmetry axes see
+    /// consistent values.
+    /// Note that the probability callback should itself be invariant under the symmetry
+    /// (eg. not depend on directions that get swapped by mirroring).
+    pub symmetry: Option<Symmetry>,
+
+    /// Only collapse cells inside this area (default: the whole map).
+    /// Cells outside of the area are left as they are (see `WaveFunctionCollapse::preset`) and
+    /// act as fixed boundary conditions. With a symmetry, the symmetry is relative to this
+    /// area.
+    pub area: Option<Rect>,
+
+    /// Radius of the neighborhood passed to the probability callback (default: 1)
+    pub neighborhood_size: u32,
+
+    /// Metric that determines the shape of the neighborhood, together with
+    /// `neighborhood_size` (default: `neighborhood::manhattan`, ie. the 4 direct neighbors)
+    pub metric: Metric,
+
+    pub cell_selection: CellSelection,
+
+    pub entropy_queue: EntropyQueue,
+
+    pub probability_storage: ProbabilityStorage,
+
+    /// Amplitude of a random offset (fixed per cell and seed) added to every cell's entropy,
+    /// so that cells with equal entropy are not always selected in the same order.
+    /// 0.0 disables jitter.
+    pub entropy_jitter: f32,
+
+    /// How often to retry (with derived seeds) after a contradiction could not be resolved.
+    pub retries: u32,
+
+    pub strategy: Strategy,
+
+    /// Maximum number of bombings per attempt, see `Strategy`
+    pub max_bombings: u32,
+
+    /// Global tile count limits, see `quota`
+    pub quotas: Vec<TileQuota>,
+
+    /// Indices of tiles that must form a single 4-connected component, see `connected`
+    pub connected: Vec<usize>,
+
+    /// Conditions for the north (y = 0), east, south and west edge of the map (indexed by
+    /// `Direction::index`), see `Border`
+    pub borders: [Border<T>; 4],
+
+    /// Layers passed to the probability callback via `ProbabilityContext`, eg. a heightmap
+    /// to vary constraints across the map. Must have the size of the map.
+    pub guidance: Option<MapStack>,
+
+    /// Use fixed-point arithmetic for entropies and tile selection, see `deterministic`
+    pub deterministic: bool,
+
+    /// Record the chosen probability and the order of every collapse, see `record_collapses`
+    pub record_collapses: bool,
+
+    // TODO: Hide this again
+    pub _tile: PhantomData<T>,
+}
+
+/// Side outputs of the collapses, see `WaveFunctionCollapseConfiguration::record_collapses`
+#[derive(Clone)]
+struct CollapseRecord {
+    probabilities: Array2<f32>,
+    order: Array2<u32>,
+    /// Index of the next collapse in the current attempt
+    next: u32,
+}
+
+impl CollapseRecord {
+    fn new(size: UVec2) -> Self {
+        Self {
+            probabilities: Array2::from_elem(size.as_index2(), NO_PROBABILITY),
+            order: Array2::from_elem(size.as_index2(), NOT_COLLAPSED),
+            next: 0,
         }
+    }
 
-        let ps = ps.map(|p| p / s);
-        probabilities
-            .slice_mut(pos.as_slice3d())
-            .assign(&arr1(&ps));
+    fn clear(&mut self, pos: UVec2) {
+        self.probabilities[pos.as_index2()] = NO_PROBABILITY;
+        self.order[pos.as_index2()] = NOT_COLLAPSED;
     }
+}
 
-    fn compute_entropies(&mut self) {
-        for ix in 0..self.configuration.size.x {
-            for iy in 0..self.configuration.size.y {
-                let idx = (ix, iy).as_index2();
-                let ps = self.probabilities.slice(idx.as_slice3d());
-                let e = -ps.mapv(|p| if p == 0.0 { 0.0 } else { p * p.log2() }).sum();
-                self.entropy.push((ix, iy).as_uvec2(), FloatOrd(e));
-            } // for iy
-        } // for ix
+/// State of a generation. Only reads its configuration, so several generators can share one
+/// configuration (see `new`), eg. to generate maps for different seeds concurrently. The
+/// generator is `Send` if the configuration is `Send + Sync`.
+pub struct WaveFunctionCollapse<T, F, const N: usize>
+where
+    F: ContextProbabilityCallback<T, N>,
+    T: Tile,
+{
+    pub configuration: Arc<WaveFunctionCollapseConfiguration<T, F, N>>,
+    pub tiles: Array2<T::Numeric>,
+    probabilities: Probabilities<N>,
+    entropy: CellQueue,
+    tie_breakers: Array2<u64>,
+    jitter: Array2<f32>,
+    contradictions: Array2<u32>,
+    attempts: Vec<Attempt>,
+
+    /// Entropy per cell, kept up to date with `probabilities`
+    entropies: Array2<f32>,
+
+    /// Tiles to start each attempt with
+    initial: Array2<T::Numeric>,
+
+    /// Tiles ruled out by backtracking, per cell
+    banned: Array3<bool>,
+    trail: VecDeque<Decision<T::Numeric>>,
+    backtracks: usize,
+    bombings: Vec<Bombing>,
+    attempt_bombings: u32,
+    report: GenerationReport,
+    /// Passed to the probability callback, reseeded with every attempt
+    callback_rng: StdRng,
+    /// Chooses the tiles, kept between `step` calls
+    rng: StdRng,
+
+    /// Number of cells per tile in the area, for quotas
+    counts: Vec<usize>,
+    /// Number of cells in the area that are not collapsed yet
+    remaining: usize,
+
+    collapse_record: Option<CollapseRecord>,
+}
+
+// Not derived, which would require `F: Clone`
+impl<T, F, const N: usize> Clone for WaveFunctionCollapse<T, F, N>
+where
+    F: ContextProbabilityCallback<T, N>,
+    T: Tile,
+{
+    fn clone(&self) -> Self {
+        Self {
+            configuration: self.configuration.clone(),
+            tiles: self.tiles.clone(),
+            probabilities: self.probabilities.clone(),
+            entropy: self.entropy.clone(),
+            tie_breakers: self.tie_breakers.clone(),
+            jitter: self.jitter.clone(),
+            contradictions: self.contradictions.clone(),
+            attempts: self.attempts.clone(),
+            entropies: self.entropies.clone(),
+            initial: self.initial.clone(),
+            banned: self.banned.clone(),
+            trail: self.trail.clone(),
+            backtracks: self.backtracks,
+            bombings: self.bombings.clone(),
+            attempt_bombings: self.attempt_bombings,
+            report: self.report.clone(),
+            callback_rng: self.callback_rng.clone(),
+            rng: self.rng.clone(),
+            counts: self.counts.clone(),
+            remaining: self.remaining,
+            collapse_record: self.collapse_record.clone(),
+        }
     }
 
-    fn compute_entropy(pos: UVec2, probabilities: &Array3<f32>, entropy: &mut PriorityQueue<UVec2, FloatOrd<f32>>) {
-        let ps = probabilities.slice(pos.as_slice3d());
-        let e = -ps.mapv(|p| if p == 0.0 { 0.0 } else { p * p.log2() }).sum();
-        entropy.change_priority(&pos, FloatOrd(e));
+    /// Reuses the buffers of `self`, see `restore`
+    fn clone_from(&mut self, source: &Self) {
+        self.configuration.clone_from(&source.configuration);
+        self.tiles.clone_from(&source.tiles);
+        self.probabilities.clone_from(&source.probabilities);
+        self.entropy.clone_from(&source.entropy);
+        self.tie_breakers.clone_from(&source.tie_breakers);
+        self.jitter.clone_from(&source.jitter);
+        self.contradictions.clone_from(&source.contradictions);
+        self.attempts.clone_from(&source.attempts);
+        self.entropies.clone_from(&source.entropies);
+        self.initial.clone_from(&source.initial);
+        self.banned.clone_from(&source.banned);
+        self.trail.clone_from(&source.trail);
+        self.backtracks = source.backtracks;
+        self.bombings.clone_from(&source.bombings);
+        self.attempt_bombings = source.attempt_bombings;
+        self.report.clone_from(&source.report);
+        self.callback_rng.clone_from(&source.callback_rng);
+        self.rng.clone_from(&source.rng);
+        self.counts.clone_from(&source.counts);
+        self.remaining = source.remaining;
+        self.collapse_record.clone_from(&source.collapse_record);
     }
 }
 
-impl<T, F, const N: usize> WaveFunctionCollapseConfiguration<T, F, N>
+/// State of a `WaveFunctionCollapse`, see `WaveFunctionCollapse::snapshot`.
+pub struct Snapshot<T, F, const N: usize>
 where
-    F: ProbabilityCallback<T, N>,
+    F: ContextProbabilityCallback<T, N>,
     T: Tile,
 {
-    pub fn build(self) -> WaveFunctionCollapse<T, F, N> {
-        WaveFunctionCollapse {
-            tiles: Array2::from_elem(self.size.as_index2(), T::invalid().as_numeric()),
-            entropy: Default::default(),
-            probabilities: Array3::from_elem(self.size.as_index3(N), NO_PROBABILITY),
-            configuration: self,
+    state: WaveFunctionCollapse<T, F, N>,
+}
+
+pub const NO_PROBABILITY: f32 = -1.0;
+
+/// Collapse order of cells that were not collapsed by the generator, see
+/// `WaveFunctionCollapse::collapse_order`
+pub const NOT_COLLAPSED: u32 = u32::MAX;
+
+/// The probability callback left no possible tile for the cell at `position` (a `UVec3` for
+/// `WaveFunctionCollapse3`).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Contradiction<P = UVec2> {
+    pub position: P,
+}
+
+impl<P: fmt::Display> fmt::Display for Contradiction<P> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "no possible tile at {}", self.position)
+    }
+}
+
+impl<P: fmt::Debug + fmt::Display> std::error::Error for Contradiction<P> {}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WfcError {
+    /// Generation failed (after all retries)
+    Contradiction(Contradiction),
+    /// The map or the area to generate has a size of zero
+    EmptyArea,
+    /// The area to generate is not fully inside the map
+    AreaOutOfBounds { area: Rect, size: UVec2 },
+    /// The symmetry can not be applied to the area, eg. `Rotate4` on a non-square area
+    InvalidSymmetry(Symmetry),
+    /// `WaveFunctionCollapse3` failed (after all retries)
+    Contradiction3(Contradiction<UVec3>),
+    /// The area of a `WaveFunctionCollapse3` is not fully inside the map
+    AreaOutOfBounds3 { area: Rect3, size: UVec3 },
+}
+
+impl fmt::Display for WfcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WfcError::Contradiction(c) => c.fmt(f),
+            WfcError::EmptyArea => write!(f, "area to generate is empty"),
+            WfcError::AreaOutOfBounds { area, size } => {
+                write!(f, "area {:?} is not inside map of size {}", area, size)
+            }
+            WfcError::InvalidSymmetry(s) => write!(f, "symmetry {:?} can not be applied to area", s),
+            WfcError::Contradiction3(c) => c.fmt(f),
+            WfcError::AreaOutOfBounds3 { area, size } => {
+                write!(f, "area {:?} is not inside map of size {}", area, size)
+            }
         }
     }
 }
 
-impl<T, const N: usize> Default
-    for WaveFunctionCollapseConfiguration<T, DefaultProbabilityCallback<T, N>, N>
+impl std::error::Error for WfcError {}
+
+impl From<Contradiction> for WfcError {
+    fn from(c: Contradiction) -> Self {
+        WfcError::Contradiction(c)
+    }
+}
+
+/// Record of one generation attempt.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Attempt<P = UVec2> {
+    pub seed: u64,
+    /// `None` if the attempt succeeded
+    pub contradiction: Option<Contradiction<P>>,
+}
+
+impl<T, F, const N: usize> WaveFunctionCollapse<T, F, N>
 where
+    F: ContextProbabilityCallback<T, N>,
     T: Tile,
 {
-    fn default() -> Self {
-        Self {
-            seed: 0_u64,
-            size: uvec2(100, 100),
-            probability: |_| [0.0_f32; N],
-            _tile: Default::default(),
+
+    /// Collapse all cells (in the configured area).
+    /// On a contradiction, generation is restarted with a derived seed up to
+    /// `configuration.retries` times (see `attempts`).
+    /// If all attempts fail, the last contradiction is returned and the partially collapsed
+    /// state of the last attempt is left for inspection (see `entropy_map` and
+    /// `contradictions`).
+    pub fn generate(&mut self) -> Result<(), WfcError> {
+        self.prepare()?;
+        let mut result = Ok(());
+        for attempt in 0..=self.configuration.retries {
+            let seed = match attempt {
+                0 => self.configuration.seed,
+                n => derive_seed(self.configuration.seed, n as u64),
+            };
+
+            self.reset();
+            result = self.generate_attempt(seed);
+            self.attempts.push(Attempt { seed, contradiction: result.err() });
+            if result.is_ok() {
+                break;
+            }
+        }
+        self.report.contradictions = self.contradictions.iter().map(|&c| c as usize).sum();
+        self.report.bombings = self.bombings.len();
+        result.map_err(WfcError::from)
+    }
+
+    /// Like `generate`, but checks the configuration first (see
+    /// `WaveFunctionCollapseConfiguration::validate`) and reports all failures as
+    /// `MapgenError`, contradictions as `MapgenError::Wfc`.
+    pub fn try_generate(&mut self) -> Result<(), MapgenError> {
+        self.configuration.validate()?;
+        Ok(self.generate()?)
+    }
+
+    /// Start a step-wise generation with `configuration.seed` (retries are not supported):
+    /// computes the probabilities of all cells, which are then collapsed one by one with
+    /// `step`. Together with `snapshot` and `restore` this allows speculative generation, eg.
+    /// collapsing a few cells and reverting them.
+    /// Stepping to the end gives the same map as the first attempt of `generate`.
+    pub fn start(&mut self) -> Result<(), WfcError> {
+        self.prepare()?;
+        self.reset();
+        let seed = self.configuration.seed;
+        let result = self.start_attempt(seed);
+        self.attempts.push(Attempt { seed, contradiction: result.err() });
+        result.map_err(WfcError::from)
+    }
+
+    /// Collapse the next cell (after `start`) and return its position, contradictions are
+    /// resolved according to the configured strategy. Returns `None` once all cells are
+    /// collapsed, or a contradiction that could not be resolved (at the area anchor if the
+    /// finished map misses quotas or connectivity).
+    pub fn step(&mut self) -> Result<Option<UVec2>, Contradiction> {
+        match self.collapse_next()? {
+            Some(p) => Ok(Some(p)),
+            None => self.finish(self.area().anchor).map(|_| None),
+        }
+    }
+
+    /// Copy of the current state, see `restore`.
+    /// Only the configuration is shared, all buffers (tiles, probabilities, entropies, queue,
+    /// ...) are copied, so a snapshot takes as much time and memory as cloning the generator.
+    pub fn snapshot(&self) -> Snapshot<T, F, N> {
+        Snapshot { state: self.clone() }
+    }
+
+    /// Return to the state of `snapshot`. Copies the state into the existing buffers, the
+    /// probability callback is not called.
+    pub fn restore(&mut self, snapshot: &Snapshot<T, F, N>) {
+        self.clone_from(&snapshot.state);
+    }
+
+    /// Checks and setup shared by `generate` and `start`
+    fn prepare(&mut self) -> Result<(), WfcError> {
+        self.check_configuration()?;
+        self.preset_borders();
+        self.attempts.clear();
+        self.bombings.clear();
+        self.report = GenerationReport::default();
+        Ok(())
+    }
+
+    /// Fix the tile at `pos` before generation. Preset tiles are never changed by
+    /// `generate` and act as constraints for their surroundings.
+    /// Inside the area, the tile is also set at all positions `pos` is mapped to by the
+    /// configured symmetry.
+    /// Panics if `pos` is outside the map.
+    pub fn preset(&mut self, pos: UVec2, tile: T) {
+        let positions = if self.check_configuration().is_ok() && self.area().contains(pos) {
+            self.orbit(pos)
+        } else {
+            vec![pos]
+        };
+        for p in positions {
+            self.initial[p.as_index2()] = tile.as_numeric();
+            self.tiles[p.as_index2()] = tile.as_numeric();
+        }
+    }
+
+    /// Preset the cells along edges with `Border::Tile`, except for cells that already are.
+    fn preset_borders(&mut self) {
+        let size = self.configuration.size;
+        let map = Rect::from_size(size);
+        let borders = self.configuration.borders;
+        for (i, border) in borders.iter().enumerate() {
+            let tile = match border {
+                Border::Tile(t) => *t,
+                _ => continue,
+            };
+            let edge: Vec<UVec2> = map
+                .iter()
+                .filter(|p| match i {
+                    0 => p.y == 0,
+                    1 => p.x + 1 == size.x,
+                    2 => p.y + 1 == size.y,
+                    _ => p.x == 0,
+                })
+                .collect();
+            for p in edge {
+                if !T::from(self.initial[p.as_index2()]).is_valid() {
+                    self.preset(p, tile);
+                }
+            }
         }
     }
+
+    /// Axes along which neighborhoods wrap around the map
+    fn wrap(&self) -> BVec2 {
+        let b = &self.configuration.borders;
+        BVec2::new(b[1] == Border::Wrap || b[3] == Border::Wrap, b[0] == Border::Wrap || b[2] == Border::Wrap)
+    }
+
+    fn check_configuration(&self) -> Result<(), WfcError> {
+        self.configuration.check_area()
+    }
+
+    /// The area to collapse
+    fn area(&self) -> Rect {
+        self.configuration.effective_area()
+    }
+
+    /// All cells that are mapped onto `pos` by the configured symmetry (including `pos`).
+    fn orbit(&self, pos: UVec2) -> Vec<UVec2> {
+        match self.configuration.symmetry {
+            Some(symmetry) => {
+                let area = self.area();
+                symmetry
+                    .orbit(pos - area.anchor, area.size)
+                    .into_iter()
+                    .map(|p| p + area.anchor)
+                    .collect()
+            }
+            None => vec![pos],
+        }
+    }
+
+    /// All attempts made by the last call to `generate`, the last one is the one whose
+    /// result is in `tiles`.
+    pub fn attempts(&self) -> &[Attempt] {
+        &self.attempts
+    }
+
+    /// Timings and counters of the last `generate` call.
+    /// `cells_processed` counts every collapse, including ones later undone by backtracking
+    /// or bombing.
+    pub fn report(&self) -> &GenerationReport {
+        &self.report
+    }
+
+    /// All bombings done by the last call to `generate`, over all attempts.
+    pub fn bombings(&self) -> &[Bombing] {
+        &self.bombings
+    }
+
+    fn reset(&mut self) {
+        self.tiles.assign(&self.initial);
+        self.count_tiles();
+        self.probabilities.clear();
+        self.entropies.fill(0.0);
+        self.banned.fill(false);
+        self.entropy.clear();
+        self.trail.clear();
+        self.backtracks = 0;
+        self.attempt_bombings = 0;
+        if let Some(record) = self.collapse_record.as_mut() {
+            *record = CollapseRecord::new(self.configuration.size);
+        }
+    }
+
+    /// Recount `counts` and `remaining` from scratch
+    fn count_tiles(&mut self) {
+        self.counts = vec![0; N];
+        self.remaining = 0;
+        for p in self.area().iter() {
+            let tile = T::from(self.tiles[p.as_index2()]);
+            match tile.is_valid() {
+                true => self.counts[tile.as_usize()] += 1,
+                false => self.remaining += 1,
+            }
+        }
+    }
+
+    /// Set cell `pos` to `value`, keeping the tile counts up to date
+    fn assign(&mut self, pos: UVec2, value: T::Numeric) {
+        if self.area().contains(pos) {
+            let old = T::from(self.tiles[pos.as_index2()]);
+            if old.is_valid() {
+                self.counts[old.as_usize()] -= 1;
+                self.remaining += 1;
+            }
+            let new = T::from(value);
+            if new.is_valid() {
+                self.counts[new.as_usize()] += 1;
+                self.remaining -= 1;
+            }
+        }
+        if let Some(record) = self.collapse_record.as_mut() {
+            if !T::from(value).is_valid() {
+                record.clear(pos);
+            }
+        }
+        self.tiles[pos.as_index2()] = value;
+    }
+
+    /// Adjust the probabilities `ps` for collapsing `target` to the configured quotas: tiles
+    /// that reached their maximum are ruled out, and once the remaining cells are just enough to
+    /// reach all minimums, only tiles below their minimum are allowed (if possible at all).
+    fn apply_quotas(&self, target: UVec2, ps: &mut [f32; N]) {
+        let quotas = &self.configuration.quotas;
+        if quotas.is_empty() {
+            return;
+        }
+
+        let cells = self.orbit(target).len();
+        for q in quotas {
+            if self.counts[q.tile] + cells > q.max {
+                ps[q.tile] = 0.0;
+            }
+        }
+
+        let deficit: usize = quotas.iter().map(|q| q.min.saturating_sub(self.counts[q.tile])).sum();
+        if deficit > 0 && deficit >= self.remaining {
+            let mut needed = [false; N];
+            for q in quotas.iter().filter(|q| self.counts[q.tile] < q.min) {
+                needed[q.tile] = true;
+            }
+            if ps.iter().zip(needed).any(|(&p, n)| n && p > 0.0) {
+                for (p, n) in ps.iter_mut().zip(needed) {
+                    if !n {
+                        *p = 0.0;
+                    }
+                }
+            }
+        }
+
+        let s: f32 = ps.iter().sum();
+        if s > 0.0 {
+            for p in ps.iter_mut() {
+                *p /= s;
+            }
+        }
+    }
+
+    /// Rule out choices for `target` that would break the connectivity constraint (see
+    /// `WaveFunctionCollapseConfiguration::connected`).
+    fn apply_connectivity(&self, target: UVec2, ps: &mut [f32; N]) {
+        let connected = &self.configuration.connected;
+        if connected.is_empty() {
+            return;
+        }
+
+        let cells = self.orbit(target);
+        let open = connected.iter().any(|&t| ps[t] > 0.0) && self.is_connected(&cells, true);
+        let closed = ps.iter().enumerate().any(|(t, &p)| p > 0.0 && !connected.contains(&t))
+            && self.is_connected(&cells, false);
+        for (t, p) in ps.iter_mut().enumerate() {
+            let allowed = match connected.contains(&t) {
+                true => open,
+                false => closed,
+            };
+            if !allowed {
+                *p = 0.0;
+            }
+        }
+
+        let s: f32 = ps.iter().sum();
+        if s > 0.0 {
+            for p in ps.iter_mut() {
+                *p /= s;
+            }
+        }
+    }
+
+    /// True iff all cells that have a connected tile (after setting `cells` to an open or a
+    /// closed tile) are 4-connected through cells that have or may still get a connected tile.
+    fn is_connected(&self, cells: &[UVec2], open: bool) -> bool {
+        let connected = &self.configuration.connected;
+        let size = self.configuration.size;
+        if connected.is_empty() {
+            return true;
+        }
+
+        // 0 = closed, 1 = may become open, 2 = open
+        let state = |p: UVec2| -> u8 {
+            if cells.contains(&p) {
+                return if open { 2 } else { 0 };
+            }
+            let tile = T::from(self.tiles[p.as_index2()]);
+            if tile.is_valid() {
+                return if connected.contains(&tile.as_usize()) { 2 } else { 0 };
+            }
+            let ps = self.probabilities.get(p);
+            match self.area().contains(p) && connected.iter().any(|&t| ps[t] > 0.0) {
+                true => 1,
+                false => 0,
+            }
+        };
+
+        // Closing a cell next to at most one potentially open cell can not split anything,
+        // neither can opening a cell next to an open one
+        if cells.len() == 1 {
+            let neighbors: Vec<u8> = neighborhood::offsets(1, neighborhood::manhattan)
+                .map(|o| cells[0].as_ivec2() + o)
+                .filter(|q| q.cmpge(IVec2::ZERO).all() && q.cmplt(size.as_ivec2()).all())
+                .map(|q| state(q.as_uvec2()))
+                .collect();
+            let trivial = match open {
+                true => neighbors.contains(&2),
+                false => neighbors.iter().filter(|&&s| s > 0).count() <= 1,
+            };
+            if trivial {
+                return true;
+            }
+        }
+
+        let states = Array2::from_shape_fn(size.as_index2(), |(x, y)| state(uvec2(x as u32, y as u32)));
+        let open_cells = states.iter().filter(|&&s| s == 2).count();
+        let start = match states.indexed_iter().find(|(_, &s)| s == 2) {
+            Some(((x, y), _)) => uvec2(x as u32, y as u32),
+            None => return true,
+        };
+
+        let mut visited = Array2::from_elem(size.as_index2(), false);
+        visited[start.as_index2()] = true;
+        let mut stack = vec![start];
+        let mut reached = 0;
+        let map = IRect::from(Rect::from_size(size));
+        while let Some(p) = stack.pop() {
+            if states[p.as_index2()] == 2 {
+                reached += 1;
+            }
+            for o in neighborhood::offsets(1, neighborhood::manhattan) {
+                let q = p.as_ivec2() + o;
+                if !map.contains(q) {
+                    continue;
+                }
+                let q = q.as_uvec2();
+                if !visited[q.as_index2()] && states[q.as_index2()] > 0 {
+                    visited[q.as_index2()] = true;
+                    stack.push(q);
+                }
+            }
+        }
+        reached == open_cells
+    }
+
+    fn generate_attempt(&mut self, seed: u64) -> Result<(), Contradiction> {
+        self.start_attempt(seed)?;
+
+        let start = Instant::now();
+        let result = self.collapse();
+        self.report.add_time("collapse", start.elapsed());
+        result
+    }
+
+    /// Compute probabilities, selection noise and entropies for a new attempt
+    fn start_attempt(&mut self, seed: u64) -> Result<(), Contradiction> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        self.callback_rng = StdRng::seed_from_u64(derive_seed(seed, 0));
+
+        // 1. compute all them probabilities
+        let start = Instant::now();
+        let probabilities = self.compute_probabilities();
+        self.report.add_time("probabilities", start.elapsed());
+        probabilities?;
+
+        self.compute_selection_noise(&mut rng);
+
+        // 2. compute all entropies
+        let start = Instant::now();
+        self.compute_entropies();
+        self.report.add_time("entropies", start.elapsed());
+
+        self.rng = rng;
+        Ok(())
+    }
+
+    fn collapse(&mut self) -> Result<(), Contradiction> {
+        let mut last = self.area().anchor;
+        while let Some(target) = self.collapse_next()? {
+            last = target;
+        }
+        self.finish(last)
+    }
+
+    /// Collapse the next cell and return its position, `None` if there is none left
+    fn collapse_next(&mut self) -> Result<Option<UVec2>, Contradiction> {
+        // 5. Find next cell according to selection heuristic
+        let target = match self.entropy.pop() {
+            None => return Ok(None), // done :)
+            Some(x) => x,
+        };
+
+        // 3. Choose tile for target location
+        let mut ps = self.probabilities.get(target);
+        self.apply_quotas(target, &mut ps);
+        self.apply_connectivity(target, &mut ps);
+        let tile = match self.configuration.deterministic {
+            true => choose_fixed(&ps, &mut self.rng),
+            false => choose(&ps, &mut self.rng),
+        };
+
+        // 4. Set tile & update surroundings
+        let result = match tile {
+            Some(t) => {
+                self.report.cells_processed += 1;
+                self.begin_decision(target, t);
+                let probability = ps[t] / ps.iter().sum::<f32>();
+                let result = self.set_tile(target, t.into(), probability);
+                if let Some(record) = self.collapse_record.as_mut() {
+                    record.next += 1;
+                }
+                result
+            }
+            None => {
+                self.refresh_queue(target);
+                Err(self.contradiction(target))
+            }
+        };
+
+        if let Err(c) = result {
+            self.resolve(c)?;
+        }
+        Ok(Some(target))
+    }
+
+    /// Check the constraints that can only be checked on the finished map
+    fn finish(&mut self, last: UVec2) -> Result<(), Contradiction> {
+        // Minimums can still be missed if the needed tiles were not possible at the last cells,
+        // and propagation can cut off open cells
+        let quotas_met = self.configuration.quotas.iter().all(|q| self.counts[q.tile] >= q.min);
+        match quotas_met && self.is_connected(&[], false) {
+            true => Ok(()),
+            false => Err(self.contradiction(last)),
+        }
+    }
+
+    fn begin_decision(&mut self, target: UVec2, tile: usize) {
+        if let Strategy::Backtrack { max_depth } = self.configuration.strategy {
+            self.trail.push_back(Decision { target, tile, changes: Vec::new() });
+            while self.trail.len() > max_depth {
+                self.trail.pop_front();
+            }
+        }
+    }
+
+    /// Remember the state of cell `pos` before it gets changed by the current decision.
+    fn record(&mut self, pos: UVec2) {
+        if let Some(decision) = self.trail.back_mut() {
+            decision.changes.push(CellChange {
+                pos,
+                tile: self.tiles[pos.as_index2()],
+                probabilities: self.probabilities.get(pos).to_vec(),
+                banned: self.banned.slice(pos.as_slice3d()).to_vec(),
+            });
+        }
+    }
+
+    fn undo(&mut self, decision: Decision<T::Numeric>) {
+        for change in decision.changes.iter().rev() {
+            self.assign(change.pos, change.tile);
+            self.probabilities.set(change.pos, &change.probabilities);
+            self.banned
+                .slice_mut(change.pos.as_slice3d())
+                .assign(&arr1(&change.banned));
+            self.entropies[change.pos.as_index2()] = self.entropy_of(&change.probabilities);
+        }
+        for change in decision.changes.iter() {
+            self.refresh_queue(change.pos);
+        }
+    }
+
+    /// Rule out `tile` for cell `pos`. Returns false if no possible tile is left.
+    fn ban(&mut self, pos: UVec2, tile: usize) -> bool {
+        self.record(pos);
+        self.banned[pos.as_index3(tile)] = true;
+
+        let mut ps = self.probabilities.get(pos);
+        let p = ps[tile];
+        ps[tile] = 0.0;
+        let s: f32 = ps.iter().sum();
+        if s <= 0.0 {
+            return false;
+        }
+        self.probabilities.set(pos, &ps.map(|p| p / s));
+
+        // Renormalizing after removing p:
+        // H' = (H + p * log2(p)) / (1 - p) + log2(1 - p)
+        if self.configuration.deterministic {
+            self.entropies[pos.as_index2()] = self.entropy_of(&ps.map(|p| p / s));
+        } else if p > 0.0 {
+            let h = &mut self.entropies[pos.as_index2()];
+            *h = ((*h + p * p.log2()) / s + s.log2()).max(0.0);
+        }
+        self.refresh_queue(pos);
+        true
+    }
+
+    /// Try to recover from contradiction `c` according to the configured strategy.
+    fn resolve(&mut self, c: Contradiction) -> Result<(), Contradiction> {
+        let mut position = c.position;
+        if let Strategy::Backtrack { .. } = self.configuration.strategy {
+            match self.backtrack() {
+                Ok(()) => return Ok(()),
+                Err(Some(p)) => position = p,
+                Err(None) => {}
+            }
+        }
+        self.bomb(position)
+    }
+
+    /// Undo decisions until one of them can be replaced by an alternative choice.
+    /// If that is not possible, returns the position of the last undone decision (if any).
+    fn backtrack(&mut self) -> Result<(), Option<UVec2>> {
+        let limit = match self.configuration.strategy {
+            Strategy::Backtrack { max_depth } => max_depth.max(1) * self.tiles.len(),
+            Strategy::Bomb => 0,
+        };
+
+        let mut position = None;
+        while let Some(decision) = self.trail.pop_back() {
+            self.backtracks += 1;
+            if self.backtracks > limit {
+                // Safety net against backtracking forever
+                self.trail.clear();
+                break;
+            }
+
+            let (target, tile) = (decision.target, decision.tile);
+            self.undo(decision);
+            if self.ban(target, tile) {
+                return Ok(());
+            }
+            self.contradiction(target);
+            position = Some(target);
+        }
+
+        Err(position)
+    }
+
+    /// Reset cells in an area around `center`, growing the area as long as the reset cells
+    /// can not be assigned possible tiles.
+    fn bomb(&mut self, mut center: UVec2) -> Result<(), Contradiction> {
+        let bounds = self.area();
+
+        loop {
+            if self.attempt_bombings >= self.configuration.max_bombings {
+                return Err(Contradiction { position: center });
+            }
+            // Grow the radius while contradictions keep coming up in the same area
+            let radius = match self.bombings.last() {
+                Some(last) if self.attempt_bombings > 0 && Self::near(last, center) => {
+                    (last.radius * 2).min(bounds.size.max_element())
+                }
+                _ => 1,
+            };
+            self.attempt_bombings += 1;
+            self.bombings.push(Bombing { center, radius });
+
+            // Bombing changes cells arbitrarily far back in history
+            self.trail.clear();
+
+            let area = match IRect::around(center.as_ivec2(), radius).clamp(&bounds) {
+                Some(area) => area,
+                // Only if `center` is outside of the area to collapse
+                None => return Err(Contradiction { position: center }),
+            };
+
+            let mut reset = Vec::new();
+            for p in area.iter() {
+                reset.extend(self.orbit(p));
+            }
+
+            for &p in reset.iter() {
+                self.assign(p, self.initial[p.as_index2()]);
+                self.banned.slice_mut(p.as_slice3d()).fill(false);
+            }
+
+            // Reset cells and all cells that see them need new probabilities
+            let mut dirty = reset.clone();
+            for &p in reset.iter() {
+                dirty.extend(self.dependents(p));
+            }
+            dirty.sort_by_key(|p| (p.x, p.y));
+            dirty.dedup();
+
+            match self.recompute(&dirty) {
+                Ok(()) => return Ok(()),
+                Err(c) => center = c.position,
+            }
+        }
+    }
+
+    /// True iff `p` is inside or directly next to the area of `bombing`.
+    fn near(bombing: &Bombing, p: UVec2) -> bool {
+        IRect::around(bombing.center.as_ivec2(), bombing.radius + 1).contains(p.as_ivec2())
+    }
+
+    /// Recompute probabilities and queue entries of all undetermined cells in `positions`.
+    fn recompute(&mut self, positions: &[UVec2]) -> Result<(), Contradiction> {
+        for &p in positions {
+            if T::from(self.tiles[p.as_index2()]).is_valid() || !self.is_fundamental(p) {
+                continue;
+            }
+            if !self.compute_probability(p) {
+                return Err(self.contradiction(p));
+            }
+            self.refresh_queue(p);
+        }
+        Ok(())
+    }
+
+    /// Make sure `pos` is in the entropy queue with the correct priority if and only if it is
+    /// still to be collapsed.
+    fn refresh_queue(&mut self, pos: UVec2) {
+        if !T::from(self.tiles[pos.as_index2()]).is_valid() && self.is_fundamental(pos) {
+            let priority = self.priority(pos, self.entropy_at(pos));
+            self.entropy.push(pos, priority);
+        } else {
+            self.entropy.remove(pos);
+        }
+    }
+
+    /// Which cells have a tile, ie. are collapsed, preset or outside of the area with a valid
+    /// tile.
+    pub fn valid(&self) -> Array2<bool> {
+        self.tiles.mapv(|t| T::from(t).is_valid())
+    }
+
+    pub fn is_valid(&self, pos: UVec2) -> bool {
+        T::from(self.tiles[pos.as_index2()]).is_valid()
+    }
+
+    /// Current probabilities of the tiles for `pos`, normalized to sum 1 (1.0 for the tile of
+    /// collapsed cells). Cells outside of the area have all 0.0, and all cells have
+    /// `NO_PROBABILITY` (or 0.0 with `ProbabilityStorage::U16`) before probabilities were
+    /// computed by `generate` or `start`.
+    /// Panics if `pos` is outside the map.
+    pub fn probabilities_at(&self, pos: UVec2) -> [f32; N] {
+        self.probabilities.get(pos)
+    }
+
+    /// Number of cells in the area that are not collapsed yet (counted by `generate` and
+    /// `start`)
+    pub fn remaining(&self) -> usize {
+        self.remaining
+    }
+
+    /// Number of cells in the area per tile index (counted by `generate` and `start`)
+    pub fn tile_counts(&self) -> &[usize] {
+        &self.counts
+    }
+
+    /// Snapshot of the current entropy of every cell.
+    /// Collapsed cells have an entropy of 0.0.
+    pub fn entropy_map(&self) -> Array2<f32> {
+        self.entropies.clone()
+    }
+
+    /// Probability of the tile of every cell when it was chosen (normalized over the tiles
+    /// that were still allowed), `NO_PROBABILITY` for cells that were not collapsed by the
+    /// generator (eg. preset cells). `None` unless enabled with `record_collapses`.
+    pub fn chosen_probabilities(&self) -> Option<&Array2<f32>> {
+        self.collapse_record.as_ref().map(|r| &r.probabilities)
+    }
+
+    /// Index of the collapse that determined every cell in the current attempt, counting
+    /// from 0. Cells of the same orbit of the symmetry share an index, indices of undone
+    /// collapses (see `Strategy`) are skipped. `NOT_COLLAPSED` for cells that were not
+    /// collapsed by the generator. `None` unless enabled with `record_collapses`.
+    pub fn collapse_order(&self) -> Option<&Array2<u32>> {
+        self.collapse_record.as_ref().map(|r| &r.order)
+    }
+
+    /// Number of contradictions encountered per cell.
+    pub fn contradictions(&self) -> &Array2<u32> {
+        &self.contradictions
+    }
+
+    fn contradiction(&mut self, position: UVec2) -> Contradiction {
+        self.contradictions[position.as_index2()] += 1;
+        Contradiction { position }
+    }
+
+    /// Generate and store the resulting tiles as layer `name` (of element type `T`) in `layers`.
+    pub fn generate_into_layer(&mut self, layers: &mut MapStack, name: &str) -> Result<(), WfcError>
+    where
+        T: Send + Sync + 'static,
+    {
+        self.generate()?;
+        layers.insert(name, self.tiles.mapv(T::from));
+        Ok(())
+    }
+
+    /// Collapse `pos` (and its orbit) to `tile`, which was chosen with `probability`
+    fn set_tile(&mut self, pos: UVec2, tile: T, probability: f32) -> Result<(), Contradiction> {
+        let positions = self.orbit(pos);
+
+        for &p in positions.iter() {
+            if let Some(record) = self.collapse_record.as_mut() {
+                record.probabilities[p.as_index2()] = probability;
+                record.order[p.as_index2()] = record.next;
+            }
+            self.set_single_tile(p, tile)?;
+            if p != pos {
+                self.entropy.remove(p);
+            }
+        }
+        Ok(())
+    }
+
+    fn set_single_tile(&mut self, pos: UVec2, tile: T) -> Result<(), Contradiction> {
+        assert!(tile.is_valid());
+        assert!(!T::from(self.tiles[pos.as_index2()]).is_valid());
+
+        self.record(pos);
+        self.assign(pos, tile.as_numeric());
+
+        // We need to recompute probabilities & entropies for all cells that see this one
+        for neigh in self.dependents(pos) {
+            if T::from(self.tiles[neigh.as_index2()]).is_valid() {
+                // We only care for invalid (== not-yet-determined) tiles
+                continue;
+            }
+            if !self.is_fundamental(neigh) {
+                // Will be determined by its source
+                continue;
+            }
+
+            self.record(neigh);
+            if !self.compute_probability(neigh) {
+                return Err(self.contradiction(neigh));
+            }
+            self.update_entropy(neigh);
+        }
+
+        // Probability for this field is 1.0 for the tile we set, 0 for everything else
+        self.set_certain(pos, tile.as_usize());
+
+        Ok(())
+    }
+
+    /// (Radius, metric, wrapping) of the neighborhoods passed to the probability callback
+    fn shape(&self) -> (u32, Metric, BVec2) {
+        (self.configuration.neighborhood_size, self.configuration.metric, self.wrap())
+    }
+
+    /// All cells whose neighborhood contains `pos`, ie. whose probabilities depend on the
+    /// tile at `pos`.
+    /// The metric does not need to be symmetric, so these are not necessarily the cells in
+    /// the neighborhood of `pos`.
+    fn dependents(&self, pos: UVec2) -> Vec<UVec2> {
+        let (radius, metric, wrap) = self.shape();
+        let size = self.configuration.size.as_ivec2();
+        let mut r: Vec<UVec2> = neighborhood::offsets(radius, metric)
+            .map(|o| {
+                let mut q = pos.as_ivec2() - o;
+                if wrap.x {
+                    q.x = q.x.rem_euclid(size.x);
+                }
+                if wrap.y {
+                    q.y = q.y.rem_euclid(size.y);
+                }
+                q
+            })
+            .filter(|q| q.cmpge(IVec2::ZERO).all() && q.cmplt(size).all() && *q != pos.as_ivec2())
+            .map(|q| q.as_uvec2())
+            .collect();
+        if wrap.any() {
+            // Small maps can wrap onto the same cell several times
+            r.sort_by_key(|p| (p.x, p.y));
+            r.dedup();
+        }
+        r
+    }
+
+    /// True iff `pos` is to be collapsed on its own, ie. it is in the area and not determined
+    /// by another cell through symmetry.
+    fn is_fundamental(&self, pos: UVec2) -> bool {
+        let area = self.area();
+        if !area.contains(pos) {
+            return false;
+        }
+        match self.configuration.symmetry {
+            Some(symmetry) => symmetry.is_fundamental(pos - area.anchor, area.size),
+            None => true,
+        }
+    }
+
+    fn compute_probabilities(&mut self) -> Result<(), Contradiction> {
+        for pos in Rect::from_size(self.configuration.size).iter() {
+            let tile = T::from(self.tiles[pos.as_index2()]);
+            if tile.is_valid() {
+                self.set_certain(pos, tile.as_usize());
+            } else if self.area().contains(pos) {
+                if !self.compute_probability(pos) {
+                    return Err(self.contradiction(pos));
+                }
+            } else {
+                // Not going to be collapsed, don't bother the callback
+                self.probabilities.set(pos, &[0.0; N]);
+                self.entropies[pos.as_index2()] = 0.0;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns false if there is no possible tile for `pos`.
+    /// Also updates the cached entropy of `pos`.
+    fn compute_probability(&mut self, pos: UVec2) -> bool {
+        let (radius, metric, wrap) = self.shape();
+        let mut context = ProbabilityContext {
+            position: pos,
+            progress: self.progress(),
+            rng: &mut self.callback_rng,
+            layers: self.configuration.guidance.as_ref(),
+        };
+        let neighborhood = Neighborhood::with_shape(&self.tiles, pos.as_ivec2(), radius, metric).wrapping(wrap);
+        let mut ps = self.configuration.probability.probabilities(&neighborhood, &mut context);
+        if ps.contains(&NO_PROBABILITY) {
+            return false;
+        }
+
+        for (p, &b) in ps.iter_mut().zip(self.banned.slice(pos.as_slice3d()).iter()) {
+            if b {
+                *p = 0.0;
+            }
+        }
+
+        let s: f32 = ps.iter().sum();
+        if s <= 0.0 {
+            return false;
+        }
+
+        let ps = ps.map(|p| p / s);
+        self.entropies[pos.as_index2()] = self.entropy_of(&ps);
+        self.probabilities.set(pos, &ps);
+        true
+    }
+
+    /// Fraction of the area that is collapsed
+    fn progress(&self) -> f32 {
+        let cells = self.area().size.x as usize * self.area().size.y as usize;
+        1.0 - self.remaining as f32 / cells.max(1) as f32
+    }
+
+    fn compute_entropies(&mut self) {
+        for pos in self.area().iter() {
+            if !self.is_fundamental(pos) || T::from(self.tiles[pos.as_index2()]).is_valid() {
+                continue;
+            }
+            let priority = self.priority(pos, self.entropy_at(pos));
+            self.entropy.push(pos, priority);
+        }
+    }
+
+    fn update_entropy(&mut self, pos: UVec2) {
+        let priority = self.priority(pos, self.entropy_at(pos));
+        self.entropy.change_priority(pos, priority);
+    }
+
+    fn compute_selection_noise(&mut self, rng: &mut impl Rng) {
+        let size = self.configuration.size;
+        let amplitude = self.configuration.entropy_jitter;
+        self.jitter = match amplitude > 0.0 {
+            true => Array2::from_shape_simple_fn(size.as_index2(), || rng.gen::<f32>() * amplitude),
+            false => Array2::zeros(size.as_index2()),
+        };
+
+        self.tie_breakers = match self.configuration.cell_selection {
+            CellSelection::RandomTies => Array2::from_shape_simple_fn(size.as_index2(), || rng.gen()),
+            // Earlier cells in scanline order first
+            _ => Array2::from_shape_fn(size.as_index2(), |(x, y)| {
+                u64::MAX - (y as u64 * size.x as u64 + x as u64)
+            }),
+        };
+    }
+
+    fn priority(&self, pos: UVec2, entropy: f32) -> Priority {
+        let entropy = entropy + self.jitter[pos.as_index2()];
+        let tie_breaker = self.tie_breakers[pos.as_index2()];
+        let score = match self.configuration.cell_selection {
+            CellSelection::MaxEntropy => entropy,
+            CellSelection::MinEntropy | CellSelection::RandomTies => -entropy,
+            CellSelection::Scanline => 0.0,
+            CellSelection::CenterDistance { weight } => {
+                let area = self.area();
+                let center = area.anchor.as_vec2() + (area.size.as_vec2() - 1.0) / 2.0;
+                -entropy - weight * pos.as_vec2().distance(center)
+            }
+        };
+        (FloatOrd(score), tie_breaker)
+    }
+
+    /// Shannon entropy of the (normalized) probabilities `ps`.
+    fn entropy_of(&self, ps: &[f32]) -> f32 {
+        if self.configuration.deterministic {
+            return fixed_entropy(ps);
+        }
+        shannon_entropy(ps)
+    }
+
+    /// Cached entropy of `pos`
+    fn entropy_at(&self, pos: UVec2) -> f32 {
+        self.entropies[pos.as_index2()]
+    }
+
+    fn set_certain(&mut self, pos: UVec2, tile: usize) {
+        let mut ps = [0.0; N];
+        ps[tile] = 1.0;
+        self.probabilities.set(pos, &ps);
+        self.entropies[pos.as_index2()] = 0.0;
+    }
+}
+
+impl<T, const N: usize> WaveFunctionCollapse<T, DefaultProbabilityCallback<T, N>, N>
+where
+    T: Tile,
+{
+    /// Default configuration for collapsing several layers at once, eg.
+    /// `WaveFunctionCollapse::layered::<(Terrain, Object)>()`.
+    /// The tiles are `Layered` combinations of the layers' tiles (`N` must be
+    /// `Layered::COUNT`), so the probability callback sees the neighborhood in all layers and can
+    /// forbid combinations across layers (eg. trees on water). Use `Layered::split` to separate
+    /// the resulting layers.
+    /// Returns `MapgenError::TileCount` if `N` is not `Layered::COUNT`.
+    pub fn layered<L: Layers<Tile = T>>(
+    ) -> Result<WaveFunctionCollapseConfiguration<T, DefaultProbabilityCallback<T, N>, N>, MapgenError> {
+        if N != T::MAX {
+            return Err(MapgenError::TileCount { n: N, tiles: T::MAX });
+        }
+        Ok(WaveFunctionCollapseConfiguration::default())
+    }
+}
+
+impl<T, F, const N: usize> WaveFunctionCollapseConfiguration<T, F, N>
+where
+    F: ContextProbabilityCallback<T, N>,
+    T: Tile,
+{
+    /// Replace the probability callback.
+    pub fn probability<G: ContextProbabilityCallback<T, N>>(self, probability: G) -> WaveFunctionCollapseConfiguration<T, G, N> {
+        WaveFunctionCollapseConfiguration {
+            seed: self.seed,
+            size: self.size,
+            probability,
+            symmetry: self.symmetry,
+            area: self.area,
+            neighborhood_size: self.neighborhood_size,
+            metric: self.metric,
+            cell_selection: self.cell_selection,
+            entropy_queue: self.entropy_queue,
+            probability_storage: self.probability_storage,
+            entropy_jitter: self.entropy_jitter,
+            retries: self.retries,
+            strategy: self.strategy,
+            max_bombings: self.max_bombings,
+            quotas: self.quotas,
+            connected: self.connected,
+            borders: self.borders,
+            guidance: self.guidance,
+            deterministic: self.deterministic,
+            record_collapses: self.record_collapses,
+            _tile: PhantomData,
+        }
+    }
+
+    pub fn cell_selection(mut self, cell_selection: CellSelection) -> Self {
+        self.cell_selection = cell_selection;
+        self
+    }
+
+    pub fn entropy_queue(mut self, entropy_queue: EntropyQueue) -> Self {
+        self.entropy_queue = entropy_queue;
+        self
+    }
+
+    pub fn probability_storage(mut self, storage: ProbabilityStorage) -> Self {
+        self.probability_storage = storage;
+        self
+    }
+
+    pub fn entropy_jitter(mut self, amplitude: f32) -> Self {
+        self.entropy_jitter = amplitude;
+        self
+    }
+
+    pub fn retries(mut self, retries: u32) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    pub fn strategy(mut self, strategy: Strategy) -> Self {
+        self.strategy = strategy;
+        self
+    }
+
+    pub fn max_bombings(mut self, max_bombings: u32) -> Self {
+        self.max_bombings = max_bombings;
+        self
+    }
+
+    /// Require between `min` and `max` cells (in the area, including preset cells) with `tile`,
+    /// eg. exactly one staircase or at most 10% water.
+    /// Maximums are enforced by ruling out the tile once it is used up. Minimums are enforced
+    /// by allowing only missing tiles once the remaining cells are just enough to place them;
+    /// if they are not possible there, the attempt fails with a contradiction (see `retries`).
+    /// Rare tiles with a minimum thus tend to end up in the last collapsed cells, preset them
+    /// if their position matters.
+    pub fn quota(mut self, tile: T, min: usize, max: usize) -> Self {
+        self.quotas.push(TileQuota { tile: tile.as_usize(), min, max });
+        self
+    }
+
+    /// Require all cells with one of `tiles` (eg. floor and doors) to form a single
+    /// 4-connected component, so that every walkable part of the map is reachable.
+    /// Before each collapse, choices that would cut off a part of the component are ruled out:
+    /// a cell can only be closed if the collapsed `tiles` stay connected through cells that are
+    /// or may still become one of `tiles`, and only be opened if it is connected to them that
+    /// way. Propagation can still close off cells indirectly; if the final map is not connected,
+    /// the attempt fails with a contradiction (see `retries`).
+    /// Each check may visit the whole area, which makes generation slower on large maps.
+    pub fn connected(mut self, tiles: &[T]) -> Self {
+        self.connected = tiles.iter().map(|t| t.as_usize()).collect();
+        self
+    }
+
+    /// Make `layers` available to the probability callback, see `ProbabilityContext`.
+    pub fn guidance(mut self, layers: MapStack) -> Self {
+        self.guidance = Some(layers);
+        self
+    }
+
+    /// Produce the same map for the same seed on all platforms.
+    /// Basic floating point operations are reproducible, but `log2` (used for entropies and
+    /// thus the order in which cells are collapsed) may differ in the last bits between
+    /// platforms and math libraries. With this option, entropies are computed with integer
+    /// arithmetic and tiles are chosen by an integer roll over probabilities quantized to
+    /// 24 bits. The probability callback must itself be reproducible (ie. not use `sin`,
+    /// `exp`, ... of platform math libraries).
+    /// Results differ from the default mode.
+    pub fn deterministic(mut self, deterministic: bool) -> Self {
+        self.deterministic = deterministic;
+        self
+    }
+
+    /// Record for every collapsed cell the probability its tile had when it was chosen and
+    /// the index of the collapse, see `WaveFunctionCollapse::chosen_probabilities` and
+    /// `WaveFunctionCollapse::collapse_order`. Eg. for reveal animations, or to find areas
+    /// that are barely constrained by the probability callback.
+    pub fn record_collapses(mut self, record: bool) -> Self {
+        self.record_collapses = record;
+        self
+    }
+
+    /// Use `border` for all edges of the map, eg. `Border::Tile(Ocean)` for a map ringed by
+    /// ocean.
+    pub fn border(mut self, border: Border<T>) -> Self {
+        self.borders = [border; 4];
+        self
+    }
+
+    /// Conditions for the north (y = 0), east, south and west edge.
+    pub fn borders(mut self, borders: [Border<T>; 4]) -> Self {
+        self.borders = borders;
+        self
+    }
+
+    pub fn neighborhood_size(mut self, radius: u32) -> Self {
+        self.neighborhood_size = radius;
+        self
+    }
+
+    /// Evaluate constraints over the neighborhood given by `metric`, eg.
+    /// `neighborhood::chebyshev` to also see diagonal neighbors, see `neighborhood::Metric`.
+    pub fn metric(mut self, metric: Metric) -> Self {
+        self.metric = metric;
+        self
+    }
+
+    pub fn area(mut self, area: Rect) -> Self {
+        self.area = Some(area);
+        self
+    }
+
+    pub fn build(self) -> WaveFunctionCollapse<T, F, N> {
+        WaveFunctionCollapse::new(Arc::new(self))
+    }
+
+    /// Like `build`, but checks the configuration first, see `validate`.
+    pub fn try_build(self) -> Result<WaveFunctionCollapse<T, F, N>, MapgenError> {
+        self.validate()?;
+        Ok(self.build())
+    }
+
+    /// Check the parameters for errors that would otherwise panic or fail later during
+    /// generation: a zero size or neighborhood, `N` not matching the tile type, an invalid area
+    /// or symmetry, quotas and connected tiles out of range, non-finite or non-positive
+    /// selection and queue parameters, and guidance layers (of `guidance` or `GuidedWeights`)
+    /// of the wrong size.
+    pub fn validate(&self) -> Result<(), MapgenError> {
+        MapgenError::check_size("size", self.size)?;
+        if N != T::MAX {
+            return Err(MapgenError::TileCount { n: N, tiles: T::MAX });
+        }
+        if self.neighborhood_size == 0 {
+            return Err(MapgenError::invalid("neighborhood_size", "must not be zero"));
+        }
+        self.check_area()?;
+        for quota in self.quotas.iter() {
+            if quota.tile >= N {
+                return Err(MapgenError::invalid("quotas", format!("tile {} is not below N = {}", quota.tile, N)));
+            }
+            if quota.min > quota.max {
+                return Err(MapgenError::invalid("quotas", format!("min {} exceeds max {}", quota.min, quota.max)));
+            }
+        }
+        if let Some(&tile) = self.connected.iter().find(|&&t| t >= N) {
+            return Err(MapgenError::invalid("connected", format!("tile {} is not below N = {}", tile, N)));
+        }
+        if self.borders.iter().any(|b| matches!(b, Border::Tile(t) if !t.is_valid())) {
+            return Err(MapgenError::invalid("borders", "border tile is not valid"));
+        }
+        if !(self.entropy_jitter.is_finite() && self.entropy_jitter >= 0.0) {
+            return Err(MapgenError::invalid("entropy_jitter", "must be finite and not negative"));
+        }
+        if let CellSelection::CenterDistance { weight } = self.cell_selection {
+            if !weight.is_finite() {
+                return Err(MapgenError::invalid("cell_selection", "center distance weight must be finite"));
+            }
+        }
+        if let EntropyQueue::Bucketed { resolution } = self.entropy_queue {
+            if !(resolution.is_finite() && resolution > 0.0) {
+                return Err(MapgenError::invalid("entropy_queue", "bucket resolution must be finite and positive"));
+            }
+        }
+        if let Some(guidance) = &self.guidance {
+            if guidance.size() != self.size {
+                return Err(MapgenError::invalid(
+                    "guidance",
+                    format!("layers have size {}, the map has size {}", guidance.size(), self.size),
+                ));
+            }
+        }
+        self.probability.validate(self.size)
+    }
+
+    /// The area to collapse
+    fn effective_area(&self) -> Rect {
+        self.area.unwrap_or_else(|| Rect::from_size(self.size))
+    }
+
+    fn check_area(&self) -> Result<(), WfcError> {
+        let area = self.effective_area();
+        if area.is_empty() {
+            return Err(WfcError::EmptyArea);
+        }
+        if area.end().cmpgt(self.size).any() {
+            return Err(WfcError::AreaOutOfBounds { area, size: self.size });
+        }
+        if let Some(symmetry @ Symmetry::Rotate4) = self.symmetry {
+            if area.size.x != area.size.y {
+                return Err(WfcError::InvalidSymmetry(symmetry));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<T, F, const N: usize> WaveFunctionCollapse<T, F, N>
+where
+    F: ContextProbabilityCallback<T, N>,
+    T: Tile,
+{
+    /// Generator for a shared configuration, eg. one per thread:
+    ///
+    /// ```
+    /// # use mapgen_2d::neighborhood::Neighborhood;
+    /// # use mapgen_2d::tile::TileEnum;
+    /// # use mapgen_2d::wave_function_collapse::WaveFunctionCollapse;
+    /// # use std::sync::Arc;
+    /// # mapgen_2d::tile_enum! { enum Color { Red, Green, Blue } }
+    /// # let mut configuration = Color::configuration().probability(|_: &Neighborhood<Color>| [1.0; 3]);
+    /// # configuration.size = glam::uvec2(16, 16);
+    /// let configuration = Arc::new(configuration);
+    /// let threads: Vec<_> = (0..4)
+    ///     .map(|_| {
+    ///         let mut wfc = WaveFunctionCollapse::new(configuration.clone());
+    ///         std::thread::spawn(move || wfc.generate().map(|_| wfc.tiles))
+    ///     })
+    ///     .collect();
+    /// for t in threads {
+    ///     assert!(t.join().unwrap().is_ok());
+    /// }
+    /// ```
+    pub fn new(configuration: Arc<WaveFunctionCollapseConfiguration<T, F, N>>) -> Self {
+        let size = configuration.size;
+        WaveFunctionCollapse {
+            tiles: Array2::from_elem(size.as_index2(), T::invalid().as_numeric()),
+            entropy: CellQueue::new(configuration.entropy_queue),
+            probabilities: Probabilities::new(configuration.probability_storage, size),
+            tie_breakers: Array2::zeros(size.as_index2()),
+            jitter: Array2::zeros(size.as_index2()),
+            contradictions: Array2::zeros(size.as_index2()),
+            attempts: Vec::new(),
+            entropies: Array2::zeros(size.as_index2()),
+            initial: Array2::from_elem(size.as_index2(), T::invalid().as_numeric()),
+            banned: Array3::from_elem(size.as_index3(N), false),
+            trail: VecDeque::new(),
+            backtracks: 0,
+            bombings: Vec::new(),
+            attempt_bombings: 0,
+            report: GenerationReport::default(),
+            callback_rng: StdRng::seed_from_u64(0),
+            rng: StdRng::seed_from_u64(0),
+            counts: vec![0; N],
+            remaining: 0,
+            collapse_record: configuration.record_collapses.then(|| CollapseRecord::new(size)),
+            configuration,
+        }
+    }
+}
+
+/// Index of a random tile with probability `ps`, `None` if all are 0.0
+fn choose<const N: usize>(ps: &[f32; N], rng: &mut StdRng) -> Option<usize> {
+    let mut p_sum = 0.0;
+    let roll = Uniform::<f32>::from(0.0..1.0).sample(rng);
+    let mut tile = None;
+    for (i, p) in ps.iter().enumerate() {
+        if *p == 0.0 {
+            continue;
+        }
+        p_sum += p;
+        // If rounding errors made the probabilities sum up to slightly less than 1.0,
+        // we end up with the last possible tile
+        tile = Some(i);
+        if roll <= p_sum {
+            break;
+        }
+    }
+    tile
+}
+
+/// Like `choose`, but rolling an integer over the fixed-point probabilities
+fn choose_fixed<const N: usize>(ps: &[f32; N], rng: &mut StdRng) -> Option<usize> {
+    let weights = ps.map(to_fixed);
+    let total: u64 = weights.iter().sum();
+    if total == 0 {
+        // Only probabilities below the fixed-point resolution
+        return ps.iter().rposition(|&p| p > 0.0);
+    }
+    let mut roll = rng.gen_range(0..total);
+    weights.iter().position(|&w| {
+        let hit = roll < w;
+        roll = roll.saturating_sub(w);
+        hit
+    })
+}
+
+/// Shannon entropy of the (normalized) probabilities `ps`
+fn shannon_entropy(ps: &[f32]) -> f32 {
+    // Independent partial sums let the compiler vectorize the loop
+    const LANES: usize = 8;
+    let mut sums = [0.0_f32; LANES];
+    let chunks = ps.chunks_exact(LANES);
+    let rest = chunks.remainder();
+    for chunk in chunks {
+        for (s, &p) in sums.iter_mut().zip(chunk) {
+            *s += if p > 0.0 { p * p.log2() } else { 0.0 };
+        }
+    }
+    for (s, &p) in sums.iter_mut().zip(rest) {
+        *s += if p > 0.0 { p * p.log2() } else { 0.0 };
+    }
+    -sums.iter().sum::<f32>()
+}
+
+/// Bits of the fractional part of fixed-point probabilities and logarithms
+const FIXED_BITS: u32 = 24;
+
+/// Probability `p` in [0, 1] as fixed-point number
+fn to_fixed(p: f32) -> u64 {
+    (p.clamp(0.0, 1.0) * (1 << FIXED_BITS) as f32).round() as u64
+}
+
+/// log2(x) for x > 0 with `FIXED_BITS` fractional bits, using only integer arithmetic
+/// (repeated squaring of the mantissa)
+fn fixed_log2(x: u64) -> i64 {
+    let n = 63 - x.leading_zeros();
+    // Mantissa in [1, 2) with 62 fractional bits
+    let mut m = (x as u128) << (62 - n);
+    let mut r = (n as i64) << FIXED_BITS;
+    for bit in (0..FIXED_BITS).rev() {
+        m = (m * m) >> 62;
+        if m >= 2 << 62 {
+            m >>= 1;
+            r += 1 << bit;
+        }
+    }
+    r
+}
+
+/// Shannon entropy of `ps` computed with integer arithmetic on the fixed-point probabilities,
+/// reproducible on all platforms
+fn fixed_entropy(ps: &[f32]) -> f32 {
+    let weights: Vec<u64> = ps.iter().map(|&p| to_fixed(p)).filter(|&w| w > 0).collect();
+    let total: u64 = weights.iter().sum();
+    if total == 0 {
+        return 0.0;
+    }
+    // H = log2(W) - sum(w * log2(w)) / W
+    let log_total = fixed_log2(total) as i128;
+    let sum: i128 = weights.iter().map(|&w| w as i128 * fixed_log2(w) as i128).sum();
+    let h = log_total - sum / total as i128;
+    h.max(0) as f32 / (1 << FIXED_BITS) as f32
+}
+
+impl<T, F, const N: usize> Index<UVec2> for WaveFunctionCollapse<T, F, N>
+where
+    F: ContextProbabilityCallback<T, N>,
+    T: Tile,
+{
+    type Output = T::Numeric;
+
+    fn index(&self, p: UVec2) -> &T::Numeric {
+        &self.tiles[p.as_index2()]
+    }
+}
+
+impl<T, const N: usize> Default
+    for WaveFunctionCollapseConfiguration<T, DefaultProbabilityCallback<T, N>, N>
+where
+    T: Tile,
+{
+    fn default() -> Self {
+        Self {
+            seed: 0_u64,
+            size: uvec2(100, 100),
+            probability: |_| [0.0_f32; N],
+            symmetry: None,
+            area: None,
+            neighborhood_size: 1,
+            metric: neighborhood::manhattan,
+            cell_selection: CellSelection::MinEntropy,
+            entropy_queue: EntropyQueue::Exact,
+            probability_storage: ProbabilityStorage::F32,
+            entropy_jitter: 0.0,
+            retries: 0,
+            strategy: Strategy::Bomb,
+            max_bombings: 0,
+            quotas: Vec::new(),
+            connected: Vec::new(),
+            borders: [Border::Any; 4],
+            guidance: None,
+            deterministic: false,
+            record_collapses: false,
+            _tile: Default::default(),
+        }
+    }
+}
+
+/// Probability callback of `WaveFunctionCollapse3`, the 3D version of `ProbabilityCallback`.
+/// Rules written against `neighborhood::Neighbors` can be shared with 2D callbacks.
+pub trait ProbabilityCallback3<T, const N: usize>: Fn(&Neighborhood3<T>) -> [f32; N] {}
+
+impl<F, T, const N: usize> ProbabilityCallback3<T, N> for F where
+    F: Fn(&Neighborhood3<T>) -> [f32; N]
+{
+}
+
+pub type DefaultProbabilityCallback3<T, const N: usize> = fn(&Neighborhood3<T>) -> [f32; N];
+
+/// Configuration of `WaveFunctionCollapse3`, the 3D version of
+/// `WaveFunctionCollapseConfiguration` on an `Array3` indexed `[x, y, z]`, eg. for voxel
+/// structures or layered cave systems.
+/// Cells are collapsed by minimum entropy (ties broken randomly) and contradictions are
+/// resolved by bombing (see `Strategy::Bomb`) and retries.
+pub struct WaveFunctionCollapseConfiguration3<T, F, const N: usize>
+where
+    F: ProbabilityCallback3<T, N>,
+    T: Tile,
+{
+    pub seed: u64,
+    pub size: UVec3,
+    pub probability: F,
+
+    /// Only collapse cells inside this box (default: the whole map), see
+    /// `WaveFunctionCollapseConfiguration::area`
+    pub area: Option<Rect3>,
+
+    /// Radius of the neighborhood passed to the probability callback (default: 1)
+    pub neighborhood_size: u32,
+
+    /// Shape of the neighborhood (default: `neighborhood::manhattan3`, ie. the 6 face
+    /// neighbors)
+    pub metric: Metric3,
+
+    /// Neighborhoods wrap around along these axes, see `Border::Wrap`
+    pub wrap: BVec3,
+
+    /// How often to retry (with derived seeds) after a contradiction could not be resolved.
+    pub retries: u32,
+
+    /// Maximum number of bombings per attempt
+    pub max_bombings: u32,
+
+    pub _tile: PhantomData<T>,
+}
+
+impl<T, const N: usize> Default
+    for WaveFunctionCollapseConfiguration3<T, DefaultProbabilityCallback3<T, N>, N>
+where
+    T: Tile,
+{
+    fn default() -> Self {
+        Self {
+            seed: 0_u64,
+            size: uvec3(16, 16, 16),
+            probability: |_| [0.0_f32; N],
+            area: None,
+            neighborhood_size: 1,
+            metric: neighborhood::manhattan3,
+            wrap: BVec3::FALSE,
+            retries: 0,
+            max_bombings: 0,
+            _tile: Default::default(),
+        }
+    }
+}
+
+impl<T, F, const N: usize> WaveFunctionCollapseConfiguration3<T, F, N>
+where
+    F: ProbabilityCallback3<T, N>,
+    T: Tile,
+{
+    /// Replace the probability callback.
+    pub fn probability<G>(self, probability: G) -> WaveFunctionCollapseConfiguration3<T, G, N>
+    where
+        G: ProbabilityCallback3<T, N>,
+    {
+        WaveFunctionCollapseConfiguration3 {
+            seed: self.seed,
+            size: self.size,
+            probability,
+            area: self.area,
+            neighborhood_size: self.neighborhood_size,
+            metric: self.metric,
+            wrap: self.wrap,
+            retries: self.retries,
+            max_bombings: self.max_bombings,
+            _tile: PhantomData,
+        }
+    }
+
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    pub fn size(mut self, size: UVec3) -> Self {
+        self.size = size;
+        self
+    }
+
+    pub fn area(mut self, area: Rect3) -> Self {
+        self.area = Some(area);
+        self
+    }
+
+    pub fn neighborhood_size(mut self, radius: u32) -> Self {
+        self.neighborhood_size = radius;
+        self
+    }
+
+    pub fn metric(mut self, metric: Metric3) -> Self {
+        self.metric = metric;
+        self
+    }
+
+    pub fn wrap(mut self, wrap: BVec3) -> Self {
+        self.wrap = wrap;
+        self
+    }
+
+    pub fn retries(mut self, retries: u32) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    pub fn max_bombings(mut self, max_bombings: u32) -> Self {
+        self.max_bombings = max_bombings;
+        self
+    }
+
+    pub fn build(self) -> WaveFunctionCollapse3<T, F, N> {
+        WaveFunctionCollapse3::new(Arc::new(self))
+    }
+
+    /// Like `build`, but checks the configuration first, see `validate`.
+    pub fn try_build(self) -> Result<WaveFunctionCollapse3<T, F, N>, MapgenError> {
+        self.validate()?;
+        Ok(self.build())
+    }
+
+    /// Check the parameters: the size and the neighborhood must not be zero, `N` must match the
+    /// tile type and the area must be inside the map.
+    pub fn validate(&self) -> Result<(), MapgenError> {
+        if self.size.cmpeq(UVec3::ZERO).any() {
+            return Err(MapgenError::invalid("size", format!("must not be zero, got {}", self.size)));
+        }
+        if N != T::MAX {
+            return Err(MapgenError::TileCount { n: N, tiles: T::MAX });
+        }
+        if self.neighborhood_size == 0 {
+            return Err(MapgenError::invalid("neighborhood_size", "must not be zero"));
+        }
+        Ok(self.check_area()?)
+    }
+
+    /// The area to collapse
+    fn effective_area(&self) -> Rect3 {
+        self.area.unwrap_or_else(|| Rect3::from_size(self.size))
+    }
+
+    fn check_area(&self) -> Result<(), WfcError> {
+        let area = self.effective_area();
+        if area.is_empty() {
+            return Err(WfcError::EmptyArea);
+        }
+        if area.end().cmpgt(self.size).any() {
+            return Err(WfcError::AreaOutOfBounds3 { area, size: self.size });
+        }
+        Ok(())
+    }
+}
+
+/// State of a 3D generation, see `WaveFunctionCollapseConfiguration3`.
+pub struct WaveFunctionCollapse3<T, F, const N: usize>
+where
+    F: ProbabilityCallback3<T, N>,
+    T: Tile,
+{
+    pub configuration: Arc<WaveFunctionCollapseConfiguration3<T, F, N>>,
+    pub tiles: Array3<T::Numeric>,
+    /// Tiles to start each attempt with
+    initial: Array3<T::Numeric>,
+    /// Normalized probabilities of the cells that are not collapsed yet
+    probabilities: Array3<[f32; N]>,
+    entropy: PriorityQueue<UVec3, Priority>,
+    tie_breakers: Array3<u64>,
+    attempts: Vec<Attempt<UVec3>>,
+    bombings: Vec<Bombing<UVec3>>,
+    attempt_bombings: u32,
+    rng: StdRng,
+}
+
+impl<T, F, const N: usize> WaveFunctionCollapse3<T, F, N>
+where
+    F: ProbabilityCallback3<T, N>,
+    T: Tile,
+{
+    /// Generator for a shared configuration, see `WaveFunctionCollapse::new`.
+    pub fn new(configuration: Arc<WaveFunctionCollapseConfiguration3<T, F, N>>) -> Self {
+        let dim = index3(configuration.size);
+        WaveFunctionCollapse3 {
+            tiles: Array3::from_elem(dim, T::invalid().as_numeric()),
+            initial: Array3::from_elem(dim, T::invalid().as_numeric()),
+            probabilities: Array3::from_elem(dim, [0.0; N]),
+            entropy: PriorityQueue::new(),
+            tie_breakers: Array3::zeros(dim),
+            attempts: Vec::new(),
+            bombings: Vec::new(),
+            attempt_bombings: 0,
+            rng: StdRng::seed_from_u64(0),
+            configuration,
+        }
+    }
+
+    /// Fix the tile at `pos` before generation, see `WaveFunctionCollapse::preset`.
+    /// Panics if `pos` is outside the map.
+    pub fn preset(&mut self, pos: UVec3, tile: T) {
+        self.initial[index3(pos)] = tile.as_numeric();
+        self.tiles[index3(pos)] = tile.as_numeric();
+    }
+
+    /// Collapse all cells (in the configured area), retrying with derived seeds on
+    /// contradictions, see `WaveFunctionCollapse::generate`.
+    pub fn generate(&mut self) -> Result<(), WfcError> {
+        self.configuration.check_area()?;
+        self.attempts.clear();
+        self.bombings.clear();
+        let mut result = Ok(());
+        for attempt in 0..=self.configuration.retries {
+            let seed = match attempt {
+                0 => self.configuration.seed,
+                n => derive_seed(self.configuration.seed, n as u64),
+            };
+
+            self.tiles.assign(&self.initial);
+            self.entropy.clear();
+            self.attempt_bombings = 0;
+            result = self.generate_attempt(seed);
+            self.attempts.push(Attempt { seed, contradiction: result.err() });
+            if result.is_ok() {
+                break;
+            }
+        }
+        result.map_err(WfcError::Contradiction3)
+    }
+
+    /// Like `generate`, but checks the configuration first (see
+    /// `WaveFunctionCollapseConfiguration3::validate`).
+    pub fn try_generate(&mut self) -> Result<(), MapgenError> {
+        self.configuration.validate()?;
+        Ok(self.generate()?)
+    }
+
+    /// All attempts of the last `generate`.
+    pub fn attempts(&self) -> &[Attempt<UVec3>] {
+        &self.attempts
+    }
+
+    /// All bombings of the last `generate`, over all attempts.
+    pub fn bombings(&self) -> &[Bombing<UVec3>] {
+        &self.bombings
+    }
+
+    /// Which cells have a tile.
+    pub fn valid(&self) -> Array3<bool> {
+        self.tiles.mapv(|t| T::from(t).is_valid())
+    }
+
+    fn generate_attempt(&mut self, seed: u64) -> Result<(), Contradiction<UVec3>> {
+        self.rng = StdRng::seed_from_u64(seed);
+        let rng = &mut self.rng;
+        self.tie_breakers.map_inplace(|t| *t = rng.gen());
+
+        let area = self.configuration.effective_area();
+        self.update(&area.iter().collect::<Vec<_>>())?;
+
+        while let Some((target, _)) = self.entropy.pop() {
+            let tile = choose(&self.probabilities[index3(target)], &mut self.rng).expect("Queued cells are possible");
+            self.tiles[index3(target)] = T::from(tile).as_numeric();
+            self.update(&self.dependents(target))?;
+        }
+        Ok(())
+    }
+
+    /// Recompute `positions`, bombing every contradiction on the way.
+    fn update(&mut self, positions: &[UVec3]) -> Result<(), Contradiction<UVec3>> {
+        for &p in positions {
+            if let Err(c) = self.recompute(&[p]) {
+                self.bomb(c.position)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Reset cells in a cube around `center`, growing it as long as the reset cells can not be
+    /// assigned possible tiles, see `Strategy::Bomb`.
+    fn bomb(&mut self, mut center: UVec3) -> Result<(), Contradiction<UVec3>> {
+        let area = self.configuration.effective_area();
+        // Cells of all cubes so far and the cells that see them
+        let mut dirty = Vec::new();
+        loop {
+            if self.attempt_bombings >= self.configuration.max_bombings {
+                return Err(Contradiction { position: center });
+            }
+            let radius = match self.bombings.last() {
+                Some(last) if self.attempt_bombings > 0 && Self::near(last, center) => {
+                    (last.radius * 2).min(area.size.max_element())
+                }
+                _ => 1,
+            };
+            self.attempt_bombings += 1;
+            self.bombings.push(Bombing { center, radius });
+
+            // The cube around `center`, clipped to the area
+            let r = UVec3::splat(radius);
+            let anchor = (center.max(r) - r).max(area.anchor);
+            let end = (center + r + UVec3::ONE).min(area.end());
+            let cube = Rect3::new(anchor, end - anchor);
+
+            for p in cube.iter() {
+                self.tiles[index3(p)] = self.initial[index3(p)];
+                dirty.push(p);
+                dirty.extend(self.dependents(p));
+            }
+            dirty.sort_by_key(|p| (p.x, p.y, p.z));
+            dirty.dedup();
+
+            match self.recompute(&dirty) {
+                Ok(()) => return Ok(()),
+                Err(c) => center = c.position,
+            }
+        }
+    }
+
+    /// True iff `p` is inside or directly next to the cube of `bombing`.
+    fn near(bombing: &Bombing<UVec3>, p: UVec3) -> bool {
+        let d = bombing.center.as_ivec3() - p.as_ivec3();
+        d.abs().max_element() as u32 <= bombing.radius + 1
+    }
+
+    /// Recompute probabilities and queue entries of all undetermined cells of the area in
+    /// `positions`.
+    fn recompute(&mut self, positions: &[UVec3]) -> Result<(), Contradiction<UVec3>> {
+        let area = self.configuration.effective_area();
+        for &p in positions {
+            if T::from(self.tiles[index3(p)]).is_valid() || !area.contains(p) {
+                continue;
+            }
+            let c = &self.configuration;
+            let neighborhood = Neighborhood3::with_shape(&self.tiles, p.as_ivec3(), c.neighborhood_size, c.metric)
+                .wrapping(c.wrap);
+            let ps = (c.probability)(&neighborhood);
+            let sum: f32 = ps.iter().sum();
+            if ps.contains(&NO_PROBABILITY) || sum <= 0.0 {
+                self.entropy.remove(&p);
+                return Err(Contradiction { position: p });
+            }
+
+            let ps = ps.map(|v| v / sum);
+            self.probabilities[index3(p)] = ps;
+            self.entropy.push(p, (FloatOrd(-shannon_entropy(&ps)), self.tie_breakers[index3(p)]));
+        }
+        Ok(())
+    }
+
+    /// All cells whose neighborhood contains `pos`, see `WaveFunctionCollapse::dependents`.
+    fn dependents(&self, pos: UVec3) -> Vec<UVec3> {
+        let c = &self.configuration;
+        let size = c.size.as_ivec3();
+        let wrap: [bool; 3] = c.wrap.into();
+        let mut r: Vec<UVec3> = neighborhood::offsets3(c.neighborhood_size, c.metric)
+            .map(|o| {
+                let mut q = pos.as_ivec3() - o;
+                for axis in 0..3 {
+                    if wrap[axis] {
+                        q[axis] = q[axis].rem_euclid(size[axis]);
+                    }
+                }
+                q
+            })
+            .filter(|q| q.cmpge(IVec3::ZERO).all() && q.cmplt(size).all() && *q != pos.as_ivec3())
+            .map(|q| q.as_uvec3())
+            .collect();
+        if c.wrap.any() {
+            r.sort_by_key(|p| (p.x, p.y, p.z));
+            r.dedup();
+        }
+        r
+    }
+}
+
+fn index3(p: UVec3) -> (usize, usize, usize) {
+    (p.x as usize, p.y as usize, p.z as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::coord::MapAccess;
+    use crate::neighborhood::Neighbors;
+    use crate::tile::TileEnum;
+
+    crate::tile_enum! {
+        #[derive(Debug)]
+        enum Height { Low, Mid, High }
+    }
+
+    /// Neighboring tiles differ by at most one step
+    fn gradient(n: &Neighborhood<Height>) -> [f32; 3] {
+        smooth(n)
+    }
+
+    /// `gradient` for 2D and 3D neighborhoods
+    fn smooth<M: Neighbors<Height>>(n: &M) -> [f32; 3] {
+        match n.range() {
+            None => [1.0; 3],
+            Some((lo, hi)) => {
+                let mut p = [0.0; 3];
+                for (i, p) in p.iter_mut().enumerate() {
+                    if i + 1 >= hi.as_usize() && i <= lo.as_usize() + 1 {
+                        *p = 1.0;
+                    }
+                }
+                p
+            }
+        }
+    }
+
+    type Configuration = WaveFunctionCollapseConfiguration<Height, DefaultProbabilityCallback<Height, 3>, 3>;
+
+    fn configuration(size: UVec2, seed: u64) -> Configuration {
+        let mut c = Height::configuration()
+            .probability(gradient as DefaultProbabilityCallback<Height, 3>)
+            .strategy(Strategy::Backtrack { max_depth: 50 })
+            .retries(10);
+        c.size = size;
+        c.seed = seed;
+        c
+    }
+
+    /// Panics unless exactly the tiles in `area` are collapsed, and all of them satisfy
+    /// `gradient`
+    fn check(tiles: &Array2<usize>, area: Rect) {
+        for (p, &t) in tiles.iter_with_positions() {
+            assert_eq!(Height::from(t).is_valid(), area.contains(p), "tile {} at {}", t, p);
+            for q in [p + UVec2::X, p + UVec2::Y] {
+                if let Some(&u) = tiles.get_at(q.as_ivec2()) {
+                    if area.contains(p) && area.contains(q) {
+                        assert!(t.abs_diff(u) <= 1, "{} at {} next to {} at {}", t, p, u, q);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn sub_area() {
+        let area = Rect::new(uvec2(3, 2), uvec2(5, 4));
+        let mut w = configuration(uvec2(11, 7), 3).area(area).build();
+        w.generate().unwrap();
+        check(&w.tiles, area);
+    }
+
+    #[test]
+    fn sub_area_at_far_corner() {
+        let size = uvec2(9, 6);
+        let area = Rect::new(uvec2(6, 4), uvec2(3, 2));
+        let mut w = configuration(size, 1).area(area).build();
+        w.generate().unwrap();
+        check(&w.tiles, area);
+    }
+
+    #[test]
+    fn sub_area_single_cell() {
+        let area = Rect::new(uvec2(2, 1), uvec2(1, 1));
+        let mut w = configuration(uvec2(4, 4), 0).area(area).build();
+        w.generate().unwrap();
+        check(&w.tiles, area);
+    }
+
+    #[test]
+    fn degenerate_sizes() {
+        for size in [uvec2(1, 1), uvec2(1, 17), uvec2(17, 1), uvec2(2, 9), uvec2(9, 2)] {
+            for seed in 0..4 {
+                let mut w = configuration(size, seed).build();
+                w.generate().unwrap();
+                check(&w.tiles, Rect::from_size(size));
+            }
+        }
+    }
+
+    #[test]
+    fn degenerate_sub_areas() {
+        let size = uvec2(6, 5);
+        for area in [Rect::new(uvec2(2, 0), uvec2(1, 5)), Rect::new(uvec2(0, 3), uvec2(6, 1))] {
+            let mut w = configuration(size, 2).area(area).build();
+            w.generate().unwrap();
+            check(&w.tiles, area);
+        }
+    }
+
+    #[test]
+    fn invalid_areas() {
+        let size = uvec2(6, 5);
+        let area = Rect::new(uvec2(4, 0), uvec2(3, 2));
+        assert_eq!(configuration(size, 0).area(area).build().generate(), Err(WfcError::AreaOutOfBounds { area, size }));
+        let area = Rect::new(uvec2(1, 1), uvec2(0, 2));
+        assert_eq!(configuration(size, 0).area(area).build().generate(), Err(WfcError::EmptyArea));
+        assert_eq!(configuration(uvec2(0, 4), 0).build().generate(), Err(WfcError::EmptyArea));
+        assert!(configuration(uvec2(4, 0), 0).validate().is_err());
+    }
+
+    /// FNV-1a over the tiles in memory order, stable across platforms and Rust versions
+    /// (unlike `DefaultHasher`)
+    fn fnv1a(tiles: &Array2<usize>) -> u64 {
+        tiles.iter().fold(0xcbf29ce484222325, |h, &t| (h ^ t as u64).wrapping_mul(0x100000001b3))
+    }
+
+    #[test]
+    fn fixed_point_logarithm() {
+        assert_eq!(fixed_log2(1), 0);
+        assert_eq!(fixed_log2(8), 3 << FIXED_BITS);
+        assert_eq!(fixed_log2(1 << 40), 40 << FIXED_BITS);
+        assert_eq!(fixed_log2(3), 26591258);
+        assert_eq!(fixed_log2(1_000_000), 334396231);
+    }
+
+    #[test]
+    fn fixed_point_entropy() {
+        assert_eq!(fixed_entropy(&[0.5, 0.5]), 1.0);
+        assert_eq!(fixed_entropy(&[0.25; 4]), 2.0);
+        assert_eq!(fixed_entropy(&[1.0, 0.0, 0.0]), 0.0);
+        assert_eq!(fixed_entropy(&[0.7, 0.2, 0.1]).to_bits(), 1066668380);
+    }
+
+    /// Changes of the fixed-point arithmetic or the tile roll must not change deterministic
+    /// maps. If this fails after an intentional change, maps generated from shared seeds
+    /// differ from earlier versions.
+    #[test]
+    fn deterministic_golden() {
+        let weighted = |n: &Neighborhood<Height>| {
+            let mut p = gradient(n);
+            for (p, w) in p.iter_mut().zip([0.5, 0.3, 0.2]) {
+                *p *= w;
+            }
+            p
+        };
+        let mut c = Height::configuration().probability(weighted).deterministic(true).retries(10);
+        c.size = uvec2(23, 17);
+        c.seed = 12345;
+        let mut w = c.strategy(Strategy::Backtrack { max_depth: 50 }).build();
+        w.generate().unwrap();
+        check(&w.tiles, Rect::from_size(uvec2(23, 17)));
+        assert_eq!(fnv1a(&w.tiles), 2177537734744791024);
+    }
+
+    crate::tile_enum! {
+        #[derive(Debug)]
+        enum Terrain { Water, Grass }
+    }
+
+    crate::tile_enum! {
+        #[derive(Debug)]
+        enum Object { Nothing, Tree }
+    }
+
+    type Ground = Layered<Terrain, Object>;
+
+    /// Trees neither on nor next to water
+    fn no_trees_by_water(n: &Neighborhood<Ground>) -> [f32; 4] {
+        let water = n.iter().flatten().any(|t| t.is_valid() && t.0 == Terrain::Water);
+        let trees = n.iter().flatten().any(|t| t.is_valid() && t.1 == Object::Tree);
+        let mut p = Layered::<Terrain, Object>::product([1.0, 1.0], [1.0, 1.0]);
+        for (i, p) in p.iter_mut().enumerate() {
+            let Layered(terrain, object) = Ground::from(i);
+            if (object == Object::Tree && (terrain == Terrain::Water || water))
+                || (terrain == Terrain::Water && trees)
+            {
+                *p = 0.0;
+            }
+        }
+        p
+    }
+
+    #[test]
+    fn layered_forbidden_combinations() {
+        for seed in 0..5 {
+            let mut c = WaveFunctionCollapse::<Ground, _, 4>::layered::<(Terrain, Object)>()
+                .unwrap()
+                .probability(no_trees_by_water as DefaultProbabilityCallback<Ground, 4>)
+                .strategy(Strategy::Backtrack { max_depth: 50 })
+                .retries(10);
+            c.size = uvec2(16, 12);
+            c.seed = seed;
+            let mut w = c.build();
+            w.generate().unwrap();
+
+            let (terrain, objects) = Ground::split(&w.tiles);
+            assert!(objects.iter().any(|&o| o == Object::Tree));
+            assert!(terrain.iter().any(|&t| t == Terrain::Water));
+            for (p, &o) in objects.iter_with_positions() {
+                if o != Object::Tree {
+                    continue;
+                }
+                assert_eq!(*terrain.at(p), Terrain::Grass, "tree on water at {}", p);
+                for q in Neighborhood::<Ground>::new(&w.tiles, p.as_ivec2()).iter().flatten() {
+                    assert_eq!(q.0, Terrain::Grass, "tree next to water at {}", p);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn layered_tile_count() {
+        let r = WaveFunctionCollapse::<Ground, _, 3>::layered::<(Terrain, Object)>();
+        assert_eq!(r.err(), Some(MapgenError::TileCount { n: 3, tiles: 4 }));
+    }
+
+    #[test]
+    fn invalid_selection_parameters() {
+        for resolution in [0.0, -1.0, f32::NAN, f32::INFINITY] {
+            let c = configuration(uvec2(5, 5), 0).entropy_queue(EntropyQueue::Bucketed { resolution });
+            assert!(matches!(c.try_build(), Err(MapgenError::InvalidParameter { .. })), "{}", resolution);
+        }
+        for weight in [f32::NAN, f32::NEG_INFINITY] {
+            let c = configuration(uvec2(5, 5), 0).cell_selection(CellSelection::CenterDistance { weight });
+            assert!(matches!(c.try_build(), Err(MapgenError::InvalidParameter { .. })), "{}", weight);
+        }
+        let c = configuration(uvec2(5, 5), 0)
+            .entropy_queue(EntropyQueue::Bucketed { resolution: 0.1 })
+            .cell_selection(CellSelection::CenterDistance { weight: -0.5 });
+        c.try_build().unwrap().try_generate().unwrap();
+    }
+
+    #[test]
+    fn non_square_map() {
+        let size = crate::coord::size(crate::coord::Width(7), crate::coord::Height(3));
+        let mut w = configuration(size, 4)
+            .borders([Border::Tile(Height::High), Border::Any, Border::Tile(Height::Low), Border::Any])
+            .build();
+        w.preset(uvec2(6, 1), Height::Mid);
+        w.generate().unwrap();
+        assert_eq!(w.tiles.dim(), (7, 3));
+        check(&w.tiles, Rect::from_size(size));
+        for x in 0..7 {
+            assert_eq!(Height::from(w.tiles[[x, 0]]), Height::High);
+            assert_eq!(Height::from(w.tiles[[x, 1]]), Height::Mid);
+            assert_eq!(Height::from(w.tiles[[x, 2]]), Height::Low);
+        }
+    }
+
+    #[test]
+    fn non_square_area() {
+        let area = Rect::new(uvec2(1, 2), uvec2(7, 3));
+        let mut w = configuration(uvec2(9, 6), 7).area(area).build();
+        w.generate().unwrap();
+        check(&w.tiles, area);
+    }
+
+    #[test]
+    fn non_square_scanline() {
+        let mut w = configuration(uvec2(7, 3), 0)
+            .cell_selection(CellSelection::Scanline)
+            .record_collapses(true)
+            .build();
+        w.generate().unwrap();
+        let order = w.collapse_order().unwrap();
+        assert_eq!(order.dim(), (7, 3));
+        for (p, &i) in order.iter_with_positions() {
+            assert_eq!(i, p.y * 7 + p.x, "collapse {} at {}", i, p);
+        }
+    }
+
+    /// No two tiles in the neighborhood are equal
+    fn distinct2(n: &Neighborhood<Height>) -> [f32; 3] {
+        let mut p = [1.0; 3];
+        for t in n.iter().flatten().filter(|t| t.is_valid()) {
+            p[t.as_usize()] = 0.0;
+        }
+        p
+    }
+
+    /// Contradictions at the map and area edges: the area reset by bombing (or by the bombing
+    /// that backtracking falls back to) is clipped to the area, and never reaches outside of it.
+    #[test]
+    fn contradictions_at_edges() {
+        let size = uvec2(9, 7);
+        let distinct = distinct2 as DefaultProbabilityCallback<Height, 3>;
+        for strategy in [Strategy::Bomb, Strategy::Backtrack { max_depth: 5 }] {
+            // With diagonal neighbors, the cells between the presets see 2 tiles and each other,
+            // so contradictions keep coming up at the corner and bombings grow to the area size
+            for (area, a, b) in [
+                (Rect::from_size(size), uvec2(1, 0), uvec2(0, 1)),
+                (Rect::new(uvec2(4, 2), uvec2(5, 5)), uvec2(7, 6), uvec2(8, 5)),
+            ] {
+                let c = configuration(size, 0).probability(distinct).metric(neighborhood::chebyshev);
+                let mut w = c.strategy(strategy).max_bombings(5).retries(0).area(area).build();
+                w.preset(a, Height::Low);
+                w.preset(b, Height::Mid);
+                assert!(matches!(w.generate(), Err(WfcError::Contradiction(_))));
+                let radii: Vec<u32> = w.bombings().iter().map(|b| b.radius).collect();
+                let max = area.size.max_element();
+                assert_eq!(radii, vec![1, 2, 4, 8.min(max), 16.min(max)], "{:?} in {:?}", strategy, area);
+                assert_eq!(w.tiles[a.as_index2()], Height::Low.as_usize());
+            }
+
+            // Solvable, but collapsing the least constrained cells first runs into
+            // contradictions, also at the edges of an area in the far corner
+            let area = Rect::new(uvec2(3, 2), uvec2(6, 5));
+            let (mut bombings, mut at_edge) = (0, 0);
+            for seed in 0..20 {
+                let c = configuration(size, seed).probability(distinct).strategy(strategy);
+                let c = c.cell_selection(CellSelection::MaxEntropy);
+                let mut w = c.max_bombings(100).area(area).build();
+                w.generate().unwrap();
+                bombings += w.bombings().len();
+                let last = area.end() - 1;
+                at_edge += w.bombings().iter().filter(|b| {
+                    b.center.cmpeq(area.anchor).any() || b.center.cmpeq(last).any()
+                }).count();
+                for (p, &t) in w.tiles.iter_with_positions() {
+                    assert_eq!(Height::from(t).is_valid(), area.contains(p), "tile {} at {}", t, p);
+                    if area.contains(p) && area.contains(p + UVec2::X) {
+                        assert_ne!(t, w.tiles[(p + UVec2::X).as_index2()], "at {}", p);
+                    }
+                    if area.contains(p) && area.contains(p + UVec2::Y) {
+                        assert_ne!(t, w.tiles[(p + UVec2::Y).as_index2()], "at {}", p);
+                    }
+                }
+            }
+            assert!(bombings > 20 && at_edge > 0, "{:?}", strategy);
+        }
+    }
+
+    /// All cells of an orbit of the symmetry (relative to `area`) have the same tile
+    fn check_symmetric(tiles: &Array2<usize>, symmetry: Symmetry, area: Rect) {
+        for p in area.iter() {
+            for q in symmetry.orbit(p - area.anchor, area.size) {
+                let q = q + area.anchor;
+                assert_eq!(tiles[p.as_index2()], tiles[q.as_index2()], "{:?}: {} and {}", symmetry, p, q);
+            }
+        }
+    }
+
+    #[test]
+    fn symmetric_orbits() {
+        use Symmetry::*;
+        for (i, symmetry) in [MirrorX, MirrorY, MirrorXY, Rotate2, Rotate4].into_iter().enumerate() {
+            let mut c = configuration(uvec2(7, 7), i as u64);
+            c.symmetry = Some(symmetry);
+            let mut w = c.build();
+            w.preset(uvec2(1, 0), Height::High);
+            w.generate().unwrap();
+            check(&w.tiles, Rect::from_size(uvec2(7, 7)));
+            check_symmetric(&w.tiles, symmetry, Rect::from_size(uvec2(7, 7)));
+            // The preset is mirrored onto its orbit
+            for p in symmetry.orbit(uvec2(1, 0), uvec2(7, 7)) {
+                assert_eq!(Height::from(w.tiles[p.as_index2()]), Height::High, "{:?} at {}", symmetry, p);
+            }
+        }
+
+        // Relative to the area, cells outside of it are left alone
+        let area = Rect::new(uvec2(2, 1), uvec2(6, 4));
+        let mut c = configuration(uvec2(9, 6), 3).area(area);
+        c.symmetry = Some(Symmetry::MirrorXY);
+        let mut w = c.build();
+        w.generate().unwrap();
+        check(&w.tiles, area);
+        check_symmetric(&w.tiles, Symmetry::MirrorXY, area);
+
+        let mut c = configuration(uvec2(6, 4), 0);
+        c.symmetry = Some(Symmetry::Rotate4);
+        assert_eq!(c.build().generate(), Err(WfcError::InvalidSymmetry(Symmetry::Rotate4)));
+    }
+
+    #[test]
+    fn entropy_and_contradiction_maps() {
+        let uniform = |_: &Neighborhood<Height>| [1.0; 3];
+        let mut c = Height::configuration().probability(uniform);
+        c.size = uvec2(4, 3);
+        let mut w = c.build();
+        w.start().unwrap();
+        assert!(w.entropy_map().iter().all(|h| (h - 3.0_f32.log2()).abs() < 1e-6));
+        while w.step().unwrap().is_some() {}
+        assert!(w.entropy_map().iter().all(|&h| h == 0.0));
+
+        // 3 tiles can not color a map where diagonal neighbors must differ too, so the first
+        // attempt fails and leaves its partial state for inspection
+        let distinct = distinct2 as DefaultProbabilityCallback<Height, 3>;
+        let c = configuration(uvec2(5, 4), 0).probability(distinct).metric(neighborhood::chebyshev);
+        let mut w = c.strategy(Strategy::Bomb).retries(0).build();
+        let position = match w.generate() {
+            Err(WfcError::Contradiction(c)) => c.position,
+            r => panic!("{:?}", r),
+        };
+        assert_eq!(w.contradictions()[position.as_index2()], 1);
+        assert_eq!(w.contradictions().sum(), 1);
+        assert_eq!(w.report().contradictions, 1);
+        let (entropies, valid) = (w.entropy_map(), w.valid());
+        assert!(!valid[position.as_index2()]);
+        assert!(entropies.iter().zip(valid.iter()).all(|(&h, &v)| !v || h == 0.0));
+        assert!(entropies.iter().zip(valid.iter()).any(|(&h, &v)| !v && h > 0.0));
+
+        let mut layers = MapStack::new(uvec2(6, 5));
+        configuration(uvec2(6, 5), 0).build().generate_into_layer(&mut layers, "height").unwrap();
+        let height = layers.get::<Height>("height").unwrap();
+        check(&height.mapv(|t| t.as_usize()), Rect::from_size(uvec2(6, 5)));
+    }
+
+    /// Positions of the first `n` collapses
+    fn first_steps<F>(w: &mut WaveFunctionCollapse<Height, F, 3>, n: usize) -> Vec<UVec2>
+    where
+        F: ContextProbabilityCallback<Height, 3>,
+    {
+        w.start().unwrap();
+        (0..n).map(|_| w.step().unwrap().unwrap()).collect()
+    }
+
+    #[test]
+    fn cell_selection_heuristics() {
+        let distinct = distinct2 as DefaultProbabilityCallback<Height, 3>;
+        let selection = |s| configuration(uvec2(5, 5), 0).probability(distinct).cell_selection(s).build();
+        // The neighbors of the first collapsed cell have one tile less to choose from
+        let steps = first_steps(&mut selection(CellSelection::MinEntropy), 2);
+        assert_eq!(neighborhood::manhattan(steps[1].as_ivec2() - steps[0].as_ivec2()), 1);
+        let steps = first_steps(&mut selection(CellSelection::MaxEntropy), 2);
+        assert_eq!(steps, vec![uvec2(0, 0), uvec2(2, 0)]);
+        let steps = first_steps(&mut selection(CellSelection::Scanline), 7);
+        assert_eq!(steps, (0..7).map(|i| uvec2(i % 5, i / 5)).collect::<Vec<_>>());
+        let steps = first_steps(&mut selection(CellSelection::CenterDistance { weight: 10.0 }), 5);
+        assert_eq!(steps[0], uvec2(2, 2));
+        assert!(steps[1..].iter().all(|p| neighborhood::manhattan(p.as_ivec2() - IVec2::splat(2)) == 1));
+
+        // Ties between cells of equal entropy are broken in scanline order, or randomly
+        let uniform = |_: &Neighborhood<Height>| [1.0; 3];
+        let selection = |s, seed| configuration(uvec2(5, 5), seed).probability(uniform).cell_selection(s).build();
+        let scanline: Vec<UVec2> = (0..25).map(|i| uvec2(i % 5, i / 5)).collect();
+        assert_eq!(first_steps(&mut selection(CellSelection::MinEntropy, 0), 25), scanline);
+        let random = first_steps(&mut selection(CellSelection::RandomTies, 0), 25);
+        assert_ne!(random, scanline);
+        assert_eq!(first_steps(&mut selection(CellSelection::RandomTies, 0), 25), random);
+        assert_ne!(first_steps(&mut selection(CellSelection::RandomTies, 1), 25), random);
+    }
+
+    #[test]
+    fn entropy_and_jitter() {
+        assert_eq!(Height::configuration().cell_selection, CellSelection::MinEntropy);
+        assert_eq!(shannon_entropy(&[0.5, 0.5]), 1.0);
+        assert_eq!(shannon_entropy(&[0.25; 4]), 2.0);
+        assert_eq!(shannon_entropy(&[1.0, 0.0, 0.0]), 0.0);
+        assert!((shannon_entropy(&[0.1; 10]) - 10.0_f32.log2()).abs() < 1e-5);
+        let two = |_: &Neighborhood<Height>| [1.0, 0.0, 3.0];
+        let mut w = configuration(uvec2(3, 2), 0).probability(two).build();
+        w.start().unwrap();
+        let expected = shannon_entropy(&[0.25, 0.0, 0.75]);
+        assert!(w.entropy_map().iter().all(|&h| h == expected && h > 0.8 && h < 0.82));
+
+        // Jitter shuffles cells of equal entropy, but does not override larger differences
+        let uniform = |_: &Neighborhood<Height>| [1.0; 3];
+        let scanline: Vec<UVec2> = (0..25).map(|i| uvec2(i % 5, i / 5)).collect();
+        let jittered = |seed| configuration(uvec2(5, 5), seed).probability(uniform).entropy_jitter(0.5).build();
+        let steps = first_steps(&mut jittered(0), 25);
+        assert_ne!(steps, scanline);
+        assert_eq!(first_steps(&mut jittered(0), 25), steps);
+        assert_ne!(first_steps(&mut jittered(1), 25), steps);
+        for seed in 0..5 {
+            let c = configuration(uvec2(5, 5), seed).probability(distinct2 as DefaultProbabilityCallback<Height, 3>);
+            let steps = first_steps(&mut c.entropy_jitter(0.1).build(), 2);
+            assert_eq!(neighborhood::manhattan(steps[1].as_ivec2() - steps[0].as_ivec2()), 1);
+        }
+    }
+
+    /// Panics unless all cells are collapsed and no two 4-neighbors have the same tile
+    fn check_distinct(tiles: &Array2<usize>) {
+        for (p, &t) in tiles.iter_with_positions() {
+            assert!(Height::from(t).is_valid(), "tile {} at {}", t, p);
+            for q in [p + UVec2::X, p + UVec2::Y] {
+                assert_ne!(tiles.get_at(q.as_ivec2()), Some(&t), "at {} and {}", p, q);
+            }
+        }
+    }
+
+    #[test]
+    fn retries_with_derived_seeds() {
+        // Without bombing, the first two attempts for seed 0 run into contradictions
+        let distinct = distinct2 as DefaultProbabilityCallback<Height, 3>;
+        let c = || {
+            let c = configuration(uvec2(9, 7), 0).probability(distinct).strategy(Strategy::Bomb);
+            c.cell_selection(CellSelection::RandomTies).max_bombings(0)
+        };
+        let mut w = c().retries(10).build();
+        w.generate().unwrap();
+        check_distinct(&w.tiles);
+        let seeds: Vec<u64> = w.attempts().iter().map(|a| a.seed).collect();
+        assert_eq!(seeds, vec![0, derive_seed(0, 1), derive_seed(0, 2)]);
+        let failed: Vec<bool> = w.attempts().iter().map(|a| a.contradiction.is_some()).collect();
+        assert_eq!(failed, vec![true, true, false]);
+
+        // The successful attempt is a plain generation with its seed
+        let mut retried = c().retries(0);
+        retried.seed = derive_seed(0, 2);
+        let mut retried = retried.build();
+        retried.generate().unwrap();
+        assert_eq!(retried.tiles, w.tiles);
+
+        // Running out of retries reports the contradiction of the last attempt
+        let mut w = c().retries(1).build();
+        assert!(w.attempts().is_empty());
+        let r = w.generate();
+        assert_eq!(w.attempts().len(), 2);
+        assert_eq!(r, Err(WfcError::Contradiction(w.attempts()[1].contradiction.unwrap())));
+    }
+
+    #[test]
+    fn backtracking_and_bombing() {
+        let distinct = distinct2 as DefaultProbabilityCallback<Height, 3>;
+        let c = |seed, strategy, max_bombings| {
+            let c = configuration(uvec2(9, 7), seed).probability(distinct).strategy(strategy);
+            c.cell_selection(CellSelection::RandomTies).max_bombings(max_bombings).retries(0).build()
+        };
+        // Seed 0 contradicts without bombing, see `retries_with_derived_seeds`
+        assert!(c(0, Strategy::Bomb, 0).generate().is_err());
+        let mut w = c(0, Strategy::Bomb, 100);
+        w.generate().unwrap();
+        check_distinct(&w.tiles);
+        assert!(!w.bombings().is_empty());
+
+        // Backtracking only undoes the decisions that led to the contradictions
+        let mut contradictions = 0;
+        for seed in 0..20 {
+            let mut w = c(seed, Strategy::Backtrack { max_depth: 50 }, 0);
+            w.generate().unwrap();
+            check_distinct(&w.tiles);
+            assert!(w.bombings().is_empty());
+            contradictions += w.contradictions().sum();
+        }
+        assert!(contradictions > 0);
+    }
+
+    /// Panics unless all tiles within `radius` in `metric` of each other differ by at most one
+    /// step
+    fn check_gradient(tiles: &Array2<usize>, radius: u32, metric: Metric) {
+        for (p, &t) in tiles.iter_with_positions() {
+            for q in Neighborhood::<Height>::with_shape(tiles, p.as_ivec2(), radius, metric).iter().flatten() {
+                assert!(t.abs_diff(q.as_usize()) <= 1, "{} at {} sees {:?}", t, p, q);
+            }
+        }
+    }
+
+    #[test]
+    fn neighborhood_radius() {
+        for seed in 0..5 {
+            let mut w = configuration(uvec2(10, 8), seed).neighborhood_size(2).build();
+            w.generate().unwrap();
+            check_gradient(&w.tiles, 2, neighborhood::manhattan);
+            assert!(w.tiles.iter().any(|&t| t != w.tiles[[0, 0]]), "seed {}", seed);
+        }
+        let c = configuration(uvec2(4, 4), 0).neighborhood_size(0);
+        assert!(matches!(c.try_build(), Err(MapgenError::InvalidParameter { .. })));
+    }
+
+    #[test]
+    fn custom_metrics() {
+        for seed in 0..3 {
+            let mut w = configuration(uvec2(8, 6), seed).metric(neighborhood::chebyshev).build();
+            w.generate().unwrap();
+            check_gradient(&w.tiles, 1, neighborhood::chebyshev);
+        }
+
+        // Cells only see their west neighbor and continue the cycle Low, Mid, High from it.
+        // The metric is not symmetric: collapsing a cell must update its east neighbor, which
+        // then has a single choice left and is collapsed next.
+        let west = |o: IVec2| if o == -IVec2::X { 1 } else { 2 * neighborhood::manhattan(o) };
+        let cycle = |n: &Neighborhood<Height>| match n.iter().flatten().find(|t| t.is_valid()) {
+            Some(t) => {
+                let mut p = [0.0; 3];
+                p[(t.as_usize() + 1) % 3] = 1.0;
+                p
+            }
+            None => [1.0; 3],
+        };
+        let mut w = configuration(uvec2(6, 3), 0).probability(cycle).metric(west).record_collapses(true).build();
+        w.generate().unwrap();
+        for (p, &t) in w.tiles.iter_with_positions().filter(|(p, _)| p.x > 0) {
+            assert_eq!(t, (w.tiles[(p - UVec2::X).as_index2()] + 1) % 3, "at {}", p);
+        }
+        let order = w.collapse_order().unwrap();
+        assert!(order.iter_with_positions().all(|(p, &i)| i == p.y * 6 + p.x));
+    }
+
+    #[test]
+    fn bucketed_queue() {
+        // `gradient` only produces the entropies 0, 1 and log2(3), which fine buckets keep apart
+        for seed in 0..5 {
+            let c = |queue| configuration(uvec2(12, 9), seed).entropy_queue(queue).record_collapses(true).build();
+            let mut exact = c(EntropyQueue::Exact);
+            exact.generate().unwrap();
+            let mut bucketed = c(EntropyQueue::Bucketed { resolution: 0.01 });
+            bucketed.generate().unwrap();
+            assert_eq!(bucketed.tiles, exact.tiles, "seed {}", seed);
+            assert_eq!(bucketed.collapse_order(), exact.collapse_order(), "seed {}", seed);
+
+            // Coarse buckets mix cells of different entropies, which still gives valid maps
+            let mut coarse = c(EntropyQueue::Bucketed { resolution: 10.0 });
+            coarse.generate().unwrap();
+            check(&coarse.tiles, Rect::from_size(uvec2(12, 9)));
+        }
+    }
+
+    #[test]
+    fn u16_probability_storage() {
+        let weighted = |n: &Neighborhood<Height>| {
+            let mut p = gradient(n);
+            p[0] *= 0.7;
+            p
+        };
+        let c = |storage| configuration(uvec2(9, 8), 2).probability(weighted).probability_storage(storage).build();
+        let (mut f32s, mut u16s) = (c(ProbabilityStorage::F32), c(ProbabilityStorage::U16));
+        assert_eq!(u16s.probabilities_at(uvec2(0, 0)), [0.0; 3]);
+        f32s.start().unwrap();
+        u16s.start().unwrap();
+        for _ in 0..10 {
+            assert_eq!(f32s.step(), u16s.step());
+        }
+        for p in Rect::from_size(uvec2(9, 8)).iter() {
+            let (a, b) = (f32s.probabilities_at(p), u16s.probabilities_at(p));
+            assert!(a.iter().zip(b).all(|(a, b)| (a - b).abs() <= 0.5 / u16::MAX as f32), "{:?} {:?}", a, b);
+        }
+        while u16s.step().unwrap().is_some() {}
+        check(&u16s.tiles, Rect::from_size(uvec2(9, 8)));
+    }
+
+    /// Number of cells per tile
+    fn counts(tiles: &Array2<usize>) -> Vec<usize> {
+        (0..3).map(|i| tiles.iter().filter(|&&t| t == i).count()).collect()
+    }
+
+    #[test]
+    fn tile_quotas() {
+        let uniform = |_: &Neighborhood<Height>| [1.0; 3];
+        for seed in 0..5 {
+            let c = configuration(uvec2(8, 6), seed).probability(uniform);
+            let mut w = c.quota(Height::High, 3, 3).quota(Height::Low, 0, 5).build();
+            w.generate().unwrap();
+            let n = counts(&w.tiles);
+            assert_eq!(n[2], 3);
+            assert!(n[0] <= 5);
+            assert_eq!(w.tile_counts(), &n[..]);
+
+            // Preset cells count towards the quota
+            let mut w = configuration(uvec2(8, 6), seed).probability(uniform).quota(Height::High, 2, 2).build();
+            w.preset(uvec2(1, 1), Height::High);
+            w.preset(uvec2(6, 4), Height::High);
+            w.generate().unwrap();
+            assert_eq!(counts(&w.tiles)[2], 2);
+
+            // Without Mid, Low and High can not meet
+            let mut w = configuration(uvec2(8, 6), seed).quota(Height::Mid, 0, 0).build();
+            w.generate().unwrap();
+            assert!(w.tiles.iter().all(|&t| t == w.tiles[[0, 0]] && t != 1));
+        }
+
+        let c = configuration(uvec2(4, 4), 0).quota(Height::Low, 3, 2);
+        assert!(matches!(c.validate(), Err(MapgenError::InvalidParameter { .. })));
+        let mut c = configuration(uvec2(4, 4), 0);
+        c.quotas.push(TileQuota { tile: 3, min: 0, max: 1 });
+        assert!(matches!(c.validate(), Err(MapgenError::InvalidParameter { .. })));
+    }
+
+    /// Number of 4-connected components of the cells for which `open` is true
+    fn components(tiles: &Array2<usize>, open: impl Fn(usize) -> bool) -> usize {
+        let mut seen = tiles.mapv(|t| !open(t));
+        let mut n = 0;
+        for start in Rect::from_size(map_size(tiles)).iter() {
+            if seen[start.as_index2()] {
+                continue;
+            }
+            n += 1;
+            seen[start.as_index2()] = true;
+            let mut stack = vec![start];
+            while let Some(p) = stack.pop() {
+                for o in neighborhood::offsets(1, neighborhood::manhattan) {
+                    let q = p.as_ivec2() + o;
+                    if seen.get_at(q) == Some(&false) {
+                        seen[q.as_uvec2().as_index2()] = true;
+                        stack.push(q.as_uvec2());
+                    }
+                }
+            }
+        }
+        n
+    }
+
+    #[test]
+    fn connectivity() {
+        // Mostly walls (Low), the rest is walkable
+        let walls = |_: &Neighborhood<Height>| [3.0, 1.0, 1.0];
+        let mut unconnected = 0;
+        for seed in 0..5 {
+            let c = configuration(uvec2(10, 8), seed).probability(walls);
+            let mut w = c.connected(&[Height::Mid, Height::High]).build();
+            w.generate().unwrap();
+            assert_eq!(components(&w.tiles, |t| t > 0), 1, "seed {}", seed);
+            assert!(w.tiles.iter().filter(|&&t| t > 0).count() > 1);
+
+            let mut w = configuration(uvec2(10, 8), seed).probability(walls).build();
+            w.generate().unwrap();
+            unconnected += (components(&w.tiles, |t| t > 0) > 1) as usize;
+        }
+        assert!(unconnected > 0);
+
+        let mut c = configuration(uvec2(4, 4), 0);
+        c.connected = vec![3];
+        assert!(matches!(c.validate(), Err(MapgenError::InvalidParameter { .. })));
+    }
+
+    #[test]
+    fn border_conditions() {
+        let size = uvec2(9, 6);
+        for seed in 0..5 {
+            let mut w = configuration(size, seed).border(Border::Wrap).build();
+            w.generate().unwrap();
+            check(&w.tiles, Rect::from_size(size));
+            for y in 0..6 {
+                assert!(w.tiles[[0, y]].abs_diff(w.tiles[[8, y]]) <= 1, "seed {} row {}", seed, y);
+            }
+            for x in 0..9 {
+                assert!(w.tiles[[x, 0]].abs_diff(w.tiles[[x, 5]]) <= 1, "seed {} column {}", seed, x);
+            }
+        }
+
+        // Tiles on some edges, presets take precedence
+        let borders = [Border::Tile(Height::Low), Border::Wrap, Border::Any, Border::Wrap];
+        let mut w = configuration(size, 1).borders(borders).build();
+        w.preset(uvec2(4, 0), Height::Mid);
+        w.generate().unwrap();
+        check(&w.tiles, Rect::from_size(size));
+        for x in 0..9 {
+            let expected = if x == 4 { Height::Mid } else { Height::Low };
+            assert_eq!(Height::from(w.tiles[[x, 0]]), expected);
+        }
+        for y in 0..6 {
+            assert!(w.tiles[[0, y]].abs_diff(w.tiles[[8, y]]) <= 1);
+        }
+
+        let c = configuration(size, 0).border(Border::Tile(Height::Invalid));
+        assert!(matches!(c.validate(), Err(MapgenError::InvalidParameter { .. })));
+    }
+
+    #[test]
+    fn probability_context() {
+        let size = uvec2(8, 5);
+        let layers = || {
+            let mut layers = MapStack::new(size);
+            layers.insert("elevation", Array2::from_shape_fn(size.as_index2(), |(x, _)| x as f64 / 8.0));
+            layers
+        };
+        let progress = std::cell::Cell::new((1.0_f32, 0.0_f32));
+        // High where the guidance is high, elsewhere a coin flip from the context's rng
+        let guided = WithContext(|_: &Neighborhood<Height>, ctx: &mut ProbabilityContext| {
+            let (lo, hi) = progress.get();
+            progress.set((lo.min(ctx.progress), hi.max(ctx.progress)));
+            match ctx.value::<f64>("elevation") {
+                Some(e) if e > 0.5 => [0.0, 0.0, 1.0],
+                Some(_) if ctx.rng.gen::<bool>() => [1.0, 0.0, 0.0],
+                Some(_) => [0.0, 1.0, 0.0],
+                None => panic!("no guidance at {}", ctx.position),
+            }
+        });
+        let mut c = Height::configuration().probability(guided).guidance(layers());
+        c.size = size;
+        let mut w = c.build();
+        w.generate().unwrap();
+        let (lo, hi) = progress.get();
+        assert!(lo == 0.0 && hi > 0.5 && hi < 1.0, "{} {}", lo, hi);
+        for (p, &t) in w.tiles.iter_with_positions() {
+            assert_eq!(t == Height::High.as_usize(), p.x > 4, "at {}", p);
+        }
+        let flips: Vec<usize> = w.tiles.iter().copied().filter(|&t| t < 2).collect();
+        assert!(flips.contains(&0) && flips.contains(&1));
+        let first = w.tiles.clone();
+        w.generate().unwrap();
+        assert_eq!(w.tiles, first);
+
+        // Guidance layers must cover the map
+        let mut c = configuration(uvec2(4, 4), 0).guidance(layers());
+        assert!(matches!(c.validate(), Err(MapgenError::InvalidParameter { .. })));
+        c.size = size;
+        c.validate().unwrap();
+
+        // Guided weights rule out tiles with zero weight
+        let weights = |size| GuidedWeights::new([Array2::zeros(size), Array2::ones(size), Array2::ones((6, 5))]);
+        let inner = gradient as DefaultProbabilityCallback<Height, 3>;
+        let mut c = Height::configuration().probability(weights((6, 5)).inner(inner));
+        c.size = uvec2(6, 5);
+        c.validate().unwrap();
+        let mut w = c.build();
+        w.generate().unwrap();
+        check(&w.tiles, Rect::from_size(uvec2(6, 5)));
+        assert!(w.tiles.iter().all(|&t| t != Height::Low.as_usize()));
+        let mut c = Height::configuration().probability(weights((5, 5)).inner(inner));
+        c.size = uvec2(6, 5);
+        assert!(matches!(c.validate(), Err(MapgenError::InvalidParameter { .. })));
+    }
+
+    #[test]
+    fn cell_state_accessors() {
+        let area = Rect::new(uvec2(1, 1), uvec2(4, 3));
+        let mut w = configuration(uvec2(6, 5), 0).area(area).build();
+        assert_eq!(w.probabilities_at(uvec2(2, 2)), [NO_PROBABILITY; 3]);
+        w.preset(uvec2(2, 2), Height::High);
+        w.preset(uvec2(0, 0), Height::Low);
+        assert!(w.is_valid(uvec2(2, 2)) && !w.is_valid(uvec2(3, 2)));
+        w.start().unwrap();
+        assert_eq!(w.remaining(), 11);
+        assert_eq!(w.tile_counts(), &[0, 0, 1]);
+        assert_eq!(w.probabilities_at(uvec2(2, 2)), [0.0, 0.0, 1.0]);
+        assert_eq!(w.probabilities_at(uvec2(0, 0)), [1.0, 0.0, 0.0]);
+        assert_eq!(w.probabilities_at(uvec2(5, 4)), [0.0; 3]);
+        // Next to High, Low is ruled out
+        assert_eq!(w.probabilities_at(uvec2(3, 2)), [0.0, 0.5, 0.5]);
+        assert_eq!(w.probabilities_at(uvec2(4, 3)), [1.0 / 3.0; 3]);
+
+        let mut collapsed = 0;
+        while let Some(p) = w.step().unwrap() {
+            collapsed += 1;
+            assert!(w.is_valid(p));
+            assert_eq!(w.remaining() + w.tile_counts().iter().sum::<usize>(), 12);
+            let ps = w.probabilities_at(p);
+            assert_eq!(ps[w.tiles[p.as_index2()]], 1.0);
+        }
+        assert_eq!((collapsed, w.remaining()), (11, 0));
+        for (p, &valid) in w.valid().iter_with_positions() {
+            assert_eq!(valid, area.contains(p) || p == UVec2::ZERO, "at {}", p);
+        }
+    }
+
+    #[test]
+    fn recorded_collapses() {
+        let mut w = configuration(uvec2(5, 4), 0).build();
+        w.generate().unwrap();
+        assert!(w.chosen_probabilities().is_none() && w.collapse_order().is_none());
+
+        let mut w = configuration(uvec2(5, 4), 1).record_collapses(true).build();
+        w.preset(uvec2(2, 1), Height::Mid);
+        w.generate().unwrap();
+        let (probabilities, order) = (w.chosen_probabilities().unwrap(), w.collapse_order().unwrap());
+        assert_eq!(probabilities[[2, 1]], NO_PROBABILITY);
+        assert_eq!(order[[2, 1]], NOT_COLLAPSED);
+        let mut indices: Vec<u32> = order.iter().copied().filter(|&i| i != NOT_COLLAPSED).collect();
+        indices.sort_unstable();
+        assert_eq!(indices, (0..19).collect::<Vec<_>>());
+        // `gradient` leaves 1, 2 or 3 equally likely tiles
+        assert!(probabilities.iter_with_positions().filter(|(p, _)| *p != uvec2(2, 1)).all(|(_, &p)| {
+            [1.0, 0.5, 1.0 / 3.0].contains(&p)
+        }));
+        assert!(probabilities.iter().any(|&p| p < 0.5));
+
+        // Cells of an orbit are collapsed together
+        let mut c = configuration(uvec2(6, 4), 2).record_collapses(true);
+        c.symmetry = Some(Symmetry::MirrorX);
+        let mut w = c.build();
+        w.generate().unwrap();
+        let order = w.collapse_order().unwrap();
+        for (p, &i) in order.iter_with_positions() {
+            assert_eq!(i, order[[5 - p.x as usize, p.y as usize]]);
+            assert!(i < 12);
+        }
+    }
+
+    /// Fails to compile unless `T` is `Send`
+    fn assert_send<T: Send>() {}
+
+    #[test]
+    fn generate_from_several_threads() {
+        assert_send::<WaveFunctionCollapse<Height, DefaultProbabilityCallback<Height, 3>, 3>>();
+        assert_send::<WaveFunctionCollapse<Height, GuidedWeights<UniformWeights, 3>, 3>>();
+        assert_send::<Snapshot<Height, DefaultProbabilityCallback<Height, 3>, 3>>();
+        assert_send::<WaveFunctionCollapse3<Height, DefaultProbabilityCallback3<Height, 3>, 3>>();
+
+        let size = uvec2(12, 9);
+        let sequential: Vec<Array2<usize>> = (0..4)
+            .map(|seed| {
+                let mut w = configuration(size, seed).build();
+                w.generate().unwrap();
+                w.tiles
+            })
+            .collect();
+
+        // One shared configuration
+        let shared = Arc::new(configuration(size, 2));
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let mut w = WaveFunctionCollapse::new(shared.clone());
+                std::thread::spawn(move || w.generate().map(|_| w.tiles))
+            })
+            .collect();
+        for t in threads {
+            assert_eq!(t.join().unwrap().unwrap(), sequential[2]);
+        }
+
+        // One configuration per seed
+        let maps: Vec<Array2<usize>> = std::thread::scope(|s| {
+            let threads: Vec<_> = (0..4)
+                .map(|seed| {
+                    s.spawn(move || {
+                        let mut w = configuration(size, seed).build();
+                        w.generate().map(|_| w.tiles)
+                    })
+                })
+                .collect();
+            threads.into_iter().map(|t| t.join().unwrap().unwrap()).collect()
+        });
+        assert_eq!(maps, sequential);
+    }
+
+    #[test]
+    fn snapshot_and_restore() {
+        let calls = std::cell::Cell::new(0);
+        let counted = |n: &Neighborhood<Height>| {
+            calls.set(calls.get() + 1);
+            gradient(n)
+        };
+        let mut c = Height::configuration().probability(counted).strategy(Strategy::Backtrack { max_depth: 50 });
+        c.size = uvec2(8, 6);
+        let mut w = c.build();
+        w.start().unwrap();
+        for _ in 0..5 {
+            w.step().unwrap();
+        }
+        let snapshot = w.snapshot();
+        let (tiles, remaining) = (w.tiles.clone(), w.remaining());
+
+        let mut ahead = Vec::new();
+        while let Some(p) = w.step().unwrap() {
+            ahead.push(p);
+        }
+        let finished = w.tiles.clone();
+        check(&finished, Rect::from_size(uvec2(8, 6)));
+
+        let before = calls.get();
+        w.restore(&snapshot);
+        assert_eq!(calls.get(), before);
+        assert_eq!((&w.tiles, w.remaining()), (&tiles, remaining));
+        // The random state is restored too, so the same cells and tiles follow
+        let mut again = Vec::new();
+        while let Some(p) = w.step().unwrap() {
+            again.push(p);
+        }
+        assert_eq!(again, ahead);
+        assert_eq!(w.tiles, finished);
+
+        // Snapshots are independent of later changes
+        w.restore(&snapshot);
+        assert_eq!(w.tiles, tiles);
+    }
+
+    type Configuration3 = WaveFunctionCollapseConfiguration3<Height, DefaultProbabilityCallback3<Height, 3>, 3>;
+
+    /// `gradient` in 3D
+    fn gradient3(n: &Neighborhood3<Height>) -> [f32; 3] {
+        smooth(n)
+    }
+
+    fn configuration3(size: UVec3, seed: u64) -> Configuration3 {
+        Configuration3::default()
+            .probability(gradient3 as DefaultProbabilityCallback3<Height, 3>)
+            .size(size)
+            .seed(seed)
+            .max_bombings(100)
+    }
+
+    /// Face neighbors of `p` in a map of `size`, wrapping along the `wrap` axes
+    fn face_neighbors(p: UVec3, size: UVec3, wrap: BVec3) -> Vec<UVec3> {
+        let wrap: [bool; 3] = wrap.into();
+        let mut r = Vec::new();
+        for axis in 0..3 {
+            let mut q = p;
+            q[axis] += 1;
+            if q[axis] == size[axis] && wrap[axis] {
+                q[axis] = 0;
+            }
+            if q[axis] < size[axis] {
+                r.push(q);
+            }
+        }
+        r
+    }
+
+    #[test]
+    fn wfc3_gradient() {
+        let size = uvec3(7, 5, 4);
+        let mut w = configuration3(size, 5).try_build().unwrap();
+        w.try_generate().unwrap();
+        assert!(w.valid().iter().all(|&v| v));
+        for p in Rect3::from_size(size).iter() {
+            for q in face_neighbors(p, size, BVec3::FALSE) {
+                assert!(w.tiles[index3(p)].abs_diff(w.tiles[index3(q)]) <= 1, "{} next to {}", p, q);
+            }
+        }
+        // All tiles are used, and the same seed gives the same map
+        assert!((0..3).all(|t| w.tiles.iter().any(|&u| u == t)));
+        let mut again = configuration3(size, 5).build();
+        again.generate().unwrap();
+        assert_eq!(again.tiles, w.tiles);
+        let mut other = configuration3(size, 6).build();
+        other.generate().unwrap();
+        assert_ne!(other.tiles, w.tiles);
+    }
+
+    #[test]
+    fn wfc3_area_preset_and_wrap() {
+        let size = uvec3(6, 6, 5);
+        let area = Rect3::new(uvec3(1, 2, 1), uvec3(4, 3, 3));
+        let mut w = configuration3(size, 1).area(area).build();
+        w.preset(uvec3(2, 3, 2), Height::High);
+        w.preset(uvec3(0, 0, 0), Height::Low);
+        w.generate().unwrap();
+        for p in Rect3::from_size(size).iter() {
+            let valid = Height::from(w.tiles[index3(p)]).is_valid();
+            assert_eq!(valid, area.contains(p) || p == UVec3::ZERO, "{}", p);
+        }
+        assert_eq!(Height::from(w.tiles[[2, 3, 2]]), Height::High);
+
+        // Cells on opposite faces are neighbors when wrapping
+        let wrap = BVec3::new(true, false, true);
+        let mut w = configuration3(size, 2).wrap(wrap).build();
+        w.generate().unwrap();
+        for p in Rect3::from_size(size).iter() {
+            for q in face_neighbors(p, size, wrap) {
+                assert!(w.tiles[index3(p)].abs_diff(w.tiles[index3(q)]) <= 1, "{} next to {}", p, q);
+            }
+        }
+    }
+
+    /// Face neighbors must differ, which runs into contradictions once a cell sees all 3 tiles
+    fn distinct(n: &Neighborhood3<Height>) -> [f32; 3] {
+        let mut p = [1.0; 3];
+        for t in n.iter().flatten().filter(|t| t.is_valid()) {
+            p[t.as_usize()] = 0.0;
+        }
+        p
+    }
+
+    #[test]
+    fn wfc3_bombing() {
+        let size = uvec3(6, 6, 6);
+        let distinct = distinct as DefaultProbabilityCallback3<Height, 3>;
+        let config = || Configuration3::default().probability(distinct).size(size).seed(6);
+
+        // Without bombings, this seed runs into a contradiction
+        let mut w = config().build();
+        assert!(matches!(w.generate(), Err(WfcError::Contradiction3(_))));
+        assert_eq!(w.attempts().len(), 1);
+
+        let mut w = config().max_bombings(100).build();
+        w.generate().unwrap();
+        assert!(!w.bombings().is_empty());
+        for p in Rect3::from_size(size).iter() {
+            for q in face_neighbors(p, size, BVec3::FALSE) {
+                assert_ne!(w.tiles[index3(p)], w.tiles[index3(q)], "{} next to {}", p, q);
+            }
+        }
+
+        // The corner sees all 3 tiles, bombing the cube around it is clipped to the map and does
+        // not reset the presets, so the radius grows up to the map size
+        let mut w = config().max_bombings(5).build();
+        w.preset(uvec3(1, 0, 0), Height::Low);
+        w.preset(uvec3(0, 1, 0), Height::Mid);
+        w.preset(uvec3(0, 0, 1), Height::High);
+        assert_eq!(w.generate(), Err(WfcError::Contradiction3(Contradiction { position: UVec3::ZERO })));
+        let radii: Vec<u32> = w.bombings().iter().map(|b| b.radius).collect();
+        assert_eq!(radii, vec![1, 2, 4, 6, 6]);
+        assert!(w.bombings().iter().all(|b| b.center == UVec3::ZERO));
+    }
+
+    #[test]
+    fn wfc3_invalid_configuration() {
+        let config = |size| configuration3(size, 0);
+        assert!(config(uvec3(4, 0, 4)).try_build().is_err());
+        assert!(config(uvec3(4, 4, 4)).neighborhood_size(0).try_build().is_err());
+        let outside = Rect3::new(uvec3(2, 2, 2), uvec3(2, 2, 3));
+        let mut w = config(uvec3(4, 4, 4)).area(outside).build();
+        assert!(matches!(w.generate(), Err(WfcError::AreaOutOfBounds3 { .. })));
+        assert!(matches!(w.try_generate(), Err(MapgenError::Wfc(WfcError::AreaOutOfBounds3 { .. }))));
+        let two = WaveFunctionCollapseConfiguration3::<Height, DefaultProbabilityCallback3<Height, 2>, 2>::default();
+        assert!(matches!(two.validate(), Err(MapgenError::TileCount { n: 2, tiles: 3 })));
+    }
 }
@@ -0,0 +1,198 @@
+
+use crate::colored_noise::ColoredNoise;
+use crate::map_stack::MapStack;
+use crate::seed::derive_seed;
+use glam::{uvec2, vec2, UVec2, Vec2};
+use ndarray::Array2;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::collections::VecDeque;
+use std::f32::consts::{FRAC_PI_2, PI, TAU};
+
+/// Result of `Worms::generate`.
+#[derive(Clone, Debug)]
+pub struct Tunnels {
+    /// `true` = carved
+    pub mask: Array2<bool>,
+    /// Centerline of every worm (including branches), in tile coordinates
+    pub paths: Vec<Vec<Vec2>>,
+}
+
+/// Cave network generator that carves sinuous tunnels with "worms".
+///
+/// Each worm moves one tile per step in a direction read from a noise field ("perlin worms"),
+/// so that tunnels curve smoothly instead of wandering randomly. Worms turn around at the
+/// map border. Worms may branch, branches are half as long as the rest of their parent. The
+/// tunnel radius varies along the way with a second noise field.
+/// Uses the same conventions as `ColoredNoise` (`size` field, `generate()`).
+#[derive(Clone)]
+pub struct Worms {
+    pub size: UVec2,
+    pub seed: u64,
+    /// Number of worms started at random positions
+    pub count: usize,
+    /// Steps (tiles) per worm
+    pub length: usize,
+    pub radius: f32,
+    /// Relative radius variation in [0, 1], 0.5 means radii between 0.5 and 1.5 times `radius`
+    pub radius_variation: f32,
+    /// How far a worm's heading deviates from its initial heading, in half turns: 0 = straight
+    /// tunnels, 1 = the noise field covers all directions
+    pub winding: f32,
+    /// Color of the noise fields for turning and radius, see `ColoredNoise`
+    pub noise_color: f64,
+    /// Probability per step to spawn a branch
+    pub branch_probability: f64,
+    /// Upper limit for the total number of worms including branches
+    pub max_worms: usize,
+}
+
+impl Default for Worms {
+    fn default() -> Self {
+        Self {
+            size: uvec2(100, 100),
+            seed: 0,
+            count: 3,
+            length: 100,
+            radius: 1.5,
+            radius_variation: 0.5,
+            winding: 1.0,
+            noise_color: -2.0,
+            branch_probability: 0.02,
+            max_worms: 32,
+        }
+    }
+}
+
+impl Worms {
+    pub fn generate(&self) -> Tunnels {
+        let size = (self.size.x as usize, self.size.y as usize);
+        let mut mask = Array2::from_elem(size, false);
+        let mut paths = Vec::new();
+        if size.0 == 0 || size.1 == 0 {
+            return Tunnels { mask, paths };
+        }
+
+        let noise = |n| ColoredNoise { size: self.size, color: self.noise_color, seed: derive_seed(self.seed, n), ..Default::default() }.generate();
+        let (steering, thickness) = (noise(0), noise(1));
+        let mut rng = StdRng::seed_from_u64(derive_seed(self.seed, 2));
+
+        // (position, heading, length)
+        let mut queue: VecDeque<(Vec2, f32, usize)> = (0..self.count)
+            .map(|_| {
+                let p = vec2(rng.gen_range(0.0..self.size.x as f32), rng.gen_range(0.0..self.size.y as f32));
+                (p, rng.gen_range(0.0..TAU), self.length)
+            })
+            .collect();
+        let mut started = queue.len();
+
+        while let Some((mut p, mut initial, length)) = queue.pop_front() {
+            let mut heading = initial;
+            let mut path = Vec::with_capacity(length + 1);
+            for step in 0..=length {
+                let i = [p.x as usize, p.y as usize];
+                let r = self.radius * (1.0 + self.radius_variation * (2.0 * thickness[i] as f32 - 1.0));
+                carve(&mut mask, p, r);
+                path.push(p);
+
+                let remaining = length - step;
+                if started < self.max_worms && remaining > 1 && rng.gen::<f64>() < self.branch_probability {
+                    let side = if rng.gen() { FRAC_PI_2 } else { -FRAC_PI_2 };
+                    queue.push_back((p, heading + side, remaining / 2));
+                    started += 1;
+                }
+
+                heading = initial + self.winding * PI * (2.0 * steering[i] as f32 - 1.0);
+                let mut next = p + Vec2::from_angle(heading);
+                if !inside(next, self.size) {
+                    initial += PI;
+                    heading += PI;
+                    next = p + Vec2::from_angle(heading);
+                }
+                p = next.clamp(Vec2::ZERO, self.size.as_vec2() - 1e-3);
+            }
+            paths.push(path);
+        }
+
+        Tunnels { mask, paths }
+    }
+
+    /// Generate tunnels of the size of `layers` and store the carved mask as layer `name`.
+    pub fn generate_into_layer(&self, layers: &mut MapStack, name: &str) {
+        let worms = Self { size: layers.size(), ..self.clone() };
+        layers.insert(name, worms.generate().mask);
+    }
+}
+
+fn inside(p: Vec2, size: UVec2) -> bool {
+    p.x >= 0.0 && p.y >= 0.0 && p.x < size.x as f32 && p.y < size.y as f32
+}
+
+/// Mark all tiles whose center is within `radius` of `center`.
+fn carve(mask: &mut Array2<bool>, center: Vec2, radius: f32) {
+    let (sx, sy) = mask.dim();
+    let r = radius.max(0.5);
+    let x0 = (center.x - r).floor().max(0.0) as usize;
+    let y0 = (center.y - r).floor().max(0.0) as usize;
+    let x1 = ((center.x + r).ceil() as usize).min(sx);
+    let y1 = ((center.y + r).ceil() as usize).min(sy);
+    for x in x0..x1 {
+        for y in y0..y1 {
+            if (vec2(x as f32, y as f32) + 0.5).distance(center) <= r {
+                mask[[x, y]] = true;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn worms() -> Worms {
+        Worms { size: uvec2(40, 30), seed: 5, count: 4, length: 60, ..Default::default() }
+    }
+
+    #[test]
+    fn paths_are_continuous_and_carved() {
+        let w = Worms { branch_probability: 0.0, ..worms() };
+        let t = w.generate();
+        assert_eq!(t.paths.len(), 4);
+        for path in &t.paths {
+            assert_eq!(path.len(), 61);
+            assert!(path.iter().all(|&p| inside(p, w.size) && t.mask[[p.x as usize, p.y as usize]]));
+            assert!(path.windows(2).all(|s| s[0].distance(s[1]) <= 1.0 + 1e-5));
+        }
+        assert!(t.mask.iter().any(|&m| !m));
+        assert_eq!(t.mask, w.generate().mask);
+        assert_ne!(t.mask, Worms { seed: 6, ..w.clone() }.generate().mask);
+    }
+
+    #[test]
+    fn branches() {
+        let t = Worms { branch_probability: 1.0, max_worms: 7, ..worms() }.generate();
+        assert_eq!(t.paths.len(), 7);
+        // Branches start on their parent and are at most half as long as the rest of it
+        for branch in &t.paths[4..] {
+            assert!(branch.len() <= 31);
+            assert!(t.paths.iter().any(|parent| !std::ptr::eq(parent, branch) && parent.contains(&branch[0])));
+        }
+    }
+
+    #[test]
+    fn carve_radius() {
+        let count = |radius| {
+            let mut mask = Array2::from_elem((9, 9), false);
+            carve(&mut mask, vec2(4.5, 4.5), radius);
+            mask.iter().filter(|&&m| m).count()
+        };
+        assert_eq!(count(0.1), 1);
+        assert_eq!(count(1.5), 9);
+        assert_eq!(count(2.0), 13);
+        assert_eq!(count(10.0), 81);
+
+        assert!(Worms { size: uvec2(0, 5), ..worms() }.generate().mask.is_empty());
+        let mut layers = MapStack::new(uvec2(12, 8));
+        worms().generate_into_layer(&mut layers, "caves");
+        assert_eq!(layers.get::<bool>("caves").unwrap().dim(), (12, 8));
+    }
+}